@@ -0,0 +1,311 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::available_parallelism,
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use image::Rgba;
+use pixfix::{
+    convert::{self, BorderPixel, BorderThinning, Image16},
+    options::{Grid, Options},
+    progress, workers,
+};
+use tokio::sync::{mpsc, Semaphore};
+
+/// A round sprite in the middle of a transparent canvas, so there is a
+/// border ring and a large transparent region around it.
+fn sprite(size: u32) -> Image16 {
+    let center = size as f64 / 2.0;
+    let radius = size as f64 / 3.0;
+
+    Image16::from_fn(size, size, |x, y| {
+        let dx = x as f64 - center;
+        let dy = y as f64 - center;
+
+        if (dx * dx + dy * dy).sqrt() > radius {
+            return Rgba([0, 0, 0, 0]);
+        }
+
+        Rgba([(x * 64) as u16, (y * 64) as u16, 32768, u16::MAX])
+    })
+}
+
+/// Deterministic pseudo-random border pixels spread over a `size` square.
+fn random_border_pixels(count: usize, size: u32) -> Vec<BorderPixel> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % size as u64) as u32
+    };
+
+    (0..count)
+        .map(|_| BorderPixel::new(next(), next(), Rgba([1000, 2000, 3000, u16::MAX])))
+        .collect()
+}
+
+fn collect_pixels(c: &mut Criterion) {
+    let img = sprite(1024);
+    let colored = convert::colored_mask(&img);
+
+    c.bench_function("collect_pixels 1024x1024", |b| {
+        b.iter(|| convert::collect_pixels(&img, &colored))
+    });
+}
+
+fn bulk_load(c: &mut Criterion) {
+    c.bench_function("bulk_load 10K points", |b| {
+        b.iter_batched(
+            || random_border_pixels(10_000, 4096),
+            |border_pixels| convert::triangulate(border_pixels).unwrap(),
+            criterion::BatchSize::LargeInput,
+        )
+    });
+}
+
+fn nearest_neighbor(c: &mut Criterion) {
+    // 317 x 317 is just over 100K transparent pixels.
+    let mut img = Image16::new(317, 317);
+    let transparent_pixels: Vec<_> = img.enumerate_pixels().map(|(x, y, p)| (x, y, *p)).collect();
+    let triangulation = convert::triangulate(random_border_pixels(10_000, 317)).unwrap();
+
+    c.bench_function("fill_transparent 100K pixels", |b| {
+        b.iter(|| convert::fill_transparent(&mut img, &triangulation, &transparent_pixels, 0))
+    });
+}
+
+/// The same fill as `nearest_neighbor` with `--progress` tracking the file,
+/// to compare against it. Runs last, as tracking stays on once enabled.
+fn nearest_neighbor_tracked(c: &mut Criterion) {
+    let mut img = Image16::new(317, 317);
+    let transparent_pixels: Vec<_> = img.enumerate_pixels().map(|(x, y, p)| (x, y, *p)).collect();
+    let triangulation = convert::triangulate(random_border_pixels(10_000, 317)).unwrap();
+
+    progress::enable();
+    let _tracked = progress::track(Path::new("bench.png"));
+    progress::filling(transparent_pixels.len());
+
+    c.bench_function("fill_transparent 100K pixels with progress", |b| {
+        b.iter(|| convert::fill_transparent(&mut img, &triangulation, &transparent_pixels, 0))
+    });
+}
+
+/// A 4096 x 4096 sheet of 256 sprites, filled with more and more threads to
+/// see how well the cells scale up to the number of cores.
+fn sprite_sheet(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fill_grid 4096x4096 256 cells");
+    group.sample_size(10);
+
+    let cell = sprite(256);
+    let sheet = Image16::from_fn(4096, 4096, |x, y| *cell.get_pixel(x % 256, y % 256));
+    let grid = Grid {
+        columns: 16,
+        rows: 16,
+    };
+    let cores = available_parallelism().map_or(1, |cores| cores.get());
+
+    for threads in (0..)
+        .map(|power| 1 << power)
+        .take_while(|&threads| threads <= cores)
+    {
+        // The thread running the fill counts as one, like a file of a batch.
+        let pool = Arc::new(Semaphore::new(threads - 1));
+
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &pool, |b, pool| {
+            b.iter_batched(
+                || sheet.clone(),
+                |mut sheet| {
+                    workers::with_pool(pool.clone(), || {
+                        convert::fill_grid(&mut sheet, grid, |cell| {
+                            convert::fill_islands(cell, 1, 0, false, BorderThinning::default())
+                        })
+                    })
+                },
+                criterion::BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+/// Creates `fanout` subfolders of `dir`, `depth` levels deep, with
+/// `files` PNG files holding `png` in every folder.
+fn make_tree(dir: &Path, depth: u32, fanout: u32, files: u32, png: &[u8]) {
+    std::fs::create_dir_all(dir).unwrap();
+
+    for file in 0..files {
+        std::fs::write(dir.join(format!("{}.png", file)), png).unwrap();
+    }
+
+    if depth > 0 {
+        for sub in 0..fanout {
+            make_tree(&dir.join(sub.to_string()), depth - 1, fanout, files, png);
+        }
+    }
+}
+
+/// A recursive scan of a deep tree of about 20,000 files, listed by one
+/// thread like a serial walk and by more. Folders on a network share are
+/// slower to list, so more threads than cores still help there.
+fn resolve_files(c: &mut Criterion) {
+    let mut group = c.benchmark_group("resolve_files 4 levels 20k files");
+    group.sample_size(10);
+
+    let root = std::env::temp_dir().join("pixfix_bench_tree");
+    let _ = std::fs::remove_dir_all(&root);
+    make_tree(&root, 4, 6, 12, &[]);
+
+    for jobs in [1, 2, 4, 8, 16] {
+        let options = Options {
+            recursive: true,
+            jobs,
+            ..Default::default()
+        };
+
+        group.bench_with_input(BenchmarkId::from_parameter(jobs), &options, |b, options| {
+            b.iter(|| pixfix::files::resolve_files(vec![root.clone().into()], options))
+        });
+    }
+
+    let _ = std::fs::remove_dir_all(&root);
+    group.finish();
+}
+
+/// How long a recursive scan of a deep tree takes until fixing can start:
+/// when the first file arrives from the streamed walker, against when the
+/// whole tree is resolved and pre-scanned as runs that need the full list
+/// do it.
+fn first_file(c: &mut Criterion) {
+    let mut group = c.benchmark_group("first file of 4 levels 20k files");
+    group.sample_size(10);
+
+    let root = std::env::temp_dir().join("pixfix_bench_stream_tree");
+    let _ = std::fs::remove_dir_all(&root);
+    make_tree(&root, 4, 6, 12, &pixfix::fixtures::fringed_sprite());
+
+    for jobs in [1, 8] {
+        let options = Options {
+            recursive: true,
+            jobs,
+            ..Default::default()
+        };
+
+        group.bench_with_input(
+            BenchmarkId::new("streamed", jobs),
+            &options,
+            |b, options| {
+                b.iter_custom(|iters| {
+                    let mut total = Duration::ZERO;
+
+                    for _ in 0..iters {
+                        let start = Instant::now();
+                        let (sender, mut receiver) = mpsc::unbounded_channel();
+
+                        std::thread::scope(|scope| {
+                            scope.spawn(|| {
+                                pixfix::files::resolve_files_streamed(
+                                    vec![root.clone().into()],
+                                    options,
+                                    sender,
+                                )
+                            });
+
+                            assert!(receiver.blocking_recv().is_some());
+                            total += start.elapsed();
+
+                            while receiver.blocking_recv().is_some() {}
+                        });
+                    }
+
+                    total
+                })
+            },
+        );
+
+        group.bench_with_input(BenchmarkId::new("listed", jobs), &options, |b, options| {
+            b.iter(|| {
+                let mut resolved = pixfix::files::resolve_files(vec![root.clone().into()], options);
+                pixfix::prescan::prescan(&mut resolved, options);
+
+                assert!(!resolved.files.is_empty());
+            })
+        });
+    }
+
+    let _ = std::fs::remove_dir_all(&root);
+    group.finish();
+}
+
+fn end_to_end(c: &mut Criterion) {
+    let mut group = c.benchmark_group("convert_image");
+    group.sample_size(10);
+
+    let options = Options::default();
+
+    for size in [512, 1024, 4096] {
+        let path: PathBuf = std::env::temp_dir().join(format!("pixfix_bench_{}.png", size));
+        image::DynamicImage::ImageRgba16(sprite(size))
+            .to_rgba8()
+            .save(&path)
+            .unwrap();
+
+        // The fixed image keeps its transparent pixels, so every iteration
+        // does the same amount of work on the overwritten file.
+        group.bench_with_input(BenchmarkId::from_parameter(size), &path, |b, path| {
+            b.iter(|| convert::convert_image(path, &options))
+        });
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    group.finish();
+}
+
+/// Many tiny images, where the cost of each image matters more than its
+/// pixels, like a folder of icons.
+fn small_images(c: &mut Criterion) {
+    let mut group = c.benchmark_group("small_images");
+    group.sample_size(10);
+
+    let options = Options::default();
+    let name = Path::new("icon.png");
+    let icons: Vec<Vec<u8>> = (0..10_000)
+        .map(|index| {
+            let mut bytes = std::io::Cursor::new(Vec::new());
+            image::DynamicImage::ImageRgba16(sprite(24 + index % 9))
+                .to_rgba8()
+                .write_to(&mut bytes, image::ImageOutputFormat::Png)
+                .unwrap();
+
+            bytes.into_inner()
+        })
+        .collect();
+
+    group.bench_function("fix_bytes 10K icons", |b| {
+        b.iter(|| {
+            for icon in &icons {
+                let _ = convert::fix_bytes(icon, name, &options);
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    collect_pixels,
+    bulk_load,
+    nearest_neighbor,
+    sprite_sheet,
+    resolve_files,
+    first_file,
+    end_to_end,
+    nearest_neighbor_tracked,
+    small_images
+);
+criterion_main!(benches);
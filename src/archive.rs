@@ -0,0 +1,180 @@
+//! Fixes the PNGs inside zip archives in memory, copying every other entry
+//! over untouched.
+
+use std::{
+    io::{Cursor, Read, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use zip::{result::ZipError, write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{
+    convert::{check_timeout, fix_bytes, lock_input, Converted, Deadline, FixedBytes},
+    error::PixfixError,
+    files::is_png_file,
+    options::Options,
+    paths::{self, io_path},
+    retry::with_retries,
+};
+
+pub struct ArchiveReport {
+    /// Where the fixed archive was written, `None` for `--check`.
+    pub output_path: Option<PathBuf>,
+    /// Every PNG entry by name, and what happened to it.
+    pub entries: Vec<(String, Result<Converted, PixfixError>)>,
+}
+
+pub fn is_zip_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+/// Where the fixed archive is written. `pack.zip` becomes `pack-fixed.zip`
+/// unless `--in-place` is set.
+pub fn output_path(path: &Path, options: &Options) -> PathBuf {
+    if options.in_place {
+        return path.to_path_buf();
+    }
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    path.with_file_name(format!("{}-fixed.zip", stem))
+}
+
+fn read_error(error: ZipError) -> PixfixError {
+    match error {
+        ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED) => {
+            PixfixError::Archive("Encrypted archives are not supported".into())
+        }
+        error => PixfixError::Archive(format!("An error occured reading the archive: {}", error)),
+    }
+}
+
+fn write_error(error: impl std::fmt::Display) -> PixfixError {
+    PixfixError::Archive(format!("An error occured writing the archive: {}", error))
+}
+
+/// Fixes every PNG entry of the archive at `path`. Entries are reported one
+/// by one, while problems with the archive itself fail it as a whole.
+pub fn fix_archive(path: &Path, options: &Options) -> Result<Converted, PixfixError> {
+    let start = Instant::now();
+    let _deadline = Deadline::start(start, options);
+    let _lock = lock_input(path, options)?;
+
+    let bytes = with_retries(&io_path(path), options.retries, options.verbose, |path| {
+        std::fs::read(path)
+    })
+    .map_err(|err| PixfixError::Read {
+        attempts: err.attempts,
+        error: err.error,
+    })?;
+
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(read_error)?;
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    writer.set_raw_comment(archive.comment().to_vec());
+
+    let output_path = output_path(path, options);
+    let mut entries = Vec::new();
+
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index).map_err(read_error)?;
+        let name = entry.name().to_string();
+
+        if entry.is_dir() || !is_png_file(Path::new(&name)) {
+            writer.raw_copy_file(entry).map_err(write_error)?;
+            continue;
+        }
+
+        // The entry keeps its own time rather than the time of the fix, so
+        // fixing the same archive twice gives the same bytes.
+        let mut file_options = FileOptions::default()
+            .last_modified_time(entry.last_modified())
+            .compression_method(match entry.compression() {
+                CompressionMethod::Stored => CompressionMethod::Stored,
+                _ => CompressionMethod::Deflated,
+            });
+
+        if let Some(mode) = entry.unix_mode() {
+            file_options = file_options.unix_permissions(mode);
+        }
+
+        drop(entry);
+
+        // Encrypted entries fail here, which fails the whole archive.
+        let mut entry = archive.by_index(index).map_err(read_error)?;
+        let mut data = Vec::new();
+
+        let result = match entry.read_to_end(&mut data) {
+            Ok(_) => fix_bytes(&data, &path.join(&name), options),
+            Err(err) => Err(PixfixError::Archive(format!(
+                "An error occured reading the entry: {}",
+                err
+            ))),
+        };
+
+        drop(entry);
+
+        let result = match result {
+            Ok(FixedBytes::Fixed {
+                bytes,
+                changed_pixels,
+                trim,
+            }) => {
+                let file_options = file_options.large_file(bytes.len() as u64 >= u32::MAX as u64);
+
+                writer
+                    .start_file(name.as_str(), file_options)
+                    .map_err(write_error)?;
+                writer.write_all(&bytes).map_err(write_error)?;
+
+                let fixed = Converted::Fixed {
+                    output_path: output_path.join(&name),
+                    digest: options.digest.map(|algorithm| algorithm.digest(&bytes)),
+                    changed_pixels,
+                    extra_outputs: Vec::new(),
+                    trim,
+                };
+                entries.push((name, Ok(fixed)));
+
+                continue;
+            }
+            Ok(FixedBytes::NoAlphaChannel) => Ok(Converted::NoAlphaChannel),
+            Ok(FixedBytes::Checked { bad_pixels }) => Ok(Converted::Checked { bad_pixels }),
+            Err(err) => Err(err),
+        };
+
+        // Anything not fixed is copied over as it was.
+        let entry = archive.by_index_raw(index).map_err(read_error)?;
+        writer.raw_copy_file(entry).map_err(write_error)?;
+
+        entries.push((name, result));
+    }
+
+    if options.check {
+        return Ok(Converted::Archive(ArchiveReport {
+            output_path: None,
+            entries,
+        }));
+    }
+
+    let output = writer.finish().map_err(write_error)?.into_inner();
+    check_timeout(start, options)?;
+
+    with_retries(
+        &io_path(&output_path),
+        options.retries,
+        options.verbose,
+        |path| paths::write_atomic(path, &output),
+    )
+    .map_err(|err| PixfixError::Save {
+        attempts: err.attempts,
+        error: err.error,
+    })?;
+
+    Ok(Converted::Archive(ArchiveReport {
+        output_path: Some(output_path),
+        entries,
+    }))
+}
@@ -0,0 +1,463 @@
+//! Reads sprite atlas descriptors, so every sprite of an atlas is fixed on
+//! its own and colors never bleed between neighbouring sprites. TexturePacker
+//! JSON, Sparrow/Starling XML, LibGDX `.atlas` files and the `.meta` files of
+//! Unity sprite sheets are supported.
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use serde_json::Value;
+
+use crate::convert::Rect;
+
+/// A sprite of the atlas and the area it covers in the atlas image.
+pub struct Frame {
+    pub name: String,
+    pub rect: Rect,
+}
+
+pub struct Atlas {
+    /// The atlas image named by the descriptor, relative paths resolved
+    /// against the descriptor. Without it the descriptor applies to every
+    /// input.
+    pub image: Option<PathBuf>,
+    pub frames: Vec<Frame>,
+}
+
+pub enum AtlasError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, String),
+}
+
+impl fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AtlasError::Read(path, error) => {
+                write!(f, "Unable to read atlas \"{}\": {}", path.display(), error)
+            }
+            AtlasError::Parse(path, error) => {
+                write!(f, "Unable to parse atlas \"{}\": {}", path.display(), error)
+            }
+        }
+    }
+}
+
+fn parse_frame(name: &str, frame: &Value) -> Result<Frame, String> {
+    let rect = frame
+        .get("frame")
+        .ok_or_else(|| format!("Frame \"{}\" has no \"frame\" rect", name))?;
+    let field = |key: &str| {
+        rect.get(key)
+            .and_then(Value::as_u64)
+            .and_then(|value| u32::try_from(value).ok())
+            .ok_or_else(|| format!("Frame \"{}\" has an invalid \"{}\"", name, key))
+    };
+
+    let (x, y, width, height) = (field("x")?, field("y")?, field("w")?, field("h")?);
+
+    // Rotated frames are stored turned 90 degrees, while `w` and `h` keep the
+    // sprite's own size. Trimmed frames need no special care, `frame` is
+    // already the trimmed area in the atlas.
+    let rotated = frame
+        .get("rotated")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    let (width, height) = if rotated {
+        (height, width)
+    } else {
+        (width, height)
+    };
+
+    Ok(Frame {
+        name: name.to_string(),
+        rect: Rect {
+            x,
+            y,
+            width,
+            height,
+        },
+    })
+}
+
+/// `frames` is either a map of names to frames (the "hash" format) or a list
+/// of frames with a `filename` (the "array" format).
+fn parse_frames(frames: &Value) -> Result<Vec<Frame>, String> {
+    match frames {
+        Value::Object(frames) => frames
+            .iter()
+            .map(|(name, frame)| parse_frame(name, frame))
+            .collect(),
+        Value::Array(frames) => frames
+            .iter()
+            .enumerate()
+            .map(|(index, frame)| {
+                let name = match frame.get("filename").and_then(Value::as_str) {
+                    Some(name) => name.to_string(),
+                    None => format!("#{}", index),
+                };
+
+                parse_frame(&name, frame)
+            })
+            .collect(),
+        _ => Err("\"frames\" must be an object or an array".into()),
+    }
+}
+
+/// Where `image` is, relative to the descriptor at `path`.
+fn beside(path: &Path, image: &str) -> PathBuf {
+    match path.parent() {
+        Some(parent) => parent.join(image),
+        None => PathBuf::from(image),
+    }
+}
+
+fn parse_texture_packer(path: &Path, contents: &str) -> Result<Atlas, String> {
+    let descriptor: Value = serde_json::from_str(contents).map_err(|err| err.to_string())?;
+
+    let frames = descriptor
+        .get("frames")
+        .ok_or_else(|| "It has no \"frames\"".to_string())
+        .and_then(parse_frames)?;
+
+    let image = descriptor
+        .get("meta")
+        .and_then(|meta| meta.get("image"))
+        .and_then(Value::as_str)
+        .map(|image| beside(path, image));
+
+    Ok(Atlas { image, frames })
+}
+
+/// A whole number of pixels from a coordinate, which some tools write with
+/// decimals.
+fn parse_pixels(value: &str) -> Option<u32> {
+    let value: f64 = value.trim().trim_matches('"').parse().ok()?;
+
+    (value >= 0.0 && value <= f64::from(u32::MAX)).then(|| value.round() as u32)
+}
+
+/// The `name="value"` attributes of an XML tag.
+fn attributes(tag: &str) -> Vec<(&str, &str)> {
+    let mut attributes = Vec::new();
+    let mut rest = tag;
+
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.split_whitespace().last().unwrap_or_default();
+        let after = after.trim_start();
+
+        let Some(quote) = after
+            .chars()
+            .next()
+            .filter(|quote| *quote == '"' || *quote == '\'')
+        else {
+            break;
+        };
+        let Some(end) = after[1..].find(quote) else {
+            break;
+        };
+
+        attributes.push((key, &after[1..=end]));
+        rest = &after[end + 2..];
+    }
+
+    attributes
+}
+
+/// Sparrow/Starling XML, a `<TextureAtlas imagePath="...">` with a
+/// `<SubTexture name x y width height>` for every sprite. The area of a
+/// rotated sprite is already the one it covers in the atlas.
+fn parse_sparrow(path: &Path, contents: &str) -> Result<Atlas, String> {
+    let tags = |name: &'static str| {
+        contents
+            .split('<')
+            .filter(move |tag| tag.starts_with(name))
+            .map(|tag| attributes(tag.split('>').next().unwrap_or_default()))
+    };
+
+    let image = tags("TextureAtlas")
+        .next()
+        .ok_or_else(|| "It has no <TextureAtlas>".to_string())?
+        .into_iter()
+        .find(|(key, _)| *key == "imagePath")
+        .map(|(_, image)| beside(path, image));
+
+    let frames = tags("SubTexture")
+        .enumerate()
+        .map(|(index, attributes)| {
+            let get = |key: &str| {
+                attributes
+                    .iter()
+                    .find(|(name, _)| *name == key)
+                    .map(|(_, value)| *value)
+            };
+            let name = get("name").map_or_else(|| format!("#{}", index), str::to_string);
+            let field = |key: &str| {
+                get(key)
+                    .and_then(parse_pixels)
+                    .ok_or_else(|| format!("SubTexture \"{}\" has an invalid \"{}\"", name, key))
+            };
+
+            Ok(Frame {
+                rect: Rect {
+                    x: field("x")?,
+                    y: field("y")?,
+                    width: field("width")?,
+                    height: field("height")?,
+                },
+                name,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok(Atlas { image, frames })
+}
+
+/// A LibGDX region in the making, with the fields read so far.
+struct Region<'a> {
+    name: &'a str,
+    fields: Vec<(&'a str, &'a str)>,
+}
+
+impl Region<'_> {
+    fn into_frame(self) -> Result<Frame, String> {
+        let get = |key: &str| {
+            self.fields
+                .iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| *value)
+        };
+        let numbers = |key: &str| -> Result<Vec<u32>, String> {
+            get(key)
+                .ok_or_else(|| format!("Region \"{}\" has no \"{}\"", self.name, key))?
+                .split(',')
+                .map(|value| {
+                    parse_pixels(value).ok_or_else(|| {
+                        format!("Region \"{}\" has an invalid \"{}\"", self.name, key)
+                    })
+                })
+                .collect()
+        };
+
+        // Newer files have `bounds`, older ones `xy` and `size`.
+        let bounds = match get("bounds") {
+            Some(_) => numbers("bounds")?,
+            None => [numbers("xy")?, numbers("size")?].concat(),
+        };
+        let [x, y, width, height] = bounds[..] else {
+            return Err(format!("Region \"{}\" has an invalid size", self.name));
+        };
+
+        // Like in TexturePacker, the size is the sprite's own and the area
+        // of a rotated one is turned 90 degrees.
+        let rotated = get("rotate").is_some_and(|rotate| !matches!(rotate, "false" | "0"));
+        let (width, height) = if rotated {
+            (height, width)
+        } else {
+            (width, height)
+        };
+
+        // Frames of an animation share their name.
+        let name = match get("index") {
+            Some(index) if index != "-1" => format!("{}_{}", self.name, index),
+            _ => self.name.to_string(),
+        };
+
+        Ok(Frame {
+            name,
+            rect: Rect {
+                x,
+                y,
+                width,
+                height,
+            },
+        })
+    }
+}
+
+/// A LibGDX `.atlas`, one atlas for every page. A page starts with the name
+/// of its image after an empty line, followed by `key: value` lines about
+/// the page and then its regions, each a name followed by its own fields.
+fn parse_libgdx(path: &Path, contents: &str) -> Result<Vec<Atlas>, String> {
+    let mut atlases: Vec<Atlas> = Vec::new();
+    let mut region: Option<Region> = None;
+    let mut new_page = true;
+
+    for line in contents.lines().map(str::trim) {
+        let finished = match line.split_once(':') {
+            Some((key, value)) => {
+                if let Some(region) = &mut region {
+                    region.fields.push((key.trim(), value.trim()));
+                }
+
+                continue;
+            }
+            None => region.take(),
+        };
+
+        if let (Some(finished), Some(page)) = (finished, atlases.last_mut()) {
+            page.frames.push(finished.into_frame()?);
+        }
+
+        if line.is_empty() {
+            new_page = true;
+        } else if new_page {
+            atlases.push(Atlas {
+                image: Some(beside(path, line)),
+                frames: Vec::new(),
+            });
+            new_page = false;
+        } else {
+            region = Some(Region {
+                name: line,
+                fields: Vec::new(),
+            });
+        }
+    }
+
+    if let (Some(finished), Some(page)) = (region, atlases.last_mut()) {
+        page.frames.push(finished.into_frame()?);
+    }
+
+    if atlases.is_empty() {
+        return Err("It has no pages".into());
+    }
+
+    Ok(atlases)
+}
+
+/// The `spriteSheet.sprites` of a Unity `.meta` file, next to the image it
+/// describes. Unity measures `y` from the bottom of the image.
+fn parse_unity(path: &Path, contents: &str) -> Result<Atlas, String> {
+    let image = path.with_extension("");
+    let (_, image_height) = image::image_dimensions(&image).map_err(|err| {
+        format!(
+            "Unable to read the size of \"{}\": {}",
+            image.display(),
+            err
+        )
+    })?;
+
+    let mut lines = contents.lines();
+    let indent = |line: &str| line.len() - line.trim_start().len();
+
+    let sprites_indent = lines
+        .by_ref()
+        .find(|line| line.trim() == "sprites:")
+        .map(indent)
+        .ok_or_else(|| "It isn't a sprite sheet with multiple sprites".to_string())?;
+
+    // Every sprite is a list item with a `name` and a `rect` block.
+    let mut sprites: Vec<(String, [Option<u32>; 4])> = Vec::new();
+    let mut in_rect = false;
+
+    for line in lines {
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        let item = trimmed.strip_prefix("- ");
+
+        if indent(line) < sprites_indent || (indent(line) == sprites_indent && item.is_none()) {
+            break;
+        }
+
+        if let Some(item) = item.filter(|_| indent(line) == sprites_indent) {
+            sprites.push((String::new(), [None; 4]));
+            in_rect = false;
+
+            if item.is_empty() {
+                continue;
+            }
+        }
+
+        let Some(sprite) = sprites.last_mut() else {
+            continue;
+        };
+        let Some((key, value)) = trimmed.trim_start_matches("- ").split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+
+        match key.trim() {
+            "rect" => in_rect = true,
+            "name" if !in_rect => {
+                sprite.0 = value.trim_matches(|c| c == '"' || c == '\'').to_string()
+            }
+            key if in_rect => {
+                let field = ["x", "y", "width", "height"]
+                    .iter()
+                    .position(|name| *name == key);
+
+                match field {
+                    Some(field) => sprite.1[field] = parse_pixels(value),
+                    None if key != "serializedVersion" => in_rect = false,
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let frames = sprites
+        .into_iter()
+        .map(|(name, rect)| {
+            let [Some(x), Some(y), Some(width), Some(height)] = rect else {
+                return Err(format!("Sprite \"{}\" has an invalid \"rect\"", name));
+            };
+
+            Ok(Frame {
+                rect: Rect {
+                    x,
+                    y: image_height.saturating_sub(y + height),
+                    width,
+                    height,
+                },
+                name,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok(Atlas {
+        image: Some(image),
+        frames,
+    })
+}
+
+/// Loads the atlas descriptor at `path`, picking the format from its
+/// extension. A LibGDX atlas gives one atlas for every page.
+pub fn load_atlas(path: &Path) -> Result<Vec<Atlas>, AtlasError> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|err| AtlasError::Read(path.to_path_buf(), err))?;
+
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+
+    let atlases = match extension.as_deref() {
+        Some("atlas") => parse_libgdx(path, &contents),
+        Some("xml") => parse_sparrow(path, &contents).map(|atlas| vec![atlas]),
+        Some("meta") => parse_unity(path, &contents).map(|atlas| vec![atlas]),
+        _ => parse_texture_packer(path, &contents).map(|atlas| vec![atlas]),
+    };
+
+    atlases.map_err(|err| AtlasError::Parse(path.to_path_buf(), err))
+}
+
+impl Atlas {
+    /// Whether `path` is the image this descriptor belongs to.
+    pub fn applies_to(&self, path: &Path) -> bool {
+        let Some(image) = &self.image else {
+            return true;
+        };
+
+        match (image.canonicalize(), path.canonicalize()) {
+            (Ok(image), Ok(path)) => image == path,
+            _ => image.file_name() == path.file_name(),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Backups of the inputs a run overwrites, kept in a journal folder:
+//! `.pixfix_orig` in the current folder for `--backup-store`, or the one
+//! given to `--journal`. `pixfix restore` puts single files back and
+//! `pixfix undo` rolls back everything a journal recorded.
+//!
+//! The backups are named by the hash of their content, so identical images
+//! are only stored once. An `index` file next to them has a line for every
+//! overwrite: the hash of the original, the hash of what replaced it, the
+//! Pixfix version that wrote it and the absolute path of the file.
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{
+    convert, diagnostic,
+    error::PixfixError,
+    files, human,
+    options::{self, Options},
+    paths::{self, io_path},
+};
+
+/// The folder `--backup-store` keeps the backups in.
+pub const STORE: &str = ".pixfix_orig";
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Keeps files being fixed at the same time from mixing their index lines.
+static INDEX: Mutex<()> = Mutex::new(());
+
+/// One overwrite recorded in a journal.
+struct Entry {
+    original: String,
+    fixed: String,
+    version: String,
+    path: PathBuf,
+}
+
+fn index_path(journal: &Path) -> PathBuf {
+    journal.join("index")
+}
+
+fn backup_path(journal: &Path, hash: &str) -> PathBuf {
+    journal.join(format!("{}.png", hash))
+}
+
+/// Stores the current content of `path` in `journal` before `fixed`
+/// overwrites it.
+pub fn back_up(
+    journal: &Path,
+    path: &Path,
+    fixed: &[u8],
+    options: &Options,
+) -> Result<(), PixfixError> {
+    let bytes = convert::read_input(path, options)?;
+    let original = blake3::hash(&bytes).to_hex();
+    let backup = backup_path(journal, &original);
+
+    let stored = || -> io::Result<()> {
+        std::fs::create_dir_all(io_path(journal))?;
+
+        if !backup.exists() {
+            std::fs::write(io_path(&backup), &bytes)?;
+        }
+
+        let _index = INDEX
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut index = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(io_path(&index_path(journal)))?;
+
+        writeln!(
+            index,
+            "{}  {}  {}  {}",
+            original,
+            blake3::hash(fixed).to_hex(),
+            VERSION,
+            paths::canonical(path).display()
+        )
+    };
+
+    stored().map_err(PixfixError::Backup)
+}
+
+/// Every overwrite `journal` recorded, oldest first. A journal that was
+/// never written to is empty.
+fn read_index(journal: &Path) -> io::Result<Vec<Entry>> {
+    let index = match std::fs::read_to_string(io_path(&index_path(journal))) {
+        Ok(value) => value,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    Ok(index
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, "  ");
+
+            Some(Entry {
+                original: fields.next()?.into(),
+                fixed: fields.next()?.into(),
+                version: fields.next()?.into(),
+                path: PathBuf::from(fields.next()?),
+            })
+        })
+        .collect())
+}
+
+/// Puts the last backup of `path` in `journal` back, returning whether it
+/// had one.
+pub(crate) fn restore_file(journal: &Path, path: &Path, options: &Options) -> Result<bool, String> {
+    let entries = read_index(journal).map_err(|err| err.to_string())?;
+    let canonical = paths::canonical(path);
+
+    let Some(entry) = entries.iter().rev().find(|entry| entry.path == canonical) else {
+        return Ok(false);
+    };
+
+    let backup = backup_path(journal, &entry.original);
+    let bytes = std::fs::read(io_path(&backup))
+        .map_err(|err| format!("Unable to read \"{}\": {}", backup.display(), err))?;
+    convert::save(path, &bytes, options).map_err(|err| err.to_string())?;
+
+    Ok(true)
+}
+
+/// Restores every image in `args` from the backup store, printing each one
+/// that had a backup. Returns whether none of them failed.
+pub fn restore(args: Vec<OsString>) -> Result<bool, String> {
+    let (options, args) = options::parse_args(args)?;
+    let journal = options.journal_dir().unwrap_or(Path::new(STORE)).to_owned();
+    let files = files::resolve_files(args, &options).files;
+    let (mut restored, mut failed) = (0, 0);
+
+    for path in &files {
+        match restore_file(&journal, path, &options) {
+            Ok(true) => {
+                human!("Restored \"{}\"", path.display());
+                restored += 1;
+            }
+            Ok(false) => human!("Ignoring \"{}\" - No backup", path.display()),
+            Err(err) => {
+                diagnostic!("Unable to restore \"{}\": {}", path.display(), err);
+                failed += 1;
+            }
+        }
+    }
+
+    human!("\nRestored {} of {} images", restored, files.len());
+
+    Ok(failed == 0)
+}
+
+/// How undoing one file went.
+enum Undone {
+    Restored,
+    /// Not restored without `--force`, for the reason given.
+    Refused(String),
+}
+
+/// Restores `path` to the original of its first overwrite in `entries`, if
+/// it still holds what the last one wrote.
+fn undo_file(
+    journal: &Path,
+    path: &Path,
+    entries: &[&Entry],
+    options: &Options,
+) -> Result<Undone, String> {
+    let (Some(first), Some(last)) = (entries.first(), entries.last()) else {
+        return Ok(Undone::Refused("Nothing recorded".into()));
+    };
+
+    if !options.force {
+        if let Some(entry) = entries.iter().find(|entry| entry.version != VERSION) {
+            return Ok(Undone::Refused(format!(
+                "Recorded by Pixfix {}, this is {}",
+                entry.version, VERSION
+            )));
+        }
+
+        // A file that is gone has nothing left to lose.
+        match std::fs::read(io_path(path)) {
+            Ok(current) if blake3::hash(&current).to_hex().as_str() != last.fixed => {
+                return Ok(Undone::Refused("Changed since it was fixed".into()));
+            }
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.to_string()),
+        }
+    }
+
+    let backup = backup_path(journal, &first.original);
+    let bytes = std::fs::read(io_path(&backup))
+        .map_err(|err| format!("Unable to read \"{}\": {}", backup.display(), err))?;
+
+    if blake3::hash(&bytes).to_hex().as_str() != first.original && !options.force {
+        return Ok(Undone::Refused("The backup is damaged".into()));
+    }
+
+    convert::save(path, &bytes, options).map_err(|err| err.to_string())?;
+
+    Ok(Undone::Restored)
+}
+
+/// Rolls back every file the journal of `--journal <dir>`, `--from-backups
+/// <dir>` or `--backup-store` recorded, printing how each went. Returns
+/// whether all of them were restored.
+pub fn undo(args: Vec<OsString>) -> Result<bool, String> {
+    let (options, _) = options::parse_args(args)?;
+    let journal = options.journal_dir().unwrap_or(Path::new(STORE)).to_owned();
+    let entries = read_index(&journal)
+        .map_err(|err| format!("Unable to read \"{}\": {}", journal.display(), err))?;
+
+    // Every file once, in the order they were first fixed.
+    let mut order = Vec::new();
+    let mut by_path: HashMap<&Path, Vec<&Entry>> = HashMap::new();
+
+    for entry in &entries {
+        by_path
+            .entry(&entry.path)
+            .or_insert_with(|| {
+                order.push(&entry.path);
+                Vec::new()
+            })
+            .push(entry);
+    }
+
+    let (mut restored, mut failed) = (0, 0);
+
+    for path in &order {
+        match undo_file(&journal, path, &by_path[path.as_path()], &options) {
+            Ok(Undone::Restored) => {
+                human!("Restored \"{}\"", path.display());
+                restored += 1;
+            }
+            Ok(Undone::Refused(reason)) => {
+                diagnostic!(
+                    "Not restoring \"{}\" - {}, pass --force to restore it anyway",
+                    path.display(),
+                    reason
+                );
+                failed += 1;
+            }
+            Err(err) => {
+                diagnostic!("Unable to restore \"{}\": {}", path.display(), err);
+                failed += 1;
+            }
+        }
+    }
+
+    human!("\nRestored {} of {} files", restored, order.len());
+
+    Ok(failed == 0)
+}
@@ -0,0 +1,296 @@
+//! The batch engine: fixes many files at once and reports each as it
+//! finishes, so frontends can show progress live.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    sync::{
+        mpsc::{self, UnboundedReceiver},
+        Semaphore,
+    },
+    task::JoinSet,
+};
+
+use crate::{
+    archive,
+    convert::{
+        convert_image, fix_image, lock_input, read_input, write_output, Converted, Deadline,
+    },
+    error::PixfixError,
+    options::Options,
+    workers,
+};
+
+/// A file that is done, in the order they finish.
+pub struct Finished {
+    /// Position of the file in the list given to `fix_files`.
+    pub index: usize,
+    pub path: PathBuf,
+    pub result: Result<Converted, PixfixError>,
+}
+
+fn fix_file(path: &Path, options: &Options) -> Result<Converted, PixfixError> {
+    if archive::is_zip_file(path) {
+        archive::fix_archive(path, options)
+    } else {
+        convert_image(path, options)
+    }
+}
+
+/// With `--parallel-io`, how many files per job may be read ahead of the
+/// ones being fixed.
+const READ_AHEAD: usize = 2;
+
+/// The threads of each stage with `--parallel-io`.
+struct Stages {
+    /// Files read but not fixed yet, so reading never runs far ahead.
+    buffered: Semaphore,
+    reads: Semaphore,
+    fixes: Arc<Semaphore>,
+    writes: Semaphore,
+}
+
+impl Stages {
+    fn new(jobs: usize) -> Self {
+        Self {
+            buffered: Semaphore::new(jobs * READ_AHEAD),
+            reads: Semaphore::new(jobs),
+            fixes: Arc::new(Semaphore::new(jobs)),
+            writes: Semaphore::new(jobs),
+        }
+    }
+}
+
+/// Runs `work` on a blocking thread, failing once it takes longer than
+/// `timeout`.
+async fn blocking<T: Send + 'static>(
+    timeout: Option<Duration>,
+    work: impl FnOnce() -> Result<T, PixfixError> + Send + 'static,
+) -> Result<T, PixfixError> {
+    let task = tokio::task::spawn_blocking(work);
+
+    let joined = match timeout {
+        Some(limit) => tokio::time::timeout(limit, task).await,
+        None => Ok(task.await),
+    };
+
+    match joined {
+        Ok(Ok(result)) => result,
+        Ok(Err(err)) => Err(PixfixError::Panicked(err.to_string())),
+        Err(_) => Err(PixfixError::TimedOut(timeout.unwrap_or_default())),
+    }
+}
+
+/// Fixes a file with `--parallel-io`: reading, fixing and writing each wait
+/// for a thread of their own stage, so while one file is being fixed the
+/// next ones are read and the last ones written.
+async fn fix_file_staged(
+    path: PathBuf,
+    options: Arc<Options>,
+    stages: Arc<Stages>,
+    cancel: Arc<AtomicBool>,
+) -> Result<Converted, PixfixError> {
+    let timeout = options.timeout_per_file;
+    let buffered = stages.buffered.acquire().await;
+
+    if cancel.load(Ordering::Relaxed) {
+        return Err(PixfixError::Cancelled);
+    }
+
+    // Archives read and write their entries themselves.
+    if archive::is_zip_file(&path) {
+        let _fixing = stages.fixes.acquire().await;
+        return blocking(timeout, move || archive::fix_archive(&path, &options)).await;
+    }
+
+    let start = Instant::now();
+
+    // Held until the fix is written.
+    let (lock, bytes) = {
+        let _reading = stages.reads.acquire().await;
+        let (path, options) = (path.clone(), options.clone());
+        blocking(timeout, move || {
+            let lock = lock_input(&path, &options)?;
+            Ok((lock, read_input(&path, &options)?))
+        })
+        .await?
+    };
+
+    let (converted, writes) = {
+        let _fixing = stages.fixes.acquire().await;
+        let (path, options, pool) = (path.clone(), options.clone(), stages.fixes.clone());
+        blocking(timeout, move || {
+            workers::with_pool(pool, || fix_image(&path, &bytes, &options, start))
+        })
+        .await?
+    };
+
+    drop(buffered);
+    let _writing = stages.writes.acquire().await;
+
+    blocking(timeout, move || {
+        let _deadline = Deadline::start(start, &options);
+
+        for write in &writes {
+            write_output(write, &path, &options)?;
+        }
+
+        drop(lock);
+        Ok(converted)
+    })
+    .await
+}
+
+/// Fixes one file of a batch once `jobs` lets it start.
+async fn fix_task(
+    index: usize,
+    path: PathBuf,
+    options: Arc<Options>,
+    cancel: Arc<AtomicBool>,
+    jobs: Arc<Semaphore>,
+    stages: Arc<Stages>,
+) -> Finished {
+    if options.parallel_io {
+        let result = fix_file_staged(path.clone(), options, stages, cancel).await;
+
+        return Finished {
+            index,
+            path,
+            result,
+        };
+    }
+
+    let _permit = jobs.clone().acquire_owned().await;
+
+    if cancel.load(Ordering::Relaxed) {
+        return Finished {
+            index,
+            path,
+            result: Err(PixfixError::Cancelled),
+        };
+    }
+
+    let result = blocking(options.timeout_per_file, {
+        let (path, pool) = (path.clone(), jobs.clone());
+        move || workers::with_pool(pool, || fix_file(&path, &options))
+    })
+    .await;
+
+    Finished {
+        index,
+        path,
+        result,
+    }
+}
+
+/// Fixes every file, at most `--jobs` at a time, calling `on_finished` as
+/// each one completes. Setting `cancel` fails the files that haven't started
+/// yet with `PixfixError::Cancelled`, the ones already running still finish.
+pub async fn fix_files(
+    files: Vec<PathBuf>,
+    options: &Arc<Options>,
+    cancel: &Arc<AtomicBool>,
+    on_finished: impl FnMut(Finished),
+) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    for path in files {
+        // The receiver is right here.
+        let _ = sender.send(path);
+    }
+    drop(sender);
+
+    fix_stream(receiver, options, cancel, on_finished).await;
+}
+
+/// Like `fix_files`, but for files that are still being found: each one
+/// starts as soon as it arrives on `files`, and the batch is done once the
+/// sender is dropped and every file finished. `Finished::index` counts the
+/// files in the order they arrived.
+pub async fn fix_stream(
+    mut files: UnboundedReceiver<PathBuf>,
+    options: &Arc<Options>,
+    cancel: &Arc<AtomicBool>,
+    mut on_finished: impl FnMut(Finished),
+) {
+    let mut threads = JoinSet::new();
+    let jobs = Arc::new(Semaphore::new(options.jobs));
+    let stages = Arc::new(Stages::new(options.jobs));
+    let mut arrived = 0;
+    let mut open = true;
+
+    while open || !threads.is_empty() {
+        tokio::select! {
+            path = files.recv(), if open => match path {
+                Some(path) => {
+                    threads.spawn(fix_task(
+                        arrived,
+                        path,
+                        options.clone(),
+                        cancel.clone(),
+                        jobs.clone(),
+                        stages.clone(),
+                    ));
+                    arrived += 1;
+                }
+                None => open = false,
+            },
+            Some(joined) = threads.join_next(), if !threads.is_empty() => {
+                // The tasks above never panic themselves, so this only fails
+                // if the runtime is shutting down.
+                if let Ok(finished) = joined {
+                    on_finished(finished);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::DynamicImage;
+
+    use super::*;
+    use crate::fixtures::{encode, fringed_sprite, scattered, TempDir};
+
+    #[tokio::test]
+    async fn a_file_that_times_out_is_left_alone() {
+        let dir = TempDir::new("timeout").unwrap();
+        let (slow, quick) = (dir.path().join("slow.png"), dir.path().join("quick.png"));
+
+        // Thousands of border pixels over a large image take far longer to
+        // fill than the timeout, unlike the small sprite.
+        let slow_png = encode(DynamicImage::ImageRgba16(scattered(800, 800, 5_000)));
+        let quick_png = fringed_sprite();
+        std::fs::write(&slow, &slow_png).unwrap();
+        std::fs::write(&quick, &quick_png).unwrap();
+
+        let options = Arc::new(Options {
+            timeout_per_file: Some(Duration::from_millis(300)),
+            jobs: 2,
+            ..Default::default()
+        });
+        let mut results = Vec::new();
+
+        fix_files(
+            vec![slow.clone(), quick.clone()],
+            &options,
+            &Arc::new(AtomicBool::new(false)),
+            |finished| results.push(finished),
+        )
+        .await;
+        results.sort_by_key(|finished| finished.index);
+
+        assert!(matches!(results[0].result, Err(PixfixError::TimedOut(_))));
+        assert!(matches!(results[1].result, Ok(Converted::Fixed { .. })));
+        assert!(std::fs::read(&slow).unwrap() == slow_png);
+        assert!(std::fs::read(&quick).unwrap() != quick_png);
+    }
+}
@@ -0,0 +1,205 @@
+//! The chunks of a PNG file, so the metadata of an input survives the fix.
+//! The encoder only writes `IHDR`, `IDAT` and `IEND`, the chunks worth
+//! keeping are copied over from the input afterwards.
+
+pub const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Chunks that only describe the colors, which renderers need to show the
+/// image right. `--strip-color-info` drops them too.
+const COLOR_CHUNKS: &[&[u8; 4]] = &[b"sRGB", b"gAMA", b"cHRM"];
+
+/// Metadata `--strip-metadata` drops, along with `iCCP`. Every other ancillary chunk, like
+/// `tRNS`, `bKGD` or `sBIT`, depends on the color type of the input and
+/// would be wrong for the re-encoded image, so it is never copied.
+const METADATA_CHUNKS: &[&[u8; 4]] = &[b"tEXt", b"zTXt", b"iTXt", b"tIME", b"eXIf", b"pHYs"];
+
+pub struct Chunk {
+    pub kind: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+impl Chunk {
+    /// The size of the chunk in the file, with its length, type and CRC.
+    pub fn size(&self) -> usize {
+        self.data.len() + 12
+    }
+
+    /// Ancillary chunks start with a lowercase letter, the image decodes
+    /// without them.
+    pub fn is_ancillary(&self) -> bool {
+        self.kind[0].is_ascii_lowercase()
+    }
+}
+
+/// What is dropped from the metadata of the input.
+#[derive(Clone, Copy, Default)]
+pub struct Strip {
+    pub metadata: bool,
+    pub color_info: bool,
+}
+
+impl Strip {
+    fn keeps(&self, chunk: &Chunk) -> bool {
+        // An ICC profile is color information as well.
+        if COLOR_CHUNKS.contains(&&chunk.kind) || &chunk.kind == b"iCCP" {
+            !self.color_info && (&chunk.kind != b"iCCP" || !self.metadata)
+        } else {
+            METADATA_CHUNKS.contains(&&chunk.kind) && !self.metadata
+        }
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+/// The chunks of a PNG file, or `None` when it isn't one or is cut short.
+pub fn read_chunks(bytes: &[u8]) -> Option<Vec<Chunk>> {
+    let mut rest = bytes.strip_prefix(PNG_SIGNATURE)?;
+    let mut chunks = Vec::new();
+
+    while !rest.is_empty() {
+        let length = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+        let kind: [u8; 4] = rest.get(4..8)?.try_into().ok()?;
+        let data = rest.get(8..8 + length)?.to_vec();
+
+        rest = rest.get(12 + length..)?;
+
+        let end = &kind == b"IEND";
+        chunks.push(Chunk { kind, data });
+
+        if end {
+            break;
+        }
+    }
+
+    Some(chunks)
+}
+
+/// How a PNG file is damaged.
+pub struct Damage {
+    /// The file ends before `IEND`, so the image data is likely cut short.
+    pub truncated: bool,
+    /// Bytes after `IEND`.
+    pub trailing: usize,
+}
+
+/// Walks the chunks of a PNG file without decoding them, or returns `None`
+/// when it isn't one.
+pub fn damage(bytes: &[u8]) -> Option<Damage> {
+    let mut rest = bytes.strip_prefix(PNG_SIGNATURE)?;
+
+    loop {
+        let Some(header) = rest.get(..8) else {
+            return Some(Damage {
+                truncated: true,
+                trailing: 0,
+            });
+        };
+
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let end = &header[4..8] == b"IEND";
+
+        match rest.get(12 + length..) {
+            Some(after) if end => {
+                return Some(Damage {
+                    truncated: false,
+                    trailing: after.len(),
+                })
+            }
+            Some(after) => rest = after,
+            None => {
+                return Some(Damage {
+                    truncated: true,
+                    trailing: 0,
+                })
+            }
+        }
+    }
+}
+
+pub fn write_chunks(chunks: &[Chunk]) -> Vec<u8> {
+    let mut bytes = PNG_SIGNATURE.to_vec();
+
+    for chunk in chunks {
+        bytes.extend_from_slice(&(chunk.data.len() as u32).to_be_bytes());
+
+        let start = bytes.len();
+        bytes.extend_from_slice(&chunk.kind);
+        bytes.extend_from_slice(&chunk.data);
+
+        let crc = crc32(&bytes[start..]);
+        bytes.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    bytes
+}
+
+/// Adds a `tEXt` chunk with `keyword` and `text` before the image data of
+/// `png`, or returns `None` when it can't be read.
+pub fn add_text(png: &[u8], keyword: &str, text: &str) -> Option<Vec<u8>> {
+    let mut chunks = read_chunks(png)?;
+    let data = chunks
+        .iter()
+        .position(|chunk| &chunk.kind == b"IDAT")
+        .unwrap_or(chunks.len());
+
+    // Keyword and text are Latin-1, separated by a null byte.
+    let text = [keyword.as_bytes(), &[0], text.as_bytes()].concat();
+    chunks.insert(
+        data,
+        Chunk {
+            kind: *b"tEXt",
+            data: text,
+        },
+    );
+
+    Some(write_chunks(&chunks))
+}
+
+/// Removes every `tEXt` chunk with `keyword` from `png`, returning the new
+/// file and how many were removed, or `None` when it can't be read.
+pub fn remove_text(png: &[u8], keyword: &str) -> Option<(Vec<u8>, usize)> {
+    let mut chunks = read_chunks(png)?;
+    let before = chunks.len();
+
+    let prefix = [keyword.as_bytes(), &[0]].concat();
+    chunks.retain(|chunk| !(&chunk.kind == b"tEXt" && chunk.data.starts_with(&prefix)));
+
+    Some((write_chunks(&chunks), before - chunks.len()))
+}
+
+/// Copies the metadata of the `input` PNG into the freshly encoded
+/// `output`, before its image data. Returns the new output and how many
+/// bytes of metadata were left behind, or `None` when either can't be read.
+pub fn carry_over(input: &[u8], output: &[u8], strip: Strip) -> Option<(Vec<u8>, usize)> {
+    let input = read_chunks(input)?;
+    let mut output = read_chunks(output)?;
+
+    let (kept, dropped): (Vec<Chunk>, Vec<Chunk>) = input
+        .into_iter()
+        .filter(Chunk::is_ancillary)
+        .partition(|chunk| strip.keeps(chunk));
+
+    let data = output
+        .iter()
+        .position(|chunk| &chunk.kind == b"IDAT")
+        .unwrap_or(output.len());
+    output.splice(data..data, kept);
+
+    Some((write_chunks(&output), dropped.iter().map(Chunk::size).sum()))
+}
@@ -0,0 +1,283 @@
+//! The color spaces `--colorspace` averages colors in. Averaging the stored
+//! sRGB values darkens and muddies blends between different hues, linear
+//! light fixes the brightness, and CIELAB and OKLab also keep the hue.
+
+/// Where colors are summed and averaged.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum ColorSpace {
+    /// The stored values as they are.
+    #[default]
+    Srgb,
+    Linear,
+    /// CIELAB with a D65 white point.
+    Lab,
+    Oklab,
+}
+
+impl std::str::FromStr for ColorSpace {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "srgb" => Ok(ColorSpace::Srgb),
+            "linear" => Ok(ColorSpace::Linear),
+            "lab" => Ok(ColorSpace::Lab),
+            "oklab" => Ok(ColorSpace::Oklab),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How the colors of neighbors are combined into one.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Blend {
+    #[default]
+    Mean,
+    /// Every channel on its own, so a single outlier can't tint the result.
+    Median,
+}
+
+impl std::str::FromStr for Blend {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "mean" => Ok(Blend::Mean),
+            "median" => Ok(Blend::Median),
+            _ => Err(()),
+        }
+    }
+}
+
+fn to_linear(channel: f64) -> f64 {
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn from_linear(channel: f64) -> f64 {
+    if channel <= 0.0031308 {
+        channel * 12.92
+    } else {
+        1.055 * channel.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Linear sRGB to OKLab, see https://bottosson.github.io/posts/oklab/.
+fn linear_to_oklab([r, g, b]: [f64; 3]) -> [f64; 3] {
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+fn oklab_to_linear([lightness, a, b]: [f64; 3]) -> [f64; 3] {
+    let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+
+    [
+        4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+        -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+        -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+    ]
+}
+
+/// Linear sRGB to CIELAB with a D65 white point.
+fn linear_to_lab([r, g, b]: [f64; 3]) -> [f64; 3] {
+    let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047;
+    let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+    let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / 1.08883;
+
+    let f = |t: f64| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (x, y, z) = (f(x), f(y), f(z));
+
+    [116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z)]
+}
+
+fn lab_to_linear([lightness, a, b]: [f64; 3]) -> [f64; 3] {
+    let f_y = (lightness + 16.0) / 116.0;
+    let (f_x, f_z) = (f_y + a / 500.0, f_y - b / 200.0);
+
+    let inverse = |f: f64| {
+        if f.powi(3) > 216.0 / 24389.0 {
+            f.powi(3)
+        } else {
+            (116.0 * f - 16.0) * 27.0 / 24389.0
+        }
+    };
+    let (x, y, z) = (inverse(f_x) * 0.95047, inverse(f_y), inverse(f_z) * 1.08883);
+
+    [
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    ]
+}
+
+/// How different two 16-bit sRGB colors look, as the CIE76 ΔE between them.
+/// Around 2 is barely noticeable, above 10 the colors are clearly distinct.
+pub fn delta_e(first: [u16; 3], second: [u16; 3]) -> f64 {
+    let lab = |color: [u16; 3]| {
+        linear_to_lab(color.map(|channel| to_linear(f64::from(channel) / f64::from(u16::MAX))))
+    };
+    let (first, second) = (lab(first), lab(second));
+
+    first
+        .iter()
+        .zip(second)
+        .map(|(first, second)| (first - second).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+impl ColorSpace {
+    /// A 16-bit sRGB color in this space, ready to be summed.
+    pub fn from_srgb(self, rgb: [u16; 3]) -> [f64; 3] {
+        // Kept as the stored values, so averages round exactly like integer
+        // math did.
+        if self == ColorSpace::Srgb {
+            return rgb.map(f64::from);
+        }
+
+        let linear = rgb.map(|channel| to_linear(f64::from(channel) / f64::from(u16::MAX)));
+
+        match self {
+            ColorSpace::Lab => linear_to_lab(linear),
+            ColorSpace::Oklab => linear_to_oklab(linear),
+            _ => linear,
+        }
+    }
+
+    /// A color of this space, e.g. an average, back in 16-bit sRGB.
+    pub fn to_srgb(self, color: [f64; 3]) -> [u16; 3] {
+        let max = f64::from(u16::MAX);
+
+        if self == ColorSpace::Srgb {
+            return color.map(|channel| channel.round().clamp(0.0, max) as u16);
+        }
+
+        let linear = match self {
+            ColorSpace::Lab => lab_to_linear(color),
+            ColorSpace::Oklab => oklab_to_linear(color),
+            _ => color,
+        };
+
+        linear.map(|channel| (from_linear(channel.clamp(0.0, 1.0)) * max).round() as u16)
+    }
+
+    /// The average of `colors`, all given in 16-bit sRGB.
+    pub fn average(self, colors: impl IntoIterator<Item = [u16; 3]>) -> Option<[u16; 3]> {
+        let mut sum = [0.0; 3];
+        let mut count = 0;
+
+        for color in colors {
+            for (sum, channel) in sum.iter_mut().zip(self.from_srgb(color)) {
+                *sum += channel;
+            }
+            count += 1;
+        }
+
+        (count > 0).then(|| self.to_srgb(sum.map(|sum| sum / f64::from(count))))
+    }
+
+    /// The median of every channel of up to 8 `colors`, the neighbors of a
+    /// pixel, all given in 16-bit sRGB. With an even number of colors the two
+    /// in the middle are averaged.
+    pub fn median(self, colors: &[[u16; 3]]) -> Option<[u16; 3]> {
+        let count = colors.len().min(8);
+
+        if count == 0 {
+            return None;
+        }
+
+        let mut converted = [[0.0; 3]; 8];
+
+        for (converted, color) in converted.iter_mut().zip(colors) {
+            *converted = self.from_srgb(*color);
+        }
+
+        let mut median = [0.0; 3];
+
+        for (channel, median) in median.iter_mut().enumerate() {
+            let mut values = converted.map(|color| color[channel]);
+            let values = &mut values[..count];
+            values.sort_by(f64::total_cmp);
+
+            let middle = count / 2;
+            *median = if count.is_multiple_of(2) {
+                (values[middle - 1] + values[middle]) / 2.0
+            } else {
+                values[middle]
+            };
+        }
+
+        Some(self.to_srgb(median))
+    }
+
+    /// `colors` combined with `blend`.
+    pub fn blend(self, blend: Blend, colors: &[[u16; 3]]) -> Option<[u16; 3]> {
+        match blend {
+            Blend::Mean => self.average(colors.iter().copied()),
+            Blend::Median => self.median(colors),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every 8-bit value of each channel, against a grid of the other two.
+    fn colors() -> impl Iterator<Item = [u16; 3]> {
+        (0..3).flat_map(|channel| {
+            (0..=255u16).flat_map(move |value| {
+                (0..=255u16).step_by(51).flat_map(move |first| {
+                    (0..=255u16).step_by(51).map(move |second| {
+                        let mut others = [first, second].into_iter();
+                        let mut color = [0; 3];
+
+                        for (index, color) in color.iter_mut().enumerate() {
+                            *color = if index == channel {
+                                value
+                            } else {
+                                others.next().unwrap()
+                            };
+                        }
+
+                        color
+                    })
+                })
+            })
+        })
+    }
+
+    #[test]
+    fn colors_survive_a_round_trip_through_every_space() {
+        for space in ["srgb", "linear", "lab", "oklab"] {
+            let space: ColorSpace = space.parse().unwrap();
+
+            for color in colors() {
+                let round_trip = space.to_srgb(space.from_srgb(color.map(|value| value * 257)));
+
+                for (value, round_trip) in color.into_iter().zip(round_trip) {
+                    let error = (f64::from(round_trip) / 257.0 - f64::from(value)).abs();
+                    assert!(error <= 1.0, "{:?} came back as {:?}", color, round_trip);
+                }
+            }
+        }
+    }
+}
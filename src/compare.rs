@@ -0,0 +1,213 @@
+//! `pixfix benchmark-compare <before> <after>`, which compares the images of
+//! two folders with the same names, e.g. the outputs of a test corpus before
+//! and after a change to the fill, so a change that degrades quality shows
+//! up without looking at every image. The worst images come first.
+//!
+//! PSNR and the max error are over every channel of every pixel, the fill
+//! included. SSIM only looks at the pixels visible in either image.
+
+use std::{ffi::OsString, path::Path};
+
+use crate::{
+    convert::{self, Image16},
+    diagnostic,
+    error::PixfixError,
+    files, human,
+    options::{self, Options},
+    result,
+};
+
+/// The side of the windows SSIM compares, and how far apart they start.
+const WINDOW: u32 = 8;
+const WINDOW_STEP: u32 = 4;
+
+/// The constants keeping SSIM stable for flat windows, for 8-bit values.
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// How one pair of images compares.
+struct Comparison {
+    /// Infinite when the images are the same.
+    psnr: f64,
+    /// `None` when neither image has a visible pixel.
+    ssim: Option<f64>,
+    /// The largest difference of any channel, from 0 to 255.
+    max_error: f64,
+}
+
+/// A channel scaled from 16 bits to 0-255.
+fn channel(value: u16) -> f64 {
+    f64::from(value) / 257.0
+}
+
+fn luma(color: &[u16; 4]) -> f64 {
+    0.299 * channel(color[0]) + 0.587 * channel(color[1]) + 0.114 * channel(color[2])
+}
+
+/// SSIM of the luma of `before` and `after` over windows of the pixels
+/// visible in either, averaged over the windows that have any.
+fn masked_ssim(before: &Image16, after: &Image16) -> Option<f64> {
+    let (width, height) = before.dimensions();
+    let (mut total, mut windows) = (0.0, 0);
+
+    let starts = |size: u32| (0..size.saturating_sub(WINDOW) + 1).step_by(WINDOW_STEP as usize);
+
+    for top in starts(height) {
+        for left in starts(width) {
+            let pixels: Vec<(f64, f64)> = (top..(top + WINDOW).min(height))
+                .flat_map(|y| (left..(left + WINDOW).min(width)).map(move |x| (x, y)))
+                .filter_map(|(x, y)| {
+                    let (a, b) = (before.get_pixel(x, y).0, after.get_pixel(x, y).0);
+                    (a[3] > 0 || b[3] > 0).then(|| (luma(&a), luma(&b)))
+                })
+                .collect();
+
+            if pixels.is_empty() {
+                continue;
+            }
+
+            let count = pixels.len() as f64;
+            let mean_a = pixels.iter().map(|(a, _)| a).sum::<f64>() / count;
+            let mean_b = pixels.iter().map(|(_, b)| b).sum::<f64>() / count;
+            let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+
+            for (a, b) in &pixels {
+                var_a += (a - mean_a).powi(2);
+                var_b += (b - mean_b).powi(2);
+                covariance += (a - mean_a) * (b - mean_b);
+            }
+
+            let (var_a, var_b, covariance) = (var_a / count, var_b / count, covariance / count);
+
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a.powi(2) + mean_b.powi(2) + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+
+    (windows > 0).then(|| total / f64::from(windows))
+}
+
+fn compare_images(before: &Image16, after: &Image16) -> Comparison {
+    let (mut squared, mut max_error) = (0.0, 0.0f64);
+
+    for (a, b) in before.pixels().zip(after.pixels()) {
+        for (a, b) in a.0.iter().zip(b.0) {
+            let error = (channel(*a) - channel(b)).abs();
+            squared += error * error;
+            max_error = max_error.max(error);
+        }
+    }
+
+    let mse = squared / (before.len() as f64).max(1.0);
+    let psnr = match mse {
+        0.0 => f64::INFINITY,
+        mse => 10.0 * (255.0 * 255.0 / mse).log10(),
+    };
+
+    Comparison {
+        psnr,
+        ssim: masked_ssim(before, after),
+        max_error,
+    }
+}
+
+fn read_image(path: &Path, options: &Options) -> Result<Image16, PixfixError> {
+    let bytes = convert::read_input(path, options)?;
+
+    Ok(convert::decode(&bytes, path, options)?.into_rgba16())
+}
+
+/// Compares `before` with the image of the same name in `after_dir`.
+fn compare_file(
+    before: &Path,
+    before_dir: &Path,
+    after_dir: &Path,
+    options: &Options,
+) -> Result<Comparison, String> {
+    let relative = before.strip_prefix(before_dir).unwrap_or(before);
+    let after = after_dir.join(relative);
+
+    if !after.is_file() {
+        return Err(format!("\"{}\" does not exist", after.display()));
+    }
+
+    let read = |path: &Path| {
+        read_image(path, options)
+            .map_err(|err| format!("Unable to read \"{}\": {}", path.display(), err))
+    };
+    let (before, after) = (read(before)?, read(&after)?);
+
+    if before.dimensions() != after.dimensions() {
+        return Err(format!(
+            "The sizes differ, {}x{} before and {}x{} after",
+            before.width(),
+            before.height(),
+            after.width(),
+            after.height()
+        ));
+    }
+
+    Ok(compare_images(&before, &after))
+}
+
+/// Compares the images of the two folders in `args`, printing a table of
+/// them from the worst to the best. Returns whether every image could be
+/// compared.
+pub fn benchmark_compare(args: Vec<OsString>) -> Result<bool, String> {
+    let (mut options, args) = options::parse_args(args)?;
+
+    let [before_dir, after_dir] = <[OsString; 2]>::try_from(args).map_err(|_| {
+        String::from("Usage: pixfix benchmark-compare <before_dir> <after_dir> [options]")
+    })?;
+    let (before_dir, after_dir) = (Path::new(&before_dir), Path::new(&after_dir));
+
+    // The images are only read, so read-only files are as good as any.
+    options.check = true;
+
+    let files = files::resolve_files(vec![before_dir.into()], &options).files;
+    let mut compared = Vec::new();
+    let mut failed = 0;
+
+    for path in &files {
+        match compare_file(path, before_dir, after_dir, &options) {
+            Ok(comparison) => compared.push((path, comparison)),
+            Err(err) => {
+                diagnostic!("Unable to compare \"{}\": {}", path.display(), err);
+                failed += 1;
+            }
+        }
+    }
+
+    // The lowest SSIM first, then the lowest PSNR. Images without visible
+    // pixels only have their PSNR to go by.
+    compared.sort_by(|(_, a), (_, b)| {
+        let ssim = |comparison: &Comparison| comparison.ssim.unwrap_or(1.0);
+
+        ssim(a).total_cmp(&ssim(b)).then(a.psnr.total_cmp(&b.psnr))
+    });
+
+    result!("{:>9}  {:>7}  {:>9}  Image", "PSNR", "SSIM", "Max error");
+
+    for (path, comparison) in &compared {
+        let psnr = match comparison.psnr.is_finite() {
+            true => format!("{:.2}", comparison.psnr),
+            false => "inf".into(),
+        };
+        let ssim = comparison
+            .ssim
+            .map_or("-".into(), |ssim| format!("{:.4}", ssim));
+
+        result!(
+            "{:>9}  {:>7}  {:>9.1}  {}",
+            psnr,
+            ssim,
+            comparison.max_error,
+            path.display()
+        );
+    }
+
+    human!("\nCompared {} of {} images", compared.len(), files.len());
+
+    Ok(failed == 0)
+}
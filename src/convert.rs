@@ -0,0 +1,3095 @@
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Instant,
+};
+
+use image::{
+    codecs::png::PngDecoder,
+    imageops::{self, FilterType},
+    ColorType, DynamicImage, ImageBuffer, ImageDecoder, Luma, Rgba,
+};
+use spade::{DelaunayTriangulation, HasPosition, InsertionError, Point2, Triangulation};
+
+use crate::{
+    archive::ArchiveReport,
+    atlas::Atlas,
+    backup,
+    chunks::{self, Strip},
+    color::{self, Blend, ColorSpace},
+    diagnostic,
+    error::PixfixError,
+    format::{FormatOptions, OutputFormat},
+    frames::{self, Step},
+    human,
+    jfa::{self, Backend},
+    options::{AlphaChannelOutput, ColorKey, Grid, Options, ScaleFilter},
+    paths,
+    paths::{io_path, FileLock},
+    progress::{self, Stage, FILL_STEP},
+    retry::with_retries,
+    stats, workers,
+};
+
+static NEIGHBORS: &[(i32, i32)] = &[
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+];
+
+/// Images are fixed at 16 bits per channel so 16-bit inputs keep their precision.
+pub type Image16 = ImageBuffer<Rgba<u16>, Vec<u16>>;
+
+pub type TransparentPixel = (u32, u32, Rgba<u16>);
+
+struct VoronoiColor {
+    r: u16,
+    g: u16,
+    b: u16,
+}
+
+/// An opaque pixel next to a transparent one. Its color is stored in the
+/// triangulation vertex so the nearest neighbor lookup returns it directly.
+pub struct BorderPixel {
+    position: Point2<f64>,
+    color: VoronoiColor,
+}
+
+impl BorderPixel {
+    pub fn new(x: u32, y: u32, color: Rgba<u16>) -> Self {
+        let [r, g, b, _] = color.0;
+
+        Self {
+            position: Point2::new(x as f64, y as f64),
+            color: VoronoiColor { r, g, b },
+        }
+    }
+}
+
+impl HasPosition for BorderPixel {
+    type Scalar = f64;
+
+    fn position(&self) -> Point2<f64> {
+        self.position
+    }
+}
+
+/// Which pixels already have a color worth spreading: the opaque ones at
+/// first, plus every pixel filled by a previous pass.
+pub fn colored_mask(img: &Image16) -> Vec<bool> {
+    img.pixels().map(|color| color.0[3] != 0).collect()
+}
+
+/// An area of the image, e.g. one sprite of an atlas.
+#[derive(Clone, Copy)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && y >= self.y && x - self.x < self.width && y - self.y < self.height
+    }
+}
+
+/// Splits the image into the colored pixels bordering uncolored ones, which
+/// seed the fill, and the uncolored pixels that need a color.
+pub fn collect_pixels(
+    img: &Image16,
+    colored: &[bool],
+) -> (Vec<BorderPixel>, Vec<TransparentPixel>) {
+    let (width, height) = img.dimensions();
+
+    collect_region(
+        img,
+        colored,
+        Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        },
+    )
+}
+
+/// Like `collect_pixels`, but only looks at the pixels inside `region`, as
+/// if the rest of the image didn't exist.
+pub fn collect_region(
+    img: &Image16,
+    colored: &[bool],
+    region: Rect,
+) -> (Vec<BorderPixel>, Vec<TransparentPixel>) {
+    let mut border_pixels: Vec<BorderPixel> = Vec::new();
+    let mut transparent_pixels: Vec<TransparentPixel> = Vec::new();
+
+    let (width, height) = img.dimensions();
+    let is_colored = |x: u32, y: u32| colored[(y as usize) * (width as usize) + x as usize];
+
+    let x_end = region.x.saturating_add(region.width).min(width);
+    let y_end = region.y.saturating_add(region.height).min(height);
+
+    for y in region.y..y_end {
+        for x in region.x..x_end {
+            let color = img.get_pixel(x, y);
+
+            if !is_colored(x, y) {
+                transparent_pixels.push((x, y, *color));
+                continue;
+            }
+
+            for (nx, ny) in NEIGHBORS {
+                // Checked so pixels on the left and top edges don't wrap around.
+                let (Some(neighbor_x), Some(neighbor_y)) =
+                    (x.checked_add_signed(*nx), y.checked_add_signed(*ny))
+                else {
+                    continue;
+                };
+
+                if neighbor_x >= x_end
+                    || neighbor_y >= y_end
+                    || !region.contains(neighbor_x, neighbor_y)
+                {
+                    continue;
+                }
+
+                if is_colored(neighbor_x, neighbor_y) {
+                    continue;
+                }
+
+                border_pixels.push(BorderPixel::new(x, y, *color));
+
+                break;
+            }
+        }
+    }
+
+    (border_pixels, transparent_pixels)
+}
+
+/// Finds the set `pixel` belongs to, flattening the path on the way.
+fn find(parents: &mut [usize], mut pixel: usize) -> usize {
+    while parents[pixel] != pixel {
+        parents[pixel] = parents[parents[pixel]];
+        pixel = parents[pixel];
+    }
+
+    pixel
+}
+
+/// Like `collect_pixels`, but split per island of uncolored pixels, each
+/// with only the border pixels touching it. Islands only connect through
+/// edges, so a diagonal line of opaque pixels still separates them.
+pub fn collect_components(
+    img: &Image16,
+    colored: &[bool],
+) -> Vec<(Vec<BorderPixel>, Vec<TransparentPixel>)> {
+    let (width, height) = img.dimensions();
+    let index = |x: u32, y: u32| (y as usize) * (width as usize) + x as usize;
+
+    let mut parents: Vec<usize> = (0..colored.len()).collect();
+
+    for (x, y, _) in img.enumerate_pixels() {
+        if colored[index(x, y)] {
+            continue;
+        }
+
+        for (neighbor_x, neighbor_y) in [(x.checked_sub(1), Some(y)), (Some(x), y.checked_sub(1))] {
+            let (Some(neighbor_x), Some(neighbor_y)) = (neighbor_x, neighbor_y) else {
+                continue;
+            };
+
+            if colored[index(neighbor_x, neighbor_y)] {
+                continue;
+            }
+
+            let root = find(&mut parents, index(x, y));
+            let neighbor_root = find(&mut parents, index(neighbor_x, neighbor_y));
+            parents[root] = neighbor_root;
+        }
+    }
+
+    // Numbers the islands in the order they are first seen.
+    let mut components: Vec<usize> = vec![usize::MAX; colored.len()];
+    let mut groups: Vec<(Vec<BorderPixel>, Vec<TransparentPixel>)> = Vec::new();
+
+    for (x, y, color) in img.enumerate_pixels() {
+        if colored[index(x, y)] {
+            continue;
+        }
+
+        let root = find(&mut parents, index(x, y));
+
+        if components[root] == usize::MAX {
+            components[root] = groups.len();
+            groups.push((Vec::new(), Vec::new()));
+        }
+
+        groups[components[root]].1.push((x, y, *color));
+    }
+
+    for (x, y, color) in img.enumerate_pixels() {
+        if !colored[index(x, y)] {
+            continue;
+        }
+
+        let mut touching: Vec<usize> = Vec::new();
+
+        for (nx, ny) in NEIGHBORS {
+            let (Some(neighbor_x), Some(neighbor_y)) =
+                (x.checked_add_signed(*nx), y.checked_add_signed(*ny))
+            else {
+                continue;
+            };
+
+            if neighbor_x >= width || neighbor_y >= height || colored[index(neighbor_x, neighbor_y)]
+            {
+                continue;
+            }
+
+            let component = components[find(&mut parents, index(neighbor_x, neighbor_y))];
+
+            if !touching.contains(&component) {
+                touching.push(component);
+                groups[component].0.push(BorderPixel::new(x, y, *color));
+            }
+        }
+    }
+
+    groups
+}
+
+/// Also seeds every island that reaches the edge of the image from the
+/// colored pixels on the edge, whether they touch a transparent pixel or not.
+fn add_edge_seeds(
+    img: &Image16,
+    colored: &[bool],
+    groups: &mut [(Vec<BorderPixel>, Vec<TransparentPixel>)],
+) {
+    let (width, height) = img.dimensions();
+    let on_edge = |x: u32, y: u32| x == 0 || y == 0 || x + 1 == width || y + 1 == height;
+
+    let edge: Vec<TransparentPixel> = img
+        .enumerate_pixels()
+        .filter(|(x, y, _)| {
+            on_edge(*x, *y) && colored[(*y as usize) * (width as usize) + *x as usize]
+        })
+        .map(|(x, y, color)| (x, y, *color))
+        .collect();
+
+    for (border_pixels, transparent_pixels) in groups {
+        if !transparent_pixels.iter().any(|(x, y, _)| on_edge(*x, *y)) {
+            continue;
+        }
+
+        let seeded: HashSet<(u32, u32)> = border_pixels
+            .iter()
+            .map(|pixel| (pixel.position.x as u32, pixel.position.y as u32))
+            .collect();
+
+        for (x, y, color) in &edge {
+            if !seeded.contains(&(*x, *y)) {
+                border_pixels.push(BorderPixel::new(*x, *y, *color));
+            }
+        }
+    }
+}
+
+pub fn triangulate(
+    border_pixels: Vec<BorderPixel>,
+) -> Result<DelaunayTriangulation<BorderPixel>, InsertionError> {
+    Triangulation::bulk_load(border_pixels)
+}
+
+/// How the border pixels are thinned out before triangulating, trading
+/// accuracy for speed.
+#[derive(Clone, Copy, Default)]
+pub struct BorderThinning {
+    /// At most about this many border pixels per fill.
+    pub max_pixels: Option<usize>,
+    /// Drop border pixels this close to one that is kept.
+    pub min_distance: Option<f64>,
+}
+
+impl BorderThinning {
+    pub fn from_options(options: &Options) -> Self {
+        Self {
+            max_pixels: options.max_border_pixels,
+            min_distance: options.poisson_disk_radius,
+        }
+    }
+
+    /// Thins the border pixels of every island. Islands keep at least one
+    /// so they are still filled.
+    fn apply(&self, groups: &mut [(Vec<BorderPixel>, Vec<TransparentPixel>)]) {
+        if let Some(radius) = self.min_distance {
+            for (border_pixels, _) in groups.iter_mut() {
+                poisson_disk(border_pixels, radius);
+            }
+        }
+
+        if let Some(max) = self.max_pixels {
+            subsample(groups, max);
+        }
+    }
+}
+
+/// Drops every border pixel within `radius` of one kept before it, in scan
+/// order, so clusters of nearly the same seed thin out while every part of
+/// the border keeps one. Kept pixels are bucketed by `radius`, so only the
+/// neighboring buckets need checking.
+fn poisson_disk(border_pixels: &mut Vec<BorderPixel>, radius: f64) {
+    let bucket = |position: Point2<f64>| {
+        (
+            (position.x / radius).floor() as i64,
+            (position.y / radius).floor() as i64,
+        )
+    };
+    let mut kept: HashMap<(i64, i64), Vec<Point2<f64>>> = HashMap::new();
+
+    border_pixels.retain(|pixel| {
+        let position = pixel.position;
+        let (column, row) = bucket(position);
+
+        let crowded = (row - 1..=row + 1).any(|row| {
+            (column - 1..=column + 1).any(|column| {
+                kept.get(&(column, row)).is_some_and(|points| {
+                    points.iter().any(|point| {
+                        let (dx, dy) = (point.x - position.x, point.y - position.y);
+                        dx * dx + dy * dy <= radius * radius
+                    })
+                })
+            })
+        });
+
+        if !crowded {
+            kept.entry((column, row)).or_default().push(position);
+        }
+
+        !crowded
+    });
+}
+
+/// Keeps every n-th border pixel of every island when there are more than
+/// `max` in all. The stride keeps them spread along each border.
+fn subsample(groups: &mut [(Vec<BorderPixel>, Vec<TransparentPixel>)], max: usize) {
+    let total: usize = groups
+        .iter()
+        .map(|(border_pixels, _)| border_pixels.len())
+        .sum();
+
+    if total <= max {
+        return;
+    }
+
+    let stride = total.div_ceil(max);
+
+    for (border_pixels, _) in groups.iter_mut() {
+        let mut index = 0;
+
+        border_pixels.retain(|_| {
+            index += 1;
+            (index - 1) % stride == 0
+        });
+    }
+}
+
+/// Gives every transparent pixel the color of its nearest border pixel,
+/// returning how many pixels changed.
+pub fn fill_transparent(
+    img: &mut Image16,
+    triangulation: &DelaunayTriangulation<BorderPixel>,
+    transparent_pixels: &[TransparentPixel],
+    alpha_fill: u8,
+) -> usize {
+    fill_transparent_recording(img, triangulation, transparent_pixels, alpha_fill, None)
+}
+
+/// `fill_transparent`, also recording which pixel each one took its color
+/// from into `steps`, see `frames.rs`.
+fn fill_transparent_recording(
+    img: &mut Image16,
+    triangulation: &DelaunayTriangulation<BorderPixel>,
+    transparent_pixels: &[TransparentPixel],
+    alpha_fill: u8,
+    mut steps: Option<&mut Vec<Step>>,
+) -> usize {
+    let mut changed = 0;
+    let width = img.width();
+
+    // Scale the 8-bit fill value up to the 16-bit working range.
+    let a = u16::from(alpha_fill) * 257;
+
+    for (index, (x, y, _)) in transparent_pixels.iter().enumerate() {
+        if index % FILL_STEP == FILL_STEP - 1 {
+            progress::filled(FILL_STEP);
+
+            if expired() {
+                break;
+            }
+        }
+
+        let closest_neighbor =
+            match &triangulation.nearest_neighbor(Point2::new(*x as f64, *y as f64)) {
+                Some(value) => *value,
+                None => continue,
+            };
+
+        let closest = closest_neighbor.data();
+        let closest_color = &closest.color;
+        let fixed = Rgba::<u16>([closest_color.r, closest_color.g, closest_color.b, a]);
+
+        if let Some(steps) = steps.as_deref_mut() {
+            let source = closest.position.y as u32 * width + closest.position.x as u32;
+            steps.push((*y * width + *x, source));
+        }
+
+        if *img.get_pixel(*x, *y) != fixed {
+            changed += 1;
+        }
+
+        img.put_pixel(*x, *y, fixed)
+    }
+
+    progress::filled(transparent_pixels.len() % FILL_STEP);
+
+    changed
+}
+
+/// For every pixel of a `width` by `height` image, the index of the first
+/// of `rects` it is in, marked one rect at a time.
+fn rect_map(
+    width: u32,
+    height: u32,
+    rects: impl IntoIterator<Item = (usize, Rect)>,
+) -> Vec<Option<usize>> {
+    let mut map = vec![None; width as usize * height as usize];
+
+    for (index, rect) in rects {
+        let x_end = rect.x.saturating_add(rect.width).min(width);
+        let y_end = rect.y.saturating_add(rect.height).min(height);
+
+        for y in rect.y..y_end {
+            for x in rect.x..x_end {
+                map[(y as usize) * (width as usize) + x as usize].get_or_insert(index);
+            }
+        }
+    }
+
+    map
+}
+
+/// For every pixel, the index of the nearest rect of `inside`, a `rect_map`,
+/// the lowest of the ones equally near. A distance transform: the nearest
+/// rect pixel of every column first, then the nearest of those along every
+/// row, so it takes two passes over the pixels however many rects there are.
+fn nearest_rects(width: u32, height: u32, inside: &[Option<usize>]) -> Vec<Option<usize>> {
+    let (width, height) = (width as usize, height as usize);
+
+    // The squared distance to the nearest rect pixel of the column, and the
+    // index of its rect, from above and then from below.
+    let mut columns: Vec<Option<(i64, usize)>> = vec![None; inside.len()];
+
+    for x in 0..width {
+        let mut last = None;
+
+        for y in 0..height {
+            if let Some(index) = inside[y * width + x] {
+                last = Some((y, index));
+            }
+
+            columns[y * width + x] = last.map(|(seed, index)| (((y - seed) as i64).pow(2), index));
+        }
+
+        let mut last = None;
+
+        for y in (0..height).rev() {
+            if let Some(index) = inside[y * width + x] {
+                last = Some((y, index));
+            }
+
+            if let Some((seed, index)) = last {
+                let below = (((seed - y) as i64).pow(2), index);
+                let column = &mut columns[y * width + x];
+
+                if column.is_none_or(|above| below < above) {
+                    *column = Some(below);
+                }
+            }
+        }
+    }
+
+    // Along a row, every column is a parabola over x keyed by the distance
+    // and then the index, which stays a parabola, so their lower envelope
+    // gives the nearest rect with ties going to the first one.
+    let count = inside
+        .iter()
+        .flatten()
+        .max()
+        .map_or(1, |max| *max as i64 + 1);
+    let key = |column: usize, (distance, index): (i64, usize)| {
+        (
+            count * ((column as i64).pow(2) + distance) + index as i64,
+            column as i64,
+        )
+    };
+    // The first x where the parabola of `right` is below the one of `left`.
+    let overtakes = |(left, left_column): (i64, i64), (right, right_column): (i64, i64)| {
+        (right - left).div_euclid(2 * count * (right_column - left_column)) + 1
+    };
+
+    let mut nearest = vec![None; inside.len()];
+    // The columns of the envelope, each with the first x where it is lowest.
+    let mut envelope: Vec<(usize, i64)> = Vec::with_capacity(width);
+
+    for y in 0..height {
+        let row = &columns[y * width..][..width];
+        envelope.clear();
+
+        for (column, seed) in row.iter().enumerate() {
+            let Some(seed) = *seed else {
+                continue;
+            };
+
+            let mut start = i64::MIN;
+
+            while let Some(&(top, top_start)) = envelope.last() {
+                start = overtakes(key(top, row[top].unwrap()), key(column, seed));
+
+                if start > top_start {
+                    break;
+                }
+
+                envelope.pop();
+                start = i64::MIN;
+            }
+
+            envelope.push((column, start));
+        }
+
+        let mut lowest = 0;
+
+        for x in 0..width {
+            while envelope
+                .get(lowest + 1)
+                .is_some_and(|(_, start)| *start <= x as i64)
+            {
+                lowest += 1;
+            }
+
+            if let Some((column, _)) = envelope.get(lowest) {
+                nearest[y * width + x] = row[*column].map(|(_, index)| index);
+            }
+        }
+    }
+
+    nearest
+}
+
+/// Fills every frame of `atlas` from its own border pixels, then the gutter
+/// between frames from the nearest frame only. Returns how many pixels
+/// changed in each frame and in the gutter, or `None` when no frame has
+/// anything to fill from.
+pub fn fill_atlas(
+    img: &mut Image16,
+    atlas: &Atlas,
+    alpha_fill: u8,
+    thinning: BorderThinning,
+) -> Result<Option<(Vec<usize>, usize)>, InsertionError> {
+    let colored = colored_mask(img);
+    let mut triangulations = Vec::new();
+    let mut changed = Vec::new();
+
+    let mut regions: Vec<_> = atlas
+        .frames
+        .iter()
+        .map(|frame| collect_region(img, &colored, frame.rect))
+        .collect();
+    thinning.apply(&mut regions);
+
+    for (border_pixels, transparent_pixels) in regions {
+        if border_pixels.is_empty() {
+            triangulations.push(None);
+            changed.push(0);
+            continue;
+        }
+
+        let triangulation = triangulate(border_pixels)?;
+        changed.push(fill_transparent(
+            img,
+            &triangulation,
+            &transparent_pixels,
+            alpha_fill,
+        ));
+        triangulations.push(Some(triangulation));
+    }
+
+    if triangulations.iter().all(Option::is_none) {
+        return Ok(None);
+    }
+
+    // Group the gutter pixels by the frame closest to them. Both maps are
+    // built once, so atlases with hundreds of frames don't search all of
+    // them for every pixel.
+    let (width, height) = img.dimensions();
+    let rects = atlas.frames.iter().map(|frame| frame.rect).enumerate();
+    let inside = rect_map(width, height, rects.clone());
+    let nearest = nearest_rects(
+        width,
+        height,
+        &rect_map(
+            width,
+            height,
+            rects.filter(|(index, _)| triangulations[*index].is_some()),
+        ),
+    );
+    let mut gutters: Vec<Vec<TransparentPixel>> = vec![Vec::new(); atlas.frames.len()];
+
+    for (pixel, (x, y, color)) in img.enumerate_pixels().enumerate() {
+        if colored[pixel] || inside[pixel].is_some() {
+            continue;
+        }
+
+        if let Some(index) = nearest[pixel] {
+            gutters[index].push((x, y, *color));
+        }
+    }
+
+    let mut gutter_changed = 0;
+
+    for (triangulation, gutter) in triangulations.iter().zip(gutters) {
+        if let Some(triangulation) = triangulation {
+            gutter_changed += fill_transparent(img, triangulation, &gutter, alpha_fill);
+        }
+    }
+
+    Ok(Some((changed, gutter_changed)))
+}
+
+/// Fills the image from the border of every island of transparent pixels,
+/// `passes` times. With `edge_seeds` the colored pixels on the edge of the
+/// image seed the islands reaching it too. Every pass thins its border
+/// pixels with `thinning`. Returns how many pixels changed, or `None` when
+/// the first pass has nothing to fill from.
+pub fn fill_islands(
+    img: &mut Image16,
+    passes: u32,
+    alpha_fill: u8,
+    edge_seeds: bool,
+    thinning: BorderThinning,
+) -> Result<Option<usize>, InsertionError> {
+    fill_islands_recording(img, passes, alpha_fill, edge_seeds, thinning, None)
+}
+
+/// Which island every uncolored pixel is in, by the index of its group, and
+/// its index in the transparent pixels of that group. Colored pixels are in
+/// none.
+struct Islands {
+    width: u32,
+    height: u32,
+    island: Vec<u32>,
+    slot: Vec<u32>,
+}
+
+impl Islands {
+    fn new(width: u32, height: u32, groups: &[(Vec<BorderPixel>, Vec<TransparentPixel>)]) -> Self {
+        let pixels = width as usize * height as usize;
+        let mut island = vec![u32::MAX; pixels];
+        let mut slot = vec![u32::MAX; pixels];
+
+        for (index, (_, transparent_pixels)) in groups.iter().enumerate() {
+            for (position, (x, y, _)) in transparent_pixels.iter().enumerate() {
+                let pixel = (*y as usize) * (width as usize) + *x as usize;
+                island[pixel] = index as u32;
+                slot[pixel] = position as u32;
+            }
+        }
+
+        Self {
+            width,
+            height,
+            island,
+            slot,
+        }
+    }
+
+    /// The index of x, y in the transparent pixels of `island`, when it is
+    /// one of them.
+    fn slot(&self, x: i64, y: i64, island: u32) -> Option<usize> {
+        if x < 0 || y < 0 || x >= i64::from(self.width) || y >= i64::from(self.height) {
+            return None;
+        }
+
+        let pixel = (y as usize) * (self.width as usize) + x as usize;
+
+        (self.island[pixel] == island).then_some(self.slot[pixel] as usize)
+    }
+
+    /// The pixels of `island` a color spreads to from x, y: the ones next to
+    /// it, and the diagonal ones unless both pixels in between are colored,
+    /// so it never slips between two colored pixels touching at a corner.
+    fn steps(&self, x: u32, y: u32, island: u32) -> impl Iterator<Item = usize> {
+        let (x, y) = (i64::from(x), i64::from(y));
+        let [left, right, up, down] =
+            [(-1, 0), (1, 0), (0, -1), (0, 1)].map(|(dx, dy)| self.slot(x + dx, y + dy, island));
+        let diagonal = |dx: i64, dy: i64, beside: Option<usize>, above: Option<usize>| {
+            (beside.is_some() || above.is_some())
+                .then(|| self.slot(x + dx, y + dy, island))
+                .flatten()
+        };
+
+        [
+            left,
+            right,
+            up,
+            down,
+            diagonal(-1, -1, left, up),
+            diagonal(1, -1, right, up),
+            diagonal(-1, 1, left, down),
+            diagonal(1, 1, right, down),
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+/// A transparent pixel of an island and the border pixel it takes its color
+/// from.
+#[derive(Clone, Copy)]
+struct Reached {
+    /// Index into the transparent pixels of the island.
+    pixel: usize,
+    /// Index into the border pixels of the island.
+    source: usize,
+    /// How far the color traveled to get here.
+    distance: f64,
+}
+
+/// How a color is on its way to a pixel: from `source`, in a straight line
+/// from `anchor`, which is `traveled` away from it along the way it came.
+#[derive(Clone, Copy)]
+struct Spread {
+    distance: f64,
+    traveled: f64,
+    source: u32,
+    anchor: (u32, u32),
+}
+
+impl Spread {
+    /// Orders by distance, and ties by the border pixel.
+    fn key(&self) -> (u64, u32) {
+        // Distances are never negative, so their bits sort like them.
+        (self.distance.to_bits(), self.source)
+    }
+}
+
+/// For the transparent pixels of `island`, in order, the border pixel each
+/// is nearest to going only through transparent pixels. The colors
+/// spread from the border one pixel at a time, in a straight line as long
+/// as they move away from where they came from. A color that turns back,
+/// which it only does around the end of a wall, goes on in a straight line
+/// from the turn instead, so the distance is how far it traveled around the
+/// wall rather than straight through it. Where nothing is in the way this
+/// is the straight line distance. Border pixels only seed the pixels of the
+/// island next to them, and ties go to the first border pixel.
+fn nearest_reachable(
+    islands: &Islands,
+    island: u32,
+    border_pixels: &[BorderPixel],
+    transparent_pixels: &[TransparentPixel],
+) -> Vec<Reached> {
+    let position = |pixel: usize| {
+        let (x, y, _) = transparent_pixels[pixel];
+        (x, y)
+    };
+    let squared = |(x, y): (u32, u32), (to_x, to_y): (u32, u32)| {
+        u64::from(x.abs_diff(to_x)).pow(2) + u64::from(y.abs_diff(to_y)).pow(2)
+    };
+
+    // Pixels no color reached yet have none of the border pixels.
+    let unreached = Spread {
+        distance: f64::INFINITY,
+        traveled: 0.0,
+        source: u32::MAX,
+        anchor: (0, 0),
+    };
+    let mut best = vec![unreached; transparent_pixels.len()];
+    // Pixels to spread from, by whole pixels of distance. The distance only
+    // grows along the way, so going through them in order rarely finds a
+    // pixel a nearer color than the one it already spread.
+    let mut queue: Vec<Vec<((u64, u32), usize)>> = Vec::new();
+    let offer = |best: &mut [Spread], queue: &mut Vec<Vec<_>>, pixel: usize, spread: Spread| {
+        if spread.key() < best[pixel].key() {
+            best[pixel] = spread;
+
+            let bucket = spread.distance as usize;
+            if queue.len() <= bucket {
+                queue.resize_with(bucket + 1, Vec::new);
+            }
+            queue[bucket].push((spread.key(), pixel));
+        }
+    };
+
+    for (source, border_pixel) in border_pixels.iter().enumerate() {
+        let anchor = (
+            border_pixel.position.x as u32,
+            border_pixel.position.y as u32,
+        );
+
+        for (dx, dy) in NEIGHBORS {
+            let (x, y) = (
+                i64::from(anchor.0) + i64::from(*dx),
+                i64::from(anchor.1) + i64::from(*dy),
+            );
+
+            if let Some(pixel) = islands.slot(x, y, island) {
+                let spread = Spread {
+                    distance: (squared(position(pixel), anchor) as f64).sqrt(),
+                    traveled: 0.0,
+                    source: source as u32,
+                    anchor,
+                };
+                offer(&mut best, &mut queue, pixel, spread);
+            }
+        }
+    }
+
+    let mut spread_from = 0;
+    let mut bucket = 0;
+
+    while bucket < queue.len() {
+        // In the order of the pixels, so the reads stay close together.
+        queue[bucket].sort_unstable_by_key(|(_, pixel)| *pixel);
+        let mut index = 0;
+
+        while let Some(&(key, pixel)) = queue[bucket].get(index) {
+            index += 1;
+
+            // Left behind by a nearer color.
+            let spread = best[pixel];
+            if spread.key() != key {
+                continue;
+            }
+
+            // Nothing is filled once the file is out of time.
+            spread_from += 1;
+            if spread_from % FILL_STEP == 0 && expired() {
+                return Vec::new();
+            }
+
+            let (x, y, _) = transparent_pixels[pixel];
+            let from = position(pixel);
+
+            for next in islands.steps(x, y, island) {
+                // Already nearer than anything spread from here.
+                if best[next].distance < spread.distance {
+                    continue;
+                }
+
+                let to = position(next);
+                let next_spread = if squared(to, spread.anchor) >= squared(from, spread.anchor) {
+                    Spread {
+                        distance: spread.traveled + (squared(to, spread.anchor) as f64).sqrt(),
+                        ..spread
+                    }
+                } else {
+                    let step = if to.0 != from.0 && to.1 != from.1 {
+                        std::f64::consts::SQRT_2
+                    } else {
+                        1.0
+                    };
+
+                    Spread {
+                        distance: spread.distance + step,
+                        anchor: from,
+                        traveled: spread.distance,
+                        ..spread
+                    }
+                };
+
+                offer(&mut best, &mut queue, next, next_spread);
+            }
+        }
+
+        queue[bucket] = Vec::new();
+        bucket += 1;
+    }
+
+    best.into_iter()
+        .enumerate()
+        .filter(|(_, spread)| spread.source != u32::MAX)
+        .map(|(pixel, spread)| Reached {
+            pixel,
+            source: spread.source as usize,
+            distance: spread.distance,
+        })
+        .collect()
+}
+
+/// The pixels of `reached` the current pass of `--repeat` fills, the ones
+/// nearest to the border: within an equal share of the farthest distance
+/// for each of the `passes_left`, so the passes spread the colors outward in
+/// bands of about the same width.
+fn nearest_band(reached: Vec<Reached>, passes_left: u32) -> Vec<Reached> {
+    let reach = reached
+        .iter()
+        .map(|reached| reached.distance)
+        .fold(0.0, f64::max)
+        / f64::from(passes_left);
+
+    reached
+        .into_iter()
+        .filter(|reached| reached.distance <= reach)
+        .collect()
+}
+
+/// Gives the pixels of `reached` the color of their border pixel, also
+/// recording where each took it from into `steps`. Returns how many pixels
+/// changed.
+fn fill_reached(
+    img: &mut Image16,
+    (border_pixels, transparent_pixels): &(Vec<BorderPixel>, Vec<TransparentPixel>),
+    reached: &[Reached],
+    alpha_fill: u8,
+    mut steps: Option<&mut Vec<Step>>,
+) -> usize {
+    let mut changed = 0;
+    let width = img.width();
+
+    // Scale the 8-bit fill value up to the 16-bit working range.
+    let a = u16::from(alpha_fill) * 257;
+
+    for (index, reached) in reached.iter().enumerate() {
+        if index % FILL_STEP == FILL_STEP - 1 {
+            progress::filled(FILL_STEP);
+
+            if expired() {
+                break;
+            }
+        }
+
+        let (x, y, _) = transparent_pixels[reached.pixel];
+        let source = &border_pixels[reached.source];
+        let VoronoiColor { r, g, b } = source.color;
+        let fixed = Rgba::<u16>([r, g, b, a]);
+
+        if let Some(steps) = steps.as_deref_mut() {
+            let from = source.position.y as u32 * width + source.position.x as u32;
+            steps.push((y * width + x, from));
+        }
+
+        if *img.get_pixel(x, y) != fixed {
+            changed += 1;
+        }
+
+        img.put_pixel(x, y, fixed)
+    }
+
+    progress::filled(reached.len() % FILL_STEP);
+
+    changed
+}
+
+/// `fill_islands`, also recording every pixel it fills and where its color
+/// came from into `steps`, in order, so the fill can be replayed.
+pub(crate) fn fill_islands_recording(
+    img: &mut Image16,
+    passes: u32,
+    alpha_fill: u8,
+    edge_seeds: bool,
+    thinning: BorderThinning,
+    mut steps: Option<&mut Vec<Step>>,
+) -> Result<Option<usize>, InsertionError> {
+    let mut colored = colored_mask(img);
+    let mut changed = 0;
+    let (width, height) = img.dimensions();
+
+    // Every pass but the last only fills the pixels nearest to the border,
+    // and the next one seeds from the pixels it filled.
+    for pass in 0..passes {
+        let passes_left = passes - pass;
+
+        // Every island is filled from its own border only, and within it
+        // colors only spread through transparent pixels, so they never jump
+        // over opaque pixels. Islands without a border have nothing to fill
+        // from.
+        let mut groups = collect_components(img, &colored);
+
+        if edge_seeds {
+            add_edge_seeds(img, &colored, &mut groups);
+        }
+
+        groups.retain(|(border_pixels, _)| !border_pixels.is_empty());
+        thinning.apply(&mut groups);
+
+        if groups.is_empty() {
+            if pass == 0 {
+                return Ok(None);
+            }
+
+            break;
+        }
+
+        progress::filling(
+            groups
+                .iter()
+                .map(|(_, transparent_pixels)| transparent_pixels.len())
+                .sum(),
+        );
+        progress::stage(Stage::Filling);
+
+        let islands = Islands::new(width, height, &groups);
+
+        for (island, group) in groups.iter().enumerate() {
+            if expired() {
+                return Ok(Some(changed));
+            }
+
+            let reached = nearest_reachable(&islands, island as u32, &group.0, &group.1);
+            let reached = match passes_left {
+                1 => reached,
+                _ => nearest_band(reached, passes_left),
+            };
+
+            changed += fill_reached(img, group, &reached, alpha_fill, steps.as_deref_mut());
+
+            for reached in reached {
+                let (x, y, _) = group.1[reached.pixel];
+                colored[(y as usize) * (width as usize) + x as usize] = true;
+            }
+        }
+    }
+
+    Ok(Some(changed))
+}
+
+/// Spreads the colors `iterations` pixels outward, the padding of texture
+/// packers: every pass, each transparent pixel next to a colored one takes
+/// the colors of its colored neighbors, combined with `blend` in
+/// `colorspace`. Pixels farther out are left alone, and so is the alpha
+/// unless `extend_alpha` makes the spread pixels opaque. Returns how many
+/// pixels changed, or `None` when nothing is colored.
+pub fn dilate(
+    img: &mut Image16,
+    iterations: u32,
+    extend_alpha: bool,
+    blend: Blend,
+    colorspace: ColorSpace,
+) -> Option<usize> {
+    progress::stage(Stage::Filling);
+    let mut colored = colored_mask(img);
+
+    if !colored.contains(&true) {
+        return None;
+    }
+
+    let (width, height) = img.dimensions();
+    let index = |x: u32, y: u32| (y as usize) * (width as usize) + x as usize;
+    let mut changed = 0;
+
+    for _ in 0..iterations {
+        if expired() {
+            break;
+        }
+
+        let mut spread = Vec::new();
+
+        for (x, y, pixel) in img.enumerate_pixels() {
+            if colored[index(x, y)] {
+                continue;
+            }
+
+            // At most the 8 neighbors, kept on the stack.
+            let mut samples = [[0; 3]; 8];
+            let mut count = 0;
+
+            for (dx, dy) in NEIGHBORS {
+                let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+
+                if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                    continue;
+                }
+
+                let (nx, ny) = (nx as u32, ny as u32);
+
+                if colored[index(nx, ny)] {
+                    let [r, g, b, _] = img.get_pixel(nx, ny).0;
+                    samples[count] = [r, g, b];
+                    count += 1;
+                }
+            }
+
+            if let Some([r, g, b]) = colorspace.blend(blend, &samples[..count]) {
+                let a = if extend_alpha { u16::MAX } else { pixel.0[3] };
+
+                spread.push((x, y, Rgba([r, g, b, a])));
+            }
+        }
+
+        // Nothing left to reach.
+        if spread.is_empty() {
+            break;
+        }
+
+        // Applied after the pass, so every pixel of it averages the colors
+        // of the one before.
+        for (x, y, color) in spread {
+            if *img.get_pixel(x, y) != color {
+                changed += 1;
+            }
+
+            img.put_pixel(x, y, color);
+            colored[index(x, y)] = true;
+        }
+    }
+
+    Some(changed)
+}
+
+/// The cells of `grid` over an image of this size. The last column and row
+/// also take the pixels left over when the size doesn't divide evenly.
+fn grid_cells(grid: Grid, width: u32, height: u32) -> Vec<Rect> {
+    let (cell_width, cell_height) = (width / grid.columns, height / grid.rows);
+    let mut cells = Vec::new();
+
+    for row in 0..grid.rows {
+        for column in 0..grid.columns {
+            let (x, y) = (column * cell_width, row * cell_height);
+
+            cells.push(Rect {
+                x,
+                y,
+                width: if column + 1 == grid.columns {
+                    width - x
+                } else {
+                    cell_width
+                },
+                height: if row + 1 == grid.rows {
+                    height - y
+                } else {
+                    cell_height
+                },
+            });
+        }
+    }
+
+    cells
+}
+
+/// A cell of a sprite sheet with its rows of the image, which no other cell
+/// touches, so it can be written back without locking the image.
+struct CellRows<'a> {
+    cell: Rect,
+    rows: Vec<&'a mut [u16]>,
+}
+
+/// Splits the rows of `img` at the edges of `cells`, which must not overlap.
+fn split_cells<'a>(img: &'a mut Image16, cells: &[Rect]) -> Vec<CellRows<'a>> {
+    let width = img.width() as usize * 4;
+    let mut split: Vec<CellRows> = cells
+        .iter()
+        .map(|cell| CellRows {
+            cell: *cell,
+            rows: Vec::new(),
+        })
+        .collect();
+
+    let mut by_x: Vec<usize> = (0..cells.len()).collect();
+    by_x.sort_by_key(|&index| cells[index].x);
+
+    for (y, mut row) in img.chunks_exact_mut(width).enumerate() {
+        let mut x = 0;
+
+        for &index in &by_x {
+            let cell = cells[index];
+
+            if !(cell.y..cell.y + cell.height).contains(&(y as u32)) {
+                continue;
+            }
+
+            let (_, rest) = std::mem::take(&mut row).split_at_mut((cell.x - x) as usize * 4);
+            let (segment, rest) = rest.split_at_mut(cell.width as usize * 4);
+
+            split[index].rows.push(segment);
+            row = rest;
+            x = cell.x + cell.width;
+        }
+    }
+
+    split
+}
+
+/// Fills one cell of a sprite sheet as an image of its own with `fill` and
+/// writes it back to its rows.
+fn fill_cell(
+    cell: CellRows,
+    fill: impl Fn(&mut Image16) -> Result<Option<usize>, InsertionError>,
+) -> Result<Option<usize>, InsertionError> {
+    let CellRows { cell, mut rows } = cell;
+    let pixels = rows.iter().flat_map(|row| row.iter().copied()).collect();
+    let mut sprite = Image16::from_raw(cell.width, cell.height, pixels)
+        .expect("the rows of a cell hold all of its pixels");
+
+    let changed = fill(&mut sprite)?;
+
+    if changed.is_some() {
+        for (row, filled) in rows
+            .iter_mut()
+            .zip(sprite.chunks_exact(cell.width as usize * 4))
+        {
+            row.copy_from_slice(filled);
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Fills every cell of `grid` as an image of its own with `fill`, like
+/// `fill_islands`, so colors never bleed between the sprites of a sheet. The
+/// cells are shared out to the `--jobs` threads no other file is using.
+/// Returns how many pixels changed, or `None` when no cell has anything to
+/// fill from.
+pub fn fill_grid(
+    img: &mut Image16,
+    grid: Grid,
+    fill: impl Fn(&mut Image16) -> Result<Option<usize>, InsertionError> + Sync,
+) -> Result<Option<usize>, InsertionError> {
+    let cells: Vec<Rect> = grid_cells(grid, img.width(), img.height())
+        .into_iter()
+        .filter(|cell| cell.width > 0 && cell.height > 0)
+        .collect();
+
+    let helpers = workers::borrow(cells.len().saturating_sub(1));
+    let queue = Mutex::new(split_cells(img, &cells).into_iter().enumerate());
+    let deadline = DEADLINE.with(Cell::get);
+
+    let work = || {
+        let _deadline = Deadline::set(deadline);
+        let mut results = Vec::new();
+
+        loop {
+            if expired() {
+                return results;
+            }
+
+            // Only taking the next cell is locked, never the image.
+            let next = queue
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .next();
+            let Some((index, cell)) = next else {
+                return results;
+            };
+
+            results.push((index, fill_cell(cell, &fill)));
+        }
+    };
+
+    let mut results = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..helpers.count()).map(|_| scope.spawn(work)).collect();
+        let mut results = work();
+
+        for handle in handles {
+            results.extend(
+                handle
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+            );
+        }
+
+        results
+    });
+
+    // Report the first error in the order of the cells, whichever thread
+    // got to it first.
+    results.sort_by_key(|(index, _)| *index);
+
+    let mut changed = None;
+
+    for (_, result) in results {
+        if let Some(cell_changed) = result? {
+            *changed.get_or_insert(0) += cell_changed;
+        }
+    }
+
+    Ok(changed)
+}
+
+/// `img` with transparent pixels added on the right and bottom up to the
+/// next power of two on both sides, e.g. 100x80 becomes 128x128, or `None`
+/// when it already has those sides. The fill then reaches the padding too.
+pub fn pad_to_power_of_two(img: &Image16) -> Option<Image16> {
+    let (width, height) = img.dimensions();
+    let (padded_width, padded_height) = (width.next_power_of_two(), height.next_power_of_two());
+
+    if (padded_width, padded_height) == (width, height) {
+        return None;
+    }
+
+    let mut padded = Image16::new(padded_width, padded_height);
+    imageops::replace(&mut padded, img, 0, 0);
+
+    Some(padded)
+}
+
+/// The smallest area holding every pixel that isn't fully transparent, or
+/// `None` when the whole image is.
+pub fn content_box(img: &Image16, alpha_invert: bool) -> Option<Rect> {
+    let transparent = if alpha_invert { u16::MAX } else { 0 };
+    let (mut min_x, mut min_y) = (u32::MAX, u32::MAX);
+    let (mut max_x, mut max_y) = (0, 0);
+
+    for (x, y, color) in img.enumerate_pixels() {
+        if color.0[3] != transparent {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+
+    (min_x <= max_x).then(|| Rect {
+        x: min_x,
+        y: min_y,
+        width: max_x - min_x + 1,
+        height: max_y - min_y + 1,
+    })
+}
+
+/// `content` grown by `margin` pixels on every side, kept inside `img`.
+pub fn trim_area(img: &Image16, content: Rect, margin: u32) -> Rect {
+    let x = content.x.saturating_sub(margin);
+    let y = content.y.saturating_sub(margin);
+    let right = (content.x + content.width)
+        .saturating_add(margin)
+        .min(img.width());
+    let bottom = (content.y + content.height)
+        .saturating_add(margin)
+        .min(img.height());
+
+    Rect {
+        x,
+        y,
+        width: right - x,
+        height: bottom - y,
+    }
+}
+
+/// Resizes `img` by `scale`, keeping at least one pixel on each side.
+pub fn scale_image(img: &Image16, scale: f32, filter: ScaleFilter) -> Image16 {
+    let size = |value: u32| ((value as f32 * scale).round() as u32).max(1);
+    let filter = match filter {
+        ScaleFilter::Nearest => FilterType::Nearest,
+        ScaleFilter::Bilinear => FilterType::Triangle,
+        ScaleFilter::Lanczos3 => FilterType::Lanczos3,
+    };
+
+    imageops::resize(img, size(img.width()), size(img.height()), filter)
+}
+
+/// Turns premultiplied colors back into straight ones, so partly
+/// transparent border pixels don't seed the fill with darkened colors.
+pub fn unpremultiply(img: &mut Image16) {
+    for pixel in img.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+
+        if a == 0 {
+            continue;
+        }
+
+        let unpremultiply = |channel: u16| {
+            ((u32::from(channel) * 65535 + u32::from(a) / 2) / u32::from(a)).min(65535) as u16
+        };
+        pixel.0 = [unpremultiply(r), unpremultiply(g), unpremultiply(b), a];
+    }
+}
+
+pub fn premultiply(img: &mut Image16) {
+    for pixel in img.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let premultiply =
+            |channel: u16| ((u32::from(channel) * u32::from(a) + 32767) / 65535) as u16;
+
+        pixel.0 = [premultiply(r), premultiply(g), premultiply(b), a];
+    }
+}
+
+/// Flips the alpha of every pixel, for masks where `0` means opaque.
+pub fn invert_alpha(img: &mut Image16) {
+    for pixel in img.pixels_mut() {
+        pixel.0[3] = u16::MAX - pixel.0[3];
+    }
+}
+
+/// Black where `original` and `fixed` match, the fixed color at full opacity
+/// where they don't.
+pub fn diff_image(original: &Image16, fixed: &Image16) -> Image16 {
+    ImageBuffer::from_fn(fixed.width(), fixed.height(), |x, y| {
+        let color = fixed.get_pixel(x, y);
+
+        if original.get_pixel(x, y) == color {
+            Rgba([0, 0, 0, u16::MAX])
+        } else {
+            Rgba([color.0[0], color.0[1], color.0[2], u16::MAX])
+        }
+    })
+}
+
+/// The Voronoi cells of the fill of `img`, for `--color-bleed-debug-image`:
+/// colored pixels as they are, every filled pixel in the color of the cell
+/// it falls in at full opacity, and red where two neighboring filled pixels
+/// take their color from different border pixels. `None` when there is
+/// nothing to fill from.
+pub fn bleed_debug_image(img: &Image16, options: &Options) -> Result<Option<Image16>, PixfixError> {
+    let mut filled = img.clone();
+
+    if options.alpha_invert {
+        invert_alpha(&mut filled);
+    }
+
+    let mut steps = Vec::new();
+    let changed = fill_islands_recording(
+        &mut filled,
+        options.repeat.max(1),
+        u8::MAX,
+        options.edge_detect,
+        BorderThinning::from_options(options),
+        Some(&mut steps),
+    )
+    .map_err(PixfixError::Triangulate)?;
+
+    if changed.is_none() {
+        return Ok(None);
+    }
+
+    // Later passes fill from pixels an earlier one filled, so follow those
+    // back to the border pixel the color started from.
+    let mut sources = vec![u32::MAX; filled.len() / 4];
+
+    for (target, source) in steps {
+        let source = match sources[source as usize] {
+            u32::MAX => source,
+            root => root,
+        };
+
+        sources[target as usize] = source;
+    }
+
+    let (width, height) = img.dimensions();
+    let source = |x: u32, y: u32| sources[(y * width + x) as usize];
+    let red = Rgba([u16::MAX, 0, 0, u16::MAX]);
+
+    Ok(Some(ImageBuffer::from_fn(width, height, |x, y| {
+        let own = source(x, y);
+
+        if own == u32::MAX {
+            return *img.get_pixel(x, y);
+        }
+
+        // Only the neighbors to the right and below, so every edge is drawn
+        // one pixel wide.
+        let neighbors = [
+            (x + 1 < width).then_some((x + 1, y)),
+            (y + 1 < height).then_some((x, y + 1)),
+        ];
+        let boundary = neighbors.into_iter().flatten().any(|(x, y)| {
+            let other = source(x, y);
+            other != u32::MAX && other != own
+        });
+
+        if boundary {
+            return red;
+        }
+
+        let color = filled.get_pixel(x, y);
+        Rgba([color.0[0], color.0[1], color.0[2], u16::MAX])
+    })))
+}
+
+/// The image at the bit depth of the input, without its alpha channel
+/// unless `alpha`.
+fn to_dynamic(img: Image16, sixteen_bit: bool, alpha: bool) -> DynamicImage {
+    match (sixteen_bit, alpha) {
+        (true, true) => DynamicImage::ImageRgba16(img),
+        (false, true) => DynamicImage::ImageRgba8(DynamicImage::ImageRgba16(img).to_rgba8()),
+        (true, false) => DynamicImage::ImageRgb16(DynamicImage::ImageRgba16(img).to_rgb16()),
+        (false, false) => DynamicImage::ImageRgb8(DynamicImage::ImageRgba16(img).to_rgb8()),
+    }
+}
+
+pub(crate) fn is_16_bit(img: &DynamicImage) -> bool {
+    let color = img.color();
+
+    color.bytes_per_pixel() / color.channel_count() == 2
+}
+
+pub enum Converted {
+    /// The fixed image was saved to `output_path`.
+    Fixed {
+        output_path: PathBuf,
+        /// Hash of the saved bytes when `--digest` is set.
+        digest: Option<String>,
+        changed_pixels: usize,
+        /// The other `--multi-format` outputs, with their digests.
+        extra_outputs: Vec<(PathBuf, Option<String>)>,
+        /// The area of the input `--trim` cropped the image to.
+        trim: Option<Rect>,
+    },
+    /// The image has no alpha channel, so there is nothing to fix and the
+    /// file was left untouched.
+    NoAlphaChannel,
+    /// `--check` found this many pixels that fixing would change. Nothing
+    /// was written.
+    Checked { bad_pixels: usize },
+    /// `--diff-only` saved the changed pixels to `diff_path`, leaving the
+    /// input untouched.
+    Diffed {
+        diff_path: PathBuf,
+        changed_pixels: usize,
+    },
+    /// A zip archive, with the outcome of each PNG entry.
+    Archive(ArchiveReport),
+    /// A `data:` URI input fixed without `--output`, as a new data URI.
+    DataUri(String),
+}
+
+/// Fails once the file has taken longer than `--timeout-per-file`. The
+/// caller stops waiting at that point, but only the fill stops on its own,
+/// so this is checked before writing to make sure a late file never is.
+pub(crate) fn check_timeout(start: Instant, options: &Options) -> Result<(), PixfixError> {
+    match options.timeout_per_file {
+        Some(limit) if start.elapsed() > limit => Err(PixfixError::TimedOut(limit)),
+        _ => Ok(()),
+    }
+}
+
+thread_local! {
+    /// When the file being fixed on this thread runs out of time, for
+    /// `--timeout-per-file`.
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Gives the file being fixed on this thread until its deadline, clearing
+/// it again when dropped.
+pub(crate) struct Deadline {
+    previous: Option<Instant>,
+}
+
+impl Deadline {
+    /// `--timeout-per-file` from `start`, or no deadline without it.
+    pub(crate) fn start(start: Instant, options: &Options) -> Deadline {
+        Deadline::set(options.timeout_per_file.map(|limit| start + limit))
+    }
+
+    fn set(deadline: Option<Instant>) -> Deadline {
+        Deadline {
+            previous: DEADLINE.with(|current| current.replace(deadline)),
+        }
+    }
+}
+
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        DEADLINE.with(|current| current.set(self.previous));
+    }
+}
+
+/// Whether the file being fixed on this thread is out of time. The fill
+/// checks it as it goes and stops early, as a timed out file is abandoned
+/// anyway and would keep its thread busy for nothing.
+pub(crate) fn expired() -> bool {
+    DEADLINE.with(|deadline| {
+        deadline
+            .get()
+            .is_some_and(|deadline| Instant::now() > deadline)
+    })
+}
+
+/// Fails when the fill stopped early for `--timeout-per-file`, so a partial
+/// fill is never encoded.
+fn check_deadline(options: &Options) -> Result<(), PixfixError> {
+    match options.timeout_per_file {
+        Some(limit) if expired() => Err(PixfixError::TimedOut(limit)),
+        _ => Ok(()),
+    }
+}
+
+/// An image after the fill, before it is encoded.
+struct Filled {
+    img: Image16,
+    sixteen_bit: bool,
+    /// Whether the image is saved with an alpha channel, which color keyed
+    /// images only get with `--colorkey-to-alpha`.
+    alpha: bool,
+    changed_pixels: usize,
+    /// How many pixels the fill spread from, only counted for
+    /// `--output-stats`.
+    border_pixels: Option<usize>,
+    /// The image before the fill, kept for `--diff-only`.
+    original: Option<Image16>,
+    /// The area of the input `--trim` cropped the image to.
+    trim: Option<Rect>,
+    /// The Voronoi cells of the fill, for `--color-bleed-debug-image`.
+    bleed_debug: Option<Image16>,
+}
+
+/// Decodes the rows of a PNG image that were read before it was cut short.
+/// The rest is left transparent, so the fill covers it like any other
+/// transparent area. Returns the image and how many rows were complete, or
+/// `None` when not even the header can be read.
+fn decode_rows(bytes: &[u8]) -> Option<(DynamicImage, u32)> {
+    let decoder = PngDecoder::new(Cursor::new(bytes)).ok()?;
+    let (width, height) = decoder.dimensions();
+    let color = decoder.color_type();
+    let row_size = width as usize * color.bytes_per_pixel() as usize;
+
+    let mut data = Vec::new();
+    // The reader fails at the cut, keeping the rows it read before it.
+    #[allow(deprecated)]
+    let _ = decoder.into_reader().ok()?.read_to_end(&mut data);
+
+    let rows = (data.len() / row_size.max(1)).min(height as usize);
+    data.resize(rows * row_size, 0);
+    data.resize(height as usize * row_size, 0);
+
+    // The rows come out as stored in the file, so 16-bit samples are
+    // big endian.
+    let samples: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|sample| u16::from_be_bytes([sample[0], sample[1]]))
+        .collect();
+
+    let img = match color {
+        ColorType::L8 => DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, data)?),
+        ColorType::La8 => DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, data)?),
+        ColorType::Rgb8 => DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, data)?),
+        ColorType::Rgba8 => DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, data)?),
+        ColorType::L16 => DynamicImage::ImageLuma16(ImageBuffer::from_raw(width, height, samples)?),
+        ColorType::La16 => {
+            DynamicImage::ImageLumaA16(ImageBuffer::from_raw(width, height, samples)?)
+        }
+        ColorType::Rgb16 => {
+            DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, height, samples)?)
+        }
+        ColorType::Rgba16 => {
+            DynamicImage::ImageRgba16(ImageBuffer::from_raw(width, height, samples)?)
+        }
+        _ => return None,
+    };
+
+    // Images without alpha get one, so the missing rows can be transparent.
+    let mut rgba = img.to_rgba16();
+
+    for y in rows as u32..height {
+        for x in 0..width {
+            rgba.put_pixel(x, y, Rgba([0, 0, 0, 0]));
+        }
+    }
+
+    let img = if is_16_bit(&img) {
+        DynamicImage::ImageRgba16(rgba)
+    } else {
+        DynamicImage::ImageRgba8(DynamicImage::ImageRgba16(rgba).to_rgba8())
+    };
+
+    Some((img, rows as u32))
+}
+
+/// Decodes the image. A PNG cut short fails with the row it was cut at, or
+/// with `--tolerant` is decoded up to there.
+pub(crate) fn decode(
+    bytes: &[u8],
+    name: &Path,
+    options: &Options,
+) -> Result<DynamicImage, PixfixError> {
+    let damage = chunks::damage(bytes);
+
+    if let Some(damage) = damage.as_ref().filter(|damage| damage.trailing > 0) {
+        diagnostic!(
+            "Ignoring {} bytes after the end of \"{}\"",
+            damage.trailing,
+            name.display()
+        );
+    }
+
+    let err = match image::load_from_memory(bytes) {
+        Ok(decoded) => return Ok(decoded),
+        Err(err) => err,
+    };
+
+    if !damage.is_some_and(|damage| damage.truncated) {
+        return Err(PixfixError::Decode(err));
+    }
+
+    let Some((decoded, rows)) = decode_rows(bytes) else {
+        return Err(PixfixError::Decode(err));
+    };
+
+    if !options.tolerant {
+        return Err(PixfixError::Truncated {
+            row: rows,
+            height: decoded.height(),
+        });
+    }
+
+    diagnostic!(
+        "\"{}\" is truncated at row {} of {}, filling the rest",
+        name.display(),
+        rows,
+        decoded.height()
+    );
+
+    Ok(decoded)
+}
+
+/// Runs the fill on decoded pixels, with the alpha handling of the options
+/// around it. `name` picks the atlas and is used in messages. Returns how
+/// many pixels changed.
+fn fill_pixels(
+    img: &mut Image16,
+    name: Option<&Path>,
+    options: &Options,
+) -> Result<usize, PixfixError> {
+    if options.alpha_invert {
+        invert_alpha(img);
+    }
+
+    if options.premultiplied_alpha_input {
+        unpremultiply(img);
+    }
+
+    let mut changed_pixels = 0;
+
+    let atlas = name.and_then(|name| {
+        options
+            .atlases
+            .iter()
+            .find(|atlas| atlas.applies_to(name))
+            .map(|atlas| (name, atlas))
+    });
+    let thinning = BorderThinning::from_options(options);
+
+    if let Some((name, atlas)) = atlas {
+        let filled = fill_atlas(img, atlas, options.alpha_fill, thinning);
+
+        match filled.map_err(PixfixError::Triangulate)? {
+            Some((frames, gutter)) => {
+                let mut report = format!("Atlas frames of \"{}\":", name.display());
+
+                for (frame, changed) in atlas.frames.iter().zip(&frames) {
+                    report += &format!("\n  {} - {} pixels", frame.name, changed);
+                }
+
+                report += &format!("\n  gutter - {} pixels", gutter);
+                human!("{}", report);
+
+                changed_pixels = frames.iter().sum::<usize>() + gutter;
+            }
+            // Nothing to fill from, which a check reports as clean.
+            None if options.check => {}
+            None => return Err(PixfixError::NothingToFix),
+        }
+    } else {
+        let passes = options.repeat.max(1);
+        let fill = |img: &mut Image16| match options.dilate {
+            Some(iterations) => Ok(dilate(
+                img,
+                iterations,
+                options.dilate_alpha,
+                options.blend,
+                options.colorspace,
+            )),
+            None if options.backend != Backend::Cpu => {
+                Ok(jfa::fill(img, options.alpha_fill, options.backend))
+            }
+            None => fill_islands(
+                img,
+                passes,
+                options.alpha_fill,
+                options.edge_detect,
+                thinning,
+            ),
+        };
+        let filled = match options.sprite_sheet_grid {
+            Some(grid) => fill_grid(img, grid, fill),
+            None if options.dilate.is_none()
+                && options.backend == Backend::Cpu
+                && options.frame_cache
+                && frames::enabled() =>
+            {
+                frames::fill_islands_cached(
+                    img,
+                    passes,
+                    options.alpha_fill,
+                    options.edge_detect,
+                    thinning,
+                )
+            }
+            None => fill(img),
+        };
+
+        match filled.map_err(PixfixError::Triangulate)? {
+            Some(changed) => changed_pixels = changed,
+            None if options.check => {}
+            None => return Err(PixfixError::NothingToFix),
+        }
+    }
+
+    if options.premultiplied_alpha_input {
+        premultiply(img);
+    }
+
+    if options.alpha_invert {
+        invert_alpha(img);
+    }
+
+    Ok(changed_pixels)
+}
+
+/// Fixes raw 8-bit RGBA pixels in place, for callers that already hold the
+/// image in memory, like game engines with texture staging buffers. There
+/// is no file or format handling, so atlases, `--scale` and the outputs
+/// don't apply, and `--check` leaves `data` as it was. Returns how many
+/// pixels changed.
+pub fn fix_rgba_buffer(
+    data: &mut [u8],
+    width: u32,
+    height: u32,
+    options: &Options,
+) -> Result<usize, PixfixError> {
+    check_dimensions(width, height, options)?;
+
+    let expected = width as usize * height as usize * 4;
+
+    let Some(buffer) = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, data.to_vec())
+        .filter(|_| data.len() == expected)
+    else {
+        return Err(PixfixError::BufferSize {
+            expected,
+            actual: data.len(),
+        });
+    };
+
+    let mut img = DynamicImage::ImageRgba8(buffer).into_rgba16();
+    let changed_pixels = fill_pixels(&mut img, None, options)?;
+
+    if !options.check {
+        data.copy_from_slice(DynamicImage::ImageRgba16(img).into_rgba8().as_raw());
+    }
+
+    Ok(changed_pixels)
+}
+
+/// Counts the pixels of exactly the `key` color and makes them transparent,
+/// and every other pixel opaque.
+fn apply_colorkey(img: &mut Image16, key: ColorKey) -> usize {
+    let key = key.0.map(|channel| u16::from(channel) * 257);
+    let mut keyed = 0;
+
+    for pixel in img.pixels_mut() {
+        if pixel.0[..3] == key {
+            pixel.0[3] = 0;
+            keyed += 1;
+        } else {
+            pixel.0[3] = u16::MAX;
+        }
+    }
+
+    keyed
+}
+
+/// Fails images that don't have the size `--check-dimensions` or
+/// `--check-power-of-two` ask for.
+fn check_dimensions(width: u32, height: u32, options: &Options) -> Result<(), PixfixError> {
+    let expected = options.check_dimensions;
+
+    let matches = match expected {
+        Some(expected) => (width, height) == expected,
+        None => true,
+    };
+    let power_of_two =
+        !options.check_power_of_two || (width.is_power_of_two() && height.is_power_of_two());
+
+    if !matches || !power_of_two {
+        return Err(PixfixError::Dimensions {
+            width,
+            height,
+            expected: expected.filter(|_| !matches),
+        });
+    }
+
+    Ok(())
+}
+
+/// Decodes and fills the image, or returns `None` when it has no alpha
+/// channel and no `--colorkey` pixels. `name` is only used to pick the atlas and in messages.
+fn fill_image(
+    bytes: &[u8],
+    name: &Path,
+    options: &Options,
+    keep_original: bool,
+) -> Result<Option<Filled>, PixfixError> {
+    // Only the header is read, so images of the wrong size aren't decoded.
+    // Headers that can't be read are left for the decode to report.
+    if let Ok(header) = PngDecoder::new(Cursor::new(bytes)) {
+        let (width, height) = header.dimensions();
+        check_dimensions(width, height, options)?;
+    }
+
+    progress::stage(Stage::Decoding);
+    let decoded = decode(bytes, name, options)?;
+    progress::size(u64::from(decoded.width()) * u64::from(decoded.height()));
+
+    let alpha = decoded.color().has_alpha();
+    let colorkey = options.colorkey.filter(|_| !alpha);
+
+    if !alpha && colorkey.is_none() {
+        return Ok(None);
+    }
+
+    let sixteen_bit = is_16_bit(&decoded);
+    let mut img = decoded.to_rgba16();
+
+    if let Some(key) = colorkey {
+        let keyed = apply_colorkey(&mut img, key);
+
+        // Nothing keyed, so nothing to fill, like without an alpha channel.
+        if keyed == 0 {
+            return Ok(None);
+        }
+
+        if options.verbose {
+            human!(
+                "Treating {} pixels of \"{}\" as transparent",
+                keyed,
+                name.display()
+            );
+        }
+    }
+
+    let trim = if options.trim {
+        trim_image(&mut img, name, options)
+    } else {
+        None
+    };
+
+    if options.power_of_two_pad {
+        if let Some(padded) = pad_to_power_of_two(&img) {
+            if options.verbose {
+                human!(
+                    "Padded \"{}\" to {}x{}",
+                    name.display(),
+                    padded.width(),
+                    padded.height()
+                );
+            }
+
+            img = padded;
+        }
+    }
+
+    let original = keep_original.then(|| img.clone());
+    let border_pixels = options
+        .output_stats
+        .map(|_| count_border_pixels(&img, options.alpha_invert));
+
+    // Which pixels the fill colors, for `--quality-check` to look at after.
+    let transparent: Option<Vec<bool>> = options.quality_check.map(|_| {
+        let transparent = if options.alpha_invert { u16::MAX } else { 0 };
+        img.pixels()
+            .map(|color| color.0[3] == transparent)
+            .collect()
+    });
+
+    // Skipped by checks, which write nothing.
+    let bleed_debug = match options.bleed_debug.is_some() && !options.check {
+        true => bleed_debug_image(&img, options)?,
+        false => None,
+    };
+
+    let changed_pixels = fill_pixels(&mut img, Some(name), options)?;
+    check_deadline(options)?;
+
+    if let (Some(threshold), Some(transparent)) = (options.quality_check, transparent) {
+        let jumps = count_color_jumps(&img, &transparent, threshold);
+
+        if jumps > 0 {
+            diagnostic!(
+                "\"{}\" has {} color jumps above ΔE {} in its fill - The edges between the colors spread from different pixels are harsh, blending them with --dilate would soften them.",
+                name.display(),
+                jumps,
+                threshold
+            );
+        }
+    }
+
+    let (img, original) = match options.scale {
+        Some(scale) => {
+            let img = scale_image(&img, scale, options.scale_filter);
+
+            if options.verbose {
+                human!(
+                    "Scaled \"{}\" to {}x{}",
+                    name.display(),
+                    img.width(),
+                    img.height()
+                );
+            }
+
+            // Scaled too, so diffs and split alpha line up with the output.
+            let original =
+                original.map(|original| scale_image(&original, scale, options.scale_filter));
+
+            (img, original)
+        }
+        None => (img, original),
+    };
+
+    Ok(Some(Filled {
+        img,
+        sixteen_bit,
+        // Color keyed images stay without one, their key pixels recolored.
+        alpha: alpha || options.colorkey_to_alpha,
+        changed_pixels,
+        border_pixels,
+        original,
+        trim,
+        bleed_debug,
+    }))
+}
+
+/// Crops `img` to its content plus `--trim-margin`, returning the area of
+/// the input it was cropped to. Fully transparent images are left alone.
+fn trim_image(img: &mut Image16, name: &Path, options: &Options) -> Option<Rect> {
+    let Some(content) = content_box(img, options.alpha_invert) else {
+        diagnostic!(
+            "Not trimming \"{}\" - It is fully transparent",
+            name.display()
+        );
+        return None;
+    };
+
+    let area = trim_area(img, content, options.trim_margin);
+
+    if (area.width, area.height) != img.dimensions() {
+        *img = imageops::crop_imm(img, area.x, area.y, area.width, area.height).to_image();
+    }
+
+    Some(area)
+}
+
+/// Counts the pairs of side by side pixels, both `filled`, whose colors
+/// differ by more than `threshold` ΔE.
+fn count_color_jumps(img: &Image16, filled: &[bool], threshold: f64) -> usize {
+    let width = img.width() as usize;
+    let rgb = |color: &Rgba<u16>| [color.0[0], color.0[1], color.0[2]];
+    let mut jumps = 0;
+
+    for (x, y, color) in img.enumerate_pixels() {
+        let index = y as usize * width + x as usize;
+
+        if !filled[index] {
+            continue;
+        }
+
+        for (neighbor_x, neighbor_y) in [(x + 1, y), (x, y + 1)] {
+            if neighbor_x >= img.width() || neighbor_y >= img.height() {
+                continue;
+            }
+
+            let neighbor = img.get_pixel(neighbor_x, neighbor_y);
+
+            if filled[neighbor_y as usize * width + neighbor_x as usize]
+                && neighbor != color
+                && color::delta_e(rgb(color), rgb(neighbor)) > threshold
+            {
+                jumps += 1;
+            }
+        }
+    }
+
+    jumps
+}
+
+/// Counts the colored pixels next to transparent ones, before the fill.
+fn count_border_pixels(img: &Image16, alpha_invert: bool) -> usize {
+    let colored: Vec<bool> = if alpha_invert {
+        img.pixels().map(|color| color.0[3] != u16::MAX).collect()
+    } else {
+        colored_mask(img)
+    };
+
+    collect_pixels(img, &colored).0.len()
+}
+
+/// PNG encoder settings for output that has to stay PNG whatever
+/// `--format` says, like diffs and in-memory fixes.
+pub(crate) fn png_options(options: &Options) -> FormatOptions {
+    FormatOptions {
+        format: OutputFormat::Png,
+        png_compression: options.format.png_compression,
+        ..Default::default()
+    }
+}
+
+/// The result of fixing an image held in memory.
+pub enum FixedBytes {
+    NoAlphaChannel,
+    Checked {
+        bad_pixels: usize,
+    },
+    /// The fixed image, always encoded as PNG.
+    Fixed {
+        bytes: Vec<u8>,
+        changed_pixels: usize,
+        /// The area of the input `--trim` cropped the image to.
+        trim: Option<Rect>,
+    },
+}
+
+/// Copies the metadata of the `input` PNG over to the fixed `output`, minus
+/// what `--strip-metadata` and `--strip-color-info` drop.
+fn keep_metadata(input: &[u8], output: Vec<u8>, name: &Path, options: &Options) -> Vec<u8> {
+    let strip = Strip {
+        metadata: options.strip_metadata,
+        color_info: options.strip_color_info,
+    };
+
+    match chunks::carry_over(input, &output, strip) {
+        Some((output, stripped)) => {
+            if stripped > 0 && (strip.metadata || strip.color_info) {
+                human!(
+                    "Stripped {} bytes of metadata from \"{}\"",
+                    stripped,
+                    name.display()
+                );
+            }
+
+            output
+        }
+        None => output,
+    }
+}
+
+/// Records what the fix did in a `pixfix` text chunk of the fixed PNG, for
+/// `--output-stats png`. `border_pixels` is only counted with it.
+fn add_stats(output: Vec<u8>, changed_pixels: usize, border_pixels: Option<usize>) -> Vec<u8> {
+    let Some(border_pixels) = border_pixels else {
+        return output;
+    };
+
+    // Written by hand to keep the keys in this order.
+    let text = format!(
+        r#"{{"pixels_fixed":{},"border_pixels":{},"algorithm":"voronoi","version":"{}"}}"#,
+        changed_pixels,
+        border_pixels,
+        env!("CARGO_PKG_VERSION")
+    );
+
+    chunks::add_text(&output, stats::KEYWORD, &text).unwrap_or(output)
+}
+
+/// Fixes an encoded image held in memory without touching the filesystem.
+/// `name` is only used to pick the atlas and in messages.
+pub fn fix_bytes(bytes: &[u8], name: &Path, options: &Options) -> Result<FixedBytes, PixfixError> {
+    let Some(filled) = fill_image(bytes, name, options, false)? else {
+        return Ok(FixedBytes::NoAlphaChannel);
+    };
+
+    if options.check {
+        return Ok(FixedBytes::Checked {
+            bad_pixels: filled.changed_pixels,
+        });
+    }
+
+    progress::stage(Stage::Encoding);
+    let img = to_dynamic(filled.img, filled.sixteen_bit, filled.alpha);
+    let output = png_options(options)
+        .encode(&img)
+        .map_err(PixfixError::Encode)?;
+
+    Ok(FixedBytes::Fixed {
+        bytes: add_stats(
+            keep_metadata(bytes, output, name, options),
+            filled.changed_pixels,
+            filled.border_pixels,
+        ),
+        changed_pixels: filled.changed_pixels,
+        trim: filled.trim,
+    })
+}
+
+/// Makes `img` fully opaque, returning the alpha of `original` as a
+/// grayscale image.
+pub fn split_alpha(img: &mut Image16, original: &Image16) -> ImageBuffer<Luma<u16>, Vec<u16>> {
+    for pixel in img.pixels_mut() {
+        pixel.0[3] = u16::MAX;
+    }
+
+    ImageBuffer::from_fn(original.width(), original.height(), |x, y| {
+        Luma([original.get_pixel(x, y).0[3]])
+    })
+}
+
+/// Where `--alpha-channel-output separate` saves the alpha of the image
+/// saved to `output_path`: `<name>_alpha.png` next to it.
+pub(crate) fn alpha_path(output_path: &Path) -> PathBuf {
+    let stem = output_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+
+    output_path.with_file_name(format!("{}_alpha.png", stem))
+}
+
+/// Where `--mip-chain` saves `level` of the image saved to `output_path`:
+/// `<name>_mip<level>.png` next to it.
+fn mip_path(output_path: &Path, level: u32) -> PathBuf {
+    let stem = output_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+
+    output_path.with_file_name(format!("{}_mip{}.png", stem, level))
+}
+
+pub(crate) fn save(path: &Path, bytes: &[u8], options: &Options) -> Result<(), PixfixError> {
+    with_retries(&io_path(path), options.retries, options.verbose, |path| {
+        paths::write_atomic(path, bytes)
+    })
+    .map_err(|err| PixfixError::Save {
+        attempts: err.attempts,
+        error: err.error,
+    })
+}
+
+/// A file a fix wants written.
+pub struct PendingWrite {
+    pub path: PathBuf,
+    pub bytes: Vec<u8>,
+    /// What `--verify` expects to read back from it.
+    pub verify: Option<Verify>,
+}
+
+/// The size and opaque pixels of a fixed image, which `--verify` checks the
+/// saved file against.
+pub struct Verify {
+    width: u32,
+    height: u32,
+    /// Empty for images saved without alpha, which are opaque anyway.
+    opaque: Vec<bool>,
+}
+
+impl Verify {
+    pub fn of(img: &DynamicImage) -> Self {
+        let opaque = match img.color().has_alpha() {
+            true => (img.to_rgba16().pixels())
+                .map(|color| color.0[3] == u16::MAX)
+                .collect(),
+            false => Vec::new(),
+        };
+
+        Self {
+            width: img.width(),
+            height: img.height(),
+            opaque,
+        }
+    }
+
+    /// Why the file saved at `path` doesn't hold the image, if it doesn't.
+    fn problem(&self, path: &Path) -> Option<String> {
+        let saved = match image::open(io_path(path)) {
+            Ok(value) => value,
+            Err(err) => return Some(format!("it doesn't decode: {}", err)),
+        };
+
+        if (saved.width(), saved.height()) != (self.width, self.height) {
+            return Some(format!(
+                "it is {}x{} instead of {}x{}",
+                saved.width(),
+                saved.height(),
+                self.width,
+                self.height
+            ));
+        }
+
+        let lost = (saved.to_rgba16().pixels())
+            .zip(&self.opaque)
+            .filter(|(color, opaque)| **opaque && color.0[3] == 0)
+            .count();
+
+        (lost > 0).then(|| format!("{} opaque pixels are transparent", lost))
+    }
+}
+
+/// Locks `path` against other runs fixing it until the lock is dropped,
+/// unless this run only checks it. Taken before the file is read and held
+/// until its fix is written, see `paths::FileLock`.
+pub fn lock_input(path: &Path, options: &Options) -> Result<Option<FileLock>, PixfixError> {
+    if options.check {
+        return Ok(None);
+    }
+
+    paths::lock_file(path).map(Some).map_err(PixfixError::Lock)
+}
+
+/// Reads the image at `path`, retrying with `--retries`.
+pub fn read_input(path: &Path, options: &Options) -> Result<Vec<u8>, PixfixError> {
+    with_retries(&io_path(path), options.retries, options.verbose, |path| {
+        std::fs::read(path)
+    })
+    .map_err(|err| PixfixError::Read {
+        attempts: err.attempts,
+        error: err.error,
+    })
+}
+
+/// Writes a file of the fix of `input`. With `--force-writable` a read-only
+/// input is overwritten anyway, keeping its read-only flag, and with
+/// `--backup-store` or `--journal` it is backed up first. With `--verify`
+/// the saved file is read back and checked. Nothing is written once the
+/// file is out of time, see `Deadline`.
+pub fn write_output(
+    write: &PendingWrite,
+    input: &Path,
+    options: &Options,
+) -> Result<(), PixfixError> {
+    // A file given up on by `--timeout-per-file` was reported as left
+    // alone, though its thread may still get here.
+    check_deadline(options)?;
+
+    if let Some(journal) = options.journal_dir().filter(|_| write.path == input) {
+        backup::back_up(journal, input, &write.bytes, options)?;
+    }
+
+    let original_permissions = if options.force_writable && write.path == input {
+        paths::make_writable(&io_path(&write.path)).map_err(PixfixError::ClearReadOnly)?
+    } else {
+        None
+    };
+
+    let saved = save(&write.path, &write.bytes, options).and_then(|()| {
+        match write
+            .verify
+            .as_ref()
+            .and_then(|verify| verify.problem(&write.path))
+        {
+            Some(problem) => Err(verify_failed(write, input, problem, options)),
+            None => Ok(()),
+        }
+    });
+
+    if let Some(permissions) = original_permissions {
+        if let Err(err) = std::fs::set_permissions(io_path(&write.path), permissions) {
+            diagnostic!(
+                "Error occured restoring the read-only flag of \"{}\":\n{}",
+                write.path.display(),
+                err
+            );
+        }
+    }
+
+    saved
+}
+
+/// The error for a `--verify` failure of `write`, after putting the backup
+/// of `input` back when it was overwritten and there is one.
+fn verify_failed(
+    write: &PendingWrite,
+    input: &Path,
+    problem: String,
+    options: &Options,
+) -> PixfixError {
+    let restored = match options.journal_dir().filter(|_| write.path == input) {
+        Some(journal) => backup::restore_file(journal, input, options),
+        None => Ok(false),
+    };
+
+    let problem = match restored {
+        Ok(true) => format!("{}, restored the original from the backup", problem),
+        Ok(false) => problem,
+        Err(err) => format!("{}, and restoring the backup failed: {}", problem, err),
+    };
+
+    PixfixError::Verify {
+        path: write.path.clone(),
+        problem,
+    }
+}
+
+/// Fixes the image at `path` and saves it.
+pub fn convert_image(path: &Path, options: &Options) -> Result<Converted, PixfixError> {
+    let start = Instant::now();
+    let _lock = lock_input(path, options)?;
+    let bytes = read_input(path, options)?;
+    let (converted, writes) = fix_image(path, &bytes, options, start)?;
+    let _deadline = Deadline::start(start, options);
+
+    for write in &writes {
+        write_output(write, path, options)?;
+    }
+
+    Ok(converted)
+}
+
+/// Fixes the image read from `path` into `bytes`, returning the outcome and
+/// the files to write for it. `start` is when work on the file began, for
+/// `--timeout-per-file`.
+pub fn fix_image(
+    path: &Path,
+    bytes: &[u8],
+    options: &Options,
+    start: Instant,
+) -> Result<(Converted, Vec<PendingWrite>), PixfixError> {
+    let _tracked = progress::track(path);
+    let _deadline = Deadline::start(start, options);
+    let mut writes = Vec::new();
+
+    let Some(Filled {
+        mut img,
+        sixteen_bit,
+        alpha,
+        changed_pixels,
+        border_pixels,
+        original,
+        trim,
+        bleed_debug,
+    }) = fill_image(
+        bytes,
+        path,
+        options,
+        options.diff_only.is_some() || options.alpha_channel_output == AlphaChannelOutput::Separate,
+    )?
+    else {
+        return Ok((Converted::NoAlphaChannel, writes));
+    };
+
+    if options.check {
+        return Ok((
+            Converted::Checked {
+                bad_pixels: changed_pixels,
+            },
+            writes,
+        ));
+    }
+
+    if let (Some(debug), Some(debug_path)) = (bleed_debug, options.bleed_debug_path(path)) {
+        let debug = to_dynamic(debug, sixteen_bit, true);
+        let output = png_options(options)
+            .encode(&debug)
+            .map_err(PixfixError::Encode)?;
+        check_timeout(start, options)?;
+
+        writes.push(PendingWrite {
+            path: debug_path,
+            bytes: output,
+            verify: None,
+        });
+    }
+
+    if let (Some(original), Some(diff_path)) = (&original, options.diff_path(path)) {
+        let diff = to_dynamic(diff_image(original, &img), sixteen_bit, true);
+
+        // Diffs are always PNG so CI can compare them byte for byte.
+        let output = png_options(options)
+            .encode(&diff)
+            .map_err(PixfixError::Encode)?;
+        check_timeout(start, options)?;
+
+        writes.push(PendingWrite {
+            path: diff_path.clone(),
+            bytes: output,
+            verify: None,
+        });
+
+        return Ok((
+            Converted::Diffed {
+                diff_path,
+                changed_pixels,
+            },
+            writes,
+        ));
+    }
+
+    let output_path = options.output_path(path);
+
+    if let (AlphaChannelOutput::Separate, Some(original)) = (options.alpha_channel_output, original)
+    {
+        let alpha = split_alpha(&mut img, &original);
+        let alpha = if sixteen_bit {
+            DynamicImage::ImageLuma16(alpha)
+        } else {
+            DynamicImage::ImageLuma8(DynamicImage::ImageLuma16(alpha).to_luma8())
+        };
+
+        let output = png_options(options)
+            .encode(&alpha)
+            .map_err(PixfixError::Encode)?;
+        check_timeout(start, options)?;
+
+        writes.push(PendingWrite {
+            path: alpha_path(&output_path),
+            bytes: output,
+            verify: None,
+        });
+    }
+
+    progress::stage(Stage::Encoding);
+    let img = to_dynamic(img, sixteen_bit, alpha);
+    let mut outputs = Vec::new();
+
+    // One fill, saved in every `--multi-format` format.
+    for format in options.output_formats() {
+        let output_path = options.output_path_as(path, &format);
+        let output = format.encode(&img).map_err(PixfixError::Encode)?;
+        let output = match format.format {
+            OutputFormat::Png => {
+                let output = keep_metadata(bytes, output, path, options);
+                add_stats(output, changed_pixels, border_pixels)
+            }
+            _ => output,
+        };
+        check_timeout(start, options)?;
+
+        let digest = options.digest.map(|algorithm| algorithm.digest(&output));
+        outputs.push((output_path.clone(), digest));
+        writes.push(PendingWrite {
+            path: output_path,
+            bytes: output,
+            verify: options.verify.then(|| Verify::of(&img)),
+        });
+    }
+
+    if let Some(mip_chain) = options.mip_chain {
+        let levels = mip_chain.levels(img.width(), img.height());
+        let mut mip = img;
+
+        // Every level is scaled from the filled one above it, so their
+        // transparent pixels carry the fill too.
+        for level in 0..=levels {
+            if level > 0 {
+                let (width, height) = ((mip.width() / 2).max(1), (mip.height() / 2).max(1));
+                mip = mip.resize_exact(width, height, FilterType::Lanczos3);
+            }
+
+            let output = png_options(options)
+                .encode(&mip)
+                .map_err(PixfixError::Encode)?;
+            check_timeout(start, options)?;
+
+            let mip_path = mip_path(&output_path, level);
+            let digest = options.digest.map(|algorithm| algorithm.digest(&output));
+            outputs.push((mip_path.clone(), digest));
+            writes.push(PendingWrite {
+                path: mip_path,
+                bytes: output,
+                verify: None,
+            });
+        }
+    }
+
+    let (output_path, digest) = outputs.remove(0);
+
+    Ok((
+        Converted::Fixed {
+            output_path,
+            digest,
+            changed_pixels,
+            extra_outputs: outputs,
+            trim,
+        },
+        writes,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, LumaA, Rgb};
+
+    use super::*;
+    use crate::{
+        fixtures::{colorkey_png, encode, fringed_sprite, palette_png, scattered, TempDir},
+        options::ColorKey,
+    };
+
+    fn unfilled(img: &Image16) -> usize {
+        img.pixels().filter(|color| color.0 == [0; 4]).count()
+    }
+
+    fn fill(img: &Image16, passes: u32) -> Image16 {
+        let mut img = img.clone();
+        fill_islands(&mut img, passes, 0, false, BorderThinning::default()).unwrap();
+
+        img
+    }
+
+    #[test]
+    fn repeated_passes_fill_every_pixel() {
+        let img = scattered(64, 64, 12);
+
+        for passes in [1, 2, 5] {
+            assert_eq!(unfilled(&fill(&img, passes)), 0, "{} passes", passes);
+        }
+    }
+
+    #[test]
+    fn later_passes_seed_from_the_pixels_filled_before() {
+        let img = scattered(64, 64, 12);
+        let (once, twice) = (fill(&img, 1), fill(&img, 2));
+
+        assert!(once != twice);
+
+        // The first of two passes fills the half nearest to the border, from
+        // the same seeds a single pass uses.
+        let mut first_pass = img.clone();
+        let colored = colored_mask(&img);
+        let groups = collect_components(&img, &colored);
+        let islands = Islands::new(img.width(), img.height(), &groups);
+        let reached = nearest_reachable(&islands, 0, &groups[0].0, &groups[0].1);
+        let band = nearest_band(reached, 2);
+        fill_reached(&mut first_pass, &groups[0], &band, 0, None);
+
+        assert!(!band.is_empty());
+        for reached in band {
+            let (x, y, _) = groups[0].1[reached.pixel];
+            assert_eq!(first_pass.get_pixel(x, y), once.get_pixel(x, y));
+            assert_eq!(first_pass.get_pixel(x, y), twice.get_pixel(x, y));
+        }
+    }
+
+    const RED: Rgba<u16> = Rgba([u16::MAX, 0, 0, u16::MAX]);
+    const GREEN: Rgba<u16> = Rgba([0, u16::MAX, 0, u16::MAX]);
+    const BLUE: Rgba<u16> = Rgba([0, 0, u16::MAX, u16::MAX]);
+
+    /// A cup open at the top with walls three pixels thick: red on the
+    /// outside, blue on the inside and green in between.
+    fn cup() -> Image16 {
+        let wall = |x: u32, y: u32| (6..18).contains(&x) && (6..18).contains(&y);
+        let inside = |x: u32, y: u32| (9..15).contains(&x) && (0..15).contains(&y);
+        let solid = |x: u32, y: u32| wall(x, y) && !inside(x, y);
+        let near = |x: u32, y: u32, test: &dyn Fn(u32, u32) -> bool| {
+            (x.saturating_sub(1)..=x + 1).any(|x| (y.saturating_sub(1)..=y + 1).any(|y| test(x, y)))
+        };
+
+        Image16::from_fn(24, 24, |x, y| {
+            if !solid(x, y) {
+                Rgba([0; 4])
+            } else if near(x, y, &|x, y| inside(x, y) && wall(x, y)) {
+                BLUE
+            } else if near(x, y, &|x, y| !wall(x, y)) {
+                RED
+            } else {
+                GREEN
+            }
+        })
+    }
+
+    #[test]
+    fn a_concave_sprite_is_filled_from_the_border_on_each_side() {
+        let mut img = cup();
+        fill_islands(&mut img, 1, 255, false, BorderThinning::default()).unwrap();
+
+        // Inside the cup, and below its floor.
+        for y in 6..15 {
+            for x in 9..15 {
+                assert_eq!(*img.get_pixel(x, y), BLUE, "{},{}", x, y);
+            }
+        }
+        for y in 18..24 {
+            for x in 6..18 {
+                assert_eq!(*img.get_pixel(x, y), RED, "{},{}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn colors_go_around_a_wall_instead_of_through_it() {
+        let img = cup();
+        let colored = colored_mask(&img);
+        let groups = collect_components(&img, &colored);
+        let islands = Islands::new(img.width(), img.height(), &groups);
+
+        // Right below the middle of the floor, and the top of the inside of
+        // the left wall. The first is nearer to the bottom of the inside in
+        // a straight line, through the floor.
+        let border_pixels = [BorderPixel::new(12, 17, RED), BorderPixel::new(8, 6, BLUE)];
+        let reached = nearest_reachable(&islands, 0, &border_pixels, &groups[0].1);
+        let source = |x: u32, y: u32| {
+            reached
+                .iter()
+                .find(|reached| {
+                    groups[0].1[reached.pixel].0 == x && groups[0].1[reached.pixel].1 == y
+                })
+                .map(|reached| reached.source)
+        };
+
+        assert_eq!(source(12, 14), Some(1));
+        assert_eq!(source(12, 18), Some(0));
+        assert_eq!(reached.len(), groups[0].1.len());
+    }
+
+    #[test]
+    fn the_fill_stops_at_the_deadline() {
+        let mut img = scattered(600, 600, 50);
+        let mut expired = img.clone();
+
+        fill_islands(&mut img, 1, 0, false, BorderThinning::default()).unwrap();
+        assert_eq!(unfilled(&img), 0);
+
+        let _deadline = Deadline::set(Some(Instant::now()));
+        fill_islands(&mut expired, 1, 0, false, BorderThinning::default()).unwrap();
+        assert!(unfilled(&expired) > 0);
+        assert!(check_deadline(&Options {
+            timeout_per_file: Some(std::time::Duration::from_nanos(1)),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    /// The image `fix_bytes` makes of `png`, or `None` when it has no alpha
+    /// channel to fix.
+    fn fixed(png: &[u8]) -> Option<DynamicImage> {
+        match fix_bytes(png, Path::new("test.png"), &Options::default()) {
+            Ok(FixedBytes::Fixed { bytes, .. }) => Some(image::load_from_memory(&bytes).unwrap()),
+            Ok(FixedBytes::NoAlphaChannel) => None,
+            Ok(FixedBytes::Checked { .. }) => panic!("nothing was checked"),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn rgb_images_are_left_alone() {
+        let png = encode(DynamicImage::ImageRgb8(ImageBuffer::from_fn(
+            8,
+            8,
+            |x, y| Rgb([x as u8 * 30, y as u8 * 30, 0]),
+        )));
+        assert!(fixed(&png).is_none());
+
+        let dir = TempDir::new("rgb").unwrap();
+        let path = dir.path().join("rgb.png");
+        std::fs::write(&path, &png).unwrap();
+
+        assert!(matches!(
+            convert_image(&path, &Options::default()),
+            Ok(Converted::NoAlphaChannel)
+        ));
+        assert_eq!(std::fs::read(&path).unwrap(), png);
+    }
+
+    #[test]
+    fn grayscale_images_are_left_alone() {
+        let png = encode(DynamicImage::ImageLuma8(ImageBuffer::from_fn(
+            8,
+            8,
+            |x, _| image::Luma([x as u8 * 30]),
+        )));
+
+        assert!(fixed(&png).is_none());
+    }
+
+    #[test]
+    fn palette_images_without_trns_are_left_alone() {
+        let chunks: Vec<_> = chunks::read_chunks(&palette_png())
+            .unwrap()
+            .into_iter()
+            .filter(|chunk| &chunk.kind != b"tRNS")
+            .collect();
+
+        assert!(fixed(&chunks::write_chunks(&chunks)).is_none());
+    }
+
+    #[test]
+    fn grayscale_images_with_alpha_are_filled_in_rgba() {
+        let png = encode(DynamicImage::ImageLumaA8(ImageBuffer::from_fn(
+            8,
+            8,
+            |x, _| match x {
+                0..=3 => LumaA([100, 255]),
+                _ => LumaA([0, 0]),
+            },
+        )));
+        let img = fixed(&png).unwrap();
+
+        assert_eq!(img.color(), ColorType::Rgba8);
+        for (x, _, color) in img.to_rgba8().enumerate_pixels() {
+            let alpha = if x < 4 { 255 } else { 0 };
+            assert_eq!(color.0, [100, 100, 100, alpha], "at x {}", x);
+        }
+    }
+
+    #[test]
+    fn palette_images_with_trns_are_filled_in_rgba() {
+        let img = fixed(&palette_png()).unwrap();
+        let rgba = img.to_rgba8();
+
+        assert_eq!(img.color(), ColorType::Rgba8);
+        // The colored block keeps its colors, the transparent pixels around
+        // it take the nearest one.
+        assert_eq!(rgba.get_pixel(5, 5).0, [220, 40, 40, 255]);
+        assert_eq!(rgba.get_pixel(10, 10).0, [30, 60, 230, 255]);
+        assert_eq!(rgba.get_pixel(4, 0).0, [220, 40, 40, 0]);
+        assert_eq!(rgba.get_pixel(0, 15).0, [40, 200, 60, 0]);
+        assert_eq!(rgba.get_pixel(15, 15).0, [30, 60, 230, 0]);
+    }
+
+    fn fixed_bytes(png: &[u8], options: &Options) -> Vec<u8> {
+        match fix_bytes(png, Path::new("test.png"), options) {
+            Ok(FixedBytes::Fixed { bytes, .. }) => bytes,
+            Ok(_) => panic!("the image wasn't fixed"),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn has_text(png: &[u8]) -> bool {
+        chunks::read_chunks(png)
+            .unwrap()
+            .iter()
+            .any(|chunk| &chunk.kind == b"tEXt")
+    }
+
+    #[test]
+    fn text_chunks_are_kept_unless_stripped() {
+        let png = chunks::add_text(&fringed_sprite(), "Comment", "drawn by hand").unwrap();
+        assert!(has_text(&png));
+
+        let kept = fixed_bytes(&png, &Options::default());
+        let stripped = fixed_bytes(
+            &png,
+            &Options {
+                strip_metadata: true,
+                ..Default::default()
+            },
+        );
+
+        assert!(has_text(&kept));
+        assert!(!has_text(&stripped));
+        // Only the chunk is gone, the image is the same.
+        assert!(
+            image::load_from_memory(&kept).unwrap() == image::load_from_memory(&stripped).unwrap()
+        );
+    }
+
+    fn colorkeyed(to_alpha: bool) -> DynamicImage {
+        let options = Options {
+            colorkey: Some(ColorKey([255, 0, 255])),
+            colorkey_to_alpha: to_alpha,
+            ..Default::default()
+        };
+
+        image::load_from_memory(&fixed_bytes(&colorkey_png(), &options)).unwrap()
+    }
+
+    #[test]
+    fn colorkey_recolors_the_key_pixels() {
+        let img = colorkeyed(false);
+        let rgb = img.to_rgb8();
+
+        assert_eq!(img.color(), ColorType::Rgb8);
+        assert_eq!(rgb.get_pixel(5, 5).0, [220, 40, 40]);
+        assert_eq!(rgb.get_pixel(10, 10).0, [40, 200, 60]);
+        // The pink around the block takes the nearest color of it.
+        assert_eq!(rgb.get_pixel(0, 0).0, [220, 40, 40]);
+        assert_eq!(rgb.get_pixel(15, 15).0, [40, 200, 60]);
+        assert!(rgb.pixels().all(|color| color.0 != [255, 0, 255]));
+    }
+
+    #[test]
+    fn colorkey_to_alpha_makes_the_key_pixels_transparent() {
+        let img = colorkeyed(true);
+        let rgba = img.to_rgba8();
+
+        assert_eq!(img.color(), ColorType::Rgba8);
+        assert_eq!(rgba.get_pixel(5, 5).0, [220, 40, 40, 255]);
+        assert_eq!(rgba.get_pixel(10, 10).0, [40, 200, 60, 255]);
+        assert_eq!(rgba.get_pixel(0, 0).0, [220, 40, 40, 0]);
+        assert_eq!(rgba.get_pixel(15, 15).0, [40, 200, 60, 0]);
+    }
+
+    /// The nearest of `rects` to every pixel, searching all of them.
+    fn nearest_searched(width: u32, height: u32, rects: &[(usize, Rect)]) -> Vec<Option<usize>> {
+        let axis = |value: u32, start: u32, size: u32| {
+            let end = start.saturating_add(size.saturating_sub(1));
+            u64::from(start.saturating_sub(value).max(value.saturating_sub(end)))
+        };
+
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                rects
+                    .iter()
+                    .min_by_key(|(_, rect)| {
+                        axis(x, rect.x, rect.width).pow(2) + axis(y, rect.y, rect.height).pow(2)
+                    })
+                    .map(|(index, _)| *index)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_nearest_frame_matches_a_search_of_every_frame() {
+        let rect = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        // Gutters of odd and even widths, overlapping frames, frames past
+        // the edge of the image and frames left out as if they had nothing
+        // to fill from. Frames entirely outside the image never have
+        // anything to fill from, so the random ones start inside it.
+        let mut rects: Vec<(usize, Rect)> = vec![
+            (0, rect(0, 0, 8, 8)),
+            (1, rect(11, 0, 8, 8)),
+            (3, rect(0, 13, 5, 5)),
+            (4, rect(3, 15, 9, 4)),
+            (5, rect(30, 28, 10, 10)),
+        ];
+        let mut seed = 7u32;
+        let mut random = |max: u32| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) % max
+        };
+
+        for index in 6..40 {
+            let rect = rect(random(36), random(32), 1 + random(6), 1 + random(6));
+
+            if random(4) > 0 {
+                rects.push((index, rect));
+            }
+        }
+
+        for rects in [&rects[..], &rects[..2], &rects[4..5], &[]] {
+            let nearest = nearest_rects(36, 32, &rect_map(36, 32, rects.iter().copied()));
+
+            assert!(nearest == nearest_searched(36, 32, rects));
+        }
+    }
+
+    #[test]
+    fn the_median_blend_rejects_an_outlier_border_color() {
+        // A column of sprite color with one black pixel in the middle, next
+        // to a transparent column.
+        let sprite = [60000, 20000, 10000];
+        let img = Image16::from_fn(2, 3, |x, y| match (x, y) {
+            (0, _) => Rgba([0; 4]),
+            (_, 1) => Rgba([0, 0, 0, u16::MAX]),
+            _ => Rgba([sprite[0], sprite[1], sprite[2], u16::MAX]),
+        });
+        let dilated = |blend| {
+            let mut img = img.clone();
+            dilate(&mut img, 1, true, blend, ColorSpace::Srgb).unwrap();
+
+            img.get_pixel(0, 1).0
+        };
+
+        assert_eq!(dilated(Blend::Median), [60000, 20000, 10000, u16::MAX]);
+        assert_eq!(dilated(Blend::Mean), [40000, 13333, 6667, u16::MAX]);
+    }
+
+    #[test]
+    fn the_colorspace_decides_how_the_dilate_blend_mixes_colors() {
+        // A transparent pixel between a red and a green one.
+        let img = Image16::from_fn(3, 1, |x, _| match x {
+            0 => RED,
+            1 => Rgba([0; 4]),
+            _ => GREEN,
+        });
+        let dilated = |colorspace| {
+            let mut img = img.clone();
+            dilate(&mut img, 1, true, Blend::Mean, colorspace).unwrap();
+
+            img.get_pixel(1, 0).0
+        };
+
+        let (srgb, linear, oklab) = (
+            dilated(ColorSpace::Srgb),
+            dilated(ColorSpace::Linear),
+            dilated(ColorSpace::Oklab),
+        );
+        // The stored values average to a muddy dark yellow and the light to
+        // a brighter one, while Oklab mixes the way the eye sees them, into
+        // a bright yellow leaning to orange.
+        assert_eq!(srgb, [32768, 32768, 0, u16::MAX]);
+        assert_eq!(linear, [48192, 48192, 0, u16::MAX]);
+        assert!(oklab[0] > linear[0] && oklab[1] < linear[1] && oklab[1] > srgb[1]);
+        assert!(oklab[2] < 256 && oklab[3] == u16::MAX);
+    }
+
+    #[test]
+    fn premultiplied_colors_survive_a_round_trip() {
+        // Every premultiplied 8-bit color, one image row per alpha.
+        let mut img = Image16::from_fn(256, 256, |channel, alpha| {
+            let channel = channel.min(alpha) as u16 * 257;
+            Rgba([channel, channel, channel, alpha as u16 * 257])
+        });
+        let original = img.clone();
+
+        unpremultiply(&mut img);
+        premultiply(&mut img);
+
+        for (pixel, original) in img.pixels().zip(original.pixels()) {
+            let to_8_bits = |channel: u16| (f64::from(channel) / 257.0).round() as i32;
+            let error = (to_8_bits(pixel.0[0]) - to_8_bits(original.0[0])).abs();
+
+            assert!(error <= 1, "{:?} came back as {:?}", original.0, pixel.0);
+        }
+    }
+
+    #[test]
+    fn nothing_is_written_after_the_deadline() {
+        let dir = TempDir::new("write-deadline").unwrap();
+        let path = dir.path().join("a.png");
+        std::fs::write(&path, "original").unwrap();
+
+        let write = PendingWrite {
+            path: path.clone(),
+            bytes: b"fixed".to_vec(),
+            verify: None,
+        };
+        let options = Options {
+            timeout_per_file: Some(std::time::Duration::from_nanos(1)),
+            ..Default::default()
+        };
+
+        {
+            let _deadline = Deadline::set(Some(Instant::now()));
+            assert!(matches!(
+                write_output(&write, &path, &options),
+                Err(PixfixError::TimedOut(_))
+            ));
+            assert_eq!(std::fs::read(&path).unwrap(), b"original");
+        }
+
+        assert!(write_output(&write, &path, &options).is_ok());
+        assert_eq!(std::fs::read(&path).unwrap(), b"fixed");
+        // The temporary file was renamed over it.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn fixing_is_timed_from_decoding_to_encoding() {
+        let bytes = crate::fixtures::fringed_sprite();
+        let (fixed, stages) = progress::time_stages(|| {
+            fix_bytes(&bytes, Path::new("sprite.png"), &Options::default())
+        });
+        let stages: Vec<Stage> = stages.into_iter().map(|(stage, _)| stage).collect();
+
+        assert!(matches!(fixed, Ok(FixedBytes::Fixed { .. })));
+        assert_eq!(stages.first(), Some(&Stage::Decoding));
+        assert_eq!(stages.last(), Some(&Stage::Encoding));
+        // Other tests may have filled the same sprite, in which case the fill
+        // is replayed from the frame cache without triangulating.
+        assert!(stages.contains(&Stage::Filling));
+    }
+}
@@ -0,0 +1,76 @@
+//! `data:` URIs as inputs, so web tooling can pass images without
+//! temporary files.
+
+use std::{ffi::OsStr, path::PathBuf};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::{
+    convert::{fix_bytes, Converted, FixedBytes},
+    error::PixfixError,
+    options::Options,
+    paths::{self, io_path},
+    retry::with_retries,
+};
+
+const PREFIX: &str = "data:image/";
+const BASE64_MARKER: &str = ";base64,";
+
+pub fn is_data_uri(arg: &OsStr) -> bool {
+    arg.to_str().is_some_and(|arg| arg.starts_with(PREFIX))
+}
+
+fn decode(uri: &str) -> Result<Vec<u8>, PixfixError> {
+    let (_, payload) = uri
+        .split_once(BASE64_MARKER)
+        .ok_or_else(|| PixfixError::InvalidDataUri("Only base64 data URIs are supported".into()))?;
+
+    STANDARD
+        .decode(payload.trim())
+        .map_err(|err| PixfixError::InvalidDataUri(err.to_string()))
+}
+
+/// Fixes the image in `uri`, saving it to `--output` when given and
+/// returning it as a new data URI otherwise.
+pub fn fix_data_uri(uri: &OsStr, options: &Options) -> Result<Converted, PixfixError> {
+    let uri = uri.to_string_lossy();
+    let bytes = decode(&uri)?;
+
+    let (fixed, changed_pixels, trim) = match fix_bytes(&bytes, "data URI".as_ref(), options)? {
+        FixedBytes::Fixed {
+            bytes,
+            changed_pixels,
+            trim,
+        } => (bytes, changed_pixels, trim),
+        FixedBytes::NoAlphaChannel => return Ok(Converted::NoAlphaChannel),
+        FixedBytes::Checked { bad_pixels } => return Ok(Converted::Checked { bad_pixels }),
+    };
+
+    let Some(output_path) = &options.output else {
+        return Ok(Converted::DataUri(format!(
+            "{}png{}{}",
+            PREFIX,
+            BASE64_MARKER,
+            STANDARD.encode(fixed)
+        )));
+    };
+
+    with_retries(
+        &io_path(output_path),
+        options.retries,
+        options.verbose,
+        |path| paths::write_atomic(path, &fixed),
+    )
+    .map_err(|err| PixfixError::Save {
+        attempts: err.attempts,
+        error: err.error,
+    })?;
+
+    Ok(Converted::Fixed {
+        output_path: PathBuf::from(output_path),
+        digest: options.digest.map(|algorithm| algorithm.digest(&fixed)),
+        changed_pixels,
+        extra_outputs: Vec::new(),
+        trim,
+    })
+}
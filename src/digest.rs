@@ -0,0 +1,32 @@
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Copy)]
+pub enum DigestAlgorithm {
+    Sha256,
+    Blake3,
+}
+
+impl std::str::FromStr for DigestAlgorithm {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "sha256" => Ok(DigestAlgorithm::Sha256),
+            "blake3" => Ok(DigestAlgorithm::Blake3),
+            _ => Err(()),
+        }
+    }
+}
+
+impl DigestAlgorithm {
+    /// Hex encoded hash of `bytes`.
+    pub fn digest(&self, bytes: &[u8]) -> String {
+        match self {
+            DigestAlgorithm::Sha256 => Sha256::digest(bytes)
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+            DigestAlgorithm::Blake3 => blake3::hash(bytes).to_hex().to_string(),
+        }
+    }
+}
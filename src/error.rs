@@ -0,0 +1,133 @@
+use std::{fmt, io, path::PathBuf, time::Duration};
+
+use image::ImageError;
+
+/// Why a single file couldn't be fixed. These are reported per file and
+/// never abort the rest of the batch.
+pub enum PixfixError {
+    Read {
+        attempts: u32,
+        error: io::Error,
+    },
+    /// The file couldn't be locked against other runs fixing it.
+    Lock(io::Error),
+    Decode(ImageError),
+    /// A pixel buffer of the wrong size for its dimensions.
+    BufferSize {
+        expected: usize,
+        actual: usize,
+    },
+    /// The file was cut short, only the rows before `row` are complete.
+    Truncated {
+        row: u32,
+        height: u32,
+    },
+    /// The size doesn't match `--check-dimensions`, or with `expected` of
+    /// `None` one of the sides isn't a power of two.
+    Dimensions {
+        width: u32,
+        height: u32,
+        expected: Option<(u32, u32)>,
+    },
+    NothingToFix,
+    Triangulate(spade::InsertionError),
+    Encode(ImageError),
+    ClearReadOnly(io::Error),
+    /// The input couldn't be copied to `--backup-store`, so it was kept.
+    Backup(io::Error),
+    /// `--verify` read back something else than what was saved to `path`.
+    Verify {
+        path: PathBuf,
+        problem: String,
+    },
+    Save {
+        attempts: u32,
+        error: io::Error,
+    },
+    /// Processing took longer than `--timeout-per-file`.
+    TimedOut(Duration),
+    /// A zip archive couldn't be read or written as a whole.
+    Archive(String),
+    /// A `data:` URI argument that couldn't be decoded.
+    InvalidDataUri(String),
+    /// The batch was cancelled before this file was started.
+    Cancelled,
+    /// Fixing the file crashed.
+    Panicked(String),
+}
+
+impl fmt::Display for PixfixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PixfixError::Read { attempts, error } => write!(
+                f,
+                "An error occured reading the image after {} attempts: {}",
+                attempts, error
+            ),
+            PixfixError::Lock(error) => {
+                write!(f, "An error occured locking the image: {}", error)
+            }
+            PixfixError::Decode(error) => {
+                write!(f, "An error occured decoding the image: {}", error)
+            }
+            PixfixError::BufferSize { expected, actual } => write!(
+                f,
+                "The buffer holds {} bytes, an RGBA image of that size needs {}",
+                actual, expected
+            ),
+            PixfixError::Truncated { row, height } => write!(
+                f,
+                "The image is truncated at row {} of {}, pass --tolerant to fix the rows before it",
+                row, height
+            ),
+            PixfixError::Dimensions {
+                width,
+                height,
+                expected: Some((expected_width, expected_height)),
+            } => write!(
+                f,
+                "The image is {}x{}, expected {}x{} (--check-dimensions)",
+                width, height, expected_width, expected_height
+            ),
+            PixfixError::Dimensions {
+                width,
+                height,
+                expected: None,
+            } => write!(
+                f,
+                "The image is {}x{}, expected power of two sides (--check-power-of-two)",
+                width, height
+            ),
+            PixfixError::NothingToFix => write!(f, "No transparent pixels to fix"),
+            PixfixError::Triangulate(error) => {
+                write!(f, "An error occured triangulating the border: {:?}", error)
+            }
+            PixfixError::Encode(error) => {
+                write!(f, "An error occured encoding the image: {}", error)
+            }
+            PixfixError::ClearReadOnly(error) => {
+                write!(f, "An error occured clearing the read-only flag: {}", error)
+            }
+            PixfixError::Backup(error) => {
+                write!(f, "An error occured backing up the image: {}", error)
+            }
+            PixfixError::Verify { path, problem } => {
+                write!(f, "Verifying \"{}\" failed, {}", path.display(), problem)
+            }
+            PixfixError::Save { attempts, error } => write!(
+                f,
+                "An error occured saving the image after {} attempts: {}",
+                attempts, error
+            ),
+            PixfixError::TimedOut(limit) => write!(
+                f,
+                "Timed out after {} seconds (--timeout-per-file)",
+                limit.as_secs_f64()
+            ),
+            PixfixError::Archive(error) => write!(f, "{}", error),
+            PixfixError::InvalidDataUri(error) => write!(f, "Invalid data URI: {}", error),
+            PixfixError::Cancelled => write!(f, "Cancelled"),
+            PixfixError::Panicked(error) => write!(f, "Fixing the image crashed: {}", error),
+        }
+    }
+}
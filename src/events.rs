@@ -0,0 +1,109 @@
+//! `--events`: newline delimited JSON on stdout as things happen, for tools
+//! that wrap Pixfix and want to show live progress.
+//!
+//! Every line is one object with an `event` field:
+//!
+//! - `{"event":"start","total":N}` once the files to fix are known.
+//! - `{"event":"file","path":...,"status":...,"message":...}` for every file
+//!   as it finishes, with `pixels`, `output` and `digest` when there are any,
+//!   and the `x`, `y`, `width` and `height` of the area `--trim` kept as
+//!   `trim`.
+//!   `status` is one of `fixed`, `no_alpha`, `failed`, `clean`, `dirty` or
+//!   `skipped`.
+//! - `{"event":"progress","done":N,"total":N,"seconds":S}` every second
+//!   while files are being fixed.
+//! - `{"event":"done",...}` with the counts of the summary and `seconds`.
+
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::{
+    convert::Rect,
+    output,
+    report::{Status, Summary},
+};
+
+/// What a file event tells besides its status, when there is anything.
+#[derive(Default)]
+pub struct Details {
+    /// Pixels changed, or that need changing for `--check`.
+    pub pixels: Option<usize>,
+    /// The saved file, or the fixed data URI.
+    pub output: Option<String>,
+    pub digest: Option<String>,
+    /// The area of the input `--trim` cropped the image to.
+    pub trim: Option<Rect>,
+}
+
+/// Writes one event and flushes it, so consumers see it straight away.
+fn emit(event: Value) {
+    output::print_result(format_args!("{}", event));
+}
+
+pub fn start(total: usize) {
+    emit(json!({ "event": "start", "total": total }));
+}
+
+pub fn file(path: &Path, status: Status, message: &str, details: &Details) {
+    let mut event = json!({
+        "event": "file",
+        "path": path.to_string_lossy(),
+        "status": status.name(),
+        "message": message,
+    });
+
+    let fields = [
+        ("pixels", details.pixels.map(Value::from)),
+        ("output", details.output.clone().map(Value::from)),
+        ("digest", details.digest.clone().map(Value::from)),
+        (
+            "trim",
+            details.trim.map(|trim| {
+                json!({
+                    "x": trim.x,
+                    "y": trim.y,
+                    "width": trim.width,
+                    "height": trim.height,
+                })
+            }),
+        ),
+    ];
+
+    for (name, value) in fields {
+        if let Some(value) = value {
+            event[name] = value;
+        }
+    }
+
+    emit(event);
+}
+
+pub fn progress(done: usize, total: usize, seconds: f32) {
+    emit(json!({
+        "event": "progress",
+        "done": done,
+        "total": total,
+        "seconds": seconds,
+    }));
+}
+
+pub fn done(summary: &Summary, seconds: f32) {
+    emit(json!({
+        "event": "done",
+        "processed": summary.processed(),
+        "fixed": summary.fixed,
+        "failed": summary.failed,
+        "no_alpha": summary.no_alpha,
+        "read_only": summary.read_only,
+        "oversized": summary.oversized,
+        "duplicates": summary.duplicates,
+        "ignored": summary.ignored,
+        "clean": summary.clean,
+        "dirty": summary.dirty,
+        "copied": summary.copied,
+        "linked": summary.linked,
+        "resumed": summary.resumed,
+        "seconds": seconds,
+    }));
+}
@@ -0,0 +1,194 @@
+//! `pixfix extract-alpha`, which saves the alpha channel of images as
+//! grayscale PNGs for debugging, and with `--silhouette` the black and white
+//! mask of the pixels the fill treats as colored, for baking alpha tests in
+//! an engine. The inputs are never changed.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use image::{DynamicImage, ImageBuffer, Luma};
+
+use crate::{
+    convert::{self, Image16},
+    diagnostic,
+    error::PixfixError,
+    files, human,
+    options::{self, Options},
+};
+
+/// What `extract-alpha` saves for every image.
+#[derive(Clone, Copy, Default)]
+pub struct Extract {
+    /// Also save the silhouette next to the alpha channel.
+    pub silhouette: bool,
+}
+
+/// Splits `--silhouette` from the options used to find the files.
+pub fn parse_extract_args(args: Vec<OsString>) -> (Extract, Vec<OsString>) {
+    let mut extract = Extract::default();
+    let mut rest = Vec::new();
+
+    for arg in args {
+        match arg.to_str() {
+            Some("--silhouette") => extract.silhouette = true,
+            _ => rest.push(arg),
+        }
+    }
+
+    (extract, rest)
+}
+
+/// Where the image saved as `<name>_<kind>.png` for `path` goes: next to it,
+/// or in the `--output` folder.
+fn extract_path(path: &Path, kind: &str, options: &Options) -> PathBuf {
+    let named = match kind {
+        "alpha" => convert::alpha_path(path),
+        _ => {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            path.with_file_name(format!("{}_{}.png", stem, kind))
+        }
+    };
+
+    match (&options.output, named.file_name()) {
+        (Some(output), Some(name)) => output.join(name),
+        _ => named,
+    }
+}
+
+/// White where the fill takes colors from, black where it fills.
+fn silhouette(img: &Image16) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    let colored = convert::colored_mask(img);
+
+    ImageBuffer::from_fn(img.width(), img.height(), |x, y| {
+        let index = y as usize * img.width() as usize + x as usize;
+        Luma([if colored[index] { u8::MAX } else { 0 }])
+    })
+}
+
+/// Saves the alpha channel of `path`, and its silhouette when asked,
+/// returning the files written, or `None` when it has no alpha channel.
+fn extract_file(
+    path: &Path,
+    extract: Extract,
+    options: &Options,
+) -> Result<Option<Vec<PathBuf>>, PixfixError> {
+    let bytes = convert::read_input(path, options)?;
+    let decoded = convert::decode(&bytes, path, options)?;
+
+    if !decoded.color().has_alpha() {
+        return Ok(None);
+    }
+
+    let sixteen_bit = convert::is_16_bit(&decoded);
+    let mut img = decoded.into_rgba16();
+
+    // The alpha the fill sees, like the fixer classifies it.
+    if options.alpha_invert {
+        convert::invert_alpha(&mut img);
+    }
+
+    let alpha = ImageBuffer::from_fn(img.width(), img.height(), |x, y| {
+        Luma([img.get_pixel(x, y).0[3]])
+    });
+    let alpha = if sixteen_bit {
+        DynamicImage::ImageLuma16(alpha)
+    } else {
+        DynamicImage::ImageLuma8(DynamicImage::ImageLuma16(alpha).to_luma8())
+    };
+
+    let mut images = vec![("alpha", alpha)];
+
+    if extract.silhouette {
+        images.push(("silhouette", DynamicImage::ImageLuma8(silhouette(&img))));
+    }
+
+    let mut written = Vec::new();
+
+    for (kind, image) in images {
+        let output = convert::png_options(options)
+            .encode(&image)
+            .map_err(PixfixError::Encode)?;
+        let output_path = extract_path(path, kind, options);
+
+        convert::save(&output_path, &output, options)?;
+        written.push(output_path);
+    }
+
+    Ok(Some(written))
+}
+
+/// Extracts the alpha of every image in `args` on `--jobs` threads,
+/// printing what was written for each. Returns whether all of them worked.
+pub fn extract_alpha(args: Vec<OsString>) -> Result<bool, String> {
+    let (extract, args) = parse_extract_args(args);
+    let (mut options, args) = options::parse_args(args)?;
+
+    // The inputs are only read, so read-only files are as good as any.
+    options.check = true;
+
+    if let Some(output) = &options.output {
+        std::fs::create_dir_all(output)
+            .map_err(|err| format!("Unable to create \"{}\": {}", output.display(), err))?;
+    }
+
+    let files = files::resolve_files(args, &options).files;
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+
+    std::thread::scope(|scope| {
+        for _ in 0..options.jobs.clamp(1, files.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = files.get(index) else {
+                    break;
+                };
+
+                let result = extract_file(path, extract, &options);
+                results
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .push((index, result));
+            });
+        }
+    });
+
+    let mut results = results
+        .into_inner()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    results.sort_by_key(|(index, _)| *index);
+
+    let (mut extracted, mut failed) = (0, 0);
+
+    for (index, result) in results {
+        let path = &files[index];
+
+        match result {
+            Ok(Some(written)) => {
+                extracted += 1;
+
+                for output in written {
+                    human!("{} -> {}", path.display(), output.display());
+                }
+            }
+            Ok(None) => human!("Ignoring \"{}\" - No alpha channel", path.display()),
+            Err(err) => {
+                diagnostic!("Unable to extract \"{}\": {}", path.display(), err);
+                failed += 1;
+            }
+        }
+    }
+
+    human!(
+        "\nExtracted the alpha of {} of {} images",
+        extracted,
+        files.len()
+    );
+
+    Ok(failed == 0)
+}
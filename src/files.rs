@@ -0,0 +1,668 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fs::Metadata,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{Condvar, Mutex},
+};
+
+use glob::MatchOptions;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    archive::is_zip_file,
+    backup,
+    chunks::PNG_SIGNATURE,
+    diagnostic,
+    digest::DigestAlgorithm,
+    ignore::Ignores,
+    lock::LOCK_FILE,
+    options::{FormatDetection, Options, BYTES_PER_MB},
+    paths::{self, io_path, Ancestors},
+    prescan,
+};
+
+static EXTENSIONS: &[&str] = &["png"];
+
+#[derive(Default)]
+pub struct ResolvedFiles {
+    pub files: Vec<PathBuf>,
+    /// Every file that was a candidate for fixing, including the ignored ones.
+    pub all_files: usize,
+    pub read_only: usize,
+    /// Files over `--max-file-size` or `--max-dimensions`.
+    pub oversized: usize,
+    /// Files with the same content as one added before, for `--deduplicate`.
+    pub duplicates: usize,
+    /// Files of a type Pixfix doesn't fix, which aren't failures.
+    pub ignored: usize,
+    /// Images the pre-scan found without an alpha channel, never decoded.
+    pub no_alpha: Vec<PathBuf>,
+    /// Files that were given or found but won't be fixed, with the reason.
+    pub skipped: Vec<(PathBuf, String)>,
+    seen: HashSet<PathBuf>,
+    /// The first file added with each content hash.
+    hashes: HashMap<String, PathBuf>,
+    excluded: Excluded,
+    /// Where files go as they are found, for `resolve_files_streamed`.
+    stream: Option<UnboundedSender<PathBuf>>,
+}
+
+/// Folders scans never pick up, resolved.
+#[derive(Clone, Default)]
+struct Excluded {
+    /// `--output`.
+    output: Option<PathBuf>,
+    /// The `--journal` folder.
+    journal: Option<PathBuf>,
+}
+
+impl Excluded {
+    /// Whether `path` is the output, a lock file or a backup store, none of
+    /// which belong to the scan.
+    fn contains(&self, path: &Path) -> bool {
+        let canonical = paths::canonical(path);
+
+        self.output.as_ref() == Some(&canonical)
+            || self.journal.as_ref() == Some(&canonical)
+            || path.file_name() == Some(LOCK_FILE.as_ref())
+            || path.file_name() == Some(backup::STORE.as_ref())
+    }
+}
+
+/// Whether the extension is one we accept, ignoring case so `IMAGE.PNG` counts.
+pub fn is_png_file(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .and_then(|str| str.to_str())
+        .unwrap_or_default();
+
+    EXTENSIONS
+        .iter()
+        .any(|accepted| accepted.eq_ignore_ascii_case(extension))
+}
+
+/// Whether the file starts with the PNG signature, whatever it is called.
+pub fn has_png_signature(path: &Path) -> bool {
+    let mut signature = [0; PNG_SIGNATURE.len()];
+
+    std::fs::File::open(io_path(path))
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .is_ok_and(|()| signature == PNG_SIGNATURE)
+}
+
+/// How far `path` is over `--max-file-size`, or `None` when it isn't.
+/// Files whose size can't be read are left for the fix to report.
+/// `--max-dimensions` is left to the pre-scan.
+fn exceeded_limit(path: &Path, options: &Options) -> Option<String> {
+    if let Some(max_file_size) = options.max_file_size {
+        let size = std::fs::metadata(io_path(path)).map_or(0, |metadata| metadata.len());
+
+        if size > max_file_size {
+            return Some(format!(
+                "{:.1} MB exceeds the limit of {:.1} MB",
+                size as f64 / BYTES_PER_MB,
+                max_file_size as f64 / BYTES_PER_MB
+            ));
+        }
+    }
+
+    None
+}
+
+/// Why `path` isn't an image Pixfix accepts, or `None` when it is.
+fn rejection(path: &Path, options: &Options) -> Option<String> {
+    if is_zip_file(path) {
+        return None;
+    }
+
+    let extension = is_png_file(path);
+
+    let accepted = match options.format_detection {
+        FormatDetection::Extension => extension,
+        FormatDetection::Magic => has_png_signature(path),
+        FormatDetection::Both => extension && has_png_signature(path),
+    };
+
+    if accepted {
+        return None;
+    }
+
+    Some(match options.format_detection {
+        FormatDetection::Both if extension => "Its contents are not a PNG image".into(),
+        FormatDetection::Magic => "It is not a PNG image or zip archive".into(),
+        _ => format!("Only {}|zip are accepted", EXTENSIONS.join("|")),
+    })
+}
+
+/// Whether `path`, found while scanning `root`, matches one of the
+/// `--include` patterns, or there are none.
+fn is_included(path: &Path, root: &Path, options: &Options) -> bool {
+    if options.include.is_empty() {
+        return true;
+    }
+
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let relative = relative.to_string_lossy().replace('\\', "/");
+    let match_options = MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+
+    options
+        .include
+        .iter()
+        .any(|pattern| pattern.matches_with(&relative, match_options))
+}
+
+impl ResolvedFiles {
+    /// Candidates that failed before they could be fixed, e.g. files whose
+    /// metadata couldn't be read.
+    pub fn failed(&self) -> usize {
+        self.all_files.saturating_sub(
+            self.files.len()
+                + self.read_only
+                + self.oversized
+                + self.duplicates
+                + self.ignored
+                + self.no_alpha.len(),
+        )
+    }
+
+    pub fn skip(&mut self, path: &Path, reason: String) {
+        diagnostic!("Ignoring \"{}\" - {}!", path.display(), reason);
+        self.skipped.push((path.to_path_buf(), reason));
+    }
+
+    pub fn add_file(&mut self, path: PathBuf, options: &Options) {
+        // The same file can be reached through several arguments, e.g. a
+        // folder and a file inside it. Fixing it twice races on the save.
+        let canonical = std::fs::canonicalize(io_path(&path)).unwrap_or_else(|_| path.clone());
+
+        if !self.seen.insert(canonical) {
+            if options.verbose {
+                diagnostic!("Ignoring \"{}\" - It was already added!", path.display());
+            }
+
+            return;
+        }
+
+        self.all_files += 1;
+
+        if let Some(reason) = rejection(&path, options) {
+            self.skip(&path, reason);
+            self.ignored += 1;
+
+            return;
+        }
+
+        if let Some(reason) = exceeded_limit(&path, options) {
+            self.skip(&path, reason);
+            self.oversized += 1;
+
+            return;
+        }
+
+        if options.blocked_by_read_only(&path) {
+            self.skip(
+                &path,
+                "It is read-only, skipped (use --force-writable to clear the flag)".into(),
+            );
+            self.read_only += 1;
+
+            return;
+        }
+
+        if options.deduplicate {
+            if let Some(first) = self.duplicate_of(&path) {
+                self.skip(
+                    &path,
+                    format!("It is a duplicate of \"{}\"", first.display()),
+                );
+                self.duplicates += 1;
+
+                return;
+            }
+        }
+
+        match self.stream.clone() {
+            // There is no list to pre-scan once the scan is done, so each
+            // file is checked as it is found.
+            Some(stream) => {
+                if prescan::prescan_file(self, path.clone(), options) {
+                    // The receiver only goes away when the run is over.
+                    let _ = stream.send(path);
+                }
+            }
+            None => self.files.push(path),
+        }
+    }
+
+    /// The file added before `path` with the same content, if there is one.
+    /// Files that can't be read are left for the fix to report.
+    fn duplicate_of(&mut self, path: &Path) -> Option<PathBuf> {
+        let bytes = std::fs::read(io_path(path)).ok()?;
+        let hash = DigestAlgorithm::Sha256.digest(&bytes);
+
+        match self.hashes.get(&hash) {
+            Some(first) => Some(first.clone()),
+            None => {
+                self.hashes.insert(hash, path.to_path_buf());
+                None
+            }
+        }
+    }
+
+    /// Adds the files of `root`, listing it and, with `--recursive`, every
+    /// folder below it the scan enters on `--jobs` threads. Reading folders
+    /// and the metadata of their entries is what makes scans of large trees
+    /// slow, especially on network shares, so the files of each folder are
+    /// added as soon as it is listed rather than once the whole tree is.
+    fn add_root(&mut self, root: &Path, ignores: Ignores, options: &Options) {
+        let threads = if options.recursive {
+            options.jobs.max(1)
+        } else {
+            1
+        };
+        let walk = Walk {
+            state: Mutex::new(WalkState {
+                queue: vec![(root.to_path_buf(), ignores, Ancestors::new(root))],
+                pending: 1,
+                listings: HashMap::new(),
+            }),
+            changed: Condvar::new(),
+            excluded: self.excluded.clone(),
+        };
+
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| walk.list_folders(options));
+            }
+
+            self.add_dir(root, root, options, &walk);
+        });
+    }
+
+    /// Adds the files of `path` once `walk` listed it, in the order the
+    /// folder lists them, going into subfolders as they come like a serial
+    /// scan.
+    fn add_dir(&mut self, root: &Path, path: &Path, options: &Options, walk: &Walk) {
+        let Some(entries) = walk.take(path) else {
+            self.skip(path, "An error occured reading directory".into());
+            self.all_files += 1;
+
+            return;
+        };
+
+        for found in entries {
+            let path = found.path;
+
+            let Some(metadata) = found.metadata else {
+                self.skip(&path, "An error occured reading file metadata".into());
+                self.all_files += 1;
+
+                continue;
+            };
+
+            if found.excluded {
+                continue;
+            }
+
+            if found.ignored {
+                if options.verbose {
+                    diagnostic!("Ignoring \"{}\" - It is in an ignore file!", path.display());
+                }
+
+                continue;
+            }
+
+            if metadata.is_dir() && options.recursive {
+                if found.entered.is_none() {
+                    if options.verbose {
+                        diagnostic!(
+                            "Ignoring \"{}\" - It links back to a folder above it!",
+                            path.display()
+                        );
+                    }
+
+                    continue;
+                }
+
+                self.add_dir(root, &path, options, walk);
+            }
+
+            if !metadata.is_file() || !is_included(&path, root, options) {
+                continue;
+            }
+
+            self.add_file(path, options);
+        }
+    }
+}
+
+/// An entry of a folder, read by `Walk::list`.
+struct Found {
+    path: PathBuf,
+    /// `None` when it couldn't be read.
+    metadata: Option<Metadata>,
+    /// See `Excluded::contains`.
+    excluded: bool,
+    /// Matched by an ignore file.
+    ignored: bool,
+    /// For the folders a recursive scan enters, the folders above them,
+    /// `None` for the ones that would loop back to one of those.
+    entered: Option<Ancestors>,
+}
+
+/// The folders of a scan being listed on several threads, while
+/// `ResolvedFiles::add_dir` takes the listings as they are done.
+struct Walk {
+    state: Mutex<WalkState>,
+    /// Signalled whenever a folder is queued or listed.
+    changed: Condvar,
+    excluded: Excluded,
+}
+
+struct WalkState {
+    /// The folders still to list, with the ignore rules and the folders
+    /// above them.
+    queue: Vec<(PathBuf, Ignores, Ancestors)>,
+    /// Folders queued or being listed. The walk is done at zero.
+    pending: usize,
+    /// The entries of the folders listed and not taken yet, `None` for the
+    /// ones that couldn't be read.
+    listings: HashMap<PathBuf, Option<Vec<Found>>>,
+}
+
+impl Walk {
+    fn lock(&self) -> std::sync::MutexGuard<'_, WalkState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn wait<'a>(
+        &self,
+        state: std::sync::MutexGuard<'a, WalkState>,
+    ) -> std::sync::MutexGuard<'a, WalkState> {
+        self.changed
+            .wait(state)
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Waits until `dir` is listed and takes its entries, `None` when it
+    /// can't be read.
+    fn take(&self, dir: &Path) -> Option<Vec<Found>> {
+        let mut state = self.lock();
+
+        loop {
+            if let Some(listing) = state.listings.remove(dir) {
+                return listing;
+            }
+
+            state = self.wait(state);
+        }
+    }
+
+    /// Lists queued folders until there are none left, queueing the
+    /// subfolders the scan enters with `--recursive`.
+    fn list_folders(&self, options: &Options) {
+        loop {
+            let mut state = self.lock();
+
+            let (dir, mut ignores, ancestors) = loop {
+                if let Some(task) = state.queue.pop() {
+                    break task;
+                }
+
+                if state.pending == 0 {
+                    return;
+                }
+
+                state = self.wait(state);
+            };
+            drop(state);
+
+            let listing = self.list(&dir, &mut ignores, &ancestors, options);
+            let subdirs: Vec<(PathBuf, Ancestors)> = match &listing {
+                Some(found) => found
+                    .iter()
+                    .filter(|found| !found.excluded && !found.ignored)
+                    .filter_map(|found| Some((found.path.clone(), found.entered.clone()?)))
+                    .collect(),
+                None => Vec::new(),
+            };
+
+            // Reversed, so the first subfolder is listed first, in the
+            // depth-first order `ResolvedFiles::add_dir` takes them in.
+            let mut state = self.lock();
+            state.pending += subdirs.len();
+            state.pending -= 1;
+            state.queue.extend(
+                subdirs
+                    .into_iter()
+                    .rev()
+                    .map(|(subdir, ancestors)| (subdir, ignores.clone(), ancestors)),
+            );
+            state.listings.insert(dir, listing);
+            self.changed.notify_all();
+        }
+    }
+
+    /// Reads the entries of `dir`, with the rules of its ignore files added
+    /// to `ignores`, or `None` when it can't be read. `ancestors` are the
+    /// folders above `dir`, it included.
+    fn list(
+        &self,
+        dir: &Path,
+        ignores: &mut Ignores,
+        ancestors: &Ancestors,
+        options: &Options,
+    ) -> Option<Vec<Found>> {
+        let entries = std::fs::read_dir(io_path(dir)).ok()?;
+        ignores.push(dir);
+
+        let found = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let path = entry.path();
+                let metadata = std::fs::metadata(&path).ok();
+                let excluded = self.excluded.contains(&path);
+                let ignored = !excluded
+                    && metadata
+                        .as_ref()
+                        .is_some_and(|metadata| ignores.is_ignored(&path, metadata.is_dir()));
+                let entered = match &metadata {
+                    Some(metadata) if metadata.is_dir() && options.recursive => {
+                        ancestors.enter(&path)
+                    }
+                    _ => None,
+                };
+
+                Found {
+                    path,
+                    metadata,
+                    excluded,
+                    ignored,
+                    entered,
+                }
+            })
+            .collect();
+
+        Some(found)
+    }
+}
+
+pub fn resolve_files(args: Vec<OsString>, options: &Options) -> ResolvedFiles {
+    resolve(args, options, None)
+}
+
+/// Like `resolve_files`, but sends every file to fix to `stream` as soon as
+/// it is found, already pre-scanned, so fixing can start before the scan
+/// is done. `files` of the result still lists them.
+pub fn resolve_files_streamed(
+    args: Vec<OsString>,
+    options: &Options,
+    stream: UnboundedSender<PathBuf>,
+) -> ResolvedFiles {
+    resolve(args, options, Some(stream))
+}
+
+fn resolve(
+    args: Vec<OsString>,
+    options: &Options,
+    stream: Option<UnboundedSender<PathBuf>>,
+) -> ResolvedFiles {
+    let mut resolved = ResolvedFiles {
+        excluded: Excluded {
+            output: options.output.as_deref().map(paths::canonical),
+            journal: options.journal.as_deref().map(paths::canonical),
+        },
+        stream,
+        ..Default::default()
+    };
+
+    for arg in args {
+        let path = PathBuf::from(arg);
+
+        let metadata = match std::fs::metadata(io_path(&path)) {
+            Ok(data) => data,
+            Err(_) => {
+                resolved.skip(&path, "It does not exist".into());
+                // A path given by name that isn't there is a failure, like
+                // a folder that can't be read.
+                resolved.all_files += 1;
+
+                continue;
+            }
+        };
+
+        if metadata.is_file() {
+            resolved.add_file(path, options);
+        } else if metadata.is_dir() {
+            let ignores = Ignores::new(&path, options.respect_gitignore);
+            resolved.add_root(&path, ignores, options);
+        }
+    }
+
+    // Dropping the sender tells the receiver the scan is done.
+    resolved.stream = None;
+
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{fringed_sprite, TempDir};
+
+    /// PNGs next to other files in nested folders, one more PNG reached
+    /// through a symlinked folder and another symlink to a PNG of the tree.
+    fn mixed() -> (TempDir, TempDir) {
+        let (dir, outside) = (
+            TempDir::new("walk").unwrap(),
+            TempDir::new("walk-outside").unwrap(),
+        );
+        let root = dir.path();
+
+        std::fs::create_dir_all(root.join("nested/deeper")).unwrap();
+        for name in ["a.png", "nested/b.png", "nested/deeper/c.PNG"] {
+            std::fs::write(root.join(name), fringed_sprite()).unwrap();
+        }
+        for name in ["notes.txt", "nested/readme.md"] {
+            std::fs::write(root.join(name), "not an image").unwrap();
+        }
+        std::fs::write(outside.path().join("d.png"), fringed_sprite()).unwrap();
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("nested/b.png"), root.join("link.png")).unwrap();
+            std::os::unix::fs::symlink(outside.path(), root.join("linked")).unwrap();
+        }
+
+        (dir, outside)
+    }
+
+    fn names(resolved: &ResolvedFiles) -> Vec<String> {
+        let mut names: Vec<String> = resolved
+            .files
+            .iter()
+            .map(|path| {
+                let canonical = std::fs::canonicalize(path).unwrap();
+                canonical
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        names.sort();
+
+        names
+    }
+
+    #[test]
+    fn a_folder_lists_only_its_own_pngs() {
+        let (dir, _outside) = mixed();
+        let resolved = resolve_files(vec![dir.path().into()], &Options::default());
+
+        if cfg!(unix) {
+            // The symlink is the only way to `b.png` without going into
+            // `nested`.
+            assert_eq!(names(&resolved), ["a.png", "b.png"]);
+            assert_eq!(resolved.all_files, 3);
+        } else {
+            assert_eq!(names(&resolved), ["a.png"]);
+            assert_eq!(resolved.all_files, 2);
+        }
+        assert_eq!(resolved.ignored, 1);
+        assert_eq!(resolved.failed(), 0);
+    }
+
+    #[test]
+    fn a_recursive_walk_finds_every_png_once() {
+        let (dir, _outside) = mixed();
+        let options = Options {
+            recursive: true,
+            jobs: 4,
+            ..Default::default()
+        };
+        let resolved = resolve_files(vec![dir.path().into()], &options);
+
+        if cfg!(unix) {
+            assert_eq!(names(&resolved), ["a.png", "b.png", "c.PNG", "d.png"]);
+            assert_eq!(resolved.all_files, 6);
+        } else {
+            assert_eq!(names(&resolved), ["a.png", "b.png", "c.PNG"]);
+            assert_eq!(resolved.all_files, 5);
+        }
+        assert_eq!(resolved.ignored, 2);
+        assert_eq!(resolved.failed(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_recursive_walk_does_not_follow_symlink_loops() {
+        let dir = TempDir::new("walk-loop").unwrap();
+        let root = dir.path();
+
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(root.join("a.png"), fringed_sprite()).unwrap();
+        std::fs::write(root.join("sub/b.png"), fringed_sprite()).unwrap();
+        std::os::unix::fs::symlink("..", root.join("sub/up")).unwrap();
+        std::os::unix::fs::symlink(".", root.join("self")).unwrap();
+
+        let options = Options {
+            recursive: true,
+            jobs: 4,
+            ..Default::default()
+        };
+        let resolved = resolve_files(vec![root.into()], &options);
+
+        assert_eq!(names(&resolved), ["a.png", "b.png"]);
+        assert_eq!(resolved.all_files, 2);
+        assert!(resolved.skipped.is_empty());
+        assert_eq!(resolved.failed(), 0);
+    }
+}
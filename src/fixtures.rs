@@ -0,0 +1,258 @@
+//! Images generated for `pixfix self-test` and the tests, one of every kind
+//! of input that tends to behave differently, so no binary fixtures have to
+//! be checked in.
+
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use image::{DynamicImage, ImageBuffer, ImageOutputFormat, Rgb, Rgba};
+
+use crate::{
+    chunks::{self, Chunk},
+    convert::Image16,
+};
+
+/// What fixing a fixture has to do.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Expect {
+    /// Nothing is transparent, or nothing is colored, so the fix fails
+    /// without touching the file.
+    NothingToFix,
+    /// Every transparent pixel takes the color of its nearest border pixel,
+    /// while alpha and the colored pixels stay as they are.
+    NearestBorder,
+}
+
+/// A generated PNG and what fixing it has to do.
+pub struct Fixture {
+    pub name: &'static str,
+    pub png: Vec<u8>,
+    pub expect: Expect,
+}
+
+/// `img` encoded as a PNG.
+pub fn encode(img: DynamicImage) -> Vec<u8> {
+    let mut png = Cursor::new(Vec::new());
+    img.write_to(&mut png, ImageOutputFormat::Png)
+        .expect("encoding a generated image can't fail");
+
+    png.into_inner()
+}
+
+/// A disc of varying colors in the middle of a transparent square, with
+/// half transparent pixels around its edge.
+pub fn sprite(size: u32, color: impl Fn(u32, u32) -> [u16; 3]) -> Image16 {
+    let center = f64::from(size) / 2.0;
+    let radius = f64::from(size) / 4.0;
+
+    ImageBuffer::from_fn(size, size, |x, y| {
+        let distance = (f64::from(x) + 0.5 - center).hypot(f64::from(y) + 0.5 - center);
+        let [r, g, b] = color(x, y);
+
+        match distance {
+            distance if distance <= radius - 1.0 => Rgba([r, g, b, u16::MAX]),
+            distance if distance <= radius => Rgba([r, g, b, u16::MAX / 2]),
+            _ => Rgba([0, 0, 0, 0]),
+        }
+    })
+}
+
+/// A 16x16 image with a palette of four colors, the first transparent, and
+/// a colored block in the middle. The image encoder can't write palettes,
+/// so the file is put together by hand, its data stored uncompressed.
+pub fn palette_png() -> Vec<u8> {
+    let (width, height) = (16u32, 16u32);
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per index, color type 3, default compression, filter and no
+    // interlacing.
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+
+    let mut rows = Vec::new();
+    for y in 0..height {
+        // Every row starts with filter type 0.
+        rows.push(0);
+        rows.extend((0..width).map(|x| match (x, y) {
+            (4..=11, 4..=7) => 1,
+            (4..=11, 8..=11) if x < 8 => 2,
+            (4..=11, 8..=11) => 3,
+            _ => 0,
+        }));
+    }
+
+    // A zlib stream of one stored deflate block, then the Adler-32 of it.
+    let mut data = vec![0x78, 0x01, 1];
+    data.extend_from_slice(&(rows.len() as u16).to_le_bytes());
+    data.extend_from_slice(&(!(rows.len() as u16)).to_le_bytes());
+    data.extend_from_slice(&rows);
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in &rows {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    data.extend_from_slice(&((b << 16) | a).to_be_bytes());
+
+    chunks::write_chunks(&[
+        Chunk {
+            kind: *b"IHDR",
+            data: header,
+        },
+        Chunk {
+            kind: *b"PLTE",
+            data: vec![0, 0, 0, 220, 40, 40, 40, 200, 60, 30, 60, 230],
+        },
+        Chunk {
+            kind: *b"tRNS",
+            data: vec![0],
+        },
+        Chunk {
+            kind: *b"IDAT",
+            data,
+        },
+        Chunk {
+            kind: *b"IEND",
+            data: Vec::new(),
+        },
+    ])
+}
+
+/// A 16x16 RGB image without alpha, magic pink (`#ff00ff`) but for a
+/// block of two colors in the middle, for `--colorkey`.
+pub fn colorkey_png() -> Vec<u8> {
+    encode(DynamicImage::ImageRgb8(ImageBuffer::from_fn(
+        16,
+        16,
+        |x, y| match (x, y) {
+            (4..=7, 4..=11) => Rgb([220, 40, 40]),
+            (8..=11, 4..=11) => Rgb([40, 200, 60]),
+            _ => Rgb([255, 0, 255]),
+        },
+    )))
+}
+
+/// A transparent image with `count` opaque pixels of different colors
+/// scattered over it, always the same ones. Every one of them borders the
+/// fill, which makes large ones slow to fill.
+pub fn scattered(width: u32, height: u32, count: usize) -> Image16 {
+    let mut img = Image16::new(width, height);
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+
+    for _ in 0..count {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        let (x, y) = ((state >> 40) as u32 % width, (state >> 16) as u32 % height);
+        let color = (state >> 8) as u16 | 1;
+
+        img.put_pixel(
+            x,
+            y,
+            Rgba([color, color.rotate_left(5), color.rotate_left(11), u16::MAX]),
+        );
+    }
+
+    img
+}
+
+/// `img` encoded as an 8-bit PNG.
+pub fn eight_bit(img: Image16) -> Vec<u8> {
+    encode(DynamicImage::ImageRgba8(
+        DynamicImage::ImageRgba16(img).to_rgba8(),
+    ))
+}
+
+/// Colors that differ from pixel to pixel, so every pixel of the fill shows
+/// where it took its color from.
+pub fn gradient(x: u32, y: u32) -> [u16; 3] {
+    [
+        (x * 8 % 256 * 257) as u16,
+        (y * 8 % 256 * 257) as u16,
+        128 * 257,
+    ]
+}
+
+/// The "fringed sprite" fixture, the everyday image to fix.
+pub fn fringed_sprite() -> Vec<u8> {
+    eight_bit(sprite(32, gradient))
+}
+
+/// The images `self-test` fixes, one of every kind of input that tends to
+/// behave differently.
+pub fn fixtures() -> Vec<Fixture> {
+    vec![
+        Fixture {
+            name: "opaque",
+            png: eight_bit(ImageBuffer::from_fn(16, 16, |x, y| {
+                let [r, g, b] = gradient(x, y);
+                Rgba([r, g, b, u16::MAX])
+            })),
+            expect: Expect::NothingToFix,
+        },
+        Fixture {
+            name: "fully transparent",
+            png: eight_bit(Image16::new(16, 16)),
+            expect: Expect::NothingToFix,
+        },
+        Fixture {
+            name: "fringed sprite",
+            png: fringed_sprite(),
+            expect: Expect::NearestBorder,
+        },
+        Fixture {
+            name: "16-bit",
+            // Values 8-bit can't hold, so losing the precision shows.
+            png: encode(DynamicImage::ImageRgba16(sprite(32, |x, y| {
+                [(x * 1999) as u16, (y * 1777) as u16, 12345]
+            }))),
+            expect: Expect::NearestBorder,
+        },
+        Fixture {
+            name: "palette",
+            png: palette_png(),
+            expect: Expect::NearestBorder,
+        },
+        Fixture {
+            name: "huge-ish",
+            png: eight_bit(sprite(1024, |_, _| [200 * 257, 100 * 257, 50 * 257])),
+            expect: Expect::NearestBorder,
+        },
+    ]
+}
+
+/// A folder of its own under the temporary folder, removed with everything
+/// in it when dropped.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> std::io::Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "pixfix-{}-{}-{}",
+            name,
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path)?;
+
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
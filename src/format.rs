@@ -0,0 +1,132 @@
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use image::{
+    codecs::{
+        png::{CompressionType, FilterType, PngEncoder},
+        webp::{WebPEncoder, WebPQuality},
+    },
+    DynamicImage, ImageEncoder, ImageResult,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Png,
+    WebP,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::WebP => "webp",
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "png" => Ok(OutputFormat::Png),
+            "webp" => Ok(OutputFormat::WebP),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Encoder settings for every format Pixfix can write.
+pub struct FormatOptions {
+    pub format: OutputFormat,
+    /// Lossy WebP quality, 0-100. Ignored when `webp_lossless` is set.
+    pub webp_quality: u8,
+    pub webp_lossless: bool,
+    /// PNG compression level, 0-9. The encoder only has three presets, so
+    /// 0-3 map to fast, 4-6 to default and 7-9 to best.
+    pub png_compression: u8,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::Png,
+            webp_quality: 90,
+            webp_lossless: false,
+            png_compression: 6,
+        }
+    }
+}
+
+impl FormatOptions {
+    /// The same encoder settings for another format.
+    pub fn with_format(&self, format: OutputFormat) -> Self {
+        Self {
+            format,
+            webp_quality: self.webp_quality,
+            webp_lossless: self.webp_lossless,
+            png_compression: self.png_compression,
+        }
+    }
+
+    /// Where the fixed image is written. PNG overwrites the input, other
+    /// formats are written next to it with their own extension.
+    pub fn output_path(&self, path: &Path) -> PathBuf {
+        match self.format {
+            OutputFormat::Png => path.to_path_buf(),
+            format => path.with_extension(format.extension()),
+        }
+    }
+
+    fn png_compression_type(&self) -> CompressionType {
+        match self.png_compression {
+            0..=3 => CompressionType::Fast,
+            4..=6 => CompressionType::Default,
+            _ => CompressionType::Best,
+        }
+    }
+
+    pub fn encode(&self, img: &DynamicImage) -> ImageResult<Vec<u8>> {
+        let mut output = Vec::new();
+
+        match self.format {
+            OutputFormat::Png => {
+                // Only the options pick the settings and no time chunk is
+                // written, so the same image always encodes to the same
+                // bytes for build caches.
+                let encoder = PngEncoder::new_with_quality(
+                    Cursor::new(&mut output),
+                    self.png_compression_type(),
+                    FilterType::Adaptive,
+                );
+
+                encoder.write_image(img.as_bytes(), img.width(), img.height(), img.color())?;
+            }
+            OutputFormat::WebP => {
+                // WebP only stores 8 bits per channel.
+                let img = img.to_rgba8();
+
+                #[allow(deprecated)]
+                let encoder = if self.webp_lossless {
+                    WebPEncoder::new_lossless(Cursor::new(&mut output))
+                } else {
+                    WebPEncoder::new_with_quality(
+                        Cursor::new(&mut output),
+                        WebPQuality::lossy(self.webp_quality),
+                    )
+                };
+
+                encoder.write_image(
+                    img.as_raw(),
+                    img.width(),
+                    img.height(),
+                    image::ColorType::Rgba8,
+                )?;
+            }
+        }
+
+        Ok(output)
+    }
+}
@@ -0,0 +1,276 @@
+//! The frames of an animation often share one silhouette while only their
+//! colors change. Where the fill puts which color only depends on which
+//! pixels are transparent, so the steps of a fill are kept and replayed on
+//! every later image with the same alpha mask instead of triangulating again.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use spade::InsertionError;
+
+use crate::{
+    convert::{expired, fill_islands_recording, BorderThinning, Image16},
+    progress::{self, Stage},
+};
+
+/// A filled pixel and the pixel it took its color from, as indices into
+/// the image.
+pub type Step = (u32, u32);
+
+/// How much memory the kept fills may take, the oldest are dropped first.
+const CACHE_BYTES: usize = 256 * 1024 * 1024;
+
+/// The steps of a fill, or `None` when it had nothing to fill from.
+type Plan = Option<Vec<Step>>;
+
+struct Cache {
+    plans: VecDeque<([u8; 32], Arc<Plan>)>,
+    bytes: usize,
+}
+
+static CACHE: Mutex<Cache> = Mutex::new(Cache {
+    plans: VecDeque::new(),
+    bytes: 0,
+});
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Keeps fills from now on, for runs of more than one file. A single file
+/// has nothing to reuse its fill, and `serve` would keep the fills of every
+/// request for as long as it runs.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether `enable` was called.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn plan_bytes(plan: &Plan) -> usize {
+    plan.as_ref()
+        .map_or(0, |steps| steps.len() * std::mem::size_of::<Step>())
+}
+
+/// Identifies a fill by the alpha mask of the image and every option that
+/// changes where colors go. The fill alpha only applies when replaying.
+fn key(img: &Image16, passes: u32, edge_seeds: bool, thinning: BorderThinning) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+
+    hasher.update(&img.width().to_le_bytes());
+    hasher.update(&img.height().to_le_bytes());
+    hasher.update(&passes.to_le_bytes());
+    hasher.update(&[u8::from(edge_seeds)]);
+    hasher.update(
+        &thinning
+            .max_pixels
+            .map_or(u64::MAX, |max| max as u64)
+            .to_le_bytes(),
+    );
+    hasher.update(
+        &thinning
+            .min_distance
+            .map_or(u64::MAX, f64::to_bits)
+            .to_le_bytes(),
+    );
+
+    let mask: Vec<u8> = img
+        .pixels()
+        .map(|color| u8::from(color.0[3] != 0))
+        .collect();
+    hasher.update(&mask);
+
+    *hasher.finalize().as_bytes()
+}
+
+fn cached(key: &[u8; 32]) -> Option<Arc<Plan>> {
+    let cache = CACHE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    cache
+        .plans
+        .iter()
+        .find(|(cached, _)| cached == key)
+        .map(|(_, plan)| plan.clone())
+}
+
+fn keep(key: [u8; 32], plan: Plan) {
+    let bytes = plan_bytes(&plan);
+
+    if bytes > CACHE_BYTES {
+        return;
+    }
+
+    let mut cache = CACHE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    while cache.bytes + bytes > CACHE_BYTES {
+        let Some((_, oldest)) = cache.plans.pop_front() else {
+            break;
+        };
+        cache.bytes -= plan_bytes(&oldest);
+    }
+
+    cache.bytes += bytes;
+    cache.plans.push_back((key, Arc::new(plan)));
+}
+
+/// Fills the pixels of `steps` in order, each from the current color of its
+/// source, exactly like the fill they were recorded from.
+fn replay(img: &mut Image16, steps: &[Step], alpha_fill: u8) -> usize {
+    let fixed_alpha = u16::from(alpha_fill) * 257;
+    let channels: &mut [u16] = img;
+    let mut changed = 0;
+
+    progress::stage(Stage::Filling);
+    progress::filling(steps.len());
+
+    for &(target, source) in steps {
+        let (target, source) = (target as usize * 4, source as usize * 4);
+        let mut fixed = [0; 4];
+        fixed[..3].copy_from_slice(&channels[source..source + 3]);
+        fixed[3] = fixed_alpha;
+
+        if channels[target..target + 4] != fixed {
+            changed += 1;
+        }
+
+        channels[target..target + 4].copy_from_slice(&fixed);
+    }
+
+    progress::filled(steps.len());
+
+    changed
+}
+
+/// `fill_islands`, reusing the fill of an earlier image with the same alpha
+/// mask and options. Fills that fail or run out of time are not kept.
+pub fn fill_islands_cached(
+    img: &mut Image16,
+    passes: u32,
+    alpha_fill: u8,
+    edge_seeds: bool,
+    thinning: BorderThinning,
+) -> Result<Option<usize>, InsertionError> {
+    // Recording every pixel of a fill too large to keep would only take
+    // memory, 8 bytes for each.
+    let pixels = img.width() as usize * img.height() as usize;
+    if pixels * std::mem::size_of::<Step>() > CACHE_BYTES {
+        return fill_islands_recording(img, passes, alpha_fill, edge_seeds, thinning, None);
+    }
+
+    let key = key(img, passes, edge_seeds, thinning);
+
+    if let Some(plan) = cached(&key) {
+        return Ok(plan
+            .as_ref()
+            .as_ref()
+            .map(|steps| replay(img, steps, alpha_fill)));
+    }
+
+    let mut steps = Vec::new();
+    let changed = fill_islands_recording(
+        img,
+        passes,
+        alpha_fill,
+        edge_seeds,
+        thinning,
+        Some(&mut steps),
+    )?;
+
+    // A fill cut short by `--timeout-per-file` only has some of the steps,
+    // and its file is given up on anyway.
+    if !expired() {
+        keep(key, changed.map(|_| steps));
+    }
+
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::{
+        convert::{fill_islands, Deadline},
+        fixtures::{scattered, sprite},
+        options::Options,
+    };
+
+    fn thinned(max_pixels: usize) -> BorderThinning {
+        BorderThinning {
+            max_pixels: Some(max_pixels),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fills_cut_short_are_not_replayed() {
+        // A size no other test uses, so nothing else fills into the cache.
+        let original = scattered(601, 599, 50);
+        let mut expected = original.clone();
+        fill_islands(&mut expected, 1, 0, false, BorderThinning::default()).unwrap();
+
+        {
+            let options = Options {
+                timeout_per_file: Some(Duration::from_nanos(1)),
+                ..Default::default()
+            };
+            let _deadline = Deadline::start(Instant::now(), &options);
+            let mut cut_short = original.clone();
+            fill_islands_cached(&mut cut_short, 1, 0, false, BorderThinning::default()).unwrap();
+
+            assert!(cut_short != expected);
+        }
+
+        let mut img = original.clone();
+        fill_islands_cached(&mut img, 1, 0, false, BorderThinning::default()).unwrap();
+
+        assert!(img == expected);
+    }
+
+    #[test]
+    fn other_options_are_not_replayed() {
+        // A size no other test uses, and frames with the same alpha mask but
+        // their own colors.
+        let frames: Vec<Image16> = (0..2u32)
+            .map(|frame| {
+                sprite(97, move |x, y| {
+                    [(x * 600) as u16, (y * 600) as u16, (frame * 30_000) as u16]
+                })
+            })
+            .collect();
+
+        for (passes, alpha_fill, edge_seeds, thinning) in [
+            (1, 0, false, BorderThinning::default()),
+            (2, 0, false, BorderThinning::default()),
+            (1, 255, false, BorderThinning::default()),
+            (1, 0, true, BorderThinning::default()),
+            (1, 0, false, thinned(20)),
+        ] {
+            for frame in &frames {
+                let mut expected = frame.clone();
+                fill_islands(&mut expected, passes, alpha_fill, edge_seeds, thinning).unwrap();
+
+                let mut img = frame.clone();
+                fill_islands_cached(&mut img, passes, alpha_fill, edge_seeds, thinning).unwrap();
+
+                assert!(
+                    img == expected,
+                    "{} passes, alpha {}, edge seeds {}, {:?} border pixels",
+                    passes,
+                    alpha_fill,
+                    edge_seeds,
+                    thinning.max_pixels
+                );
+            }
+        }
+    }
+}
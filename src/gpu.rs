@@ -0,0 +1,327 @@
+//! `--backend gpu`: the Jump Flooding of jfa.rs in wgpu compute shaders.
+//! The image, its areas and first seeds are uploaded, the passes and the
+//! color copy run on the GPU, and the filled image is read back. One device
+//! is set up for the whole run, on the first image that needs it, and every
+//! image after that reuses it.
+
+use std::sync::{mpsc, OnceLock};
+
+use wgpu::util::DeviceExt;
+
+use crate::{
+    convert::Image16,
+    jfa::{Flood, NONE},
+};
+
+/// The side of the workgroups in `gpu.wgsl`.
+const WORKGROUP: u32 = 8;
+
+/// Above this the squared distances in `gpu.wgsl` overflow.
+const MAX_SIDE: u32 = 32768;
+
+struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    layout: wgpu::BindGroupLayout,
+    flood: wgpu::ComputePipeline,
+    copy: wgpu::ComputePipeline,
+}
+
+static GPU: OnceLock<Result<Gpu, String>> = OnceLock::new();
+
+impl Gpu {
+    fn new() -> Result<Self, String> {
+        // OpenGL has no compute shaders on most of the machines it is the only
+        // backend of, `WGPU_BACKEND=gl` still picks it.
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::PRIMARY),
+            ..Default::default()
+        });
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface: None,
+        }))
+        .ok_or("No GPU adapter was found")?;
+
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("pixfix"),
+                required_features: wgpu::Features::empty(),
+                // The largest images the adapter can take.
+                required_limits: adapter.limits(),
+            },
+            None,
+        ))
+        .map_err(|err| format!("The GPU couldn't be set up ({})", err))?;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("jump flooding"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("gpu.wgsl").into()),
+        });
+
+        let buffer = |binding: u32, ty: wgpu::BufferBindingType| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let read = wgpu::BufferBindingType::Storage { read_only: true };
+        let write = wgpu::BufferBindingType::Storage { read_only: false };
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("jump flooding"),
+            entries: &[
+                buffer(0, wgpu::BufferBindingType::Uniform),
+                buffer(1, read),
+                buffer(2, read),
+                buffer(3, write),
+                buffer(4, write),
+                buffer(5, read),
+                buffer(6, write),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("jump flooding"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point,
+            })
+        };
+
+        Ok(Self {
+            flood: pipeline("flood"),
+            copy: pipeline("copy"),
+            device,
+            queue,
+            layout,
+        })
+    }
+
+    /// Why `flood` is too large for this GPU, if it is.
+    fn too_large(&self, flood: &Flood) -> Option<String> {
+        let limits = self.device.limits();
+        // The colors take the most, 8 bytes per pixel.
+        let bytes = u64::from(flood.width) * u64::from(flood.height) * 8;
+        let groups = flood.width.max(flood.height).div_ceil(WORKGROUP);
+
+        (bytes > u64::from(limits.max_storage_buffer_binding_size)
+            || bytes > limits.max_buffer_size
+            || flood.width.max(flood.height) > MAX_SIDE
+            || groups > limits.max_compute_workgroups_per_dimension)
+            .then(|| {
+                format!(
+                    "A {}x{} image is too large for the GPU",
+                    flood.width, flood.height
+                )
+            })
+    }
+
+    fn storage(&self, label: &str, contents: &[u8], usage: wgpu::BufferUsages) -> wgpu::Buffer {
+        self.device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage: wgpu::BufferUsages::STORAGE | usage,
+            })
+    }
+
+    /// Runs one pass of `pipeline` over the image, with `bind_group`.
+    fn dispatch(
+        &self,
+        pipeline: &wgpu::ComputePipeline,
+        bind_group: &wgpu::BindGroup,
+        flood: &Flood,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.dispatch_workgroups(
+            flood.width.div_ceil(WORKGROUP),
+            flood.height.div_ceil(WORKGROUP),
+            1,
+        );
+    }
+
+    /// Copies `buffer` into a new buffer the CPU can read, waits for the GPU
+    /// and reads it.
+    fn read_back(&self, buffer: &wgpu::Buffer, mut encoder: wgpu::CommandEncoder) -> Vec<u8> {
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("read back"),
+            size: buffer.size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, buffer.size());
+        self.queue.submit([encoder.finish()]);
+
+        let (sender, receiver) = mpsc::channel();
+        let slice = staging.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |mapped| {
+            let _ = sender.send(mapped);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+
+        match receiver.recv() {
+            Ok(Ok(())) => slice.get_mapped_range().to_vec(),
+            _ => panic!("the GPU lost a buffer being read back"),
+        }
+    }
+
+    fn run(&self, img: &Image16, flood: &Flood, alpha_fill: u8) -> (Vec<u32>, Vec<u16>) {
+        let params = |step: u32| -> [u32; 4] {
+            [flood.width, flood.height, step, u32::from(alpha_fill) * 257]
+        };
+        let params_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("params"),
+                contents: bytemuck::cast_slice(&params(1)),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        let area = self.storage(
+            "area",
+            bytemuck::cast_slice(&flood.area),
+            wgpu::BufferUsages::empty(),
+        );
+        let seeds = [
+            self.storage(
+                "seeds",
+                bytemuck::cast_slice(&flood.seeds),
+                wgpu::BufferUsages::COPY_SRC,
+            ),
+            self.storage(
+                "seeds",
+                bytemuck::cast_slice(&flood.seeds),
+                wgpu::BufferUsages::COPY_SRC,
+            ),
+        ];
+        let changed = self.storage(
+            "changed",
+            &[0; 4],
+            wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+        );
+        let colors = self.storage(
+            "colors",
+            bytemuck::cast_slice(img.as_raw()),
+            wgpu::BufferUsages::empty(),
+        );
+        let filled = self.storage(
+            "filled",
+            bytemuck::cast_slice(img.as_raw()),
+            wgpu::BufferUsages::COPY_SRC,
+        );
+
+        // One reading the seeds of each buffer and writing the other.
+        let bind_groups = [(0, 1), (1, 0)].map(|(from, to)| {
+            let entries: Vec<wgpu::BindGroupEntry> = [
+                &params_buffer,
+                &area,
+                &seeds[from],
+                &seeds[to],
+                &changed,
+                &colors,
+                &filled,
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+
+            self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("jump flooding"),
+                layout: &self.layout,
+                entries: &entries,
+            })
+        });
+        let mut current = 0;
+
+        let new_encoder = || {
+            self.device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor::default())
+        };
+
+        for step in flood.steps() {
+            // Written before the commands submitted next run.
+            self.queue
+                .write_buffer(&params_buffer, 0, bytemuck::cast_slice(&params(step)));
+            let mut encoder = new_encoder();
+            self.dispatch(&self.flood, &bind_groups[current], flood, &mut encoder);
+            self.queue.submit([encoder.finish()]);
+            current = 1 - current;
+        }
+
+        self.queue
+            .write_buffer(&params_buffer, 0, bytemuck::cast_slice(&params(1)));
+
+        loop {
+            self.queue.write_buffer(&changed, 0, &[0; 4]);
+            let mut encoder = new_encoder();
+            self.dispatch(&self.flood, &bind_groups[current], flood, &mut encoder);
+            current = 1 - current;
+
+            if self.read_back(&changed, encoder) == [0; 4] {
+                break;
+            }
+        }
+
+        let mut encoder = new_encoder();
+        self.dispatch(&self.copy, &bind_groups[current], flood, &mut encoder);
+        let pixels = self.read_back(&filled, encoder);
+        let seeds = self.read_back(&seeds[current], new_encoder());
+
+        (
+            seeds
+                .chunks_exact(4)
+                .map(|seed| u32::from_le_bytes([seed[0], seed[1], seed[2], seed[3]]))
+                .collect(),
+            pixels
+                .chunks_exact(2)
+                .map(|channel| u16::from_le_bytes([channel[0], channel[1]]))
+                .collect(),
+        )
+    }
+}
+
+/// Floods `flood` on the GPU and copies the colors, returning the seed of
+/// every pixel and the filled image, or why it can't run on the GPU.
+pub fn flood(img: &Image16, flood: &Flood, alpha_fill: u8) -> Result<(Vec<u32>, Vec<u16>), String> {
+    let gpu = GPU.get_or_init(Gpu::new).as_ref().map_err(Clone::clone)?;
+
+    if let Some(reason) = gpu.too_large(flood) {
+        return Err(reason);
+    }
+
+    Ok(gpu.run(img, flood, alpha_fill))
+}
+
+/// Fills `img` on the GPU like `jfa::fill`, returning how many pixels
+/// changed, or why it can't run on the GPU.
+pub fn fill(img: &mut Image16, flood: &Flood, alpha_fill: u8) -> Result<usize, String> {
+    let (seeds, pixels) = self::flood(img, flood, alpha_fill)?;
+
+    let changed = (img.as_raw().chunks_exact(4))
+        .zip(pixels.chunks_exact(4))
+        .zip(&seeds)
+        .filter(|((before, after), seed)| **seed != NONE && before != after)
+        .count();
+
+    *img = Image16::from_raw(flood.width, flood.height, pixels)
+        .expect("the GPU returns as many pixels as it was given");
+
+    Ok(changed)
+}
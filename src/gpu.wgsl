@@ -0,0 +1,101 @@
+// Jump Flooding and the color copy of `--backend gpu`, pass for pass the
+// same as `Flood::run` and `copy_colors` in jfa.rs.
+
+struct Params {
+    width: u32,
+    height: u32,
+    step: u32,
+    // The 16-bit alpha of filled pixels.
+    alpha: u32,
+}
+
+const NONE: u32 = 0xffffffffu;
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> area: array<u32>;
+@group(0) @binding(2) var<storage, read> seeds: array<u32>;
+@group(0) @binding(3) var<storage, read_write> next: array<u32>;
+@group(0) @binding(4) var<storage, read_write> changed: atomic<u32>;
+// Two words per pixel, red and green, then blue and alpha.
+@group(0) @binding(5) var<storage, read> colors: array<u32>;
+@group(0) @binding(6) var<storage, read_write> filled: array<u32>;
+
+fn distance(x: u32, y: u32, seed: u32) -> u32 {
+    let dx = i32(seed % params.width) - i32(x);
+    let dy = i32(seed / params.width) - i32(y);
+
+    return u32(dx * dx) + u32(dy * dy);
+}
+
+fn nearer(x: u32, y: u32, candidate: u32, seed: u32) -> bool {
+    if candidate == NONE {
+        return false;
+    }
+
+    if seed == NONE {
+        return true;
+    }
+
+    let candidate_distance = distance(x, y, candidate);
+    let seed_distance = distance(x, y, seed);
+
+    return candidate_distance < seed_distance
+        || (candidate_distance == seed_distance && candidate < seed);
+}
+
+@compute @workgroup_size(8, 8)
+fn flood(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.width || id.y >= params.height {
+        return;
+    }
+
+    let pixel = id.y * params.width + id.x;
+    let own = area[pixel];
+    var best = seeds[pixel];
+
+    if own != NONE {
+        let step = i32(params.step);
+
+        for (var dy = -1; dy <= 1; dy += 1) {
+            for (var dx = -1; dx <= 1; dx += 1) {
+                let x = i32(id.x) + dx * step;
+                let y = i32(id.y) + dy * step;
+
+                if x < 0 || y < 0 || x >= i32(params.width) || y >= i32(params.height) {
+                    continue;
+                }
+
+                let other = u32(y) * params.width + u32(x);
+
+                if area[other] == own && nearer(id.x, id.y, seeds[other], best) {
+                    best = seeds[other];
+                }
+            }
+        }
+
+        if best != seeds[pixel] {
+            atomicAdd(&changed, 1u);
+        }
+    }
+
+    next[pixel] = best;
+}
+
+@compute @workgroup_size(8, 8)
+fn copy(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.width || id.y >= params.height {
+        return;
+    }
+
+    let pixel = id.y * params.width + id.x;
+    let seed = seeds[pixel];
+
+    if seed == NONE {
+        filled[pixel * 2u] = colors[pixel * 2u];
+        filled[pixel * 2u + 1u] = colors[pixel * 2u + 1u];
+        return;
+    }
+
+    filled[pixel * 2u] = colors[seed * 2u];
+    filled[pixel * 2u + 1u] = (colors[seed * 2u + 1u] & 0xffffu) | (params.alpha << 16u);
+}
@@ -0,0 +1,461 @@
+//! `--gui`: a small window to drop images and folders on, for artists who'd
+//! rather not open a terminal. Only built with the `gui` feature.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+use eframe::egui::{self, Color32, RichText};
+use tokio::{runtime::Handle, task::JoinHandle};
+
+use crate::{
+    batch::{self, Finished},
+    convert::Converted,
+    error::PixfixError,
+    files, lock,
+    options::{self, Options},
+    prescan,
+    report::Status,
+};
+
+/// One line of the list in the window.
+struct Row {
+    path: PathBuf,
+    status: Status,
+    message: String,
+    /// Where the fixed image was written.
+    output: Option<PathBuf>,
+}
+
+impl Row {
+    fn new(path: &Path, status: Status, message: impl Into<String>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            status,
+            message: message.into(),
+            output: None,
+        }
+    }
+
+    fn color(&self) -> Color32 {
+        match self.status {
+            Status::Fixed | Status::Clean | Status::NoAlpha => Color32::from_rgb(80, 180, 80),
+            Status::Failed | Status::Dirty => Color32::from_rgb(220, 70, 70),
+            Status::Skipped => Color32::GRAY,
+        }
+    }
+}
+
+/// The rows for a file that is done, one per entry for archives.
+fn rows(path: &Path, result: Result<Converted, PixfixError>) -> Vec<Row> {
+    let row = match result {
+        Ok(Converted::Fixed {
+            output_path,
+            changed_pixels,
+            ..
+        }) => Row {
+            output: Some(output_path),
+            ..Row::new(
+                path,
+                Status::Fixed,
+                format!("Fixed {} pixels", changed_pixels),
+            )
+        },
+        Ok(Converted::Diffed {
+            diff_path,
+            changed_pixels,
+        }) => Row {
+            output: Some(diff_path),
+            ..Row::new(
+                path,
+                Status::Fixed,
+                format!("{} pixels changed", changed_pixels),
+            )
+        },
+        Ok(Converted::DataUri(_)) => Row::new(path, Status::Fixed, "Fixed"),
+        Ok(Converted::Archive(archive)) => {
+            return archive
+                .entries
+                .into_iter()
+                .flat_map(|(name, result)| rows(&path.join(name), result))
+                .collect();
+        }
+        Ok(Converted::NoAlphaChannel) => {
+            Row::new(path, Status::NoAlpha, "No alpha channel, nothing to fix")
+        }
+        Ok(Converted::Checked { bad_pixels: 0 }) => Row::new(path, Status::Clean, "Clean"),
+        Ok(Converted::Checked { bad_pixels }) => Row::new(
+            path,
+            Status::Dirty,
+            format!("{} pixels need fixing", bad_pixels),
+        ),
+        Err(PixfixError::NothingToFix) => {
+            Row::new(path, Status::Clean, PixfixError::NothingToFix.to_string())
+        }
+        Err(PixfixError::Cancelled) => Row::new(path, Status::Skipped, "Cancelled"),
+        Err(err) => Row::new(path, Status::Failed, err.to_string()),
+    };
+
+    vec![row]
+}
+
+/// Shows `folder` in the file manager.
+fn open_folder(folder: &Path) -> std::io::Result<()> {
+    let program = if cfg!(target_os = "windows") {
+        "explorer"
+    } else if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+
+    Command::new(program).arg(folder).spawn().map(|_| ())
+}
+
+struct App {
+    /// The options the window was opened with, parsed again for every drop.
+    args: Vec<OsString>,
+    /// `--verbose`, and the output of every file in the list.
+    debug: bool,
+    /// Where fixed images go, next to the dropped ones when `None`.
+    output: Option<PathBuf>,
+    /// The folder of the last image written, for "Open output folder" when
+    /// no output folder was picked.
+    last_output: Option<PathBuf>,
+    rows: Vec<Row>,
+    sender: Sender<Row>,
+    receiver: Receiver<Row>,
+    /// Drops that are still being fixed.
+    running: Arc<AtomicUsize>,
+    cancel: Arc<AtomicBool>,
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    runtime: Handle,
+}
+
+impl App {
+    fn options(&self, path: &Path) -> Result<Options, String> {
+        let (mut options, _) = options::parse_args(self.args.clone())?;
+
+        options.recursive = true;
+        options.verbose |= self.debug;
+
+        // Like `--mirror`, dropped folders keep their name in the output.
+        if let Some(output) = &self.output {
+            options.output = Some(output.clone());
+            options.mirror_root = path.parent().map(Path::to_path_buf);
+        }
+
+        Ok(options)
+    }
+
+    /// Fixes a dropped file or folder in the background, sending a row for
+    /// every file as it is done.
+    fn fix(&mut self, path: PathBuf, ctx: &egui::Context) {
+        let options = match self.options(&path) {
+            Ok(options) => Arc::new(options),
+            Err(err) => {
+                self.rows.push(Row::new(&path, Status::Failed, err));
+                return;
+            }
+        };
+
+        let sender = self.sender.clone();
+        let running = self.running.clone();
+        let cancel = self.cancel.clone();
+        let ctx = ctx.clone();
+
+        running.fetch_add(1, Ordering::Relaxed);
+
+        let task = self.runtime.spawn(async move {
+            let send = |row| {
+                let _ = sender.send(row);
+                ctx.request_repaint();
+            };
+
+            let found = {
+                let (path, options) = (path.clone(), options.clone());
+
+                tokio::task::spawn_blocking(move || {
+                    let roots = lock::roots(&[path.clone().into()]);
+                    let locks = lock::acquire(&roots, options.wait_lock)?;
+                    let mut resolved = files::resolve_files(vec![path.into()], &options);
+                    prescan::prescan(&mut resolved, &options);
+
+                    // Output folders have to exist before anything is
+                    // written into them.
+                    if options.output.is_some() {
+                        for file in &resolved.files {
+                            if let Some(parent) = options.output_path(file).parent() {
+                                std::fs::create_dir_all(parent).map_err(|err| {
+                                    format!("Unable to create \"{}\": {}", parent.display(), err)
+                                })?;
+                            }
+                        }
+                    }
+
+                    Ok::<_, String>((locks, resolved))
+                })
+                .await
+            };
+
+            match found {
+                Ok(Ok((_locks, resolved))) => {
+                    for (path, reason) in &resolved.skipped {
+                        send(Row::new(path, Status::Skipped, reason.clone()));
+                    }
+                    for path in &resolved.no_alpha {
+                        send(Row::new(
+                            path,
+                            Status::NoAlpha,
+                            "No alpha channel, nothing to fix",
+                        ));
+                    }
+
+                    batch::fix_files(resolved.files, &options, &cancel, |finished: Finished| {
+                        for row in rows(&finished.path, finished.result) {
+                            send(row);
+                        }
+                    })
+                    .await;
+                }
+                Ok(Err(err)) => send(Row::new(&path, Status::Failed, err)),
+                Err(err) => send(Row::new(&path, Status::Failed, err.to_string())),
+            }
+
+            running.fetch_sub(1, Ordering::Relaxed);
+            ctx.request_repaint();
+        });
+
+        self.tasks.lock().unwrap().push(task);
+    }
+
+    fn output_folder(&self) -> Option<&Path> {
+        self.output.as_deref().or(self.last_output.as_deref())
+    }
+
+    fn controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let output = match &self.output {
+                Some(output) => output.display().to_string(),
+                None => "Next to the dropped files".into(),
+            };
+            ui.label(format!("Output folder: {}", output));
+
+            if ui.button("Choose...").clicked() {
+                if let Some(folder) = rfd::FileDialog::new().pick_folder() {
+                    self.output = Some(folder);
+                }
+            }
+            if self.output.is_some() && ui.button("Reset").clicked() {
+                self.output = None;
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.debug, "Debug");
+
+            let folder = self.output_folder().map(Path::to_path_buf);
+            let open = ui.add_enabled(folder.is_some(), egui::Button::new("Open output folder"));
+
+            if let (true, Some(folder)) = (open.clicked(), folder) {
+                if let Err(err) = open_folder(&folder) {
+                    self.rows.push(Row::new(
+                        &folder,
+                        Status::Failed,
+                        format!("Unable to open the folder: {}", err),
+                    ));
+                }
+            }
+
+            let running = self.running.load(Ordering::Relaxed);
+            if running > 0 {
+                ui.spinner();
+                ui.label("Fixing...");
+            }
+        });
+    }
+
+    fn list(&self, ui: &mut egui::Ui, dropping: bool) {
+        if self.rows.is_empty() || dropping {
+            ui.centered_and_justified(|ui| {
+                ui.heading("Drop images or folders here");
+            });
+            return;
+        }
+
+        let row_height = ui.text_style_height(&egui::TextStyle::Body);
+
+        egui::ScrollArea::vertical()
+            .auto_shrink(false)
+            .stick_to_bottom(true)
+            .show_rows(ui, row_height, self.rows.len(), |ui, range| {
+                for row in &self.rows[range] {
+                    ui.horizontal(|ui| {
+                        ui.label(RichText::new("●").color(row.color()));
+                        ui.label(row.path.display().to_string());
+                        ui.label(RichText::new(&row.message).color(row.color()));
+
+                        if let (true, Some(output)) = (self.debug, &row.output) {
+                            ui.weak(format!("-> {}", output.display()));
+                        }
+                    });
+                }
+            });
+    }
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let dropped: Vec<PathBuf> = ctx.input(|input| {
+            input
+                .raw
+                .dropped_files
+                .iter()
+                .filter_map(|file| file.path.clone())
+                .collect()
+        });
+
+        for path in dropped {
+            self.fix(path, ctx);
+        }
+
+        for row in self.receiver.try_iter() {
+            if let Some(parent) = row.output.as_deref().and_then(Path::parent) {
+                self.last_output = Some(parent.to_path_buf());
+            }
+            self.rows.push(row);
+        }
+
+        let dropping = ctx.input(|input| !input.raw.hovered_files.is_empty());
+
+        egui::TopBottomPanel::top("controls").show(ctx, |ui| self.controls(ui));
+        egui::CentralPanel::default().show(ctx, |ui| self.list(ui, dropping));
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Opens the window and fixes whatever is dropped on it with the options in
+/// `args`, until it is closed or the process gets Ctrl-C. Paths in `args`
+/// are fixed right away.
+pub async fn gui(args: Vec<OsString>) -> Result<bool, String> {
+    // Fails early on invalid options instead of on every drop.
+    let (_, paths) = options::parse_args(args.clone())?;
+    let args: Vec<OsString> = args
+        .into_iter()
+        .filter(|arg| !paths.contains(arg))
+        .collect();
+
+    let (sender, receiver) = mpsc::channel();
+    let cancel = Arc::new(AtomicBool::new(false));
+    let tasks = Arc::new(Mutex::new(Vec::new()));
+    let mut app = App {
+        args,
+        debug: false,
+        output: None,
+        last_output: None,
+        rows: Vec::new(),
+        sender,
+        receiver,
+        running: Arc::new(AtomicUsize::new(0)),
+        cancel: cancel.clone(),
+        tasks: tasks.clone(),
+        runtime: Handle::current(),
+    };
+
+    let window = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_title("Pixfix")
+            .with_inner_size([560.0, 400.0])
+            .with_drag_and_drop(true),
+        ..Default::default()
+    };
+
+    let ctrl_c = Arc::new(Mutex::new(None));
+    let ctrl_c_task = ctrl_c.clone();
+
+    let result = eframe::run_native(
+        "Pixfix",
+        window,
+        Box::new(move |cc| {
+            for path in paths {
+                app.fix(path.into(), &cc.egui_ctx);
+            }
+
+            // Ctrl-C in the terminal the window was opened from closes it
+            // like the close button does.
+            let ctx = cc.egui_ctx.clone();
+            let cancel = app.cancel.clone();
+            *ctrl_c_task.lock().unwrap() = Some(app.runtime.spawn(async move {
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    cancel.store(true, Ordering::Relaxed);
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                    ctx.request_repaint();
+                }
+            }));
+
+            Box::new(app)
+        }),
+    );
+
+    cancel.store(true, Ordering::Relaxed);
+
+    if let Some(task) = ctrl_c.lock().unwrap().take() {
+        task.abort();
+    }
+
+    // Files already being fixed are finished, so none is left half written.
+    let tasks = std::mem::take(&mut *tasks.lock().unwrap());
+    for task in tasks {
+        let _ = task.await;
+    }
+
+    result.map_err(|err| format!("Unable to open the window: {}", err))?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::ArchiveReport;
+
+    #[test]
+    fn every_entry_of_an_archive_gets_a_row() {
+        let archive = ArchiveReport {
+            output_path: Some("sprites_fixed.zip".into()),
+            entries: vec![
+                ("a.png".into(), Ok(Converted::NoAlphaChannel)),
+                ("b.png".into(), Err(PixfixError::Archive("bad".into()))),
+            ],
+        };
+
+        let rows = rows(Path::new("sprites.zip"), Ok(Converted::Archive(archive)));
+
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].path == Path::new("sprites.zip/a.png"));
+        assert!(rows[0].status == Status::NoAlpha);
+        assert!(rows[1].status == Status::Failed);
+        assert_eq!(
+            rows[1].message,
+            PixfixError::Archive("bad".into()).to_string()
+        );
+    }
+
+    #[test]
+    fn cancelled_files_are_not_failures() {
+        let rows = rows(Path::new("a.png"), Err(PixfixError::Cancelled));
+
+        assert!(rows[0].status == Status::Skipped);
+        assert!(rows[0].color() != Row::new(Path::new(""), Status::Failed, "").color());
+    }
+}
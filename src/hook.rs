@@ -0,0 +1,288 @@
+//! Git pre-commit hook support, so PNGs with fringe colors can't be committed.
+
+use std::{
+    ffi::OsString,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::{
+    convert::{self, FixedBytes},
+    diagnostic,
+    error::PixfixError,
+    files::is_png_file,
+    human,
+    options::{self, Options},
+};
+
+const HOOK_COMMAND: &str = "hook --staged";
+
+/// Runs git in `dir` with `input` on its stdin, returning its stdout.
+fn git_bytes(dir: Option<&Path>, args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>, String> {
+    let mut command = Command::new("git");
+    command
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+
+    let mut child = command
+        .spawn()
+        .map_err(|err| format!("Unable to run git: {}", err))?;
+
+    // Dropped once written, so git sees the end of its input.
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(input.unwrap_or_default())
+            .map_err(|err| format!("Unable to run git: {}", err))?;
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|err| format!("Unable to run git: {}", err))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(output.stdout)
+}
+
+fn git(args: &[&str]) -> Result<String, String> {
+    Ok(String::from_utf8_lossy(&git_bytes(None, args, None)?).into_owned())
+}
+
+fn repository_root() -> Result<PathBuf, String> {
+    Ok(PathBuf::from(
+        git(&["rev-parse", "--show-toplevel"])?.trim(),
+    ))
+}
+
+/// A PNG as the index holds it, which is what gets committed whatever the
+/// working tree has.
+pub struct Staged {
+    /// Relative to the repository root.
+    pub path: String,
+    mode: String,
+    blob: String,
+}
+
+/// The staged PNGs that were added, copied, modified or renamed, under
+/// their new names. Git reports them relative to the repository root, so
+/// this works from any subdirectory.
+pub fn staged_pngs(root: &Path) -> Result<Vec<Staged>, String> {
+    let raw = git_bytes(
+        Some(root),
+        &[
+            "diff",
+            "--cached",
+            "--raw",
+            "--no-abbrev",
+            "-z",
+            "--diff-filter=ACMR",
+        ],
+        None,
+    )?;
+    let raw = String::from_utf8_lossy(&raw);
+
+    // `:<old mode> <new mode> <old blob> <new blob> <status>`, then the
+    // path, or the old and the new path for renames and copies.
+    let mut fields = raw.split('\0').filter(|field| !field.is_empty());
+    let mut staged = Vec::new();
+
+    while let Some(meta) = fields.next() {
+        let meta: Vec<&str> = meta.trim_start_matches(':').split(' ').collect();
+        let [_, mode, _, blob, status] = meta[..] else {
+            return Err(format!("Unexpected output from git: {:?}", meta));
+        };
+
+        if status.starts_with(['R', 'C']) {
+            fields.next();
+        }
+
+        let Some(path) = fields.next() else {
+            break;
+        };
+
+        if is_png_file(Path::new(path)) {
+            staged.push(Staged {
+                path: path.into(),
+                mode: mode.into(),
+                blob: blob.into(),
+            });
+        }
+    }
+
+    Ok(staged)
+}
+
+/// How one staged PNG went.
+enum Hooked {
+    Clean,
+    Dirty(usize),
+    Fixed,
+}
+
+/// Checks the staged content of `staged`, or with `fix` fixes it in the
+/// index. The working copy is only fixed too when it matches the index, so
+/// changes that aren't staged are never staged along with the fix.
+fn hook_file(root: &Path, staged: &Staged, fix: bool, options: &Options) -> Result<Hooked, String> {
+    let bytes = git_bytes(Some(root), &["cat-file", "blob", &staged.blob], None)?;
+    let path = root.join(&staged.path);
+
+    let fixed = match convert::fix_bytes(&bytes, &path, options) {
+        Ok(FixedBytes::Checked { bad_pixels: 0 })
+        | Ok(FixedBytes::NoAlphaChannel)
+        | Err(PixfixError::NothingToFix) => return Ok(Hooked::Clean),
+        Ok(FixedBytes::Checked { bad_pixels }) => return Ok(Hooked::Dirty(bad_pixels)),
+        Ok(FixedBytes::Fixed {
+            changed_pixels: 0, ..
+        }) => return Ok(Hooked::Clean),
+        Ok(FixedBytes::Fixed { bytes, .. }) => bytes,
+        Err(err) => return Err(err.to_string()),
+    };
+
+    if !fix {
+        return Ok(Hooked::Clean);
+    }
+
+    let blob = git_bytes(Some(root), &["hash-object", "-w", "--stdin"], Some(&fixed))?;
+    let blob = String::from_utf8_lossy(&blob);
+    let cacheinfo = format!("{},{},{}", staged.mode, blob.trim(), staged.path);
+    git_bytes(
+        Some(root),
+        &["update-index", "--cacheinfo", &cacheinfo],
+        None,
+    )?;
+
+    if std::fs::read(&path).is_ok_and(|working| working == bytes) {
+        convert::save(&path, &fixed, options).map_err(|err| err.to_string())?;
+    }
+
+    Ok(Hooked::Fixed)
+}
+
+/// Checks every staged PNG, or fixes it with `fix`, printing how each went.
+/// Returns whether the commit can go ahead: nothing staged still needs
+/// fixing and nothing failed.
+pub fn run_staged(fix: bool, args: Vec<OsString>) -> Result<bool, String> {
+    let (mut options, _) = options::parse_args(args)?;
+    options.check = !fix;
+
+    let root = repository_root()?;
+    let staged = staged_pngs(&root)?;
+    let (mut dirty, mut failed) = (0, 0);
+
+    for staged in &staged {
+        let path = Path::new(&staged.path);
+
+        match hook_file(&root, staged, fix, &options) {
+            Ok(Hooked::Clean) => human!("{:?} - Clean", path.display()),
+            Ok(Hooked::Dirty(bad_pixels)) => {
+                human!("{:?} - {} pixels need fixing", path.display(), bad_pixels);
+                dirty += 1;
+            }
+            Ok(Hooked::Fixed) => human!("{:?} - Fixed and restaged", path.display()),
+            Err(err) => {
+                diagnostic!("Failed to check \"{}\" - {}", path.display(), err);
+                failed += 1;
+            }
+        }
+    }
+
+    if dirty > 0 {
+        diagnostic!(
+            "{} staged images need fixing - Run \"pixfix hook --staged --fix\" to fix and restage them",
+            dirty
+        );
+    }
+
+    Ok(dirty == 0 && failed == 0)
+}
+
+/// Splits the `--staged` and `--fix` flags from the other options.
+pub fn parse_hook_args(args: Vec<OsString>) -> (bool, bool, Vec<OsString>) {
+    let mut staged = false;
+    let mut fix = false;
+    let mut rest = Vec::new();
+
+    for arg in args {
+        match arg.to_str() {
+            Some("--staged") => staged = true,
+            Some("--fix") => fix = true,
+            _ => rest.push(arg),
+        }
+    }
+
+    (staged, fix, rest)
+}
+
+/// Writes `.git/hooks/pre-commit` so it runs Pixfix on staged PNGs.
+pub fn install() -> Result<Vec<String>, String> {
+    let hooks = git(&["rev-parse", "--git-path", "hooks"])?;
+    let hooks = Path::new(hooks.trim());
+    let hook_path = hooks.join("pre-commit");
+
+    let exe = std::env::current_exe()
+        .map_err(|err| format!("Unable to find the Pixfix executable: {}", err))?;
+    let shim = format!(
+        "#!/bin/sh\nexec \"{}\" {}\n",
+        exe.display().to_string().replace('\\', "/"),
+        HOOK_COMMAND
+    );
+
+    let existing = std::fs::read_to_string(&hook_path).ok();
+
+    if existing.as_deref() == Some(shim.as_str()) {
+        return Ok(vec![format!("Unchanged {}", hook_path.display())]);
+    }
+
+    if let Some(existing) = &existing {
+        if !existing.contains(HOOK_COMMAND) {
+            return Err(format!(
+                "\"{}\" already exists and wasn't written by Pixfix, add \"pixfix {}\" to it instead!",
+                hook_path.display(),
+                HOOK_COMMAND
+            ));
+        }
+    }
+
+    std::fs::create_dir_all(hooks)
+        .map_err(|err| format!("Unable to create \"{}\": {}", hooks.display(), err))?;
+    std::fs::write(&hook_path, shim)
+        .map_err(|err| format!("Unable to write \"{}\": {}", hook_path.display(), err))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::set_permissions(&hook_path, std::fs::Permissions::from_mode(0o755)).map_err(
+            |err| {
+                format!(
+                    "Unable to make \"{}\" executable: {}",
+                    hook_path.display(),
+                    err
+                )
+            },
+        )?;
+    }
+
+    Ok(vec![format!(
+        "{} {}",
+        if existing.is_some() {
+            "Updated"
+        } else {
+            "Added"
+        },
+        hook_path.display()
+    )])
+}
@@ -0,0 +1,244 @@
+//! `.gitignore` style ignore files for folder scans: `.pixfixignore` files
+//! always, `.gitignore` files with `--respect-gitignore`.
+
+use std::path::{Component, Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+
+use crate::paths::io_path;
+
+const PIXFIX_IGNORE: &str = ".pixfixignore";
+const GIT_IGNORE: &str = ".gitignore";
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+#[derive(Clone)]
+struct Rule {
+    /// The folder of the ignore file, which anchored patterns are relative to.
+    base: PathBuf,
+    pattern: Pattern,
+    negated: bool,
+    dir_only: bool,
+}
+
+impl Rule {
+    fn parse(line: &str, base: &Path) -> Option<Self> {
+        let line = line.trim_end();
+
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+
+        // A slash anywhere but at the end anchors the pattern to the ignore
+        // file's folder, otherwise it matches at any depth.
+        let pattern = match line.strip_prefix('/') {
+            Some(rest) => rest.to_string(),
+            None if line.contains('/') => line.to_string(),
+            None => format!("**/{}", line),
+        };
+
+        Some(Self {
+            base: base.to_path_buf(),
+            pattern: Pattern::new(&pattern).ok()?,
+            negated,
+            dir_only,
+        })
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let Ok(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+
+        let relative: Vec<_> = relative
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            })
+            .collect();
+
+        self.pattern
+            .matches_with(&relative.join("/"), MATCH_OPTIONS)
+    }
+}
+
+/// The ignore rules that apply to the folder being scanned. Every folder
+/// gets a copy of the rules of its parent with its own pushed on top, so
+/// deeper files come last and win, like in git.
+#[derive(Clone)]
+pub struct Ignores {
+    rules: Vec<Rule>,
+    respect_gitignore: bool,
+    /// The folder argument as given and where it really is, so the rules of
+    /// folders above it can be matched against the paths inside it.
+    root: PathBuf,
+    canonical_root: PathBuf,
+}
+
+impl Ignores {
+    /// Starts a scan of `root`. With `respect_gitignore`, the `.gitignore`
+    /// files between the repository root and `root` apply too.
+    pub fn new(root: &Path, respect_gitignore: bool) -> Self {
+        let canonical_root =
+            std::fs::canonicalize(io_path(root)).unwrap_or_else(|_| root.to_path_buf());
+
+        let mut ignores = Self {
+            rules: Vec::new(),
+            respect_gitignore,
+            root: root.to_path_buf(),
+            canonical_root: canonical_root.clone(),
+        };
+
+        if respect_gitignore {
+            let ancestors: Vec<&Path> = canonical_root.ancestors().collect();
+            let repository = ancestors
+                .iter()
+                .position(|ancestor| ancestor.join(".git").exists());
+
+            // The root's own ignore files are read once the scan gets to it.
+            if let Some(repository) = repository {
+                let repository_root = ancestors[repository];
+                ignores.read(&repository_root.join(".git/info/exclude"), repository_root);
+
+                for ancestor in ancestors[1..=repository].iter().rev() {
+                    ignores.read(&ancestor.join(GIT_IGNORE), ancestor);
+                }
+            }
+        }
+
+        ignores
+    }
+
+    fn canonical(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.root) {
+            Ok(relative) => self.canonical_root.join(relative),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+
+    fn read(&mut self, file: &Path, base: &Path) {
+        let Ok(contents) = std::fs::read_to_string(io_path(file)) else {
+            return;
+        };
+
+        self.rules
+            .extend(contents.lines().filter_map(|line| Rule::parse(line, base)));
+    }
+
+    /// Adds the rules of the ignore files in `dir`.
+    pub fn push(&mut self, dir: &Path) {
+        let base = self.canonical(dir);
+
+        if self.respect_gitignore {
+            self.read(&dir.join(GIT_IGNORE), &base);
+        }
+
+        self.read(&dir.join(PIXFIX_IGNORE), &base);
+    }
+
+    /// Whether the last rule matching `path` ignores it.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let path = self.canonical(path);
+
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(&path, is_dir))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{files::resolve_files, fixtures::TempDir, options::Options};
+
+    /// `*.png` ignored at the root, with `keep/` bringing back `hero.png`.
+    fn setup() -> TempDir {
+        let dir = TempDir::new("ignore").unwrap();
+        let root = dir.path();
+
+        for folder in ["keep", "keep/deeper", "other"] {
+            std::fs::create_dir(root.join(folder)).unwrap();
+        }
+
+        std::fs::write(root.join(PIXFIX_IGNORE), "*.png\n").unwrap();
+        std::fs::write(root.join("keep").join(PIXFIX_IGNORE), "!hero.png\n").unwrap();
+
+        for file in [
+            "a.png",
+            "keep/hero.png",
+            "keep/other.png",
+            "keep/deeper/hero.png",
+            "other/hero.png",
+        ] {
+            std::fs::write(root.join(file), []).unwrap();
+        }
+
+        dir
+    }
+
+    #[test]
+    fn a_nested_negation_overrides_its_parent() {
+        let dir = setup();
+        let root = dir.path();
+
+        let mut ignores = Ignores::new(root, false);
+        ignores.push(root);
+
+        let mut other = ignores.clone();
+        other.push(&root.join("other"));
+
+        let mut keep = ignores.clone();
+        keep.push(&root.join("keep"));
+
+        let mut deeper = keep.clone();
+        deeper.push(&root.join("keep/deeper"));
+
+        assert!(ignores.is_ignored(&root.join("a.png"), false));
+        assert!(!keep.is_ignored(&root.join("keep/hero.png"), false));
+        assert!(keep.is_ignored(&root.join("keep/other.png"), false));
+        assert!(!deeper.is_ignored(&root.join("keep/deeper/hero.png"), false));
+        // The negation only applies below the folder it is in.
+        assert!(other.is_ignored(&root.join("other/hero.png"), false));
+    }
+
+    #[test]
+    fn scans_follow_nested_negations() {
+        let dir = setup();
+        let options = Options {
+            recursive: true,
+            ..Default::default()
+        };
+
+        let mut found = resolve_files(vec![dir.path().into()], &options).files;
+        found.sort();
+
+        assert_eq!(
+            found,
+            [
+                dir.path().join("keep/deeper/hero.png"),
+                dir.path().join("keep/hero.png"),
+            ]
+        );
+    }
+}
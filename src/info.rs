@@ -0,0 +1,132 @@
+//! `pixfix info`, which prints the size of images and the area their pixels
+//! that aren't fully transparent take up, the area `--trim` would keep. With
+//! `--json` every image is one line of JSON instead.
+
+use std::{ffi::OsString, path::Path};
+
+use serde_json::{json, Value};
+
+use crate::{
+    convert::{self, Rect},
+    diagnostic,
+    error::PixfixError,
+    files,
+    options::{self, Options},
+    result,
+};
+
+/// What `info` found out about one image.
+struct Info {
+    width: u32,
+    height: u32,
+    alpha: bool,
+    /// `None` when every pixel is fully transparent.
+    content: Option<Rect>,
+}
+
+/// Splits `--json` from the options used to find the files.
+pub fn parse_info_args(args: Vec<OsString>) -> (bool, Vec<OsString>) {
+    let mut json = false;
+    let mut rest = Vec::new();
+
+    for arg in args {
+        match arg.to_str() {
+            Some("--json") => json = true,
+            _ => rest.push(arg),
+        }
+    }
+
+    (json, rest)
+}
+
+fn read_info(path: &Path, options: &Options) -> Result<Info, PixfixError> {
+    let bytes = convert::read_input(path, options)?;
+    let decoded = convert::decode(&bytes, path, options)?;
+    let (width, height) = (decoded.width(), decoded.height());
+    let alpha = decoded.color().has_alpha();
+
+    // Without an alpha channel every pixel is content.
+    let content = if alpha {
+        convert::content_box(&decoded.into_rgba16(), options.alpha_invert)
+    } else {
+        (width > 0 && height > 0).then_some(Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        })
+    };
+
+    Ok(Info {
+        width,
+        height,
+        alpha,
+        content,
+    })
+}
+
+fn info_json(path: &Path, info: &Info) -> Value {
+    json!({
+        "path": path.to_string_lossy(),
+        "width": info.width,
+        "height": info.height,
+        "alpha": info.alpha,
+        "content": info.content.map(|content| {
+            json!({
+                "x": content.x,
+                "y": content.y,
+                "width": content.width,
+                "height": content.height,
+            })
+        }),
+    })
+}
+
+/// Prints the info of every image in `args`. Returns whether all of them
+/// could be read.
+pub fn info(args: Vec<OsString>) -> Result<bool, String> {
+    let (json, args) = parse_info_args(args);
+    let (mut options, args) = options::parse_args(args)?;
+
+    // The inputs are only read, so read-only files are as good as any.
+    options.check = true;
+
+    let files = files::resolve_files(args, &options).files;
+    let mut failed = 0;
+
+    for path in &files {
+        let info = match read_info(path, &options) {
+            Ok(value) => value,
+            Err(err) => {
+                diagnostic!("Unable to read \"{}\": {}", path.display(), err);
+                failed += 1;
+                continue;
+            }
+        };
+
+        if json {
+            result!("{}", info_json(path, &info));
+            continue;
+        }
+
+        let content = match info.content {
+            Some(content) => format!(
+                "content {}x{} at {},{}",
+                content.width, content.height, content.x, content.y
+            ),
+            None => "no content, fully transparent".into(),
+        };
+        let alpha = if info.alpha { "" } else { ", no alpha channel" };
+
+        result!(
+            "{} - {}x{}{}, {}",
+            path.display(),
+            info.width,
+            info.height,
+            alpha,
+            content
+        );
+    }
+
+    Ok(failed == 0)
+}
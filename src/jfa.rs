@@ -0,0 +1,399 @@
+//! `--backend jfa` and `--backend gpu`: the fill by Jump Flooding, which
+//! finds about the nearest border pixel of every transparent pixel in a
+//! fixed number of passes over the image, however many border pixels there
+//! are. The passes are the same on the CPU and in the compute shaders of
+//! `gpu.rs`, so both pick the same colors.
+//!
+//! Every transparent area still only takes colors from its own border, but
+//! within one the nearest border pixel is found in a straight line, so in a
+//! concave area colors can reach across opaque pixels, which the default
+//! fill never does.
+
+use image::Rgba;
+
+use crate::{
+    convert::{collect_components, colored_mask, expired, Image16},
+    progress::{self, Stage},
+};
+
+/// Where the fill finds the border pixel of every transparent pixel.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Backend {
+    /// Exactly the nearest one that can be reached without crossing opaque
+    /// pixels.
+    #[default]
+    Cpu,
+    /// Jump Flooding on the CPU.
+    Jfa,
+    /// Jump Flooding on the GPU, or on the CPU without one.
+    Gpu,
+}
+
+impl std::str::FromStr for Backend {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "cpu" => Ok(Backend::Cpu),
+            "jfa" => Ok(Backend::Jfa),
+            "gpu" => Ok(Backend::Gpu),
+            _ => Err(()),
+        }
+    }
+}
+
+/// No area or no seed.
+pub const NONE: u32 = u32::MAX;
+
+/// What Jump Flooding works on, by pixel index.
+pub struct Flood {
+    pub width: u32,
+    pub height: u32,
+    /// The transparent area every pixel is in, `NONE` for colored pixels and
+    /// for areas without a border to fill from.
+    pub area: Vec<u32>,
+    /// The colored pixel every transparent pixel next to one takes its color
+    /// from, the nearest of them, `NONE` for the others.
+    pub seeds: Vec<u32>,
+    /// How many pixels there are to fill.
+    pub pixels: usize,
+}
+
+impl Flood {
+    /// `None` when there is nothing to fill from.
+    pub fn new(img: &Image16) -> Option<Self> {
+        let (width, height) = img.dimensions();
+        let colored = colored_mask(img);
+        let mut groups = collect_components(img, &colored);
+        groups.retain(|(border_pixels, _)| !border_pixels.is_empty());
+
+        if groups.is_empty() {
+            return None;
+        }
+
+        let mut area = vec![NONE; colored.len()];
+        let mut seeds = vec![NONE; colored.len()];
+        let mut pixels = 0;
+
+        for (index, (_, transparent_pixels)) in groups.iter().enumerate() {
+            for (x, y, _) in transparent_pixels {
+                area[(y * width + x) as usize] = index as u32;
+            }
+
+            pixels += transparent_pixels.len();
+        }
+
+        for (pixel, seed) in seeds.iter_mut().enumerate() {
+            if area[pixel] == NONE {
+                continue;
+            }
+
+            let (x, y) = (pixel as u32 % width, pixel as u32 / width);
+
+            for (other_x, other_y) in neighbors(x, y, 1, width, height) {
+                let other = other_y * width + other_x;
+
+                if colored[other as usize] && nearer(width, pixel as u32, other, *seed) {
+                    *seed = other;
+                }
+            }
+        }
+
+        Some(Self {
+            width,
+            height,
+            area,
+            seeds,
+            pixels,
+        })
+    }
+
+    /// How far each pass looks, from half the image down to the next pixel.
+    /// Passes that look at the next pixel are then repeated until nothing
+    /// changes, the first of them makes up for most of the misses of the
+    /// long jumps and the rest reach into long winding areas.
+    pub fn steps(&self) -> Vec<u32> {
+        let mut step = self.width.max(self.height).next_power_of_two() / 2;
+        let mut steps = Vec::new();
+
+        while step >= 1 {
+            steps.push(step);
+            step /= 2;
+        }
+
+        steps
+    }
+
+    /// One pass, every pixel taking the nearest seed of the pixels of its
+    /// area `step` away, its own included. Returns how many changed.
+    fn pass(&self, seeds: &[u32], next: &mut [u32], step: u32) -> usize {
+        let mut changed = 0;
+
+        for (pixel, next) in next.iter_mut().enumerate() {
+            let own = self.area[pixel];
+            let mut best = seeds[pixel];
+
+            if own != NONE {
+                let (x, y) = (pixel as u32 % self.width, pixel as u32 / self.width);
+
+                for (other_x, other_y) in neighbors(x, y, step, self.width, self.height) {
+                    let other = (other_y * self.width + other_x) as usize;
+
+                    if self.area[other] == own
+                        && nearer(self.width, pixel as u32, seeds[other], best)
+                    {
+                        best = seeds[other];
+                    }
+                }
+
+                if best != seeds[pixel] {
+                    changed += 1;
+                }
+            }
+
+            *next = best;
+        }
+
+        changed
+    }
+
+    /// The seed of every pixel once the flood is done, or `None` when the
+    /// file ran out of time.
+    pub fn run(&self) -> Option<Vec<u32>> {
+        let mut seeds = self.seeds.clone();
+        let mut next = vec![NONE; seeds.len()];
+
+        for step in self.steps() {
+            if expired() {
+                return None;
+            }
+
+            self.pass(&seeds, &mut next, step);
+            std::mem::swap(&mut seeds, &mut next);
+        }
+
+        loop {
+            if expired() {
+                return None;
+            }
+
+            let changed = self.pass(&seeds, &mut next, 1);
+            std::mem::swap(&mut seeds, &mut next);
+
+            if changed == 0 {
+                return Some(seeds);
+            }
+        }
+    }
+}
+
+/// The pixels `step` away from x, y in the 8 directions, and x, y itself.
+fn neighbors(
+    x: u32,
+    y: u32,
+    step: u32,
+    width: u32,
+    height: u32,
+) -> impl Iterator<Item = (u32, u32)> {
+    let (x, y, step) = (i64::from(x), i64::from(y), i64::from(step));
+
+    (-1..=1)
+        .flat_map(move |dy| (-1..=1).map(move |dx| (x + dx * step, y + dy * step)))
+        .filter(move |(x, y)| *x >= 0 && *y >= 0 && *x < i64::from(width) && *y < i64::from(height))
+        .map(|(x, y)| (x as u32, y as u32))
+}
+
+/// The squared distance from `pixel` to `seed`.
+pub fn distance(width: u32, pixel: u32, seed: u32) -> u64 {
+    let dx = i64::from(seed % width) - i64::from(pixel % width);
+    let dy = i64::from(seed / width) - i64::from(pixel / width);
+
+    (dx * dx + dy * dy) as u64
+}
+
+/// Whether `candidate` is nearer to `pixel` than `seed`, ties going to the
+/// one that comes first in the image so every backend picks the same.
+fn nearer(width: u32, pixel: u32, candidate: u32, seed: u32) -> bool {
+    if candidate == NONE {
+        return false;
+    }
+
+    if seed == NONE {
+        return true;
+    }
+
+    let (candidate_distance, seed_distance) = (
+        distance(width, pixel, candidate),
+        distance(width, pixel, seed),
+    );
+
+    candidate_distance < seed_distance || (candidate_distance == seed_distance && candidate < seed)
+}
+
+/// Gives every pixel with a seed the color of its seed, at `alpha_fill`.
+/// Returns how many pixels changed.
+fn copy_colors(img: &mut Image16, seeds: &[u32], alpha_fill: u8) -> usize {
+    let original = img.clone();
+    let width = img.width();
+    let a = u16::from(alpha_fill) * 257;
+    let mut changed = 0;
+
+    for (pixel, seed) in seeds.iter().enumerate() {
+        if *seed == NONE {
+            continue;
+        }
+
+        let [r, g, b, _] = original.get_pixel(seed % width, seed / width).0;
+        let fixed = Rgba([r, g, b, a]);
+        let (x, y) = (pixel as u32 % width, pixel as u32 / width);
+
+        if *img.get_pixel(x, y) != fixed {
+            changed += 1;
+        }
+
+        img.put_pixel(x, y, fixed);
+    }
+
+    changed
+}
+
+/// Fills every transparent pixel of `img` with the color of about the
+/// nearest border pixel of its area, on `backend`. Returns how many pixels
+/// changed, or `None` when there is nothing to fill from.
+pub fn fill(img: &mut Image16, alpha_fill: u8, backend: Backend) -> Option<usize> {
+    let flood = Flood::new(img)?;
+
+    progress::filling(flood.pixels);
+    progress::stage(Stage::Filling);
+
+    #[cfg(feature = "gpu")]
+    if backend == Backend::Gpu {
+        match crate::gpu::fill(img, &flood, alpha_fill) {
+            Ok(changed) => {
+                progress::filled(flood.pixels);
+                return Some(changed);
+            }
+            Err(reason) => warn_fallback(&reason),
+        }
+    }
+    #[cfg(not(feature = "gpu"))]
+    let _ = backend;
+
+    let Some(seeds) = flood.run() else {
+        return Some(0);
+    };
+
+    let changed = copy_colors(img, &seeds, alpha_fill);
+    progress::filled(flood.pixels);
+
+    Some(changed)
+}
+
+/// Says why an image is filled on the CPU after all, once per reason.
+#[cfg(feature = "gpu")]
+fn warn_fallback(reason: &str) {
+    use std::{collections::HashSet, sync::Mutex};
+
+    static WARNED: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+    let mut warned = WARNED.lock().unwrap_or_else(|err| err.into_inner());
+
+    if warned
+        .get_or_insert_with(HashSet::new)
+        .insert(reason.to_string())
+    {
+        crate::diagnostic!("{}, filling on the CPU instead.", reason);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::scattered;
+
+    /// The squared distance from every pixel of `img` to the nearest border
+    /// pixel of its area, by brute force.
+    fn nearest(img: &Image16, flood: &Flood) -> Vec<Option<u64>> {
+        let colored = colored_mask(img);
+        let (width, height) = img.dimensions();
+
+        // Every border pixel with the areas it is next to.
+        let borders: Vec<(u32, Vec<u32>)> = (0..flood.area.len() as u32)
+            .filter(|seed| colored[*seed as usize])
+            .map(|seed| {
+                let areas = neighbors(seed % width, seed / width, 1, width, height)
+                    .map(|(x, y)| flood.area[(y * width + x) as usize])
+                    .filter(|area| *area != NONE)
+                    .collect();
+
+                (seed, areas)
+            })
+            .collect();
+
+        (0..flood.area.len() as u32)
+            .map(|pixel| {
+                let own = flood.area[pixel as usize];
+
+                (own != NONE).then(|| {
+                    borders
+                        .iter()
+                        .filter(|(_, areas)| areas.contains(&own))
+                        .map(|(seed, _)| distance(width, pixel, *seed))
+                        .min()
+                        .unwrap()
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn jump_flooding_is_within_a_pixel_of_the_nearest_border_pixel() {
+        let img = scattered(64, 48, 40);
+        let flood = Flood::new(&img).unwrap();
+        let seeds = flood.run().unwrap();
+
+        for (pixel, (seed, nearest)) in seeds.iter().zip(nearest(&img, &flood)).enumerate() {
+            let Some(nearest) = nearest else {
+                assert_eq!(*seed, NONE);
+                continue;
+            };
+            let found = (distance(img.width(), pixel as u32, *seed) as f64).sqrt();
+
+            assert!(found - (nearest as f64).sqrt() <= 1.0, "pixel {}", pixel);
+        }
+    }
+
+    #[test]
+    fn a_single_border_pixel_colors_the_whole_image() {
+        let red = Rgba([u16::MAX, 0, 0, u16::MAX]);
+        let mut img = Image16::from_fn(33, 17, |x, y| match (x, y) {
+            (0, 0) => red,
+            _ => Rgba([0; 4]),
+        });
+
+        assert_eq!(fill(&mut img, 255, Backend::Jfa), Some(33 * 17 - 1));
+        assert!(img.pixels().all(|color| *color == red));
+    }
+
+    #[cfg(feature = "gpu")]
+    #[test]
+    fn the_gpu_finds_the_seeds_the_cpu_does_within_a_pixel() {
+        let img = scattered(300, 200, 150);
+        let flood = Flood::new(&img).unwrap();
+        let Ok((seeds, _)) = crate::gpu::flood(&img, &flood, 255) else {
+            eprintln!("No GPU to compare with");
+            return;
+        };
+
+        for (pixel, (seed, expected)) in seeds.iter().zip(flood.run().unwrap()).enumerate() {
+            assert_eq!(*seed == NONE, expected == NONE, "pixel {}", pixel);
+
+            if *seed != NONE {
+                let found = (distance(img.width(), pixel as u32, *seed) as f64).sqrt();
+                let expected = (distance(img.width(), pixel as u32, expected) as f64).sqrt();
+
+                assert!((found - expected).abs() <= 1.0, "pixel {}", pixel);
+            }
+        }
+    }
+}
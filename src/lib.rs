@@ -0,0 +1,44 @@
+pub mod archive;
+pub mod atlas;
+pub mod backup;
+pub mod batch;
+pub mod chunks;
+pub mod color;
+pub mod compare;
+pub mod convert;
+pub mod data_uri;
+pub mod digest;
+pub mod error;
+pub mod events;
+pub mod extract;
+pub mod files;
+pub mod fixtures;
+pub mod format;
+pub mod frames;
+#[cfg(feature = "gpu")]
+mod gpu;
+#[cfg(feature = "gui")]
+pub mod gui;
+pub mod hook;
+mod ignore;
+pub mod info;
+pub mod jfa;
+pub mod lock;
+pub mod mirror;
+pub mod options;
+pub mod output;
+mod paths;
+pub mod prescan;
+#[cfg(all(feature = "profile", unix))]
+pub mod profile;
+pub mod progress;
+pub mod report;
+pub mod resume;
+mod retry;
+pub mod rojo;
+pub mod selftest;
+pub mod serve;
+pub mod shell;
+pub mod stats;
+pub mod validate;
+pub mod workers;
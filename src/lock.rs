@@ -0,0 +1,210 @@
+//! `.pixfix.lock` files in the folders being fixed, so two runs never fix
+//! the same folder at the same time and interleave their writes.
+
+use std::{
+    ffi::OsString,
+    fs::OpenOptions,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{data_uri, diagnostic, paths};
+
+pub const LOCK_FILE: &str = ".pixfix.lock";
+
+/// How often a locked folder is checked again with `--wait-lock`.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A lock that was just created may not have its contents yet, so an
+/// unreadable one only counts as stale once it is this old.
+const UNREADABLE_GRACE: Duration = Duration::from_secs(2);
+
+/// The locks held by this run. They are removed when it is dropped.
+pub struct Locks {
+    paths: Vec<PathBuf>,
+}
+
+impl Locks {
+    /// Also removes the locks when the run is cancelled with Ctrl-C, which
+    /// would otherwise exit without dropping them.
+    pub fn release_on_ctrl_c(&self) {
+        let paths = self.paths.clone();
+
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                release(&paths);
+                std::process::exit(130);
+            }
+        });
+    }
+}
+
+impl Drop for Locks {
+    fn drop(&mut self) {
+        release(&self.paths);
+    }
+}
+
+fn release(paths: &[PathBuf]) {
+    for path in paths {
+        let _ = std::fs::remove_file(paths::io_path(path));
+    }
+}
+
+/// The run holding a lock, as written in the lock file.
+struct Holder {
+    pid: u32,
+    /// Seconds since the unix epoch.
+    started: u64,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+fn read_holder(path: &Path) -> Option<Holder> {
+    let contents = std::fs::read_to_string(paths::io_path(path)).ok()?;
+    let mut lines = contents.lines();
+
+    Some(Holder {
+        pid: lines.next()?.trim().parse().ok()?,
+        started: lines.next()?.trim().parse().ok()?,
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn is_running(pid: u32) -> bool {
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+#[cfg(not(unix))]
+fn is_running(pid: u32) -> bool {
+    std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .output()
+        .is_ok_and(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .split_whitespace()
+                .any(|word| word == pid.to_string())
+        })
+}
+
+enum State {
+    /// Held by a run that is still going, which is unknown while the lock
+    /// is being written.
+    Live(Option<Holder>),
+    /// Left behind by a run that crashed.
+    Stale,
+}
+
+fn state(path: &Path) -> State {
+    match read_holder(path) {
+        Some(holder) if is_running(holder.pid) => State::Live(Some(holder)),
+        Some(_) => State::Stale,
+        None => {
+            let age = std::fs::metadata(paths::io_path(path))
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok());
+
+            match age {
+                Some(age) if age >= UNREADABLE_GRACE => State::Stale,
+                _ => State::Live(None),
+            }
+        }
+    }
+}
+
+/// The folders a run writes to: folder arguments themselves and the folders
+/// of file arguments.
+pub fn roots(args: &[OsString]) -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = Vec::new();
+
+    for arg in args.iter().filter(|arg| !data_uri::is_data_uri(arg)) {
+        let path = Path::new(arg);
+        let root = if path.is_dir() {
+            path.to_path_buf()
+        } else {
+            match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            }
+        };
+
+        if root.is_dir()
+            && !roots
+                .iter()
+                .any(|known| paths::canonical(known) == paths::canonical(&root))
+        {
+            roots.push(root);
+        }
+    }
+
+    roots
+}
+
+/// Locks every folder in `roots`. A folder locked by another run is waited
+/// for up to `wait`, locks of runs that crashed are taken over. Folders that
+/// can't hold a lock file, like read-only ones, are fixed without one.
+pub fn acquire(roots: &[PathBuf], wait: Option<Duration>) -> Result<Locks, String> {
+    let deadline = Instant::now() + wait.unwrap_or_default();
+    let mut locks = Locks { paths: Vec::new() };
+
+    for root in roots {
+        let path = root.join(LOCK_FILE);
+
+        loop {
+            let created = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(paths::io_path(&path));
+
+            match created {
+                Ok(mut file) => {
+                    let _ = write!(file, "{}\n{}\n", std::process::id(), unix_now());
+                    locks.paths.push(path);
+                    break;
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+                Err(_) => break,
+            }
+
+            match state(&path) {
+                State::Live(_) if Instant::now() < deadline => std::thread::sleep(POLL_INTERVAL),
+                State::Live(holder) => {
+                    let holder = holder.map_or(String::new(), |holder| {
+                        format!(
+                            " (PID {}, started {} seconds ago)",
+                            holder.pid,
+                            unix_now().saturating_sub(holder.started)
+                        )
+                    });
+
+                    return Err(format!(
+                        "\"{}\" is being fixed by another Pixfix{}! Use \"--wait-lock <secs>\" to wait for it.",
+                        root.display(),
+                        holder
+                    ));
+                }
+                State::Stale => {
+                    diagnostic!("Taking over the stale lock in \"{}\"", root.display());
+                    let _ = std::fs::remove_file(paths::io_path(&path));
+                }
+            }
+        }
+    }
+
+    Ok(locks)
+}
@@ -1,294 +1,711 @@
 use std::{
-    io::stdin,
+    ffi::OsString,
+    io::{stderr, stdin, IsTerminal},
     path::{Path, PathBuf},
-    sync::{atomic::AtomicU16, Arc},
-    time::Instant,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
-use tokio::task::JoinSet;
-
-use image::{GenericImage, GenericImageView, Rgba};
-use spade::{DelaunayTriangulation, Point2, Triangulation};
-
-static EXTENSIONS: &[&'static str] = &["png"];
-static NEIGHBORS: &[(i32, i32)] = &[
-    (-1, -1),
-    (0, -1),
-    (1, -1),
-    (1, 0),
-    (1, 1),
-    (0, 1),
-    (-1, 1),
-    (-1, 0),
-];
-
-struct VoronoiColor {
-    r: u8,
-    g: u8,
-    b: u8,
+use pixfix::{
+    archive, backup, batch, compare,
+    convert::Converted,
+    data_uri, diagnostic,
+    error::PixfixError,
+    events, extract,
+    files::{self, ResolvedFiles},
+    frames, hook, human, info, lock,
+    mirror::{self, Copied},
+    options::{self, Options},
+    output, prescan,
+    progress::{self, Estimator},
+    report::{self, ReportFormat, Status, Summary},
+    resume::Resume,
+    rojo, selftest, serve, shell, stats, validate,
+};
+
+fn draw_watermark() {
+    diagnostic!(
+        "   ____ _____  _______ _____  __
+  |  _ \\_ _\\ \\/ /  ___|_ _\\ \\/ /
+  | |_) | | \\  /| |_   | | \\  / 
+  |  __/| | /  \\|  _|  | | /  \\ 
+  |_|  |___/_/\\_\\_|   |___/_/\\_\\\n"
+    );
 }
 
-fn valid_extension(path: &Path) -> bool {
-    let extension = match path.extension() {
-        Some(str) => str.to_str().unwrap(),
-        None => "",
-    };
+/// Whether the run would overwrite the files it was given, rather than only
+/// checking them or writing somewhere else.
+fn overwrites_inputs(options: &Options, args: &[OsString], has_project: bool) -> bool {
+    if options.check || options.mirror || options.diff_only.is_some() {
+        return false;
+    }
 
-    EXTENSIONS.contains(&extension)
+    has_project
+        || args.iter().any(|arg| {
+            !data_uri::is_data_uri(arg)
+                && (options.in_place || !archive::is_zip_file(Path::new(arg)))
+        })
 }
 
-fn resolve_files(args: Vec<String>) -> (Vec<PathBuf>, u16) {
-    let mut files = Vec::new();
-    let mut all_files: u16 = 0;
+/// Whether to refuse a run that would overwrite its inputs without
+/// `--in-place`. Only scripts have to pass it, with no terminal on stdin,
+/// and inputs that `--backup-store` or `--journal` keep a copy of can be put
+/// back, so those runs go ahead. `--output` only writes elsewhere together
+/// with `--mirror`, which `overwrites_inputs` already lets through.
+fn refuses_in_place(
+    options: &Options,
+    args: &[OsString],
+    has_project: bool,
+    terminal: bool,
+) -> bool {
+    !options.in_place
+        && !terminal
+        && !options.backup_store
+        && options.journal.is_none()
+        && overwrites_inputs(options, args, has_project)
+}
 
-    for arg in args {
-        let path = Path::new(&arg);
+/// Prints what an install or uninstall changed, passing on why it failed
+/// to `run_subcommand`.
+fn print_changes(result: Result<Vec<String>, String>) -> Result<bool, String> {
+    for change in result? {
+        human!("{}", change);
+    }
 
-        let metadata = match std::fs::metadata(path) {
-            Ok(data) => data,
-            Err(_) => {
-                println!("Ignoring \"{}\" - It does not exist!", arg);
+    Ok(true)
+}
 
-                continue;
-            }
-        };
+/// Exits with the outcome of a subcommand: 0 when it succeeded, 1 when it
+/// found problems or failed, printing why it failed.
+fn run_subcommand(result: Result<bool, String>) -> ! {
+    match result {
+        Ok(true) => std::process::exit(0),
+        Ok(false) => std::process::exit(1),
+        Err(err) => {
+            diagnostic!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
 
-        all_files += 1;
+#[cfg(all(feature = "profile", unix))]
+fn run_profile(args: Vec<OsString>) {
+    if let Err(err) = pixfix::profile::profile(args) {
+        diagnostic!("{}", err);
+        std::process::exit(1);
+    }
+}
 
-        if metadata.is_file() {
-            if !valid_extension(path) {
-                println!(
-                    "Ignoring \"{}\" - Only {} are accepted!",
-                    arg,
-                    EXTENSIONS.join("|")
-                );
+#[cfg(not(all(feature = "profile", unix)))]
+fn run_profile(_: Vec<OsString>) {
+    diagnostic!(
+        "This Pixfix was built without profiling! Build it with \"--features profile\" on Linux or macOS."
+    );
+    std::process::exit(1);
+}
 
-                continue;
-            }
+#[cfg(feature = "gui")]
+async fn run_gui(args: Vec<OsString>) -> ! {
+    run_subcommand(pixfix::gui::gui(args).await)
+}
 
-            files.push(path.to_path_buf());
-        }
+#[cfg(not(feature = "gui"))]
+async fn run_gui(_: Vec<OsString>) -> ! {
+    diagnostic!("This Pixfix was built without the window! Build it with \"--features gui\".");
+    std::process::exit(1);
+}
 
-        if !metadata.is_dir() {
-            continue;
+/// Fixes every file, returning the status of each in input order.
+async fn fix_files(
+    files: Vec<PathBuf>,
+    options: &Arc<Options>,
+    summary: &mut Summary,
+    resume: &mut Option<Resume>,
+) -> Vec<Status> {
+    let mut statuses = vec![Status::Failed; files.len()];
+
+    // Files finished by an interrupted run keep the status they got then.
+    // The rest are fixed, remembering where they were in `files`.
+    let mut indices = Vec::new();
+    let mut pending = Vec::new();
+
+    for (index, path) in files.into_iter().enumerate() {
+        match resume.as_ref().and_then(|resume| resume.finished(&path)) {
+            Some(status) => {
+                statuses[index] = status;
+                summary.resumed += 1;
+            }
+            None => {
+                indices.push(index);
+                pending.push(path);
+            }
         }
+    }
 
-        let dir = match std::fs::read_dir(arg.clone()) {
-            Ok(data) => data,
-            Err(_) => {
-                println!("Ignoring \"{}\" - An error occured reading directory!", arg);
+    // With --sort-output results are held back and printed in input
+    // order once everything is done, so the output is deterministic.
+    let mut results = Vec::new();
 
-                continue;
+    // Only a batch has later images that can reuse a fill.
+    if pending.len() > 1 {
+        frames::enable();
+    }
+
+    if options.validate_first {
+        let problems = validate::check_files(&pending, options);
+
+        if !problems.is_empty() {
+            diagnostic!(
+                "{} of {} files failed validation, nothing was changed!",
+                problems.len(),
+                pending.len()
+            );
+
+            for index in &indices {
+                statuses[*index] = Status::Skipped;
             }
-        };
 
-        all_files -= 1;
+            for (index, err) in problems {
+                statuses[indices[index]] = summary.record(&pending[index], Err(err));
+            }
+
+            summary.aborted = true;
+            return statuses;
+        }
+    }
 
-        for path in dir {
-            if path.is_err() {
-                continue;
+    // With --strict the first failure cancels the files that haven't
+    // started yet, including failures from before the batch.
+    let cancel = Arc::new(AtomicBool::new(options.strict && summary.failed > 0));
+
+    // Counts what was recorded before the batch too, like the start event.
+    let done = Arc::new(AtomicUsize::new(summary.reports.len()));
+    let progress = options.events.then(|| {
+        let total = pending.len() + summary.reports.len();
+        let done = done.clone();
+        let start = Instant::now();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            // The first tick is immediate, nothing is done yet.
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+                let seconds = start.elapsed().as_secs_f32();
+                events::progress(done.load(Ordering::Relaxed), total, seconds);
             }
+        })
+    });
+
+    // --progress redraws its line at most twice a second on a terminal, and
+    // prints one every 30 seconds when stderr is redirected.
+    let sizes: Vec<u64> = match options.progress {
+        true => pending
+            .iter()
+            .map(|path| std::fs::metadata(path).map_or(0, |metadata| metadata.len()))
+            .collect(),
+        false => Vec::new(),
+    };
+    let estimator = Arc::new(Mutex::new(Estimator::new(
+        pending.len(),
+        sizes.iter().sum(),
+        Instant::now(),
+    )));
+    let live = stderr().is_terminal();
+
+    if options.progress {
+        progress::enable();
+    }
 
-            let path: std::path::PathBuf = path.unwrap().path();
-            let metadata = match std::fs::metadata(path.clone()) {
-                Ok(data) => data,
-                Err(_) => {
-                    println!(
-                        "Ignoring \"{}\" - An error occured reading file metadata!",
-                        path.display()
-                    );
+    let ticker = options.progress.then(|| {
+        let estimator = estimator.clone();
+        let period = if live { 500 } else { 30_000 };
 
-                    continue;
-                }
-            };
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(period));
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+                let line = estimator
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .line(Instant::now());
 
-            if !metadata.is_file() {
-                continue;
+                if live {
+                    output::set_status(&line);
+                } else {
+                    diagnostic!("{}", line);
+                }
             }
+        })
+    });
 
-            all_files += 1;
+    batch::fix_files(pending, options, &cancel, |finished| {
+        done.fetch_add(1, Ordering::Relaxed);
 
-            if !valid_extension(&path) {
-                println!(
-                    "Ignoring \"{}\" - Only {} are accepted!",
-                    path.display(),
-                    EXTENSIONS.join("|")
-                );
+        if options.progress {
+            estimator
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .finished(sizes[finished.index], Instant::now());
+        }
 
-                continue;
+        // Nothing to fix is clean, not a failure.
+        let failed = matches!(
+            &finished.result,
+            Err(err) if !matches!(err, PixfixError::NothingToFix)
+        );
+
+        if options.strict && failed {
+            cancel.store(true, Ordering::Relaxed);
+        }
+
+        if options.sort_output {
+            results.push(finished);
+        } else {
+            let status = summary.record(&finished.path, finished.result);
+            statuses[indices[finished.index]] = status;
+
+            if let Some(resume) = resume {
+                resume.record(&finished.path, status);
             }
+        }
+    })
+    .await;
+
+    if let Some(progress) = progress {
+        progress.abort();
+    }
+
+    if let Some(ticker) = ticker {
+        ticker.abort();
+
+        if live {
+            output::set_status("");
+        }
+    }
+
+    results.sort_by_key(|finished| finished.index);
 
-            files.push(path);
+    for finished in results {
+        let status = summary.record(&finished.path, finished.result);
+        statuses[indices[finished.index]] = status;
+
+        if let Some(resume) = resume {
+            resume.record(&finished.path, status);
         }
     }
 
-    (files, all_files)
+    statuses
 }
 
-fn convert_image(path: &Path, debug: bool) -> bool {
-    let mut img = match image::open(path) {
-        Ok(value) => value,
-        Err(err) => {
-            println!(
-                "Error occured opening image \"{}\":\n{:?}",
-                path.display(),
-                err
-            );
-            return false;
+/// Fixes the files of `args` while they are still being found, rather than
+/// resolving every file of a large tree before fixing the first one.
+/// Returns what the scan found once it is done.
+async fn fix_streamed(
+    args: Vec<OsString>,
+    options: &Arc<Options>,
+    summary: &mut Summary,
+) -> ResolvedFiles {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let resolving = tokio::task::spawn_blocking({
+        let options = options.clone();
+        move || files::resolve_files_streamed(args, &options, sender)
+    });
+
+    let mut results = Vec::new();
+    let cancel = Arc::new(AtomicBool::new(false));
+
+    // A recursive scan is a batch, even though how many files it finds is
+    // only known at the end.
+    frames::enable();
+
+    batch::fix_stream(receiver, options, &cancel, |finished| {
+        if options.sort_output {
+            results.push(finished);
+        } else {
+            summary.record(&finished.path, finished.result);
         }
-    };
+    })
+    .await;
 
-    let mut points: Vec<Point2<f64>> = Vec::new();
-    let mut colors: Vec<VoronoiColor> = Vec::new();
-    let mut transparent_pixels: Vec<(u32, u32, Rgba<u8>)> = Vec::new();
-    let mut position_to_index: std::collections::HashMap<(u32, u32), usize> = Default::default();
+    results.sort_by_key(|finished| finished.index);
 
-    let (width, height) = img.dimensions();
+    for finished in results {
+        summary.record(&finished.path, finished.result);
+    }
 
-    for (x, y, color) in img.pixels() {
-        let rgba = color.0;
+    resolving
+        .await
+        .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))
+}
 
-        let a = rgba[3];
+/// Records the files the scan skipped and counted.
+fn record_resolved(summary: &mut Summary, resolved: &ResolvedFiles) {
+    summary.record_skipped(&resolved.skipped);
+    for path in &resolved.no_alpha {
+        summary.record(path, Ok(Converted::NoAlphaChannel));
+    }
+    summary.failed += resolved.failed();
+    summary.read_only = resolved.read_only;
+    summary.oversized = resolved.oversized;
+    summary.duplicates = resolved.duplicates;
+    summary.ignored = resolved.ignored;
+}
 
-        if a == 0 {
-            transparent_pixels.push((x, y, color));
-            continue;
-        }
+fn print_project_summary(project: &rojo::Project, ranges: &[(usize, usize)], statuses: &[Status]) {
+    human!();
 
-        let r = rgba[0];
-        let g = rgba[1];
-        let b = rgba[2];
+    for (entry, (start, end)) in project.entries.iter().zip(ranges) {
+        let statuses = &statuses[*start..*end];
+        let count = |status| statuses.iter().filter(|s| **s == status).count();
 
-        for (nx, ny) in NEIGHBORS {
-            let neighbor_x = x as i32 + nx;
-            let neighbor_y = y as i32 + ny;
+        human!(
+            "{} ({}): {} fixed, {} failed, {} without alpha",
+            entry.name,
+            entry.path.display(),
+            count(Status::Fixed),
+            count(Status::Failed),
+            count(Status::NoAlpha)
+        );
+    }
+}
 
-            if neighbor_x as u32 >= width || neighbor_y < 0 {
-                continue;
+#[tokio::main]
+async fn main() {
+    if std::env::args_os()
+        .skip(1)
+        .any(|arg| arg == "--help" || arg == "-h")
+    {
+        human!("{}", options::help());
+        return;
+    }
+
+    // Environment defaults go before the command line so it overrides them.
+    let args = options::env_args().and_then(|env_args| {
+        let (args, env_args) =
+            options::apply_working_dir(std::env::args_os().skip(1).collect(), env_args)?;
+        // A response file may change the directory too, from where it was read.
+        let (args, _) =
+            options::apply_working_dir(options::expand_response_files(args)?, Vec::new())?;
+
+        Ok((args, env_args))
+    });
+    let (mut args, env_args) = match args {
+        Ok(value) => value,
+        Err(err) => {
+            diagnostic!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let mut project = None;
+
+    if let Some(command) = args.first() {
+        if command == "hook" {
+            if args.get(1).is_some_and(|arg| arg == "install") {
+                run_subcommand(print_changes(hook::install()));
             }
 
-            if neighbor_y as u32 >= height || neighbor_y < 0 {
-                continue;
+            let (staged, fix, rest) = hook::parse_hook_args(args.split_off(1));
+
+            if !staged {
+                diagnostic!(
+                    "Usage: pixfix hook --staged [--fix] [options]\n       pixfix hook install"
+                );
+                std::process::exit(2);
             }
 
-            let neighbor_rgba = img.get_pixel(neighbor_x as u32, neighbor_y as u32).0;
+            run_subcommand(hook::run_staged(fix, [env_args, rest].concat()));
+        } else if command == "serve" {
+            let result = match serve::parse_serve_args(args.split_off(1)) {
+                Ok((serve_options, rest)) => {
+                    serve::serve(serve_options, [env_args, rest].concat()).await
+                }
+                Err(err) => Err(err),
+            };
 
-            if neighbor_rgba[3] != 0 {
-                continue;
+            if let Err(err) = result {
+                diagnostic!("{}", err);
+                std::process::exit(1);
             }
 
-            position_to_index.insert((x, y), points.len());
-            points.push(Point2::new(x as f64, y as f64));
-            colors.push(VoronoiColor { r, g, b });
+            return;
+        } else if command == "validate" {
+            run_subcommand(validate::validate(args.split_off(1)));
+        } else if command == "extract-alpha" {
+            run_subcommand(extract::extract_alpha(args.split_off(1)));
+        } else if command == "info" {
+            run_subcommand(info::info(args.split_off(1)));
+        } else if command == "restore" {
+            run_subcommand(backup::restore(args.split_off(1)));
+        } else if command == "self-test" {
+            run_subcommand(selftest::self_test());
+        } else if command == "undo" {
+            run_subcommand(backup::undo(args.split_off(1)));
+        } else if command == "benchmark-compare" || command == "--benchmark-compare" {
+            run_subcommand(compare::benchmark_compare(args.split_off(1)));
+        } else if command == "strip-stats" {
+            run_subcommand(stats::strip_stats(args.split_off(1)));
+        } else if command == "rojo" {
+            let Some(project_path) = args.get(1) else {
+                diagnostic!("Usage: pixfix rojo <project.json> [options]");
+                std::process::exit(2);
+            };
 
-            break;
+            match rojo::load_project(Path::new(project_path)) {
+                Ok(value) => {
+                    // Options given on the command line override the
+                    // environment's, which override the project's.
+                    let mut project_args: Vec<OsString> = std::mem::take(&mut args);
+                    args = value.args.clone();
+                    args.extend(env_args.iter().cloned());
+                    args.extend(project_args.drain(2..));
+                    project = Some(value);
+                }
+                Err(err) => {
+                    diagnostic!("{}", err);
+                    std::process::exit(1);
+                }
+            }
+        } else if command == "profile" {
+            run_profile(args[1..].to_vec());
+            return;
+        } else if command == "install-shell-integration" {
+            run_subcommand(print_changes(shell::install()));
+        } else if command == "uninstall-shell-integration" {
+            run_subcommand(print_changes(shell::uninstall()));
         }
     }
 
-    if points.len() == 0 {
-        println!("No transparent pixels to fix: {:?}", path);
-        return false;
+    if project.is_none() {
+        args.splice(0..0, env_args);
     }
 
-    let triangulation: DelaunayTriangulation<Point2<f64>> =
-        Triangulation::bulk_load(points).unwrap();
+    if let Some(index) = args.iter().position(|arg| arg == "--gui") {
+        args.remove(index);
+        run_gui(args).await;
+    }
 
-    for (x, y, color) in transparent_pixels.iter() {
-        let rgba = color.0;
-        let mut a = rgba[3];
+    // `--resume` only carries on a run with the very same arguments.
+    let run_args = args.clone();
 
-        let closest_neighbor =
-            match &triangulation.nearest_neighbor(Point2::new(*x as f64, *y as f64)) {
-                Some(value) => value.clone(),
-                None => continue,
-            };
+    // Usage errors exit with 2, like the hook's, so scripts and CI can't
+    // mistake a mistyped option for a run that went fine.
+    let (mut options, args) = match options::parse_args(args) {
+        Ok(value) => value,
+        Err(err) => {
+            diagnostic!("{}", err);
+            std::process::exit(2);
+        }
+    };
 
-        let closest_position = closest_neighbor.position();
+    if let Some(log_file) = &options.log_file {
+        if let Err(err) = output::log_to(log_file) {
+            diagnostic!("Unable to open \"{}\": {}", log_file.display(), err);
+            std::process::exit(1);
+        }
+    }
 
-        if let Some(closest_index) =
-            position_to_index.get(&(closest_position.x as u32, closest_position.y as u32))
-        {
-            let closest_color = &colors[*closest_index];
+    if options.output_format != ReportFormat::Human || options.events || options.print_modified {
+        output::human_to_stderr(true);
+    }
 
-            if debug {
-                a = 255;
-            }
+    if options.events || options.print_modified {
+        options.no_pause = true;
+    }
 
-            img.put_pixel(
-                *x,
-                *y,
-                Rgba::<u8> {
-                    0: [closest_color.r, closest_color.g, closest_color.b, a],
-                },
-            )
-        }
+    // `--output` only applies to data URIs and `--mirror`, so the images
+    // would still be overwritten where they are.
+    if options.output.is_some()
+        && !options.mirror
+        && (project.is_some() || args.iter().any(|arg| !data_uri::is_data_uri(arg)))
+    {
+        diagnostic!("--output only applies to data: URIs and --mirror - Pass --mirror --output <dir> to write the fixed images elsewhere, or leave --output out to fix them in place.");
+        std::process::exit(1);
     }
 
-    img.save(path).expect("Unable to save image");
-    println!("{:?}", path.display());
+    // Dropping files on the exe is meant to fix them where they are, but in a
+    // script a wrong path would quietly overwrite the wrong folder.
+    if refuses_in_place(&options, &args, project.is_some(), stdin().is_terminal()) {
+        diagnostic!("Refusing to overwrite images in place from a script - Pass --in-place to confirm, or --mirror --output <dir> to write them elsewhere.");
+        std::process::exit(1);
+    }
 
-    true
-}
+    // Trimmed images no longer line up with whatever refers to their pixels.
+    if options.trim && !options.force && overwrites_inputs(&options, &args, project.is_some()) {
+        diagnostic!("Refusing to trim images in place - Pass --force to confirm, or --mirror --output <dir> to write them elsewhere.");
+        std::process::exit(1);
+    }
 
-fn draw_watermark() {
-    println!(
-        "   ____ _____  _______ _____  __
-  |  _ \\_ _\\ \\/ /  ___|_ _\\ \\/ /
-  | |_) | | \\  /| |_   | | \\  / 
-  |  __/| | /  \\|  _|  | | /  \\ 
-  |_|  |___/_/\\_\\_|   |___/_/\\_\\\n"
-    );
-}
+    let mirror = if options.mirror {
+        match mirror::prepare(&args, &mut options) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                diagnostic!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
 
-#[tokio::main]
-async fn main() {
-    let mut args: Vec<_> = std::env::args().collect();
-    let mut debug = false;
+    // Checks write nothing, so they can run next to anything.
+    let locks = if options.check {
+        None
+    } else {
+        let mut roots = match &project {
+            Some(project) => vec![project.root.clone()],
+            None => lock::roots(&args),
+        };
 
-    args.remove(0);
+        if options.mirror {
+            roots.extend(options.output.clone());
+        }
 
-    for index in 0..args.len() {
-        let arg = &args[index];
-        if arg == &"-d".to_owned() {
-            debug = true;
-            args.remove(index);
-            break;
+        match lock::acquire(&roots, options.wait_lock) {
+            Ok(locks) => {
+                locks.release_on_ctrl_c();
+                Some(locks)
+            }
+            Err(err) => {
+                diagnostic!("{}", err);
+                std::process::exit(1);
+            }
         }
-    }
+    };
+
+    let mut resume = match &options.resume {
+        Some(path) => match Resume::open(path, &run_args) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                diagnostic!("{}", err);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    let options = Arc::new(options);
 
     let start = Instant::now();
-    let files_fixed: Arc<AtomicU16> = Arc::new(AtomicU16::new(0));
-    let files_failed: Arc<AtomicU16> = Arc::new(AtomicU16::new(0));
+    let mut summary = Summary {
+        events: options.events,
+        print_modified: options.print_modified,
+        ..Default::default()
+    };
 
     draw_watermark();
 
-    if args.len() == 0 {
-        println!("Drop png files on the exe to fix them!");
-    } else {
-        let mut threads = JoinSet::new();
+    if let Some(project) = project {
+        for arg in args {
+            diagnostic!(
+                "Ignoring \"{}\" - Paths can't be combined with a Rojo project!",
+                Path::new(&arg).display()
+            );
+        }
 
-        let (files, all_files) = resolve_files(args);
-        let num_failed = all_files - files.len() as u16;
-        files_failed.fetch_add(num_failed, std::sync::atomic::Ordering::Relaxed);
+        let (mut resolved, ranges) = project.resolve_files(&options);
+        let kept = prescan::prescan(&mut resolved, &options);
+        let ranges = prescan::kept_ranges(&ranges, &kept);
 
-        for path in files {
-            let files_fixed_thread = files_fixed.clone();
-            let files_unfixed_thread = files_failed.clone();
+        if options.events {
+            events::start(resolved.files.len() + resolved.skipped.len() + resolved.no_alpha.len());
+        }
 
-            threads.spawn_blocking(move || {
-                let converted = convert_image(path.as_path(), debug);
-                if converted == true {
-                    files_fixed_thread.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                } else {
-                    files_unfixed_thread.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        record_resolved(&mut summary, &resolved);
+
+        let statuses = fix_files(resolved.files, &options, &mut summary, &mut resume).await;
+        print_project_summary(&project, &ranges, &statuses);
+    } else if let Some(mirror) = mirror {
+        if options.events {
+            events::start(mirror.images.len());
+        }
+
+        let statuses = fix_files(mirror.images.clone(), &options, &mut summary, &mut resume).await;
+
+        // Images that weren't fixed still belong in the mirror.
+        let unfixed = mirror
+            .images
+            .iter()
+            .zip(statuses)
+            .filter(|(_, status)| *status != Status::Fixed)
+            .map(|(path, _)| path);
+
+        // A failed --validate-first writes nothing at all.
+        let copies = if summary.aborted {
+            Vec::new()
+        } else {
+            mirror.passthrough.iter().chain(unfixed).collect()
+        };
+
+        for path in copies {
+            match mirror.copy_through(path, options.link) {
+                Ok(Copied::Copied) => summary.copied += 1,
+                Ok(Copied::Linked) => summary.linked += 1,
+                Err(err) => {
+                    diagnostic!("Failed to copy \"{}\" - {}", path.display(), err);
+                    summary.failed += 1;
                 }
-            });
+            }
         }
+    } else if args.is_empty() {
+        human!("Drop png files on the exe to fix them!");
+    } else {
+        let (data_uris, args): (Vec<OsString>, Vec<OsString>) =
+            args.into_iter().partition(|arg| data_uri::is_data_uri(arg));
+
+        // Recursive scans of large trees start fixing as files are found,
+        // unless something needs the whole list before the first file.
+        let streamed = options.recursive
+            && !options.events
+            && !options.progress
+            && !options.validate_first
+            && !options.strict
+            && resume.is_none();
+
+        if streamed {
+            let resolved = fix_streamed(args, &options, &mut summary).await;
+            record_resolved(&mut summary, &resolved);
+
+            for uri in data_uris {
+                let result = data_uri::fix_data_uri(&uri, &options);
+                summary.record(Path::new("data URI"), result);
+            }
+        } else {
+            let mut resolved = files::resolve_files(args, &options);
+            prescan::prescan(&mut resolved, &options);
+
+            if options.events {
+                events::start(
+                    resolved.files.len()
+                        + resolved.skipped.len()
+                        + resolved.no_alpha.len()
+                        + data_uris.len(),
+                );
+            }
+
+            record_resolved(&mut summary, &resolved);
 
-        loop {
-            if let None = threads.join_next().await {
-                break;
+            for uri in data_uris {
+                let result = data_uri::fix_data_uri(&uri, &options);
+                summary.record(Path::new("data URI"), result);
             }
+
+            fix_files(resolved.files, &options, &mut summary, &mut resume).await;
+        }
+    }
+
+    drop(locks);
+
+    // Failed files aren't finished, resuming again tries only those.
+    if let Some(resume) = resume {
+        if summary.failed == 0 {
+            resume.finish();
         }
     }
 
@@ -296,21 +713,173 @@ async fn main() {
         .saturating_duration_since(start)
         .as_secs_f32();
 
-    println!("");
+    human!();
 
-    if files_fixed.load(std::sync::atomic::Ordering::Relaxed) > 0 {
-        println!(
+    if options.check {
+        human!(
+            "Checked {} images in {:.10} seconds, {} need fixing!",
+            summary.clean + summary.dirty,
+            time_taken,
+            summary.dirty
+        );
+    } else if summary.fixed > 0 {
+        human!(
             "Successfully fixed {:?} images in {:.10} seconds!",
-            files_fixed, time_taken
+            summary.fixed,
+            time_taken
         );
     } else {
-        println!("No files where able to be fixed!")
+        human!("No files where able to be fixed!")
+    }
+    if !options.check && summary.clean > 0 {
+        human!("{} images had nothing to fix!", summary.clean);
+    }
+    if summary.ignored > 0 {
+        human!("Ignored {} files of unsupported types!", summary.ignored);
+    }
+    if summary.failed > 0 {
+        human!("Skipped {:?} files that couldn't be fixed!", summary.failed);
+    }
+    if summary.no_alpha > 0 {
+        human!(
+            "Skipped {:?} images without an alpha channel!",
+            summary.no_alpha
+        );
+    }
+    if summary.resumed > 0 {
+        human!(
+            "Skipped {} files finished before the run was interrupted!",
+            summary.resumed
+        );
+    }
+    if summary.copied > 0 {
+        human!("Copied {} files to the mirror!", summary.copied);
     }
-    if files_failed.load(std::sync::atomic::Ordering::Relaxed) > 0 {
-        println!("Skipped {:?} files that couldn't be fixed!", files_failed);
+    if summary.linked > 0 {
+        human!("Linked {} files to the mirror!", summary.linked);
+    }
+    if summary.read_only > 0 {
+        human!("Skipped {} read-only files!", summary.read_only);
+    }
+    if summary.oversized > 0 {
+        human!("Skipped {} files over the size limits!", summary.oversized);
+    }
+    if summary.duplicates > 0 {
+        human!("Skipped {} duplicate files!", summary.duplicates);
     }
 
-    println!("\npress enter to exit");
+    if options.events {
+        events::done(&summary, time_taken);
+    }
+
+    if options.print_modified {
+        let separator = if options.print0 { '\0' } else { '\n' };
+        let list: String = summary
+            .modified
+            .iter()
+            .map(|path| format!("{}{}", path.display(), separator))
+            .collect();
+
+        output::write_result(&list);
+    }
 
-    stdin().read_line(&mut String::new()).unwrap();
+    match options.output_format {
+        ReportFormat::Human => {}
+        ReportFormat::Github => report::print_github(&summary.reports),
+        ReportFormat::Junit => {
+            let written = std::fs::File::create(&options.report_file)
+                .and_then(|mut file| report::write_junit(&summary.reports, time_taken, &mut file));
+
+            match written {
+                Ok(()) => human!(
+                    "Wrote JUnit report to \"{}\"",
+                    options.report_file.display()
+                ),
+                Err(err) => diagnostic!(
+                    "Unable to write JUnit report \"{}\": {}",
+                    options.report_file.display(),
+                    err
+                ),
+            }
+        }
+    }
+
+    if !options.no_pause {
+        human!("\npress enter to exit");
+
+        let _ = stdin().read_line(&mut String::new());
+    }
+
+    // Checks fail when anything still needs fixing, so hooks and CI can
+    // block on them.
+    let failed_check = options.check && (summary.dirty > 0 || summary.failed > 0);
+    let failed_strict = options.strict && summary.failed > 0;
+    let failed_validation = summary.aborted;
+
+    // Exit explicitly, files abandoned by --timeout-per-file would otherwise
+    // keep the runtime alive until they finish.
+    std::process::exit(i32::from(
+        failed_check || failed_strict || failed_validation,
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refused(options: Options, terminal: bool) -> bool {
+        refuses_in_place(&options, &["a.png".into()], false, terminal)
+    }
+
+    fn in_place() -> Options {
+        Options {
+            in_place: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn a_terminal_without_in_place_goes_ahead() {
+        assert!(!refused(Options::default(), true));
+    }
+
+    #[test]
+    fn a_terminal_with_in_place_goes_ahead() {
+        assert!(!refused(in_place(), true));
+    }
+
+    #[test]
+    fn a_script_without_in_place_is_refused() {
+        assert!(refused(Options::default(), false));
+    }
+
+    #[test]
+    fn a_script_with_in_place_goes_ahead() {
+        assert!(!refused(in_place(), false));
+    }
+
+    #[test]
+    fn a_script_that_keeps_backups_goes_ahead() {
+        let backup_store = Options {
+            backup_store: true,
+            ..Default::default()
+        };
+        let journal = Options {
+            journal: Some("journal".into()),
+            ..Default::default()
+        };
+
+        assert!(!refused(backup_store, false));
+        assert!(!refused(journal, false));
+    }
+
+    #[test]
+    fn a_script_that_writes_elsewhere_goes_ahead() {
+        let check = Options {
+            check: true,
+            ..Default::default()
+        };
+
+        assert!(!refused(check, false));
+    }
 }
@@ -0,0 +1,265 @@
+//! `--mirror` turns a folder into a complete copy under `--output`, with
+//! PNGs fixed and everything else passed through, so builds can point at it.
+
+use std::{
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    diagnostic,
+    files::is_png_file,
+    lock::LOCK_FILE,
+    options::{strip_path_prefix, Options},
+    paths::{self, io_path, Ancestors},
+};
+
+pub struct Mirror {
+    pub root: PathBuf,
+    pub output: PathBuf,
+    /// `output` resolved, to leave it out when it is inside `root`.
+    canonical_output: PathBuf,
+    /// `--path-prefix-strip`.
+    strip: Option<PathBuf>,
+    /// PNGs to fix into the mirror.
+    pub images: Vec<PathBuf>,
+    /// Everything else, copied or linked as it is.
+    pub passthrough: Vec<PathBuf>,
+}
+
+pub enum Copied {
+    Copied,
+    Linked,
+}
+
+/// Finds the files of `dir`, below the folders of `ancestors`, and creates
+/// its folder in the mirror. Symlinked folders are mirrored as folders,
+/// except the ones leading back to a folder above them, which are left out
+/// rather than mirrored over and over.
+fn walk(mirror: &mut Mirror, dir: &Path, ancestors: &Ancestors) -> Result<(), String> {
+    let entries = std::fs::read_dir(io_path(dir))
+        .map_err(|err| format!("Unable to read \"{}\": {}", dir.display(), err))?;
+
+    std::fs::create_dir_all(mirror.mirrored(dir)).map_err(|err| {
+        format!(
+            "Unable to create \"{}\": {}",
+            mirror.mirrored(dir).display(),
+            err
+        )
+    })?;
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        // With --allow-overlap the output may be inside the folder being
+        // mirrored, and its files must not be mirrored into itself. Locks
+        // only mean something to the run that holds them.
+        if paths::canonical(&path) == mirror.canonical_output
+            || path.file_name() == Some(LOCK_FILE.as_ref())
+        {
+            continue;
+        }
+
+        if path.is_dir() {
+            match ancestors.enter(&path) {
+                Some(ancestors) => walk(mirror, &path, &ancestors)?,
+                None => diagnostic!(
+                    "Ignoring \"{}\" - It links back to a folder above it!",
+                    path.display()
+                ),
+            }
+        } else if is_png_file(&path) {
+            mirror.images.push(path);
+        } else {
+            mirror.passthrough.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the options, creates every folder of the mirror and finds the
+/// files that go in it. Sets `options.mirror_root` so fixed images are
+/// written into the mirror.
+pub fn prepare(args: &[OsString], options: &mut Options) -> Result<Mirror, String> {
+    let Some(output) = options.output.clone() else {
+        return Err("\"--mirror\" needs an \"--output\" folder!".into());
+    };
+
+    if !options.recursive {
+        return Err("\"--mirror\" needs \"--recursive\"!".into());
+    }
+
+    if options.check {
+        return Err("\"--mirror\" can't be combined with \"--check\"!".into());
+    }
+
+    let [root] = args else {
+        return Err("\"--mirror\" takes a single folder!".into());
+    };
+    let root = PathBuf::from(root);
+
+    if !root.is_dir() {
+        return Err(format!("\"{}\" is not a folder!", root.display()));
+    }
+
+    if !options.allow_overlap && paths::overlaps(&root, &output) {
+        return Err(format!(
+            "\"{}\" and \"{}\" overlap, so Pixfix would pick up its own output! Use \"--allow-overlap\" to do it anyway.",
+            root.display(),
+            output.display()
+        ));
+    }
+
+    let output_is_empty = match std::fs::read_dir(&output) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(_) => true,
+    };
+
+    if !output_is_empty && !options.force {
+        return Err(format!(
+            "\"{}\" is not empty, use \"--force\" to write into it anyway!",
+            output.display()
+        ));
+    }
+
+    let mut mirror = Mirror {
+        root: root.clone(),
+        canonical_output: paths::canonical(&output),
+        output,
+        strip: options.path_prefix_strip.clone(),
+        images: Vec::new(),
+        passthrough: Vec::new(),
+    };
+
+    walk(&mut mirror, &root, &Ancestors::new(&root))?;
+    options.mirror_root = Some(root);
+
+    Ok(mirror)
+}
+
+impl Mirror {
+    /// Where `path` ends up in the mirror.
+    pub fn mirrored(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.root) {
+            Ok(relative) => self
+                .output
+                .join(strip_path_prefix(relative, self.strip.as_deref())),
+            Err(_) => self.output.clone(),
+        }
+    }
+
+    /// Copies `path` into the mirror byte for byte, or hard-links it with
+    /// `link`. Falls back to copying when linking fails, e.g. across drives.
+    pub fn copy_through(&self, path: &Path, link: bool) -> io::Result<Copied> {
+        let target = self.mirrored(path);
+
+        if target.exists() {
+            std::fs::remove_file(io_path(&target))?;
+        }
+
+        if link && std::fs::hard_link(io_path(path), io_path(&target)).is_ok() {
+            return Ok(Copied::Linked);
+        }
+
+        std::fs::copy(io_path(path), io_path(&target))?;
+
+        Ok(Copied::Copied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::TempDir;
+
+    /// Mirrors `input` into `output`, with or without `--allow-overlap`.
+    /// Outputs that already hold the input aren't empty, so `--force` is
+    /// passed too.
+    fn mirror(input: &Path, output: &Path, allow_overlap: bool) -> Result<Mirror, String> {
+        let mut options = Options {
+            output: Some(output.to_path_buf()),
+            recursive: true,
+            mirror: true,
+            force: true,
+            allow_overlap,
+            ..Default::default()
+        };
+
+        prepare(&[input.into()], &mut options)
+    }
+
+    fn refused_for_overlap(result: Result<Mirror, String>) -> bool {
+        result.is_err_and(|err| err.contains("overlap") && err.contains("--allow-overlap"))
+    }
+
+    #[test]
+    fn an_output_inside_the_input_needs_allow_overlap() {
+        let dir = TempDir::new("mirror-overlap").unwrap();
+        let (input, output) = (dir.path().join("in"), dir.path().join("in/out"));
+        std::fs::create_dir_all(&output).unwrap();
+        std::fs::write(input.join("a.png"), []).unwrap();
+        std::fs::write(output.join("b.png"), []).unwrap();
+
+        assert!(refused_for_overlap(mirror(&input, &output, false)));
+
+        // The output is left out of its own mirror.
+        let allowed = mirror(&input, &output, true).unwrap();
+        assert_eq!(allowed.images, [input.join("a.png")]);
+    }
+
+    #[test]
+    fn an_input_inside_the_output_needs_allow_overlap() {
+        let dir = TempDir::new("mirror-overlap").unwrap();
+        let (input, output) = (dir.path().join("out/in"), dir.path().join("out"));
+        std::fs::create_dir_all(&input).unwrap();
+        std::fs::write(input.join("a.png"), []).unwrap();
+
+        assert!(refused_for_overlap(mirror(&input, &output, false)));
+
+        let allowed = mirror(&input, &output, true).unwrap();
+        assert_eq!(allowed.images, [input.join("a.png")]);
+        assert_eq!(allowed.mirrored(&input.join("a.png")), output.join("a.png"));
+    }
+
+    #[test]
+    fn the_same_folder_spelled_differently_needs_allow_overlap() {
+        let dir = TempDir::new("mirror-overlap").unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir_all(&input).unwrap();
+        std::fs::write(input.join("a.png"), []).unwrap();
+        let output = dir.path().join("in/../in");
+
+        assert!(refused_for_overlap(mirror(&input, &output, false)));
+
+        let allowed = mirror(&input, &output, true).unwrap();
+        assert_eq!(allowed.images, [input.join("a.png")]);
+    }
+
+    #[test]
+    fn folders_side_by_side_are_mirrored() {
+        let dir = TempDir::new("mirror-overlap").unwrap();
+        let (input, output) = (dir.path().join("in"), dir.path().join("input-fixed"));
+        std::fs::create_dir_all(&input).unwrap();
+
+        assert!(!paths::overlaps(&input, &output));
+        assert!(mirror(&input, &output, false).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loops_are_mirrored_once() {
+        let dir = TempDir::new("mirror-loop").unwrap();
+        let (input, output) = (dir.path().join("in"), dir.path().join("out"));
+        std::fs::create_dir_all(input.join("sub")).unwrap();
+        std::fs::write(input.join("a.png"), []).unwrap();
+        std::os::unix::fs::symlink("..", input.join("sub/up")).unwrap();
+
+        let mirrored = mirror(&input, &output, false).unwrap();
+
+        assert_eq!(mirrored.images, [input.join("a.png")]);
+        assert!(output.join("sub").is_dir());
+        assert!(!output.join("sub/up").exists());
+    }
+}
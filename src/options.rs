@@ -0,0 +1,1186 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use glob::Pattern;
+
+use crate::{
+    archive::{self, is_zip_file},
+    atlas::{self, Atlas},
+    backup,
+    color::{Blend, ColorSpace},
+    diagnostic,
+    digest::DigestAlgorithm,
+    format::{FormatOptions, OutputFormat},
+    jfa::Backend,
+    paths::{io_path, is_read_only},
+    report::ReportFormat,
+};
+
+/// How the alpha channel of a fixed image is saved.
+#[derive(Clone, Copy, PartialEq)]
+pub enum AlphaChannelOutput {
+    /// In the image itself.
+    Combined,
+    /// In a grayscale `<name>_alpha.png` next to a fully opaque image.
+    Separate,
+}
+
+impl std::str::FromStr for AlphaChannelOutput {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "combined" => Ok(AlphaChannelOutput::Combined),
+            "separate" => Ok(AlphaChannelOutput::Separate),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The filter `--scale` resizes with.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ScaleFilter {
+    Nearest,
+    Bilinear,
+    Lanczos3,
+}
+
+impl std::str::FromStr for ScaleFilter {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "nearest" => Ok(ScaleFilter::Nearest),
+            "bilinear" => Ok(ScaleFilter::Bilinear),
+            "lanczos3" => Ok(ScaleFilter::Lanczos3),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Where `--output-stats` records what the fix did.
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputStats {
+    /// In a `pixfix` text chunk of the fixed PNG.
+    Png,
+}
+
+impl std::str::FromStr for OutputStats {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "png" => Ok(OutputStats::Png),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The color `--colorkey` treats as transparent in images without an alpha
+/// channel, written `RRGGBB` like `FF00FF`.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ColorKey(pub [u8; 3]);
+
+impl std::str::FromStr for ColorKey {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.strip_prefix('#').unwrap_or(value);
+
+        if value.len() != 6 || !value.is_ascii() {
+            return Err(());
+        }
+
+        let channel = |at: usize| u8::from_str_radix(&value[at..at + 2], 16).map_err(|_| ());
+
+        Ok(ColorKey([channel(0)?, channel(2)?, channel(4)?]))
+    }
+}
+
+/// `--max-file-size` is given in megabytes of this many bytes.
+pub const BYTES_PER_MB: f64 = 1024.0 * 1024.0;
+
+/// The ΔE `--quality-check` warns above unless `--quality-threshold` says
+/// otherwise.
+pub const QUALITY_THRESHOLD: f64 = 10.0;
+
+/// How many smaller levels `--mip-chain` saves below the full size image.
+#[derive(Clone, Copy, PartialEq)]
+pub enum MipChain {
+    Levels(u32),
+    /// Down to a single pixel on the longest side.
+    Auto,
+}
+
+impl MipChain {
+    pub fn levels(&self, width: u32, height: u32) -> u32 {
+        match self {
+            MipChain::Levels(levels) => *levels,
+            MipChain::Auto => width.max(height).max(1).ilog2(),
+        }
+    }
+}
+
+impl std::str::FromStr for MipChain {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "auto" => Ok(MipChain::Auto),
+            value => value.parse().map(MipChain::Levels).map_err(|_| ()),
+        }
+    }
+}
+
+/// The uniform grid of `--sprite-sheet-grid`, written `<columns>x<rows>`.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Grid {
+    pub columns: u32,
+    pub rows: u32,
+}
+
+/// Two non-zero numbers written `<a>x<b>`, like `4x4` or `8192x8192`.
+fn parse_pair(value: &str) -> Option<(u32, u32)> {
+    let value = value.to_ascii_lowercase();
+    let (first, second) = value.split_once('x')?;
+
+    match (first.parse(), second.parse()) {
+        (Ok(first), Ok(second)) if first > 0 && second > 0 => Some((first, second)),
+        _ => None,
+    }
+}
+
+impl std::str::FromStr for Grid {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (columns, rows) = parse_pair(value).ok_or(())?;
+
+        Ok(Grid { columns, rows })
+    }
+}
+
+/// How files are recognized as PNG images. Zip archives are always
+/// recognized by their extension.
+#[derive(Clone, Copy, PartialEq)]
+pub enum FormatDetection {
+    Extension,
+    /// By the PNG signature at the start of the file.
+    Magic,
+    /// Both have to agree, which catches misnamed files.
+    Both,
+}
+
+impl std::str::FromStr for FormatDetection {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "extension" => Ok(FormatDetection::Extension),
+            "magic" => Ok(FormatDetection::Magic),
+            "both" => Ok(FormatDetection::Both),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct Options {
+    /// Alpha given to filled pixels. 0 keeps them invisible, 255 shows the fill.
+    pub alpha_fill: u8,
+    pub retries: u32,
+    pub format: FormatOptions,
+    /// Resize the fixed image by this factor before saving it.
+    pub scale: Option<f32>,
+    pub scale_filter: ScaleFilter,
+    /// Also save `<name>_mip0.png` to `<name>_mipN.png`, each half the size
+    /// of the one before.
+    pub mip_chain: Option<MipChain>,
+    /// Save every image in each of these formats from a single fill,
+    /// instead of only in `format`.
+    pub multi_format: Vec<OutputFormat>,
+    /// Clear the read-only flag on files fixed in place, restoring it afterwards.
+    pub force_writable: bool,
+    /// Read every saved image back and check it.
+    pub verify: bool,
+    /// Skip files with the same content as one found before them.
+    pub deduplicate: bool,
+    /// Back up inputs before overwriting them, for `pixfix restore`.
+    pub backup_store: bool,
+    /// Back up inputs to this folder instead, for `pixfix undo`.
+    pub journal: Option<PathBuf>,
+    /// Also append the human readable and diagnostic output here.
+    pub log_file: Option<PathBuf>,
+    /// `--working-dir`, only recorded here. The directory is changed once,
+    /// by `apply_working_dir`, before the options are parsed.
+    pub working_dir: Option<PathBuf>,
+    pub verbose: bool,
+    /// Print per-file results in input order instead of completion order.
+    pub sort_output: bool,
+    /// How many times the fill is run, each pass seeding from the last.
+    pub repeat: u32,
+    /// Print a hash of every saved file, like `sha256sum` does.
+    pub digest: Option<DigestAlgorithm>,
+    /// Only report how many pixels need fixing, without writing anything.
+    pub check: bool,
+    /// Exit straight away instead of waiting for enter.
+    pub no_pause: bool,
+    /// Show how far the batch is, its throughput and the time left.
+    pub progress: bool,
+    /// Stop at the first file that fails and exit with 1.
+    pub strict: bool,
+    /// Check every file before fixing any, and fix none when one fails.
+    pub validate_first: bool,
+    /// Write newline delimited JSON events to stdout, see `events.rs`.
+    pub events: bool,
+    /// Print the files that changed to stdout once done, for scripts.
+    pub print_modified: bool,
+    /// Separate the `--print-modified` paths with NUL instead of newlines.
+    pub print0: bool,
+    /// How results are reported. Anything but human output moves the human
+    /// output to stderr.
+    pub output_format: ReportFormat,
+    /// Where the JUnit report is written.
+    pub report_file: PathBuf,
+    /// Save an image of only the changed pixels here instead of fixing the
+    /// input. A directory gets one diff per input, named after it.
+    pub diff_only: Option<PathBuf>,
+    /// Also save an image of the Voronoi cells of the fill here. A
+    /// directory gets one per input, named after it.
+    pub bleed_debug: Option<PathBuf>,
+    /// Atlases whose frames are fixed one by one, one for every page of the
+    /// descriptor.
+    pub atlases: Vec<Atlas>,
+    /// Where the progress of the batch is kept, so an interrupted run can
+    /// skip the files it already finished.
+    pub resume: Option<PathBuf>,
+    /// How long to wait for another run to finish with a folder before
+    /// giving up. Without it a locked folder is given up on straight away.
+    pub wait_lock: Option<Duration>,
+    /// Also seed the fill from the colored pixels on the edge of the image,
+    /// not only the ones next to transparent pixels.
+    pub edge_detect: bool,
+    /// Skip files larger than this many bytes.
+    pub max_file_size: Option<u64>,
+    /// Skip images wider or taller than this, read from their header.
+    pub max_dimensions: Option<(u32, u32)>,
+    /// Fail images of any other size instead of fixing them.
+    pub check_dimensions: Option<(u32, u32)>,
+    /// Fail images whose sides aren't powers of two.
+    pub check_power_of_two: bool,
+    /// Pad images with transparent pixels to power of two sides.
+    pub power_of_two_pad: bool,
+    /// Crop images to the pixels that aren't fully transparent.
+    pub trim: bool,
+    /// Transparent pixels kept around the content by `--trim`.
+    pub trim_margin: u32,
+    /// Warn about neighboring filled pixels whose colors differ by more
+    /// than this ΔE.
+    pub quality_check: Option<f64>,
+    /// Triangulate at most about this many border pixels per fill, evenly
+    /// spread over the ones found.
+    pub max_border_pixels: Option<usize>,
+    /// Drop border pixels within this distance of one that is kept, so
+    /// clusters of seeds thin out evenly.
+    pub poisson_disk_radius: Option<f64>,
+    /// Leave the text, time, EXIF, ICC profile and physical size chunks of
+    /// the input out of the fixed PNG.
+    pub strip_metadata: bool,
+    /// Leave the `sRGB`, `gAMA`, `cHRM` and `iCCP` chunks out as well.
+    pub strip_color_info: bool,
+    pub output_stats: Option<OutputStats>,
+    /// Spread the colors this many pixels outward instead of filling every
+    /// transparent pixel.
+    pub dilate: Option<u32>,
+    /// Make the pixels `dilate` spreads to opaque.
+    pub dilate_alpha: bool,
+    /// How `--dilate` combines the colors of neighbors.
+    pub blend: Blend,
+    /// Where colors are averaged, by `--dilate`.
+    pub colorspace: ColorSpace,
+    /// How the fill finds the nearest border pixels, see `jfa.rs`.
+    pub backend: Backend,
+    /// Treat the pixels of this color as transparent in images without an
+    /// alpha channel, which are skipped otherwise.
+    pub colorkey: Option<ColorKey>,
+    /// Save color keyed images with an alpha channel instead of recoloring
+    /// their key pixels only.
+    pub colorkey_to_alpha: bool,
+    /// Reuse the fill of an earlier image with the same alpha mask, in runs
+    /// of more than one file, see `frames.rs`.
+    pub frame_cache: bool,
+    /// Fill every cell of this grid on its own, for sprite sheets.
+    pub sprite_sheet_grid: Option<Grid>,
+    /// Give up on a file that takes longer than this.
+    pub timeout_per_file: Option<Duration>,
+    /// Overwrite zip archives instead of writing `<name>-fixed.zip`.
+    pub in_place: bool,
+    /// Decode PNGs that were cut short up to the last complete row.
+    pub tolerant: bool,
+    /// How many images are fixed at the same time.
+    pub jobs: usize,
+    /// Read and write files on threads of their own, next to the `jobs`
+    /// threads fixing them.
+    pub parallel_io: bool,
+    /// Where the image from a `data:` URI argument is saved. Without it the
+    /// fixed image is printed as a data URI. With `--mirror` the mirror
+    /// folder, other files are never written to it.
+    pub output: Option<PathBuf>,
+    /// The input stores premultiplied colors, which are unpremultiplied for
+    /// the fill and premultiplied again when saving.
+    pub premultiplied_alpha_input: bool,
+    /// The input stores `0` for opaque and the maximum for transparent. The
+    /// alpha is flipped for the fill and flipped back when saving.
+    pub alpha_invert: bool,
+    /// Also look for images in the subfolders of folder arguments.
+    pub recursive: bool,
+    /// Only fix the files in scanned folders that match one of these,
+    /// relative to the folder argument.
+    pub include: Vec<Pattern>,
+    pub format_detection: FormatDetection,
+    /// Skip what the repository's `.gitignore` files ignore when scanning
+    /// folders. `.pixfixignore` files are honored either way.
+    pub respect_gitignore: bool,
+    /// Mirror the folder argument into `--output`, see `mirror.rs`.
+    pub mirror: bool,
+    /// Hard-link files passed through to the mirror instead of copying them.
+    pub link: bool,
+    /// Write the mirror into an output folder that isn't empty.
+    pub force: bool,
+    /// Mirror into a folder inside the input, or the other way around.
+    pub allow_overlap: bool,
+    /// The folder being mirrored, set once the mirror is prepared.
+    pub mirror_root: Option<PathBuf>,
+    /// Left out of the paths inside the mirrored folder that start with it.
+    pub path_prefix_strip: Option<PathBuf>,
+    pub alpha_channel_output: AlphaChannelOutput,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            alpha_fill: 0,
+            retries: 0,
+            format: FormatOptions::default(),
+            multi_format: Vec::new(),
+            scale: None,
+            mip_chain: None,
+            scale_filter: ScaleFilter::Lanczos3,
+            force_writable: false,
+            verify: false,
+            deduplicate: false,
+            backup_store: false,
+            journal: None,
+            log_file: None,
+            working_dir: None,
+            verbose: false,
+            sort_output: false,
+            repeat: 1,
+            digest: None,
+            check: false,
+            no_pause: false,
+            strict: false,
+            progress: false,
+            validate_first: false,
+            events: false,
+            print_modified: false,
+            print0: false,
+            output_format: ReportFormat::Human,
+            report_file: PathBuf::from("pixfix-junit.xml"),
+            diff_only: None,
+            bleed_debug: None,
+            atlases: Vec::new(),
+            sprite_sheet_grid: None,
+            max_file_size: None,
+            max_dimensions: None,
+            check_dimensions: None,
+            check_power_of_two: false,
+            power_of_two_pad: false,
+            trim: false,
+            trim_margin: 0,
+            quality_check: None,
+            max_border_pixels: None,
+            poisson_disk_radius: None,
+            strip_metadata: false,
+            output_stats: None,
+            dilate: None,
+            dilate_alpha: false,
+            blend: Blend::Mean,
+            colorspace: ColorSpace::Srgb,
+            backend: Backend::Cpu,
+            colorkey: None,
+            colorkey_to_alpha: false,
+            frame_cache: true,
+            strip_color_info: false,
+            edge_detect: false,
+            wait_lock: None,
+            resume: None,
+            timeout_per_file: None,
+            in_place: false,
+            jobs: std::thread::available_parallelism().map_or(1, |jobs| jobs.get()),
+            parallel_io: false,
+            tolerant: false,
+            output: None,
+            premultiplied_alpha_input: false,
+            alpha_invert: false,
+            recursive: false,
+            include: Vec::new(),
+            format_detection: FormatDetection::Extension,
+            respect_gitignore: false,
+            mirror: false,
+            link: false,
+            force: false,
+            allow_overlap: false,
+            mirror_root: None,
+            path_prefix_strip: None,
+            alpha_channel_output: AlphaChannelOutput::Combined,
+        }
+    }
+}
+
+pub fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<OsString>) -> Result<T, String> {
+    let value = match value {
+        Some(value) => value.to_string_lossy().into_owned(),
+        None => return Err(format!("Missing value for \"{}\"!", flag)),
+    };
+
+    match value.parse() {
+        Ok(value) => Ok(value),
+        Err(_) => Err(format!("Invalid value \"{}\" for \"{}\"!", value, flag)),
+    }
+}
+
+fn parse_range(flag: &str, value: Option<OsString>, min: u8, max: u8) -> Result<u8, String> {
+    let value: u8 = parse_value(flag, value)?;
+
+    if value < min || value > max {
+        return Err(format!(
+            "Invalid value \"{}\" for \"{}\" - Expected {}-{}!",
+            value, flag, min, max
+        ));
+    }
+
+    Ok(value)
+}
+
+impl Options {
+    /// Whether `path` can't be fixed because it is read-only and would be
+    /// overwritten in place.
+    pub fn blocked_by_read_only(&self, path: &Path) -> bool {
+        !self.force_writable
+            && !self.check
+            && self.diff_only.is_none()
+            && (self.output_formats().iter())
+                .any(|format| self.output_path_as(path, format) == path)
+            && is_read_only(&io_path(path))
+    }
+
+    /// Every format the fixed images are saved in, the first one being the
+    /// one reported.
+    pub fn output_formats(&self) -> Vec<FormatOptions> {
+        if self.multi_format.is_empty() {
+            return vec![self.format.with_format(self.format.format)];
+        }
+
+        (self.multi_format.iter())
+            .map(|format| self.format.with_format(*format))
+            .collect()
+    }
+
+    /// Where inputs are backed up before they are overwritten, if anywhere.
+    pub fn journal_dir(&self) -> Option<&Path> {
+        match &self.journal {
+            Some(journal) => Some(journal),
+            None => self.backup_store.then_some(Path::new(backup::STORE)),
+        }
+    }
+
+    /// Where the fixed `path` is written, archives included.
+    pub fn output_path(&self, path: &Path) -> PathBuf {
+        self.output_path_as(path, &self.output_formats()[0])
+    }
+
+    /// Where the fixed `path` is written in `format`.
+    pub fn output_path_as(&self, path: &Path, format: &FormatOptions) -> PathBuf {
+        if is_zip_file(path) {
+            return archive::output_path(path, self);
+        }
+
+        if let (Some(root), Some(output)) = (&self.mirror_root, &self.output) {
+            if let Ok(relative) = path.strip_prefix(root) {
+                return format.output_path(&output.join(strip_path_prefix(
+                    relative,
+                    self.path_prefix_strip.as_deref(),
+                )));
+            }
+        }
+
+        format.output_path(path)
+    }
+
+    /// Where the diff of `path` is saved with `--diff-only`.
+    pub fn diff_path(&self, path: &Path) -> Option<PathBuf> {
+        let diff_only = self.diff_only.as_ref()?;
+
+        match path.file_name() {
+            Some(name) if diff_only.is_dir() => Some(diff_only.join(name).with_extension("png")),
+            _ => Some(diff_only.clone()),
+        }
+    }
+
+    /// Where the Voronoi cells of `path` are saved with
+    /// `--color-bleed-debug-image`.
+    pub fn bleed_debug_path(&self, path: &Path) -> Option<PathBuf> {
+        let bleed_debug = self.bleed_debug.as_ref()?;
+
+        match path.file_name() {
+            Some(name) if bleed_debug.is_dir() => {
+                Some(bleed_debug.join(name).with_extension("png"))
+            }
+            _ => Some(bleed_debug.clone()),
+        }
+    }
+}
+
+/// `relative`, a path inside the mirrored folder, without the
+/// `--path-prefix-strip` prefix when it starts with it.
+pub fn strip_path_prefix<'a>(relative: &'a Path, prefix: Option<&Path>) -> &'a Path {
+    prefix
+        .and_then(|prefix| relative.strip_prefix(prefix).ok())
+        .unwrap_or(relative)
+}
+
+/// Splits a command line fragment on whitespace, keeping double quoted
+/// parts together.
+fn split_flags(flags: &str) -> Vec<OsString> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quoted = false;
+
+    for char in flags.chars() {
+        match char {
+            '"' => {
+                quoted = !quoted;
+                in_arg = true;
+            }
+            char if char.is_whitespace() && !quoted => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current).into());
+                    in_arg = false;
+                }
+            }
+            char => {
+                current.push(char);
+                in_arg = true;
+            }
+        }
+    }
+
+    if in_arg {
+        args.push(current.into());
+    }
+
+    args
+}
+
+/// The `PIXFIX_*` variables `env_args` reads, with what they do.
+pub const ENV_VARS: &[(&str, &str)] = &[
+    ("PIXFIX_JOBS", "Same as --jobs."),
+    (
+        "PIXFIX_OUTPUT",
+        "Same as --output: where a data: URI argument is saved, or the --mirror folder.",
+    ),
+    (
+        "PIXFIX_NO_PAUSE",
+        "1, true or yes is the same as --no-pause, 0, false or no does nothing.",
+    ),
+    (
+        "PIXFIX_LOG",
+        "verbose or debug is the same as --verbose, normal or info does nothing.",
+    ),
+    (
+        "PIXFIX_FLAGS",
+        "Any other options, written like on a command line.",
+    ),
+];
+
+/// What `pixfix --help` prints.
+pub fn help() -> String {
+    let mut help = String::from(
+        "Usage: pixfix [options] <files, folders, zip archives or data: URIs>
+       pixfix <command> [arguments] [options]
+
+Fixes the color of the transparent pixels of PNG images, so no dark outline
+shows up when they are scaled.
+
+Commands:
+  rojo <project.json>             Fix the images of a Rojo project
+  hook --staged [--fix]           Check or fix the images staged in git
+  hook install                    Install the git pre-commit hook
+  serve                           Fix images sent over HTTP
+  validate <files>                Check images without fixing them
+  extract-alpha <files>           Save the alpha of images as grayscale
+  info <files>                    Print the size and content area of images
+  strip-stats <files>             Remove the stats Pixfix added to images
+  restore <files>                 Put back images from --backup-store
+  undo                            Roll back a run with --journal
+  benchmark-compare <a> <b>       Compare the images of two folders
+  self-test                       Check that fixing works on this machine
+  install-shell-integration       Add \"Fix alpha bleed\" to the right-click menu
+  uninstall-shell-integration     Remove it again
+
+Common options:
+  --check                         Only report the images that need fixing
+  --in-place                      Confirm overwriting images from a script
+  --mirror --output <dir>         Write the fixed images to another folder
+  -r, --recursive                 Also fix the images in subfolders
+  -j, --jobs <N>                  How many images are fixed at the same time
+  --no-pause                      Don't wait for enter when done
+  -v, --verbose                   Print extra detail
+  -h, --help                      Print this help
+
+Every option is described in the README.
+
+Environment variables:
+",
+    );
+
+    for (name, description) in ENV_VARS {
+        help += &format!("  {:<32}{}\n", name, description);
+    }
+
+    help
+}
+
+/// Turns the `PIXFIX_*` environment variables into arguments, which go
+/// before the command line so it takes precedence. Every variable is checked
+/// on its own so a bad value names the variable it came from.
+pub fn env_args() -> Result<Vec<OsString>, String> {
+    env_args_from(|name| std::env::var_os(name))
+}
+
+/// `env_args`, reading the variables with `var`.
+fn env_args_from(var: impl Fn(&str) -> Option<OsString>) -> Result<Vec<OsString>, String> {
+    let mut args: Vec<OsString> = Vec::new();
+
+    let mut add = |name: &str, env_args: Vec<OsString>| {
+        match parse_args(env_args.clone()) {
+            Ok((_, paths)) if paths.is_empty() => {}
+            Ok((_, paths)) => {
+                return Err(format!(
+                    "Invalid {} - \"{}\" is not an option!",
+                    name,
+                    paths[0].to_string_lossy()
+                ))
+            }
+            Err(err) => return Err(format!("Invalid {} - {}", name, err)),
+        }
+
+        args.extend(env_args);
+        Ok(())
+    };
+
+    let var = |name: &str| var(name).filter(|value| !value.is_empty());
+
+    if let Some(jobs) = var("PIXFIX_JOBS") {
+        add("PIXFIX_JOBS", vec!["--jobs".into(), jobs])?;
+    }
+
+    if let Some(output) = var("PIXFIX_OUTPUT") {
+        add("PIXFIX_OUTPUT", vec!["--output".into(), output])?;
+    }
+
+    if let Some(no_pause) = var("PIXFIX_NO_PAUSE") {
+        match no_pause.to_string_lossy().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" => add("PIXFIX_NO_PAUSE", vec!["--no-pause".into()])?,
+            "0" | "false" | "no" => {}
+            value => {
+                return Err(format!(
+                    "Invalid PIXFIX_NO_PAUSE - \"{}\" is not one of 1|0|true|false|yes|no!",
+                    value
+                ))
+            }
+        }
+    }
+
+    if let Some(log) = var("PIXFIX_LOG") {
+        match log.to_string_lossy().to_ascii_lowercase().as_str() {
+            "verbose" | "debug" => add("PIXFIX_LOG", vec!["--verbose".into()])?,
+            "normal" | "info" => {}
+            value => {
+                return Err(format!(
+                    "Invalid PIXFIX_LOG - \"{}\" is not one of normal|info|verbose|debug!",
+                    value
+                ))
+            }
+        }
+    }
+
+    if let Some(flags) = var("PIXFIX_FLAGS") {
+        add("PIXFIX_FLAGS", split_flags(&flags.to_string_lossy()))?;
+    }
+
+    Ok(args)
+}
+
+/// Takes every `--working-dir <path>` out of `args`, returning the last path.
+fn take_working_dir(args: Vec<OsString>) -> Result<(Option<PathBuf>, Vec<OsString>), String> {
+    let mut dir = None;
+    let mut rest = Vec::with_capacity(args.len());
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some(flag @ "--working-dir") => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("Missing value for \"{}\"!", flag))?;
+                dir = Some(PathBuf::from(value));
+            }
+            _ => rest.push(arg),
+        }
+    }
+
+    Ok((dir, rest))
+}
+
+/// Takes `--working-dir <path>` out of the command line and the `PIXFIX_*`
+/// arguments and changes to that directory first, so every relative path is
+/// relative to it, response files included, whatever directory the build
+/// system ran Pixfix from. The command line takes precedence, and the
+/// directory is only ever changed once per call.
+pub fn apply_working_dir(
+    args: Vec<OsString>,
+    env_args: Vec<OsString>,
+) -> Result<(Vec<OsString>, Vec<OsString>), String> {
+    let (dir, args) = take_working_dir(args)?;
+    let (env_dir, env_args) = take_working_dir(env_args)?;
+
+    if let Some(path) = dir.or(env_dir) {
+        std::env::set_current_dir(&path).map_err(|err| {
+            format!(
+                "Unable to change the working directory to \"{}\": {}",
+                path.display(),
+                err
+            )
+        })?;
+    }
+
+    Ok((args, env_args))
+}
+
+/// Replaces every `@file` argument with the lines of that file, one
+/// argument per line, so long command lines can live in a file. Blank lines
+/// and `#` comments are skipped, and response files can't reference others.
+pub fn expand_response_files(args: Vec<OsString>) -> Result<Vec<OsString>, String> {
+    let mut expanded = Vec::with_capacity(args.len());
+
+    for arg in args {
+        let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix('@')) else {
+            expanded.push(arg);
+            continue;
+        };
+
+        let contents = std::fs::read_to_string(io_path(Path::new(path)))
+            .map_err(|err| format!("Unable to read response file \"{}\": {}", path, err))?;
+
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('@') {
+                return Err(format!(
+                    "Response file \"{}\" references \"{}\", response files can't be nested!",
+                    path, line
+                ));
+            }
+
+            expanded.push(line.into());
+        }
+    }
+
+    Ok(expanded)
+}
+
+/// Splits the command line into options and the paths to fix.
+pub fn parse_args(args: Vec<OsString>) -> Result<(Options, Vec<OsString>), String> {
+    let mut options = Options::default();
+    let mut paths = Vec::new();
+    let mut args = args.into_iter().peekable();
+
+    while let Some(arg) = args.next() {
+        let flag = arg.to_string_lossy();
+
+        match flag.as_ref() {
+            "-d" => {
+                diagnostic!("\"-d\" is deprecated, use \"--alpha-fill 255\" instead.");
+                options.alpha_fill = 255;
+            }
+            "--alpha-fill" => options.alpha_fill = parse_value(&flag, args.next())?,
+            "--retries" => options.retries = parse_value(&flag, args.next())?,
+            "--format" => options.format.format = parse_value(&flag, args.next())?,
+            "--scale" => {
+                let scale: f32 = parse_value(&flag, args.next())?;
+
+                if !scale.is_finite() || scale <= 0.0 {
+                    return Err(format!("\"{}\" must be a positive number!", flag));
+                }
+
+                options.scale = Some(scale);
+            }
+            "--mip-chain" => options.mip_chain = Some(parse_value(&flag, args.next())?),
+            "--scale-filter" => options.scale_filter = parse_value(&flag, args.next())?,
+            "--multi-format" => {
+                let formats: String = parse_value(&flag, args.next())?;
+                options.multi_format.clear();
+
+                for format in formats.split(',').map(str::trim) {
+                    let format = format
+                        .parse()
+                        .map_err(|_| format!("Invalid format \"{}\" for \"{}\"!", format, flag))?;
+
+                    if !options.multi_format.contains(&format) {
+                        options.multi_format.push(format);
+                    }
+                }
+            }
+            "--webp-quality" => {
+                options.format.webp_quality = parse_range(&flag, args.next(), 0, 100)?
+            }
+            "-v" | "--verbose" => options.verbose = true,
+            "--check" => options.check = true,
+            "--no-pause" => options.no_pause = true,
+            "--strict" => options.strict = true,
+            "--progress" => options.progress = true,
+            "--validate-first" => options.validate_first = true,
+            "--events" => options.events = true,
+            "--print-modified" => options.print_modified = true,
+            "--print0" => {
+                options.print_modified = true;
+                options.print0 = true;
+            }
+            "--in-place" => options.in_place = true,
+            "--alpha-channel-output" => {
+                options.alpha_channel_output = parse_value(&flag, args.next())?
+            }
+            "-r" | "--recursive" => options.recursive = true,
+            "--respect-gitignore" => options.respect_gitignore = true,
+            // The value is optional, without one both have to agree.
+            "--format-detection" => {
+                let detection = args
+                    .peek()
+                    .and_then(|value| value.to_str())
+                    .and_then(|value| value.parse().ok());
+
+                options.format_detection = match detection {
+                    Some(detection) => {
+                        args.next();
+                        detection
+                    }
+                    None => FormatDetection::Both,
+                };
+            }
+            "--include" => {
+                let glob: String = parse_value(&flag, args.next())?;
+                let pattern = Pattern::new(&glob).map_err(|err| {
+                    format!("Invalid value \"{}\" for \"{}\" - {}!", glob, flag, err)
+                })?;
+
+                options.include.push(pattern);
+            }
+            "--mirror" => options.mirror = true,
+            "--link" => options.link = true,
+            "--path-prefix-strip" => {
+                options.path_prefix_strip =
+                    Some(PathBuf::from(parse_value::<String>(&flag, args.next())?))
+            }
+            "--force" => options.force = true,
+            "--allow-overlap" => options.allow_overlap = true,
+            "--premultiplied-alpha-input" => options.premultiplied_alpha_input = true,
+            "--alpha-invert" => options.alpha_invert = true,
+            "--connected-components" => {
+                diagnostic!("\"--connected-components\" is always on now, it can be removed.");
+            }
+            "-o" | "--output" => {
+                options.output = Some(PathBuf::from(parse_value::<String>(&flag, args.next())?))
+            }
+            "-j" | "--jobs" => {
+                options.jobs = parse_value(&flag, args.next())?;
+
+                if options.jobs == 0 {
+                    return Err(format!("\"{}\" must be at least 1!", flag));
+                }
+            }
+            "--parallel-io" => options.parallel_io = true,
+            "--tolerant" => options.tolerant = true,
+            "--resume" => options.resume = Some(parse_value(&flag, args.next())?),
+            "--wait-lock" => {
+                let seconds: f64 = parse_value(&flag, args.next())?;
+                let wait = Duration::try_from_secs_f64(seconds)
+                    .map_err(|_| format!("Invalid value \"{}\" for \"{}\"!", seconds, flag))?;
+
+                options.wait_lock = Some(wait);
+            }
+            "--timeout-per-file" | "--timeout" => {
+                let seconds: f64 = parse_value(&flag, args.next())?;
+                let timeout = Duration::try_from_secs_f64(seconds)
+                    .ok()
+                    .filter(|timeout| !timeout.is_zero())
+                    .ok_or_else(|| format!("Invalid value \"{}\" for \"{}\"!", seconds, flag))?;
+
+                options.timeout_per_file = Some(timeout);
+            }
+            "--max-file-size" => {
+                let megabytes: f64 = parse_value(&flag, args.next())?;
+
+                if !megabytes.is_finite() || megabytes <= 0.0 {
+                    return Err(format!("Invalid value \"{}\" for \"{}\"!", megabytes, flag));
+                }
+
+                options.max_file_size = Some((megabytes * BYTES_PER_MB) as u64);
+            }
+            "--max-dimensions" => {
+                let value: String = parse_value(&flag, args.next())?;
+                let dimensions = parse_pair(&value)
+                    .ok_or_else(|| format!("Invalid value \"{}\" for \"{}\"!", value, flag))?;
+
+                options.max_dimensions = Some(dimensions);
+            }
+            "--check-dimensions" => {
+                let value: String = parse_value(&flag, args.next())?;
+                let dimensions = parse_pair(&value)
+                    .ok_or_else(|| format!("Invalid value \"{}\" for \"{}\"!", value, flag))?;
+
+                options.check_dimensions = Some(dimensions);
+            }
+            "--check-power-of-two" => options.check_power_of_two = true,
+            "--power-of-two-pad" => options.power_of_two_pad = true,
+            "--trim" => options.trim = true,
+            "--trim-margin" => options.trim_margin = parse_value(&flag, args.next())?,
+            "--quality-check" => {
+                options.quality_check = options.quality_check.or(Some(QUALITY_THRESHOLD))
+            }
+            "--quality-threshold" => {
+                let threshold: f64 = parse_value(&flag, args.next())?;
+
+                if !threshold.is_finite() || threshold <= 0.0 {
+                    return Err(format!("Invalid value \"{}\" for \"{}\"!", threshold, flag));
+                }
+
+                options.quality_check = Some(threshold);
+            }
+            "--edge-detect" => options.edge_detect = true,
+            "--sprite-sheet-grid" => {
+                options.sprite_sheet_grid = Some(parse_value(&flag, args.next())?)
+            }
+            "--atlas" => {
+                let path = PathBuf::from(parse_value::<String>(&flag, args.next())?);
+                options.atlases = atlas::load_atlas(&path).map_err(|err| err.to_string())?;
+            }
+            "--diff-only" => {
+                options.diff_only = Some(PathBuf::from(parse_value::<String>(&flag, args.next())?))
+            }
+            "--color-bleed-debug-image" => {
+                options.bleed_debug =
+                    Some(PathBuf::from(parse_value::<String>(&flag, args.next())?))
+            }
+            "--output-format" => options.output_format = parse_value(&flag, args.next())?,
+            "--report-file" => {
+                options.report_file = PathBuf::from(parse_value::<String>(&flag, args.next())?)
+            }
+            "--digest" => options.digest = Some(parse_value(&flag, args.next())?),
+            "--max-border-pixels" => {
+                let max: usize = parse_value(&flag, args.next())?;
+
+                if max == 0 {
+                    return Err(format!("\"{}\" must be at least 1!", flag));
+                }
+
+                options.max_border_pixels = Some(max);
+            }
+            "--poisson-disk-radius" => {
+                let radius: f64 = parse_value(&flag, args.next())?;
+
+                if !radius.is_finite() || radius <= 0.0 {
+                    return Err(format!("Invalid value \"{}\" for \"{}\"!", radius, flag));
+                }
+
+                options.poisson_disk_radius = Some(radius);
+            }
+            "--strip-metadata" => options.strip_metadata = true,
+            "--strip-color-info" => options.strip_color_info = true,
+            "--working-dir" => {
+                options.working_dir =
+                    Some(PathBuf::from(parse_value::<String>(&flag, args.next())?))
+            }
+            "--dilate" => {
+                let iterations: u32 = parse_value(&flag, args.next())?;
+
+                if iterations == 0 {
+                    return Err(format!("\"{}\" must be at least 1!", flag));
+                }
+
+                options.dilate = Some(iterations);
+            }
+            "--dilate-alpha" => options.dilate_alpha = true,
+            "--blend" => options.blend = parse_value(&flag, args.next())?,
+            "--colorspace" | "--blend-colorspace" => {
+                options.colorspace = parse_value(&flag, args.next())?
+            }
+            "--colorkey" => options.colorkey = Some(parse_value(&flag, args.next())?),
+            "--colorkey-to-alpha" => options.colorkey_to_alpha = true,
+            "--no-frame-cache" => options.frame_cache = false,
+            "--output-stats" => options.output_stats = Some(parse_value(&flag, args.next())?),
+            "--repeat" => options.repeat = parse_value(&flag, args.next())?,
+            "--backend" => {
+                options.backend = parse_value(&flag, args.next())?;
+
+                if options.backend == Backend::Gpu && !cfg!(feature = "gpu") {
+                    return Err(
+                        "This Pixfix was built without the GPU backend! Build it with \"--features gpu\"."
+                            .to_string(),
+                    );
+                }
+            }
+            "--sort-output" => options.sort_output = true,
+            "--force-writable" => options.force_writable = true,
+            "--verify" => options.verify = true,
+            "--deduplicate" => options.deduplicate = true,
+            "--backup-store" => options.backup_store = true,
+            "--journal" | "--from-backups" => {
+                options.journal = Some(PathBuf::from(parse_value::<String>(&flag, args.next())?))
+            }
+            "--log-file" => {
+                options.log_file = Some(PathBuf::from(parse_value::<String>(&flag, args.next())?))
+            }
+            "--webp-lossless" => options.format.webp_lossless = true,
+            "--png-compression" => {
+                options.format.png_compression = parse_range(&flag, args.next(), 0, 9)?
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    if options.repeat > 1 && options.backend != Backend::Cpu {
+        return Err("\"--repeat\" only works with \"--backend cpu\"!".to_string());
+    }
+
+    Ok((options, paths))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// The options the `PIXFIX_*` variables in `vars` give, with `command_line`
+    /// after them like `main` puts it.
+    fn env_options(vars: &[(&str, &str)], command_line: &[&str]) -> Result<Options, String> {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        let mut args = env_args_from(|name| vars.get(name).map(OsString::from))?;
+        args.extend(command_line.iter().map(OsString::from));
+
+        parse_args(args).map(|(options, _)| options)
+    }
+
+    #[test]
+    fn variables_set_their_options() {
+        let options = env_options(
+            &[
+                ("PIXFIX_JOBS", "3"),
+                ("PIXFIX_OUTPUT", "out"),
+                ("PIXFIX_NO_PAUSE", "yes"),
+                ("PIXFIX_LOG", "debug"),
+                ("PIXFIX_FLAGS", "--alpha-fill 1 --diff-only \"a b\""),
+            ],
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(options.jobs, 3);
+        assert_eq!(options.output, Some(PathBuf::from("out")));
+        assert!(options.no_pause);
+        assert!(options.verbose);
+        assert_eq!(options.alpha_fill, 1);
+        assert_eq!(options.diff_only, Some(PathBuf::from("a b")));
+    }
+
+    #[test]
+    fn variables_that_turn_things_off_do_nothing() {
+        let options =
+            env_options(&[("PIXFIX_NO_PAUSE", "0"), ("PIXFIX_LOG", "info")], &[]).unwrap();
+
+        assert!(!options.no_pause);
+        assert!(!options.verbose);
+    }
+
+    #[test]
+    fn empty_variables_are_ignored() {
+        assert!(env_options(&[("PIXFIX_JOBS", "")], &[]).is_ok());
+    }
+
+    #[test]
+    fn the_command_line_takes_precedence() {
+        let options = env_options(&[("PIXFIX_JOBS", "3")], &["--jobs", "5"]).unwrap();
+
+        assert_eq!(options.jobs, 5);
+    }
+
+    #[test]
+    fn invalid_values_name_their_variable() {
+        for (name, value) in [
+            ("PIXFIX_JOBS", "many"),
+            ("PIXFIX_NO_PAUSE", "maybe"),
+            ("PIXFIX_LOG", "loud"),
+            ("PIXFIX_FLAGS", "--alpha-fill 1 stray.png"),
+            ("PIXFIX_FLAGS", "--no-such-option"),
+        ] {
+            let Err(err) = env_options(&[(name, value)], &[]) else {
+                panic!("{}={} was accepted", name, value);
+            };
+
+            assert!(err.contains(name), "{}={}: {}", name, value, err);
+        }
+    }
+
+    #[test]
+    fn every_listed_variable_is_read() {
+        for (name, value) in [
+            ("PIXFIX_JOBS", "2"),
+            ("PIXFIX_OUTPUT", "out"),
+            ("PIXFIX_NO_PAUSE", "1"),
+            ("PIXFIX_LOG", "verbose"),
+            ("PIXFIX_FLAGS", "--check"),
+        ] {
+            assert!(ENV_VARS.iter().any(|(listed, _)| *listed == name));
+
+            let args = env_args_from(|read| (read == name).then(|| value.into())).unwrap();
+            assert!(!args.is_empty(), "{} wasn't read", name);
+        }
+
+        assert_eq!(ENV_VARS.len(), 5);
+    }
+
+    #[test]
+    fn the_help_lists_every_variable() {
+        let help = help();
+
+        for (name, _) in ENV_VARS {
+            assert!(help.contains(name), "{} is missing", name);
+        }
+    }
+
+    #[test]
+    fn parsing_the_working_dir_only_records_it() {
+        let before = std::env::current_dir().unwrap();
+        let (options, _) = parse_args(vec!["--working-dir".into(), "/nonexistent".into()]).unwrap();
+
+        assert_eq!(options.working_dir, Some(PathBuf::from("/nonexistent")));
+        assert_eq!(std::env::current_dir().unwrap(), before);
+    }
+}
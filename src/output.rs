@@ -0,0 +1,181 @@
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+static HUMAN_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// The line kept at the bottom of the terminal by `--progress`, redrawn
+/// under every human readable line. Empty when there is none.
+static STATUS: Mutex<String> = Mutex::new(String::new());
+
+/// Where `--log-file` appends the human readable and diagnostic lines.
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Moves to the start of the line and clears it.
+const CLEAR_LINE: &str = "\r\x1b[2K";
+
+/// Sends the human readable output to stderr, keeping stdout free for
+/// machine readable output such as CI annotations.
+pub fn human_to_stderr(enabled: bool) {
+    HUMAN_TO_STDERR.store(enabled, Ordering::Relaxed);
+}
+
+/// Also appends every human readable and diagnostic line to `path`, each
+/// with the time it was printed.
+pub fn log_to(path: &Path) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    *LOG_FILE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(file);
+
+    Ok(())
+}
+
+/// The current time in UTC as `YYYY-MM-DDTHH:MM:SS.mmmZ`.
+fn timestamp() -> String {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, time) = (seconds / 86400, seconds % 86400);
+
+    // Days since 1970 to a date, from Howard Hinnant's `civil_from_days`.
+    let days = days as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Appends `args` to the `--log-file`, if there is one.
+fn log_line(level: &str, args: fmt::Arguments) {
+    let mut log = LOG_FILE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    if let Some(file) = log.as_mut() {
+        // Multi-line messages keep the timestamp on every line.
+        for line in args.to_string().lines() {
+            let _ = writeln!(file, "{} {} {}", timestamp(), level, line);
+        }
+    }
+}
+
+/// Writes a whole line to stdout or stderr, taking the `--progress` line
+/// down while it does and putting it back under the new line.
+fn print_line(to_stderr: bool, args: fmt::Arguments) {
+    let status = STATUS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    if !status.is_empty() {
+        eprint!("{}", CLEAR_LINE);
+    }
+
+    if to_stderr {
+        eprintln!("{}", args);
+    } else {
+        println!("{}", args);
+        let _ = std::io::stdout().flush();
+    }
+
+    if !status.is_empty() {
+        eprint!("{}", status);
+    }
+}
+
+/// Status and summary lines, on stdout unless it is kept for machine
+/// readable output.
+pub fn print_human(args: fmt::Arguments) {
+    log_line("INFO", args);
+    print_line(HUMAN_TO_STDERR.load(Ordering::Relaxed), args);
+}
+
+/// Warnings, errors and the watermark, always on stderr so stdout can be
+/// piped without them.
+pub fn print_diagnostic(args: fmt::Arguments) {
+    log_line("WARN", args);
+    print_line(true, args);
+}
+
+/// Results for other programs, like digests, data URIs and JSON, always on
+/// stdout.
+pub fn print_result(args: fmt::Arguments) {
+    print_line(false, args);
+}
+
+/// Like `print_result`, without the newline.
+pub fn write_result(text: &str) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(text.as_bytes());
+    let _ = stdout.flush();
+}
+
+/// Shows `line` at the bottom of the terminal until it is replaced, or
+/// removes it when it is empty.
+pub fn set_status(line: &str) {
+    let mut status = STATUS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    eprint!("{}{}", CLEAR_LINE, line);
+    let _ = std::io::stderr().flush();
+
+    *status = line.to_string();
+}
+
+/// Like `println!`, for the human readable progress and summary.
+#[macro_export]
+macro_rules! human {
+    () => {
+        $crate::output::print_human(format_args!(""))
+    };
+    ($($arg:tt)*) => {
+        $crate::output::print_human(format_args!($($arg)*))
+    };
+}
+
+/// Like `eprintln!`, for warnings and errors.
+#[macro_export]
+macro_rules! diagnostic {
+    ($($arg:tt)*) => {
+        $crate::output::print_diagnostic(format_args!($($arg)*))
+    };
+}
+
+/// Like `println!`, for machine readable results.
+#[macro_export]
+macro_rules! result {
+    ($($arg:tt)*) => {
+        $crate::output::print_result(format_args!($($arg)*))
+    };
+}
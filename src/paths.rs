@@ -0,0 +1,277 @@
+use std::{
+    fs::{File, OpenOptions, Permissions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Returns the path to hand to the filesystem. On Windows this is the
+/// `\\?\`-prefixed form so paths longer than 260 characters can be opened.
+pub fn io_path(path: &Path) -> PathBuf {
+    if !cfg!(target_os = "windows") {
+        return path.to_path_buf();
+    }
+
+    if let Ok(path) = std::fs::canonicalize(path) {
+        return path;
+    }
+
+    // The file may not exist yet, e.g. when writing to a new extension.
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => match std::fs::canonicalize(parent) {
+            Ok(parent) => parent.join(name),
+            Err(_) => path.to_path_buf(),
+        },
+        _ => path.to_path_buf(),
+    }
+}
+
+/// The absolute path without symlinks or `..`, also for paths that don't
+/// exist yet by resolving the part of them that does.
+pub fn canonical(path: &Path) -> PathBuf {
+    if let Ok(canonical) = std::fs::canonicalize(path) {
+        return canonical;
+    }
+
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => {
+            canonical(parent).join(name)
+        }
+        _ => std::env::current_dir()
+            .map(|dir| dir.join(path))
+            .unwrap_or_else(|_| path.to_path_buf()),
+    }
+}
+
+/// The folders a walk went through to reach the one it is in, resolved, so
+/// a symlink back to one of them, e.g. `sub/up -> ..`, isn't followed
+/// around in circles.
+#[derive(Clone, Default)]
+pub struct Ancestors(Vec<PathBuf>);
+
+impl Ancestors {
+    pub fn new(root: &Path) -> Self {
+        Self(vec![canonical(root)])
+    }
+
+    /// The ancestors of `dir`, a folder inside the last of them, or `None`
+    /// when it resolves to one of them and entering it would loop.
+    pub fn enter(&self, dir: &Path) -> Option<Self> {
+        let dir = canonical(dir);
+
+        if self.0.contains(&dir) {
+            return None;
+        }
+
+        let mut entered = self.clone();
+        entered.0.push(dir);
+
+        Some(entered)
+    }
+}
+
+/// Whether one of the folders is inside the other, or they are the same.
+pub fn overlaps(a: &Path, b: &Path) -> bool {
+    let (a, b) = (canonical(a), canonical(b));
+
+    a.starts_with(&b) || b.starts_with(&a)
+}
+
+pub fn is_read_only(path: &Path) -> bool {
+    match std::fs::metadata(path) {
+        Ok(metadata) => metadata.permissions().readonly(),
+        Err(_) => false,
+    }
+}
+
+/// Clears the read-only flag on `path`, returning the original permissions
+/// so they can be restored once the file has been written.
+pub fn make_writable(path: &Path) -> std::io::Result<Option<Permissions>> {
+    let original = std::fs::metadata(path)?.permissions();
+
+    if !original.readonly() {
+        return Ok(None);
+    }
+
+    let mut writable = original.clone();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        writable.set_mode(writable.mode() | 0o200);
+    }
+    #[cfg(not(unix))]
+    #[allow(clippy::permissions_set_readonly_false)]
+    writable.set_readonly(false);
+
+    std::fs::set_permissions(path, writable)?;
+
+    Ok(Some(original))
+}
+
+/// An exclusive advisory lock on a file being fixed, held from before it is
+/// read until its fix is written, so two runs that reach the same file take
+/// turns instead of the later one saving a fix of the file as it was before
+/// the other's. The lock is taken on `.<name>.pixfix-lock` next to the file,
+/// which, unlike the file itself, isn't replaced when the fix is saved. The
+/// lock file is removed again when this is dropped.
+pub struct FileLock {
+    /// `None` where the filesystem can't lock files.
+    file: Option<File>,
+    path: PathBuf,
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // Removed while still locked, so a run waiting for it sees that it
+        // was and locks a new one, see `lock_file`. Elsewhere the file can't
+        // be told apart from a new one, so it stays.
+        #[cfg(unix)]
+        if self.file.is_some() {
+            let _ = std::fs::remove_file(io_path(&self.path));
+        }
+    }
+}
+
+/// Waits for an exclusive lock on `path`, see `FileLock`. Where the
+/// filesystem can't lock files the returned lock holds nothing.
+pub fn lock_file(path: &Path) -> io::Result<FileLock> {
+    // Through a symlink the file it points to is locked, as that is the one
+    // that gets written.
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let path = path.with_file_name(format!(".{}.pixfix-lock", name));
+
+    loop {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(io_path(&path))?;
+
+        match file.lock() {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+                drop(file);
+                let _ = std::fs::remove_file(io_path(&path));
+
+                return Ok(FileLock { file: None, path });
+            }
+            Err(err) => return Err(err),
+        }
+
+        // The run that held the lock removed the file before letting go of
+        // it, so this one is locking a file no other run will open again.
+        if is_linked(&file, &path) {
+            return Ok(FileLock {
+                file: Some(file),
+                path,
+            });
+        }
+    }
+}
+
+/// Whether `file` is still the file at `path`.
+#[cfg(unix)]
+fn is_linked(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (file.metadata(), std::fs::metadata(path)) {
+        (Ok(locked), Ok(linked)) => locked.dev() == linked.dev() && locked.ino() == linked.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_linked(_file: &File, _path: &Path) -> bool {
+    true
+}
+
+/// Writes `bytes` to `path` like `std::fs::write`, but to a temporary file
+/// next to it first, which is then renamed over it. A run that exits in the
+/// middle of the write, like one that gave up on the file with
+/// `--timeout-per-file`, leaves the file as it was instead of cut short.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    // Writing through a symlink replaces the file it points to, not the
+    // link.
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+    let temp = temp_path(&path);
+    let replaced = write_temp(&temp, &path, bytes).and_then(|()| std::fs::rename(&temp, &path));
+
+    if replaced.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+
+    replaced
+}
+
+/// A file name next to `path` no other write uses.
+fn temp_path(path: &Path) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    path.with_file_name(format!(
+        ".{}.pixfix-{}-{}.tmp",
+        name,
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Writes `bytes` to the new file `temp`, with the permissions of
+/// `original` when it exists so the file keeps them once renamed.
+fn write_temp(temp: &Path, original: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(temp)?;
+
+    file.write_all(bytes)?;
+    file.sync_all()?;
+
+    if let Ok(metadata) = std::fs::metadata(original) {
+        file.set_permissions(metadata.permissions())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+    use crate::fixtures::TempDir;
+
+    #[test]
+    fn a_locked_file_is_read_by_the_next_run_only_once_it_is_written() {
+        let dir = TempDir::new("file-lock").unwrap();
+        let path = dir.path().join("sprite.png");
+        std::fs::write(&path, "original").unwrap();
+
+        let first = lock_file(&path).unwrap();
+
+        let next = thread::spawn({
+            let path = path.clone();
+            move || {
+                let _lock = lock_file(&path).unwrap();
+                let read = std::fs::read_to_string(&path).unwrap();
+                write_atomic(&path, format!("{}, fixed twice", read).as_bytes()).unwrap();
+            }
+        });
+
+        // The write replaces the file, the lock has to outlive it.
+        thread::sleep(Duration::from_millis(200));
+        write_atomic(&path, b"fixed once").unwrap();
+        thread::sleep(Duration::from_millis(200));
+        drop(first);
+        next.join().unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "fixed once, fixed twice"
+        );
+
+        #[cfg(unix)]
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
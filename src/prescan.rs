@@ -0,0 +1,381 @@
+//! A look at the header of every image before any of them is decoded. The
+//! signature and `IHDR` give the size and color type for a few bytes read,
+//! enough to drop files that aren't PNG images, images without an alpha
+//! channel and images over `--max-dimensions` without decoding them.
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    archive::is_zip_file, chunks::PNG_SIGNATURE, files::ResolvedFiles, options::Options,
+    paths::io_path,
+};
+
+/// The largest width or height the PNG specification allows.
+const MAX_SIZE: u32 = i32::MAX as u32;
+
+/// What the header of a PNG image says about it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Header {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    /// A `tRNS` chunk gives colors without an alpha channel transparency.
+    pub transparency: bool,
+}
+
+impl Header {
+    /// Whether the decoded image has an alpha channel, either its own or
+    /// one `tRNS` expands to.
+    pub fn has_alpha(&self) -> bool {
+        // Gray with alpha and RGBA.
+        matches!(self.color_type, 4 | 6) || self.transparency
+    }
+}
+
+fn number(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Reads the header of the PNG image in `file`, going through the chunk
+/// headers up to the image data to find `tRNS` when the color type has no
+/// alpha. `None` when the file isn't a PNG image.
+pub fn read_header(mut file: impl Read + Seek) -> io::Result<Option<Header>> {
+    // The signature, the length and type of the first chunk, which has to be
+    // `IHDR`, then its data and CRC.
+    let mut start = [0; 33];
+
+    match file.read_exact(&mut start) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    if &start[..8] != PNG_SIGNATURE || number(&start[8..12]) != 13 || &start[12..16] != b"IHDR" {
+        return Ok(None);
+    }
+
+    let mut header = Header {
+        width: number(&start[16..20]),
+        height: number(&start[20..24]),
+        bit_depth: start[24],
+        color_type: start[25],
+        transparency: false,
+    };
+
+    if header.has_alpha() {
+        return Ok(Some(header));
+    }
+
+    // `tRNS` has to come before the image data.
+    let mut chunk = [0; 8];
+
+    while file.read_exact(&mut chunk).is_ok() {
+        match &chunk[4..] {
+            b"tRNS" => {
+                header.transparency = true;
+                break;
+            }
+            b"IDAT" | b"IEND" => break,
+            _ => {
+                file.seek(SeekFrom::Current(i64::from(number(&chunk[..4])) + 4))?;
+            }
+        }
+    }
+
+    Ok(Some(header))
+}
+
+/// Why `header` keeps the image from being fixed, or `None` when nothing
+/// does.
+fn problem(header: &Header, options: &Options) -> Option<Scanned> {
+    let (width, height) = (header.width, header.height);
+
+    if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+        return Some(Scanned::Invalid(format!(
+            "Its header claims an impossible size of {}x{}",
+            width, height
+        )));
+    }
+
+    if let Some((max_width, max_height)) = options.max_dimensions {
+        if width > max_width || height > max_height {
+            return Some(Scanned::Oversized(format!(
+                "{}x{} exceeds the limit of {}x{}",
+                width, height, max_width, max_height
+            )));
+        }
+    }
+
+    // A color key makes an image without alpha worth decoding.
+    if !header.has_alpha() && options.colorkey.is_none() {
+        return Some(Scanned::NoAlpha);
+    }
+
+    None
+}
+
+/// What the pre-scan decided about one file.
+enum Scanned {
+    Fix,
+    /// Not a PNG image whatever its name says, or an impossible one.
+    Invalid(String),
+    Oversized(String),
+    NoAlpha,
+}
+
+fn scan(path: &Path, options: &Options) -> Scanned {
+    // Archives are looked into when they are fixed.
+    if is_zip_file(path) {
+        return Scanned::Fix;
+    }
+
+    // Files that can't be read are left for the fix to report.
+    let header = match File::open(io_path(path)).and_then(read_header) {
+        Ok(Some(header)) => header,
+        Ok(None) => return Scanned::Invalid("It is not actually a PNG image".into()),
+        Err(_) => return Scanned::Fix,
+    };
+
+    problem(&header, options).unwrap_or(Scanned::Fix)
+}
+
+/// Reads the header of every file in `resolved` on `--jobs` threads and
+/// takes out the ones not worth decoding: files that aren't PNG images,
+/// images over `--max-dimensions` and images without an alpha channel,
+/// which go to `resolved.no_alpha`. Returns whether each file was kept, in
+/// the order they were.
+pub fn prescan(resolved: &mut ResolvedFiles, options: &Options) -> Vec<bool> {
+    let files = std::mem::take(&mut resolved.files);
+    let threads = options.jobs.clamp(1, files.len().max(1));
+    let per_thread = files.len().div_ceil(threads).max(1);
+
+    let scanned: Vec<Scanned> = std::thread::scope(|scope| {
+        let handles: Vec<_> = files
+            .chunks(per_thread)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|path| scan(path, options))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|err| std::panic::resume_unwind(err))
+            })
+            .collect()
+    });
+
+    let mut kept = Vec::with_capacity(files.len());
+
+    for (path, scanned) in files.into_iter().zip(scanned) {
+        kept.push(sort(resolved, path, scanned));
+    }
+
+    kept
+}
+
+/// Pre-scans a single file like `prescan`, adding it to `resolved.files`
+/// when it is kept. Returns whether it was.
+pub fn prescan_file(resolved: &mut ResolvedFiles, path: PathBuf, options: &Options) -> bool {
+    let scanned = scan(&path, options);
+
+    sort(resolved, path, scanned)
+}
+
+/// Puts `path` where `scanned` says it belongs in `resolved`, returning
+/// whether it is still to be fixed.
+fn sort(resolved: &mut ResolvedFiles, path: PathBuf, scanned: Scanned) -> bool {
+    let kept = matches!(scanned, Scanned::Fix);
+
+    match scanned {
+        Scanned::Fix => resolved.files.push(path),
+        Scanned::Invalid(reason) => resolved.skip(&path, reason),
+        Scanned::Oversized(reason) => {
+            resolved.skip(&path, reason);
+            resolved.oversized += 1;
+        }
+        Scanned::NoAlpha => resolved.no_alpha.push(path),
+    }
+
+    kept
+}
+
+/// `ranges` into the files given to `prescan`, moved to the files it kept.
+pub fn kept_ranges(ranges: &[(usize, usize)], kept: &[bool]) -> Vec<(usize, usize)> {
+    let before = |index: usize| kept[..index].iter().filter(|kept| **kept).count();
+
+    ranges
+        .iter()
+        .map(|(start, end)| (before(*start), before(*end)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        chunks::{write_chunks, Chunk},
+        fixtures::TempDir,
+    };
+
+    fn chunk(kind: &[u8; 4], data: &[u8]) -> Chunk {
+        Chunk {
+            kind: *kind,
+            data: data.to_vec(),
+        }
+    }
+
+    fn ihdr(width: u32, height: u32, color_type: u8) -> Chunk {
+        let mut data = Vec::new();
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+        chunk(b"IHDR", &data)
+    }
+
+    /// A PNG of `width` by `height` and `color_type`, with `chunks` after
+    /// `IHDR`. The image data is never looked at.
+    fn png(width: u32, height: u32, color_type: u8, chunks: &[&[u8; 4]]) -> Vec<u8> {
+        let mut all = vec![ihdr(width, height, color_type)];
+        all.extend(chunks.iter().map(|kind| chunk(kind, &[0])));
+
+        write_chunks(&all)
+    }
+
+    fn header(bytes: &[u8]) -> Option<Header> {
+        read_header(Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn headers_are_read_without_the_image_data() {
+        assert_eq!(
+            header(&png(640, 480, 6, &[b"IDAT", b"IEND"])),
+            Some(Header {
+                width: 640,
+                height: 480,
+                bit_depth: 8,
+                color_type: 6,
+                transparency: false,
+            })
+        );
+    }
+
+    #[test]
+    fn files_cut_short_are_not_png_images() {
+        let full = png(16, 16, 6, &[b"IDAT", b"IEND"]);
+
+        assert_eq!(header(&[]), None);
+        assert_eq!(header(&full[..8]), None);
+        // The IHDR data ends early.
+        assert_eq!(header(&full[..24]), None);
+    }
+
+    #[test]
+    fn other_formats_are_not_png_images() {
+        let mut renamed = png(16, 16, 6, &[b"IDAT", b"IEND"]);
+        renamed[1] = b'J';
+
+        assert_eq!(header(&renamed), None);
+        assert_eq!(
+            header(b"\xff\xd8\xff\xe0 a JPEG image, not a PNG one"),
+            None
+        );
+        // A valid signature, but the first chunk isn't IHDR.
+        assert_eq!(header(&write_chunks(&[chunk(b"IDAT", &[0; 13])])), None);
+    }
+
+    #[test]
+    fn trns_only_counts_before_the_image_data() {
+        let before = header(&png(16, 16, 2, &[b"tRNS", b"IDAT", b"IEND"])).unwrap();
+        let after = header(&png(16, 16, 2, &[b"IDAT", b"tRNS", b"IEND"])).unwrap();
+        // Chunks in between are skipped over.
+        let skipped = header(&png(16, 16, 3, &[b"PLTE", b"gAMA", b"tRNS", b"IDAT"])).unwrap();
+
+        assert!(before.has_alpha());
+        assert!(!after.has_alpha());
+        assert!(skipped.has_alpha());
+    }
+
+    #[test]
+    fn a_truncated_chunk_list_ends_the_search() {
+        let full = png(16, 16, 2, &[b"gAMA", b"tRNS", b"IDAT"]);
+
+        // Cut off inside the gAMA chunk, before tRNS.
+        assert!(!header(&full[..40]).unwrap().has_alpha());
+    }
+
+    fn problem_of(bytes: &[u8], options: &Options) -> Option<Scanned> {
+        problem(&header(bytes).unwrap(), options)
+    }
+
+    #[test]
+    fn files_with_a_wrong_signature_are_invalid() {
+        let dir = TempDir::new("prescan").unwrap();
+        let path = dir.path().join("fake.png");
+        let mut bytes = png(16, 16, 6, &[b"IDAT", b"IEND"]);
+        bytes[0] = 0;
+        std::fs::write(&path, bytes).unwrap();
+
+        assert!(matches!(
+            scan(&path, &Options::default()),
+            Scanned::Invalid(reason) if reason == "It is not actually a PNG image"
+        ));
+    }
+
+    #[test]
+    fn absurd_dimensions_are_invalid() {
+        for (width, height) in [(0, 16), (16, 0), (u32::MAX, 16), (16, MAX_SIZE + 1)] {
+            assert!(
+                matches!(
+                    problem_of(&png(width, height, 6, &[b"IDAT"]), &Options::default()),
+                    Some(Scanned::Invalid(reason)) if reason.contains("impossible size")
+                ),
+                "{}x{}",
+                width,
+                height
+            );
+        }
+
+        assert!(problem_of(&png(MAX_SIZE, 1, 6, &[b"IDAT"]), &Options::default()).is_none());
+    }
+
+    #[test]
+    fn images_over_the_limit_are_oversized() {
+        let options = Options {
+            max_dimensions: Some((100, 100)),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            problem_of(&png(101, 50, 6, &[b"IDAT"]), &options),
+            Some(Scanned::Oversized(_))
+        ));
+        assert!(problem_of(&png(100, 100, 6, &[b"IDAT"]), &options).is_none());
+    }
+
+    #[test]
+    fn palettes_need_trns_to_be_fixed() {
+        let options = Options::default();
+
+        assert!(problem_of(&png(16, 16, 3, &[b"PLTE", b"tRNS", b"IDAT"]), &options).is_none());
+        assert!(matches!(
+            problem_of(&png(16, 16, 3, &[b"PLTE", b"IDAT"]), &options),
+            Some(Scanned::NoAlpha)
+        ));
+    }
+}
@@ -0,0 +1,114 @@
+//! `pixfix profile <image.png> [options]`: fixes one image the way a run with
+//! the same options would, without saving it, under a sampling CPU profiler,
+//! and draws where the time went as a flamegraph in `pixfix_profile.svg`.
+
+use std::{
+    ffi::OsString,
+    fs::File,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    convert::{self, FixedBytes},
+    human, options,
+    paths::io_path,
+    progress::{self, Stage},
+};
+
+pub const OUTPUT: &str = "pixfix_profile.svg";
+
+/// Samples taken per second.
+const FREQUENCY: i32 = 1000;
+
+/// How long the image is fixed over and over for. A small image is fixed in
+/// a few milliseconds, too few samples to tell anything from.
+const MIN_TIME: Duration = Duration::from_secs(1);
+
+fn name(stage: Stage) -> &'static str {
+    match stage {
+        Stage::Decoding => "decode and prepare",
+        Stage::Triangulating => "triangulate",
+        Stage::Filling => "fill",
+        Stage::Encoding => "encode",
+    }
+}
+
+/// Adds up the time of every stage, as a sprite sheet grid or `--repeat`
+/// goes through the same stages more than once, in the order they first ran.
+fn totals(stages: Vec<(Stage, Duration)>) -> Vec<(Stage, Duration)> {
+    let mut totals: Vec<(Stage, Duration)> = Vec::new();
+
+    for (stage, time) in stages {
+        match totals.iter_mut().find(|(total, _)| *total == stage) {
+            Some((_, total)) => *total += time,
+            None => totals.push((stage, time)),
+        }
+    }
+
+    totals
+}
+
+/// Fixes the image named in `args` with the options in `args`, without
+/// saving it, and writes a flamegraph of the fix to `pixfix_profile.svg`.
+pub fn profile(args: Vec<OsString>) -> Result<(), String> {
+    let (mut options, paths) = options::parse_args(args)?;
+    let [path] = paths.as_slice() else {
+        return Err("Usage: pixfix profile <image.png> [options]".to_string());
+    };
+    let path = Path::new(path);
+
+    // Every fix after the first would otherwise replay the fill of the first
+    // and the profile would show the replay.
+    options.frame_cache = false;
+
+    let bytes = std::fs::read(io_path(path))
+        .map_err(|err| format!("Unable to read \"{}\": {}", path.display(), err))?;
+
+    let profiler = pprof::ProfilerGuardBuilder::default()
+        .frequency(FREQUENCY)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()
+        .map_err(|err| format!("Unable to start the profiler: {}", err))?;
+
+    let start = Instant::now();
+    let mut runs = 0;
+    let mut stages = Vec::new();
+
+    while runs == 0 || start.elapsed() < MIN_TIME {
+        let (fixed, run_stages) =
+            progress::time_stages(|| convert::fix_bytes(&bytes, path, &options));
+
+        if let FixedBytes::NoAlphaChannel = fixed.map_err(|err| err.to_string())? {
+            return Err(format!("\"{}\" has no alpha channel!", path.display()));
+        }
+
+        runs += 1;
+        stages.extend(run_stages);
+    }
+
+    let report = profiler
+        .report()
+        .build()
+        .map_err(|err| format!("Unable to build the profile: {}", err))?;
+
+    human!("Fixed the image {} times", runs);
+
+    for (stage, time) in totals(stages) {
+        human!(
+            "{:>18}  {:.1} ms per fix",
+            name(stage),
+            time.as_secs_f64() * 1000.0 / runs as f64
+        );
+    }
+
+    let file =
+        File::create(OUTPUT).map_err(|err| format!("Unable to write {}: {}", OUTPUT, err))?;
+    report
+        .flamegraph(file)
+        .map_err(|err| format!("Unable to write {}: {}", OUTPUT, err))?;
+
+    human!("Wrote the flamegraph to {}", OUTPUT);
+
+    Ok(())
+}
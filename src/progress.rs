@@ -0,0 +1,386 @@
+//! `--progress`: throughput and the time left for long batches, the one
+//! from the files finished lately and the other from the batch so far, and
+//! how far along large images are.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::options::BYTES_PER_MB;
+
+/// Only files finished this recently count towards the throughput, so the
+/// estimate follows the batch as it speeds up or slows down.
+const WINDOW: Duration = Duration::from_secs(30);
+
+pub struct Estimator {
+    start: Instant,
+    total_files: usize,
+    total_bytes: u64,
+    done_files: usize,
+    done_bytes: u64,
+    /// When each recent file finished and how large it was.
+    recent: VecDeque<(Instant, u64)>,
+}
+
+/// Seconds left when `done` of `total` took `elapsed` seconds, if the rest
+/// goes at the same pace. `None` until anything is done.
+pub fn eta(done: f64, total: f64, elapsed: f64) -> Option<f64> {
+    if done <= 0.0 || elapsed <= 0.0 {
+        return None;
+    }
+
+    Some((total - done).max(0.0) * elapsed / done)
+}
+
+impl Estimator {
+    pub fn new(total_files: usize, total_bytes: u64, start: Instant) -> Self {
+        Self {
+            start,
+            total_files,
+            total_bytes,
+            done_files: 0,
+            done_bytes: 0,
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn finished(&mut self, bytes: u64, at: Instant) {
+        self.done_files += 1;
+        self.done_bytes += bytes;
+        self.recent.push_back((at, bytes));
+    }
+
+    /// Files and bytes per second over the window.
+    fn throughput(&mut self, now: Instant) -> (f64, f64) {
+        while self
+            .recent
+            .front()
+            .is_some_and(|(at, _)| now.saturating_duration_since(*at) > WINDOW)
+        {
+            self.recent.pop_front();
+        }
+
+        let since = self
+            .start
+            .max(now.checked_sub(WINDOW).unwrap_or(self.start));
+        let seconds = now.saturating_duration_since(since).as_secs_f64();
+
+        if seconds <= 0.0 {
+            return (0.0, 0.0);
+        }
+
+        let bytes: u64 = self.recent.iter().map(|(_, bytes)| bytes).sum();
+
+        (self.recent.len() as f64 / seconds, bytes as f64 / seconds)
+    }
+
+    /// The seconds left, going by the whole batch so far, which settles
+    /// as more of it is done. Large files take longer, so it goes by the
+    /// bytes left rather than the files left, unless the files are empty.
+    fn eta(&self, now: Instant) -> Option<f64> {
+        let elapsed = now.saturating_duration_since(self.start).as_secs_f64();
+
+        if self.total_bytes > 0 {
+            eta(self.done_bytes as f64, self.total_bytes as f64, elapsed)
+        } else {
+            eta(self.done_files as f64, self.total_files as f64, elapsed)
+        }
+    }
+
+    /// The progress line, e.g. `[120/800] 4.2 files/s, 1.3 MB/s, about 3
+    /// minutes left`.
+    pub fn line(&mut self, now: Instant) -> String {
+        let (files_per_second, bytes_per_second) = self.throughput(now);
+        let mut line = format!(
+            "[{}/{}] {:.1} files/s, {:.1} MB/s",
+            self.done_files,
+            self.total_files,
+            files_per_second,
+            bytes_per_second / BYTES_PER_MB
+        );
+
+        if let Some(eta) = self.eta(now).filter(|_| self.done_files < self.total_files) {
+            line += &format!(", {} left", describe(eta));
+        }
+
+        for file in large_files() {
+            line += &format!(", {}", file);
+        }
+
+        line
+    }
+}
+
+fn describe(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let plural =
+        |count: u64, unit: &str| format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" });
+
+    match seconds {
+        0..=59 => format!("about {}", plural(seconds.max(1), "second")),
+        60..=3599 => format!("about {}", plural((seconds + 30) / 60, "minute")),
+        _ => format!(
+            "about {} {}",
+            plural(seconds / 3600, "hour"),
+            plural(seconds % 3600 / 60, "minute")
+        ),
+    }
+}
+
+/// Images with at least this many pixels show how far along they are, as
+/// a single one of them can take a minute.
+const LARGE_IMAGE: u64 = 4096 * 4096;
+
+/// Filled pixels are reported in steps this large, so reporting costs
+/// next to nothing.
+pub const FILL_STEP: usize = 100_000;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static FILES: Mutex<Vec<File>> = Mutex::new(Vec::new());
+
+thread_local! {
+    /// The file being fixed on this thread, if it is tracked.
+    static CURRENT: Cell<Option<u64>> = const { Cell::new(None) };
+    /// Set while `time_stages` runs on this thread.
+    static CLOCK: RefCell<Option<Clock>> = const { RefCell::new(None) };
+}
+
+/// The stages `time_stages` has seen so far and when the last one started.
+struct Clock {
+    stages: Vec<(Stage, Duration)>,
+    since: Instant,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    Decoding,
+    Triangulating,
+    Filling,
+    Encoding,
+}
+
+/// A file being fixed, as the progress line shows it.
+struct File {
+    id: u64,
+    name: String,
+    stage: Stage,
+    large: bool,
+    /// Pixels to fill in this pass and how many are filled.
+    total: usize,
+    done: usize,
+}
+
+/// Tracks the stages of files from now on, for `--progress`.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Stops tracking the file when it is done with.
+pub struct Tracked {
+    id: u64,
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(None));
+        lock_files().retain(|file| file.id != self.id);
+    }
+}
+
+fn lock_files() -> std::sync::MutexGuard<'static, Vec<File>> {
+    FILES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Tracks the file at `path` on this thread until the returned guard is
+/// dropped. Does nothing without `--progress`.
+pub fn track(path: &Path) -> Option<Tracked> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    lock_files().push(File {
+        id,
+        name: path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned(),
+        stage: Stage::Decoding,
+        large: false,
+        total: 0,
+        done: 0,
+    });
+    CURRENT.with(|current| current.set(Some(id)));
+
+    Some(Tracked { id })
+}
+
+/// Updates the file tracked on this thread, if there is one.
+fn update(change: impl FnOnce(&mut File)) {
+    let Some(id) = CURRENT.with(Cell::get) else {
+        return;
+    };
+
+    if let Some(file) = lock_files().iter_mut().find(|file| file.id == id) {
+        change(file);
+    }
+}
+
+/// Once the image is decoded, whether it is large enough to be shown.
+pub fn size(pixels: u64) {
+    update(|file| file.large = pixels >= LARGE_IMAGE);
+}
+
+/// Runs `work`, returning how long it spent in each stage on this thread,
+/// in the order the stages started. Time before the first stage counts
+/// towards decoding.
+pub fn time_stages<T>(work: impl FnOnce() -> T) -> (T, Vec<(Stage, Duration)>) {
+    CLOCK.with(|clock| {
+        *clock.borrow_mut() = Some(Clock {
+            stages: vec![(Stage::Decoding, Duration::ZERO)],
+            since: Instant::now(),
+        })
+    });
+    let value = work();
+    enter(None);
+
+    let stages = CLOCK
+        .with(|clock| clock.borrow_mut().take())
+        .map_or(Vec::new(), |clock| clock.stages);
+
+    (value, stages)
+}
+
+/// Adds the time since the last change to the stage being timed, then
+/// moves on to `stage`.
+fn enter(stage: Option<Stage>) {
+    CLOCK.with(|clock| {
+        let mut clock = clock.borrow_mut();
+        let Some(Clock { stages, since }) = clock.as_mut() else {
+            return;
+        };
+
+        if let Some((_, time)) = stages.last_mut() {
+            *time += since.elapsed();
+        }
+        *since = Instant::now();
+
+        match stage {
+            Some(stage) if stages.last().map(|(last, _)| *last) != Some(stage) => {
+                stages.push((stage, Duration::ZERO))
+            }
+            _ => {}
+        }
+    });
+}
+
+pub fn stage(stage: Stage) {
+    enter(Some(stage));
+    update(|file| file.stage = stage);
+}
+
+/// Starts a pass filling `total` pixels.
+pub fn filling(total: usize) {
+    update(|file| {
+        file.stage = Stage::Filling;
+        file.total = total;
+        file.done = 0;
+    });
+}
+
+pub fn filled(pixels: usize) {
+    update(|file| file.done += pixels);
+}
+
+/// What the large images being fixed are doing, e.g. `hero_atlas.png:
+/// filling 42%`.
+fn large_files() -> Vec<String> {
+    lock_files()
+        .iter()
+        .filter(|file| file.large)
+        .map(|file| match file.stage {
+            Stage::Decoding => format!("{}: decoding", file.name),
+            Stage::Triangulating => format!("{}: triangulating", file.name),
+            Stage::Filling if file.total > 0 => format!(
+                "{}: filling {}%",
+                file.name,
+                (file.done * 100 / file.total).min(100)
+            ),
+            Stage::Filling => format!("{}: filling", file.name),
+            Stage::Encoding => format!("{}: encoding", file.name),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_done_has_no_estimate() {
+        assert_eq!(eta(0.0, 100.0, 10.0), None);
+        assert_eq!(eta(5.0, 100.0, 0.0), None);
+    }
+
+    #[test]
+    fn a_steady_pace_is_estimated_exactly() {
+        // Two seconds a file.
+        for done in 1..100 {
+            let estimate = eta(f64::from(done), 100.0, f64::from(done) * 2.0).unwrap();
+
+            assert!((estimate - f64::from(100 - done) * 2.0).abs() < 1e-9);
+        }
+
+        assert_eq!(eta(100.0, 100.0, 200.0), Some(0.0));
+        assert_eq!(eta(120.0, 100.0, 200.0), Some(0.0));
+    }
+
+    #[test]
+    fn an_uneven_pace_converges() {
+        // Files alternate between 1 and 5 seconds, 3 on average, after a
+        // slow start.
+        let times: Vec<f64> = (0..200)
+            .map(|file| match file {
+                0..=2 => 10.0,
+                _ if file % 2 == 0 => 1.0,
+                _ => 5.0,
+            })
+            .collect();
+        let error = |done: usize| {
+            let elapsed: f64 = times[..done].iter().sum();
+            let left: f64 = times[done..].iter().sum();
+            let estimate = eta(done as f64, times.len() as f64, elapsed).unwrap();
+
+            (estimate - left).abs() / left
+        };
+
+        assert!(error(20) > error(100));
+        assert!(error(100) > error(180));
+        assert!(error(190) < 0.05, "{}", error(190));
+    }
+
+    #[test]
+    fn the_line_goes_by_bytes_left() {
+        let start = Instant::now();
+        let mut estimator = Estimator::new(3, 400, start);
+        estimator.finished(100, start + Duration::from_secs(10));
+
+        // A quarter of the bytes in 10 seconds leaves 30, though a third
+        // of the files is done.
+        let line = estimator.line(start + Duration::from_secs(10));
+        assert!(line.starts_with("[1/3]"), "{}", line);
+        assert!(line.ends_with("about 30 seconds left"), "{}", line);
+    }
+}
@@ -0,0 +1,400 @@
+//! Per-file outcomes, the run summary and the CI report formats.
+
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    archive::ArchiveReport,
+    convert::Converted,
+    diagnostic,
+    error::PixfixError,
+    events::{self, Details},
+    human, result,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Fixed,
+    NoAlpha,
+    Failed,
+    Clean,
+    Dirty,
+    Skipped,
+}
+
+impl Status {
+    /// The name of the status in `--events` and `--resume` state files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Fixed => "fixed",
+            Status::NoAlpha => "no_alpha",
+            Status::Failed => "failed",
+            Status::Clean => "clean",
+            Status::Dirty => "dirty",
+            Status::Skipped => "skipped",
+        }
+    }
+}
+
+impl std::str::FromStr for Status {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        [
+            Status::Fixed,
+            Status::NoAlpha,
+            Status::Failed,
+            Status::Clean,
+            Status::Dirty,
+            Status::Skipped,
+        ]
+        .into_iter()
+        .find(|status| status.name() == value)
+        .ok_or(())
+    }
+}
+
+/// What happened to one input file, kept for the CI reports.
+pub struct FileReport {
+    pub path: PathBuf,
+    pub status: Status,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Human,
+    /// GitHub Actions workflow commands, so failures show up inline.
+    Github,
+    /// A JUnit XML report written to `--report-file`.
+    Junit,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "human" => Ok(ReportFormat::Human),
+            "github" => Ok(ReportFormat::Github),
+            "junit" => Ok(ReportFormat::Junit),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Summary {
+    pub fixed: usize,
+    pub failed: usize,
+    pub no_alpha: usize,
+    pub read_only: usize,
+    /// Files over `--max-file-size` or `--max-dimensions`.
+    pub oversized: usize,
+    /// Files `--deduplicate` skipped for having the content of another.
+    pub duplicates: usize,
+    /// Files of a type Pixfix doesn't fix.
+    pub ignored: usize,
+    /// Images with nothing to fix, or that a check found clean.
+    pub clean: usize,
+    pub dirty: usize,
+    /// Files a `--resume` run left alone because the interrupted run had
+    /// already finished them.
+    pub resumed: usize,
+    /// `--validate-first` found problems, so nothing was fixed.
+    pub aborted: bool,
+    /// Files passed through to a `--mirror` as they were.
+    pub copied: usize,
+    pub linked: usize,
+    pub reports: Vec<FileReport>,
+    /// Emit a `--events` line for every file as it is recorded.
+    pub events: bool,
+    /// Keep stdout for the `--print-modified` list.
+    pub print_modified: bool,
+    /// Every file that was written with changed pixels.
+    pub modified: Vec<PathBuf>,
+}
+
+impl Summary {
+    /// Files that were opened to be fixed or checked, whatever came of it.
+    /// Ignored, read-only, oversized and duplicate files aren't.
+    pub fn processed(&self) -> usize {
+        self.fixed + self.no_alpha + self.clean + self.dirty + self.failed
+    }
+
+    fn push(&mut self, path: &Path, status: Status, message: String, details: Details) -> Status {
+        if self.events {
+            events::file(path, status, &message, &details);
+        }
+
+        self.reports.push(FileReport {
+            path: path.to_path_buf(),
+            status,
+            message,
+        });
+
+        status
+    }
+
+    /// Records files that were never processed, e.g. unsupported types.
+    pub fn record_skipped(&mut self, skipped: &[(PathBuf, String)]) {
+        for (path, reason) in skipped {
+            self.push(path, Status::Skipped, reason.clone(), Details::default());
+        }
+    }
+
+    /// Prints the outcome of one file and counts it.
+    pub fn record(&mut self, path: &Path, result: Result<Converted, PixfixError>) -> Status {
+        match result {
+            Ok(Converted::Fixed {
+                output_path,
+                digest,
+                changed_pixels,
+                extra_outputs,
+                trim,
+            }) => {
+                let outputs = std::iter::once((&output_path, &digest))
+                    .chain(extra_outputs.iter().map(|(path, digest)| (path, digest)));
+
+                for (output_path, digest) in outputs {
+                    match digest {
+                        // The event carries the digest instead.
+                        Some(_) if self.events => {}
+                        Some(digest) if self.print_modified => {
+                            human!("{}  {}", digest, output_path.display())
+                        }
+                        Some(digest) => result!("{}  {}", digest, output_path.display()),
+                        None => human!("{:?}", output_path.display()),
+                    }
+
+                    if changed_pixels > 0 {
+                        self.modified.push(output_path.clone());
+                    }
+                }
+                if let Some(trim) = trim {
+                    human!(
+                        "Trimmed \"{}\" to {}x{} at {},{}",
+                        path.display(),
+                        trim.width,
+                        trim.height,
+                        trim.x,
+                        trim.y
+                    );
+                }
+
+                self.fixed += 1;
+                let details = Details {
+                    pixels: Some(changed_pixels),
+                    output: Some(output_path.to_string_lossy().into_owned()),
+                    digest,
+                    trim,
+                };
+                self.push(path, Status::Fixed, "Fixed".into(), details)
+            }
+            Ok(Converted::Diffed {
+                diff_path,
+                changed_pixels,
+            }) => {
+                let message = format!("{} pixels changed", changed_pixels);
+                human!("{:?} - {}", diff_path.display(), message);
+                self.fixed += 1;
+                let details = Details {
+                    pixels: Some(changed_pixels),
+                    output: Some(diff_path.to_string_lossy().into_owned()),
+                    ..Default::default()
+                };
+                self.push(path, Status::Fixed, message, details)
+            }
+            Ok(Converted::DataUri(uri)) => {
+                if self.print_modified {
+                    human!("{}", uri);
+                } else if !self.events {
+                    result!("{}", uri);
+                }
+                self.fixed += 1;
+                let details = Details {
+                    output: Some(uri),
+                    ..Default::default()
+                };
+                self.push(path, Status::Fixed, "Fixed".into(), details)
+            }
+            Ok(Converted::Archive(archive)) => self.record_archive(path, archive),
+            Ok(Converted::NoAlphaChannel) => {
+                let message = "Image has no alpha channel, skipping";
+                human!("{:?} - {}", path.display(), message);
+                self.no_alpha += 1;
+                self.push(path, Status::NoAlpha, message.into(), Details::default())
+            }
+            Ok(Converted::Checked { bad_pixels: 0 }) => {
+                human!("{:?} - Clean", path.display());
+                self.clean += 1;
+                let details = Details {
+                    pixels: Some(0),
+                    ..Default::default()
+                };
+                self.push(path, Status::Clean, "Clean".into(), details)
+            }
+            Ok(Converted::Checked { bad_pixels }) => {
+                let message = format!("{} pixels need fixing", bad_pixels);
+                human!("{:?} - {}", path.display(), message);
+                self.dirty += 1;
+                let details = Details {
+                    pixels: Some(bad_pixels),
+                    ..Default::default()
+                };
+                self.push(path, Status::Dirty, message, details)
+            }
+            Err(PixfixError::NothingToFix) => {
+                let message = PixfixError::NothingToFix.to_string();
+                human!("{:?} - {}", path.display(), message);
+                self.clean += 1;
+                let details = Details {
+                    pixels: Some(0),
+                    ..Default::default()
+                };
+                self.push(path, Status::Clean, message, details)
+            }
+            Err(err) => {
+                diagnostic!("Failed to fix \"{}\" - {}", path.display(), err);
+                self.failed += 1;
+                self.push(path, Status::Failed, err.to_string(), Details::default())
+            }
+        }
+    }
+
+    /// Records every entry of an archive on its own, then how the archive
+    /// as a whole went.
+    fn record_archive(&mut self, path: &Path, archive: ArchiveReport) -> Status {
+        let total = archive.entries.len();
+        let mut statuses = Vec::with_capacity(total);
+        let modified = self.modified.len();
+
+        for (name, result) in archive.entries {
+            statuses.push(self.record(&path.join(name), result));
+        }
+
+        // The entries only exist inside the archive, which is the file that
+        // changed.
+        let entries_modified = self.modified.len() > modified;
+        self.modified.truncate(modified);
+
+        if let (true, Some(output_path)) = (entries_modified, &archive.output_path) {
+            self.modified.push(output_path.clone());
+        }
+
+        let count = |status| statuses.iter().filter(|s| **s == status).count();
+
+        match archive.output_path {
+            Some(output_path) => human!(
+                "Fixed {} of {} entries in \"{}\", saved to \"{}\"",
+                count(Status::Fixed),
+                total,
+                path.display(),
+                output_path.display()
+            ),
+            None => human!(
+                "{} of {} entries in \"{}\" need fixing",
+                count(Status::Dirty),
+                total,
+                path.display()
+            ),
+        }
+
+        [Status::Failed, Status::Dirty, Status::Fixed, Status::Clean]
+            .into_iter()
+            .find(|status| count(*status) > 0)
+            .unwrap_or(Status::NoAlpha)
+    }
+}
+
+/// Escapes the characters GitHub treats specially in workflow commands.
+fn escape_github(value: &str, property: bool) -> String {
+    let value = value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A");
+
+    if property {
+        value.replace(':', "%3A").replace(',', "%2C")
+    } else {
+        value
+    }
+}
+
+/// Prints `::error` lines for failing files and `::warning` lines for
+/// skipped ones. Images without an alpha channel are already correct.
+pub fn print_github(reports: &[FileReport]) {
+    for report in reports {
+        let level = match report.status {
+            Status::Failed | Status::Dirty => "error",
+            Status::Skipped => "warning",
+            Status::Fixed | Status::Clean | Status::NoAlpha => continue,
+        };
+
+        result!(
+            "::{} file={}::{}",
+            level,
+            escape_github(&report.path.to_string_lossy(), true),
+            escape_github(&report.message, false)
+        );
+    }
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Writes a JUnit report with one test case per input file. Images without
+/// an alpha channel pass, there is nothing to fix in them.
+pub fn write_junit(reports: &[FileReport], time: f32, out: &mut impl Write) -> io::Result<()> {
+    let count = |statuses: &[Status]| {
+        reports
+            .iter()
+            .filter(|report| statuses.contains(&report.status))
+            .count()
+    };
+
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<testsuite name="pixfix" tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+        reports.len(),
+        count(&[Status::Failed, Status::Dirty]),
+        count(&[Status::Skipped]),
+        time
+    )?;
+
+    for report in reports {
+        let name = escape_xml(&report.path.to_string_lossy());
+        let message = escape_xml(&report.message);
+
+        match report.status {
+            Status::Fixed | Status::Clean | Status::NoAlpha => {
+                writeln!(out, r#"  <testcase classname="pixfix" name="{}"/>"#, name)?;
+            }
+            Status::Failed | Status::Dirty => {
+                writeln!(out, r#"  <testcase classname="pixfix" name="{}">"#, name)?;
+                writeln!(
+                    out,
+                    r#"    <failure message="{}">{}</failure>"#,
+                    message, message
+                )?;
+                writeln!(out, "  </testcase>")?;
+            }
+            Status::Skipped => {
+                writeln!(out, r#"  <testcase classname="pixfix" name="{}">"#, name)?;
+                writeln!(out, r#"    <skipped message="{}"/>"#, message)?;
+                writeln!(out, "  </testcase>")?;
+            }
+        }
+    }
+
+    writeln!(out, "</testsuite>")
+}
@@ -0,0 +1,154 @@
+//! `--resume <state-file>`, so a batch that was interrupted carries on where
+//! it stopped instead of starting over.
+//!
+//! The state file is newline delimited JSON. The first line holds the
+//! arguments of the run, every other line a file that was finished:
+//!
+//! - `{"args":[...]}`
+//! - `{"path":...,"hash":...,"status":...}` with the BLAKE3 hash of the file
+//!   once it was finished.
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{json, Value};
+
+use crate::{digest::DigestAlgorithm, paths::io_path, report::Status};
+
+pub struct Resume {
+    path: PathBuf,
+    file: File,
+    /// The hash and status of every file finished by an earlier run.
+    finished: HashMap<PathBuf, (String, Status)>,
+}
+
+fn hash_file(path: &Path) -> Option<String> {
+    let bytes = std::fs::read(io_path(path)).ok()?;
+
+    Some(DigestAlgorithm::Blake3.digest(&bytes))
+}
+
+/// The arguments that have to match for a run to be resumed, which are all
+/// of them but `--resume` itself.
+fn run_args(args: &[OsString]) -> Vec<String> {
+    let mut run_args = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--resume" {
+            args.next();
+        } else {
+            run_args.push(arg.to_string_lossy().into_owned());
+        }
+    }
+
+    run_args
+}
+
+fn parse_entry(line: &str) -> Option<(PathBuf, (String, Status))> {
+    let entry: Value = serde_json::from_str(line).ok()?;
+
+    Some((
+        PathBuf::from(entry["path"].as_str()?),
+        (
+            entry["hash"].as_str()?.to_string(),
+            entry["status"].as_str()?.parse().ok()?,
+        ),
+    ))
+}
+
+impl Resume {
+    /// Opens the state file at `path`, or starts a new one. `args` are the
+    /// arguments of this run, which have to be the ones the state file was
+    /// started with.
+    pub fn open(path: &Path, args: &[OsString]) -> Result<Self, String> {
+        let args = run_args(args);
+        let mut finished = HashMap::new();
+
+        let contents = match std::fs::read_to_string(io_path(path)) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(format!("Unable to read \"{}\": {}", path.display(), err)),
+        };
+
+        if !contents.is_empty() {
+            let mut lines = contents.lines();
+            let header: Option<Value> = lines
+                .next()
+                .and_then(|line| serde_json::from_str(line).ok());
+
+            if header.as_ref().map(|header| &header["args"]) != Some(&json!(args)) {
+                return Err(format!(
+                    "\"{}\" was started with other options or files! Delete it to start over.",
+                    path.display()
+                ));
+            }
+
+            // A line cut short by the interruption is left out, that
+            // file is simply fixed again.
+            finished.extend(lines.filter_map(parse_entry));
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(io_path(path))
+            .map_err(|err| format!("Unable to open \"{}\": {}", path.display(), err))?;
+
+        let started = if contents.is_empty() {
+            writeln!(file, "{}", json!({ "args": args }))
+        } else if !contents.ends_with('\n') {
+            // Keeps the next entry off the line that was cut short.
+            writeln!(file)
+        } else {
+            Ok(())
+        };
+
+        started.map_err(|err| format!("Unable to write \"{}\": {}", path.display(), err))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            finished,
+        })
+    }
+
+    /// The status `path` was finished with, if an earlier run finished it and
+    /// it hasn't changed since.
+    pub fn finished(&self, path: &Path) -> Option<Status> {
+        let (hash, status) = self.finished.get(path)?;
+
+        (hash_file(path).as_ref() == Some(hash)).then_some(*status)
+    }
+
+    /// Notes that `path` is finished. Failed files aren't, so they are tried
+    /// again when resuming.
+    pub fn record(&mut self, path: &Path, status: Status) {
+        if status == Status::Failed {
+            return;
+        }
+
+        let Some(hash) = hash_file(path) else {
+            return;
+        };
+
+        let entry = json!({
+            "path": path.to_string_lossy(),
+            "hash": hash,
+            "status": status.name(),
+        });
+
+        // Written straight through, so it survives the run being killed.
+        let _ = writeln!(self.file, "{}", entry);
+    }
+
+    /// Deletes the state file once the batch is done.
+    pub fn finish(self) {
+        let _ = std::fs::remove_file(io_path(&self.path));
+    }
+}
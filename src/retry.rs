@@ -0,0 +1,176 @@
+use std::{io, path::Path, thread, time::Duration};
+
+use crate::{diagnostic, paths::is_read_only};
+
+// Windows error codes returned while another process has the file open.
+const ERROR_SHARING_VIOLATION: i32 = 32;
+const ERROR_LOCK_VIOLATION: i32 = 33;
+// Windows error codes returned when a network share drops out briefly.
+const ERROR_UNEXP_NET_ERR: i32 = 59;
+const ERROR_NETNAME_DELETED: i32 = 64;
+
+/// Delay before the first retry, doubled for every one after it.
+const BACKOFF: Duration = Duration::from_millis(100);
+/// The longest a single retry waits, however large `--retries` is.
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+pub struct RetryError {
+    pub attempts: u32,
+    pub error: io::Error,
+}
+
+/// Whether the error is likely to go away on its own, e.g. an image editor
+/// or thumbnailer still holding the file open, or a network filesystem
+/// timing out.
+fn is_retryable(error: &io::Error, path: &Path) -> bool {
+    if cfg!(target_os = "windows") {
+        if let Some(
+            ERROR_SHARING_VIOLATION
+            | ERROR_LOCK_VIOLATION
+            | ERROR_UNEXP_NET_ERR
+            | ERROR_NETNAME_DELETED,
+        ) = error.raw_os_error()
+        {
+            return true;
+        }
+    }
+
+    match error.kind() {
+        io::ErrorKind::Interrupted
+        | io::ErrorKind::TimedOut
+        | io::ErrorKind::WouldBlock
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted => true,
+        io::ErrorKind::PermissionDenied => !is_read_only(path),
+        _ => false,
+    }
+}
+
+/// How long to wait after the attempt numbered `attempts` failed.
+fn backoff(attempts: u32) -> Duration {
+    // The exponent is capped so a large --retries can't overflow the
+    // multiplier.
+    (BACKOFF * 2u32.pow((attempts - 1).min(16))).min(MAX_BACKOFF)
+}
+
+/// Runs `operation` on `path`, retrying up to `retries` times with an
+/// exponential backoff (100ms, 200ms, 400ms, ... up to 5s) while it fails
+/// with a retryable error. Each retry is logged when `verbose` is set.
+pub fn with_retries<T>(
+    path: &Path,
+    retries: u32,
+    verbose: bool,
+    mut operation: impl FnMut(&Path) -> io::Result<T>,
+) -> Result<T, RetryError> {
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+
+        match operation(path) {
+            Ok(value) => return Ok(value),
+            Err(error) => {
+                if attempts > retries || !is_retryable(&error, path) {
+                    return Err(RetryError { attempts, error });
+                }
+
+                let backoff = backoff(attempts);
+
+                if verbose {
+                    diagnostic!(
+                        "Retrying \"{}\" in {}ms ({} of {}) - {}",
+                        path.display(),
+                        backoff.as_millis(),
+                        attempts,
+                        retries,
+                        error
+                    );
+                }
+
+                thread::sleep(backoff);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::PixfixError;
+
+    fn failing(kind: io::ErrorKind) -> io::Error {
+        io::Error::new(kind, "mock failure")
+    }
+
+    #[test]
+    fn retryable_errors_are_retried_until_they_pass() {
+        let mut calls = 0;
+
+        let result = with_retries(Path::new("a.png"), 5, false, |_| {
+            calls += 1;
+
+            match calls {
+                1..=3 => Err(failing(io::ErrorKind::Interrupted)),
+                _ => Ok(calls),
+            }
+        });
+
+        assert!(matches!(result, Ok(4)));
+    }
+
+    #[test]
+    fn retries_give_up_after_the_last_attempt() {
+        let mut calls = 0;
+
+        let result = with_retries(Path::new("a.png"), 2, false, |_| -> io::Result<()> {
+            calls += 1;
+            Err(failing(io::ErrorKind::TimedOut))
+        });
+
+        let Err(err) = result else {
+            panic!("a file that always fails was read");
+        };
+
+        assert_eq!(calls, 3);
+        assert_eq!(err.attempts, 3);
+        assert_eq!(err.error.kind(), io::ErrorKind::TimedOut);
+
+        let message = PixfixError::Read {
+            attempts: err.attempts,
+            error: err.error,
+        }
+        .to_string();
+        assert_eq!(
+            message,
+            "An error occured reading the image after 3 attempts: mock failure"
+        );
+    }
+
+    #[test]
+    fn the_backoff_doubles_up_to_its_cap() {
+        assert_eq!(backoff(1), Duration::from_millis(100));
+        assert_eq!(backoff(2), Duration::from_millis(200));
+        assert_eq!(backoff(6), Duration::from_millis(3200));
+        assert_eq!(backoff(7), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn other_errors_are_never_retried() {
+        for kind in [
+            io::ErrorKind::NotFound,
+            io::ErrorKind::InvalidData,
+            io::ErrorKind::AlreadyExists,
+        ] {
+            let mut calls = 0;
+
+            let result = with_retries(Path::new("a.png"), 5, false, |_| -> io::Result<()> {
+                calls += 1;
+                Err(failing(kind))
+            });
+
+            assert_eq!(calls, 1, "{:?}", kind);
+            assert!(matches!(result, Err(RetryError { attempts: 1, .. })));
+        }
+    }
+}
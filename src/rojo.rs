@@ -0,0 +1,263 @@
+//! Finds the images of a Rojo project, so a whole Roblox game can be fixed
+//! by pointing Pixfix at its project file.
+
+use std::{
+    ffi::OsString,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use glob::{MatchOptions, Pattern};
+use serde_json::Value;
+
+use crate::{files::ResolvedFiles, options::Options};
+
+/// A node of the Rojo tree that maps a filesystem path into the game.
+pub struct TreeEntry {
+    /// Dotted path of the node in the game tree, e.g. `ReplicatedStorage.Assets`.
+    pub name: String,
+    pub path: PathBuf,
+}
+
+pub struct Project {
+    pub root: PathBuf,
+    pub entries: Vec<TreeEntry>,
+    ignore: Vec<Pattern>,
+    /// Options from the project's `pixfix` table or a sibling `pixfix.toml`,
+    /// as command line arguments.
+    pub args: Vec<OsString>,
+}
+
+pub enum RojoError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, String),
+    NotAProject(PathBuf),
+    Options(PathBuf, String),
+}
+
+impl fmt::Display for RojoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RojoError::Read(path, error) => write!(
+                f,
+                "Unable to read Rojo project \"{}\": {}",
+                path.display(),
+                error
+            ),
+            RojoError::Parse(path, error) => write!(
+                f,
+                "Unable to parse Rojo project \"{}\": {}",
+                path.display(),
+                error
+            ),
+            RojoError::NotAProject(path) => write!(
+                f,
+                "\"{}\" is not a Rojo project, it has no \"tree\"!",
+                path.display()
+            ),
+            RojoError::Options(path, error) => write!(
+                f,
+                "Invalid Pixfix options in \"{}\": {}",
+                path.display(),
+                error
+            ),
+        }
+    }
+}
+
+fn collect_entries(node: &Value, name: &str, root: &Path, entries: &mut Vec<TreeEntry>) {
+    let Some(node) = node.as_object() else {
+        return;
+    };
+
+    // `$path` is either a string or `{ "optional": "..." }`.
+    let path = match node.get("$path") {
+        Some(Value::String(path)) => Some(path.as_str()),
+        Some(Value::Object(path)) => path.get("optional").and_then(Value::as_str),
+        _ => None,
+    };
+
+    if let Some(path) = path {
+        entries.push(TreeEntry {
+            name: name.to_string(),
+            path: root.join(path),
+        });
+    }
+
+    for (child_name, child) in node {
+        if child_name.starts_with('$') {
+            continue;
+        }
+
+        let child_name = if name.is_empty() {
+            child_name.clone()
+        } else {
+            format!("{}.{}", name, child_name)
+        };
+
+        collect_entries(child, &child_name, root, entries);
+    }
+}
+
+fn option_arg(name: &str, value: &str, args: &mut Vec<OsString>) {
+    args.push(format!("--{}", name).into());
+
+    if !value.is_empty() {
+        args.push(value.into());
+    }
+}
+
+fn json_option_args(table: &serde_json::Map<String, Value>) -> Result<Vec<OsString>, String> {
+    let mut args = Vec::new();
+
+    for (name, value) in table {
+        match value {
+            Value::Bool(true) => option_arg(name, "", &mut args),
+            Value::Bool(false) => {}
+            Value::Number(number) => option_arg(name, &number.to_string(), &mut args),
+            Value::String(string) => option_arg(name, string, &mut args),
+            _ => return Err(format!("\"{}\" must be a boolean, number or string", name)),
+        }
+    }
+
+    Ok(args)
+}
+
+fn toml_option_args(table: &toml::value::Table) -> Result<Vec<OsString>, String> {
+    let mut args = Vec::new();
+
+    for (name, value) in table {
+        match value {
+            toml::Value::Boolean(true) => option_arg(name, "", &mut args),
+            toml::Value::Boolean(false) => {}
+            toml::Value::Integer(number) => option_arg(name, &number.to_string(), &mut args),
+            toml::Value::Float(number) => option_arg(name, &number.to_string(), &mut args),
+            toml::Value::String(string) => option_arg(name, string, &mut args),
+            _ => return Err(format!("\"{}\" must be a boolean, number or string", name)),
+        }
+    }
+
+    Ok(args)
+}
+
+/// Reads the project file and the options that come with it.
+pub fn load_project(path: &Path) -> Result<Project, RojoError> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|err| RojoError::Read(path.to_path_buf(), err))?;
+    let project: Value = serde_json::from_str(&contents)
+        .map_err(|err| RojoError::Parse(path.to_path_buf(), err.to_string()))?;
+
+    let Some(tree) = project.get("tree") else {
+        return Err(RojoError::NotAProject(path.to_path_buf()));
+    };
+
+    let root = match path.parent() {
+        Some(parent) => parent.to_path_buf(),
+        None => PathBuf::new(),
+    };
+
+    let mut entries = Vec::new();
+    collect_entries(tree, "", &root, &mut entries);
+
+    let mut ignore = Vec::new();
+
+    if let Some(globs) = project.get("globIgnorePaths").and_then(Value::as_array) {
+        for glob in globs.iter().filter_map(Value::as_str) {
+            let pattern = Pattern::new(glob)
+                .map_err(|err| RojoError::Parse(path.to_path_buf(), err.to_string()))?;
+            ignore.push(pattern);
+        }
+    }
+
+    let toml_path = root.join("pixfix.toml");
+
+    let args = if let Some(table) = project.get("pixfix") {
+        let table = table.as_object().ok_or_else(|| {
+            RojoError::Options(path.to_path_buf(), "\"pixfix\" must be a table".into())
+        })?;
+
+        json_option_args(table).map_err(|err| RojoError::Options(path.to_path_buf(), err))?
+    } else if toml_path.is_file() {
+        let contents = std::fs::read_to_string(&toml_path)
+            .map_err(|err| RojoError::Read(toml_path.clone(), err))?;
+        let table: toml::value::Table = toml::from_str(&contents)
+            .map_err(|err| RojoError::Options(toml_path.clone(), err.to_string()))?;
+
+        toml_option_args(&table).map_err(|err| RojoError::Options(toml_path.clone(), err))?
+    } else {
+        Vec::new()
+    };
+
+    Ok(Project {
+        root,
+        entries,
+        ignore,
+        args,
+    })
+}
+
+impl Project {
+    fn is_ignored(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let match_options = MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+
+        self.ignore
+            .iter()
+            .any(|pattern| pattern.matches_with(&relative, match_options))
+    }
+
+    fn add_tree(&self, path: &Path, resolved: &mut ResolvedFiles, options: &Options) {
+        if self.is_ignored(path) {
+            return;
+        }
+
+        if path.is_file() {
+            if crate::files::is_png_file(path) {
+                resolved.add_file(path.to_path_buf(), options);
+            }
+
+            return;
+        }
+
+        let dir = match std::fs::read_dir(path) {
+            Ok(data) => data,
+            Err(_) => {
+                resolved.skip(path, "An error occured reading directory".into());
+
+                return;
+            }
+        };
+
+        for entry in dir.flatten() {
+            self.add_tree(&entry.path(), resolved, options);
+        }
+    }
+
+    /// Resolves every PNG under the project's paths, returning for each tree
+    /// entry the range of `resolved.files` that belongs to it.
+    pub fn resolve_files(&self, options: &Options) -> (ResolvedFiles, Vec<(usize, usize)>) {
+        let mut resolved = ResolvedFiles::default();
+        let mut ranges = Vec::new();
+
+        for entry in &self.entries {
+            let start = resolved.files.len();
+
+            if entry.path.exists() {
+                self.add_tree(&entry.path, &mut resolved, options);
+            } else {
+                resolved.skip(
+                    &entry.path,
+                    format!("The path of {} does not exist", entry.name),
+                );
+            }
+
+            ranges.push((start, resolved.files.len()));
+        }
+
+        (resolved, ranges)
+    }
+}
@@ -0,0 +1,174 @@
+//! `pixfix self-test`, a quick diagnostic for "it doesn't do anything on my
+//! machine". It generates images covering the usual kinds of input in a
+//! temporary folder, fixes them with the default options, checks the results
+//! and prints what it found along with the environment. The images come
+//! from `fixtures`, which the tests fix the same way.
+
+use std::{
+    path::{Path, PathBuf},
+    thread::available_parallelism,
+};
+
+use crate::{
+    convert::{self, Converted, Image16},
+    diagnostic,
+    error::PixfixError,
+    fixtures::{fixtures, Expect, Fixture},
+    human,
+    options::Options,
+};
+
+/// Colored pixels next to transparent ones, the pixels the fill spreads.
+fn border_pixels(img: &Image16) -> Vec<(u32, u32, [u16; 3])> {
+    let (width, height) = img.dimensions();
+    let transparent = |x: i64, y: i64| {
+        x >= 0
+            && y >= 0
+            && x < i64::from(width)
+            && y < i64::from(height)
+            && img.get_pixel(x as u32, y as u32).0[3] == 0
+    };
+
+    img.enumerate_pixels()
+        .filter(|(x, y, color)| {
+            let (x, y) = (i64::from(*x), i64::from(*y));
+
+            color.0[3] != 0 && (-1..=1).any(|dy| (-1..=1).any(|dx| transparent(x + dx, y + dy)))
+        })
+        .map(|(x, y, color)| (x, y, [color.0[0], color.0[1], color.0[2]]))
+        .collect()
+}
+
+/// Why `fixed` breaks the `NearestBorder` promise for `input`, if it does.
+fn nearest_border_problem(input: &Image16, fixed: &Image16) -> Option<String> {
+    let border = border_pixels(input);
+    let solid = border.windows(2).all(|pair| pair[0].2 == pair[1].2);
+
+    for ((x, y, before), after) in input.enumerate_pixels().zip(fixed.pixels()) {
+        if before.0[3] != after.0[3] {
+            return Some(format!("The alpha of {},{} changed", x, y));
+        }
+
+        let color = [after.0[0], after.0[1], after.0[2]];
+
+        if before.0[3] != 0 {
+            if before.0[..3] != color {
+                return Some(format!("The colored pixel {},{} changed", x, y));
+            }
+
+            continue;
+        }
+
+        // With a single border color there is no need to look for the
+        // nearest, which keeps large images quick.
+        let nearest = if solid {
+            border.first().map(|pixel| pixel.2) == Some(color)
+        } else {
+            let distance = |(border_x, border_y, _): &(u32, u32, [u16; 3])| {
+                (i64::from(*border_x) - i64::from(x)).pow(2)
+                    + (i64::from(*border_y) - i64::from(y)).pow(2)
+            };
+            let closest = border.iter().map(distance).min();
+
+            border
+                .iter()
+                .any(|pixel| Some(distance(pixel)) == closest && pixel.2 == color)
+        };
+
+        if !nearest {
+            return Some(format!(
+                "{},{} doesn't have the color of its nearest border pixel",
+                x, y
+            ));
+        }
+    }
+
+    None
+}
+
+/// Writes `fixture` to `dir`, fixes it like a normal run would and checks
+/// the result, returning what is wrong with it.
+pub fn run_fixture(fixture: &Fixture, dir: &Path, options: &Options) -> Result<(), String> {
+    let path = dir.join(format!("{}.png", fixture.name.replace(' ', "-")));
+    std::fs::write(&path, &fixture.png).map_err(|err| format!("Unable to write: {}", err))?;
+
+    let input = image::load_from_memory(&fixture.png)
+        .map_err(|err| format!("Unable to decode the fixture: {}", err))?;
+    let result = convert::convert_image(&path, options);
+
+    let fixed = match (fixture.expect, result) {
+        (Expect::NothingToFix, Err(PixfixError::NothingToFix)) => {
+            return match std::fs::read(&path) {
+                Ok(bytes) if bytes == fixture.png => Ok(()),
+                Ok(_) => Err("The file changed".into()),
+                Err(err) => Err(format!("Unable to read: {}", err)),
+            };
+        }
+        (Expect::NothingToFix, Err(err)) => {
+            return Err(format!("Expected nothing to fix: {}", err))
+        }
+        (Expect::NothingToFix, Ok(_)) => {
+            return Err("Expected nothing to fix, but it was fixed".into())
+        }
+        (_, Ok(Converted::Fixed { .. })) => {
+            image::open(&path).map_err(|err| format!("Unable to decode the output: {}", err))?
+        }
+        (_, Ok(_)) => return Err("Nothing was saved".into()),
+        (_, Err(err)) => return Err(err.to_string()),
+    };
+
+    if convert::is_16_bit(&input) != convert::is_16_bit(&fixed) {
+        return Err("The output has a different bit depth".into());
+    }
+
+    let (input, fixed) = (input.to_rgba16(), fixed.to_rgba16());
+
+    if input.dimensions() != fixed.dimensions() {
+        return Err("The output has a different size".into());
+    }
+
+    nearest_border_problem(&input, &fixed).map_or(Ok(()), Err)
+}
+
+/// Runs every fixture, printing the environment and a line per fixture.
+/// Returns whether all of them passed.
+pub fn self_test() -> Result<bool, String> {
+    let dir: PathBuf =
+        std::env::temp_dir().join(format!("pixfix-self-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)
+        .map_err(|err| format!("Unable to create \"{}\": {}", dir.display(), err))?;
+
+    human!("Pixfix {}", env!("CARGO_PKG_VERSION"));
+    human!("OS: {} {}", std::env::consts::OS, std::env::consts::ARCH);
+    human!(
+        "CPUs: {}",
+        available_parallelism().map_or(1, |cores| cores.get())
+    );
+    human!("Temp folder: {}\n", dir.display());
+
+    let options = Options::default();
+    let fixtures = fixtures();
+    let mut failed = 0;
+
+    for fixture in &fixtures {
+        match run_fixture(fixture, &dir, &options) {
+            Ok(()) => human!("PASS {}", fixture.name),
+            Err(err) => {
+                diagnostic!("FAIL {} - {}", fixture.name, err);
+                failed += 1;
+            }
+        }
+    }
+
+    if let Err(err) = std::fs::remove_dir_all(&dir) {
+        diagnostic!("Unable to remove \"{}\": {}", dir.display(), err);
+    }
+
+    human!(
+        "\n{} of {} cases passed",
+        fixtures.len() - failed,
+        fixtures.len()
+    );
+
+    Ok(failed == 0)
+}
@@ -0,0 +1,457 @@
+//! A small HTTP server, so services can fix images without spawning a
+//! process per request.
+
+use std::{ffi::OsString, path::Path, sync::Arc, time::Instant};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+};
+
+use crate::{
+    convert::{fix_bytes, Deadline, FixedBytes},
+    diagnostic,
+    error::PixfixError,
+    human,
+    options::{self, Options},
+};
+
+/// Limit for the request line and headers together.
+const MAX_HEAD_SIZE: u64 = 16 * 1024;
+
+/// The options a request may set, the ones that only change how its image is
+/// filled and encoded. Anything touching files or the process, like
+/// `working-dir` or `atlas`, is left to the options of `serve` itself.
+const REQUEST_OPTIONS: &[&str] = &[
+    "alpha-fill",
+    "check",
+    "premultiplied-alpha-input",
+    "alpha-invert",
+    "connected-components",
+    "edge-detect",
+    "max-border-pixels",
+    "poisson-disk-radius",
+    "dilate",
+    "dilate-alpha",
+    "blend",
+    "colorspace",
+    "blend-colorspace",
+    "colorkey",
+    "colorkey-to-alpha",
+    "repeat",
+    "sprite-sheet-grid",
+    "trim",
+    "trim-margin",
+    "power-of-two-pad",
+    "scale",
+    "scale-filter",
+    "png-compression",
+    "strip-metadata",
+    "strip-color-info",
+];
+
+pub struct ServeOptions {
+    pub listen: String,
+    /// Largest accepted request body, in bytes.
+    pub max_body_size: u64,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:7878".into(),
+            max_body_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// Splits the server's own options from the fix options, which every request
+/// starts from.
+pub fn parse_serve_args(args: Vec<OsString>) -> Result<(ServeOptions, Vec<OsString>), String> {
+    let mut serve = ServeOptions::default();
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let flag = arg.to_string_lossy();
+
+        match flag.as_ref() {
+            "--listen" => serve.listen = options::parse_value(&flag, args.next())?,
+            "--max-body-size" => serve.max_body_size = options::parse_value(&flag, args.next())?,
+            _ => rest.push(arg),
+        }
+    }
+
+    Ok((serve, rest))
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn text(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into().into_bytes(),
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+struct Head {
+    method: String,
+    path: String,
+    query: String,
+    content_length: Option<u64>,
+    expect_continue: bool,
+}
+
+async fn read_head(reader: &mut BufReader<TcpStream>) -> Result<Head, Response> {
+    let mut limited = reader.take(MAX_HEAD_SIZE);
+    let mut lines = Vec::new();
+
+    loop {
+        let mut line = String::new();
+        let read = limited
+            .read_line(&mut line)
+            .await
+            .map_err(|err| Response::text(400, err.to_string()))?;
+
+        if read == 0 || !line.ends_with('\n') {
+            return Err(Response::text(431, "The request head is too large"));
+        }
+
+        let line = line.trim_end().to_string();
+
+        if line.is_empty() {
+            break;
+        }
+
+        lines.push(line);
+    }
+
+    let mut request_line = lines.first().map_or("", String::as_str).split(' ');
+    let method = request_line.next().unwrap_or_default().to_string();
+    let target = request_line.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut head = Head {
+        method,
+        path: path.to_string(),
+        query: query.to_string(),
+        content_length: None,
+        expect_continue: false,
+    };
+
+    for line in lines.iter().skip(1) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => {
+                head.content_length = Some(
+                    value
+                        .parse()
+                        .map_err(|_| Response::text(400, "Invalid Content-Length"))?,
+                )
+            }
+            // Chunked bodies have no size up front, so they can't be limited
+            // before reading them.
+            "transfer-encoding" => {
+                return Err(Response::text(411, "Send a Content-Length"));
+            }
+            "expect" => head.expect_continue = value.eq_ignore_ascii_case("100-continue"),
+            _ => {}
+        }
+    }
+
+    Ok(head)
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+
+        index += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Turns `alpha-fill=1&dilate=4` into command line arguments, the same way
+/// project options are. Fails on options missing from `REQUEST_OPTIONS`.
+fn query_args(query: &str) -> Result<Vec<OsString>, String> {
+    let mut args = Vec::new();
+
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let (name, value) = (percent_decode(name), percent_decode(value));
+
+        if !REQUEST_OPTIONS.contains(&name.as_str()) {
+            return Err(format!("\"{}\" can't be set per request!", name));
+        }
+
+        match value.as_str() {
+            "false" => {}
+            "" | "true" => args.push(format!("--{}", name).into()),
+            _ => {
+                args.push(format!("--{}", name).into());
+                args.push(value.into());
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+fn request_options(base_args: &[OsString], query: &str) -> Result<Options, String> {
+    let mut args = base_args.to_vec();
+    args.extend(query_args(query)?);
+
+    let (mut options, unknown) = options::parse_args(args)?;
+
+    if let Some(arg) = unknown.first() {
+        return Err(format!("Unknown option \"{}\"!", arg.to_string_lossy()));
+    }
+
+    // The fills of client requests would stay cached for as long as the
+    // server runs.
+    options.frame_cache = false;
+
+    Ok(options)
+}
+
+fn fix_response(body: &[u8], options: &Options) -> Response {
+    match fix_bytes(body, Path::new("request"), options) {
+        Ok(FixedBytes::Fixed { bytes, .. }) => Response {
+            status: 200,
+            content_type: "image/png",
+            body: bytes,
+        },
+        Ok(FixedBytes::Checked { bad_pixels }) => Response::text(200, bad_pixels.to_string()),
+        Ok(FixedBytes::NoAlphaChannel) => {
+            Response::text(422, "The image has no alpha channel, nothing to fix")
+        }
+        // Fully opaque or fully transparent, so it is already as fixed as
+        // it gets.
+        Err(PixfixError::NothingToFix) => Response {
+            status: 200,
+            content_type: "image/png",
+            body: body.to_vec(),
+        },
+        Err(err @ PixfixError::Decode(_)) => Response::text(400, err.to_string()),
+        Err(err @ PixfixError::TimedOut(_)) => Response::text(503, err.to_string()),
+        Err(err) => Response::text(500, err.to_string()),
+    }
+}
+
+async fn handle(
+    reader: &mut BufReader<TcpStream>,
+    serve: &ServeOptions,
+    base_args: &[OsString],
+    jobs: &Semaphore,
+) -> Response {
+    let head = match read_head(reader).await {
+        Ok(value) => value,
+        Err(response) => return response,
+    };
+
+    match head.path.as_str() {
+        "/healthz" if head.method == "GET" => return Response::text(200, "ok"),
+        "/fix" if head.method == "POST" => {}
+        "/healthz" | "/fix" => return Response::text(405, "Method not allowed"),
+        _ => return Response::text(404, "Not found"),
+    }
+
+    let options = match request_options(base_args, &head.query) {
+        Ok(value) => value,
+        Err(err) => return Response::text(400, err),
+    };
+
+    let Some(length) = head.content_length else {
+        return Response::text(411, "Send a Content-Length");
+    };
+
+    if length > serve.max_body_size {
+        return Response::text(
+            413,
+            format!("The body is larger than {} bytes", serve.max_body_size),
+        );
+    }
+
+    // Taken before reading the body, so at most `--jobs` bodies are held in
+    // memory at once.
+    let Ok(_permit) = jobs.acquire().await else {
+        return Response::text(500, "The server is shutting down");
+    };
+
+    if head.expect_continue {
+        let _ = reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+            .await;
+    }
+
+    let mut body = Vec::new();
+
+    if let Err(err) = (&mut *reader).take(length).read_to_end(&mut body).await {
+        return Response::text(400, err.to_string());
+    }
+
+    if (body.len() as u64) < length {
+        return Response::text(400, "The body is shorter than its Content-Length");
+    }
+
+    // The fill stops on its own at the deadline, and the response doesn't
+    // wait for the decode or triangulation either.
+    let timeout = options.timeout_per_file;
+    let task = tokio::task::spawn_blocking(move || {
+        let _deadline = Deadline::start(Instant::now(), &options);
+        fix_response(&body, &options)
+    });
+
+    let joined = match timeout {
+        Some(limit) => tokio::time::timeout(limit, task).await,
+        None => Ok(task.await),
+    };
+
+    match joined {
+        Ok(response) => response.unwrap_or_else(|err| Response::text(500, err.to_string())),
+        Err(_) => Response::text(
+            503,
+            PixfixError::TimedOut(timeout.unwrap_or_default()).to_string(),
+        ),
+    }
+}
+
+async fn respond(stream: &mut TcpStream, response: Response) -> std::io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    );
+
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.shutdown().await
+}
+
+/// Serves `POST /fix` and `GET /healthz` until the process is stopped. Every
+/// connection handles a single request.
+pub async fn serve(serve: ServeOptions, base_args: Vec<OsString>) -> Result<(), String> {
+    // Fails early on invalid options instead of on every request.
+    let base_options = request_options(&base_args, "")?;
+
+    let listener = TcpListener::bind(&serve.listen)
+        .await
+        .map_err(|err| format!("Unable to listen on {}: {}", serve.listen, err))?;
+
+    human!("Listening on http://{}", serve.listen);
+
+    let serve = Arc::new(serve);
+    let base_args = Arc::new(base_args);
+    let jobs = Arc::new(Semaphore::new(base_options.jobs));
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                diagnostic!("Unable to accept a connection: {}", err);
+                continue;
+            }
+        };
+
+        let serve = serve.clone();
+        let base_args = base_args.clone();
+        let jobs = jobs.clone();
+
+        tokio::spawn(async move {
+            let mut reader = BufReader::new(stream);
+            let response = handle(&mut reader, &serve, &base_args, &jobs).await;
+            let _ = respond(reader.get_mut(), response).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_options_are_accepted() {
+        let options = request_options(&[], "dilate=4&colorspace=oklab&trim").unwrap();
+
+        assert_eq!(options.dilate, Some(4));
+        assert!(options.trim);
+        assert!(!options.frame_cache);
+    }
+
+    #[test]
+    fn options_outside_the_fill_are_refused() {
+        for query in [
+            "working-dir=/nonexistent",
+            "atlas=/etc/hostname",
+            "output=/tmp",
+            "report-file=/tmp/report",
+            "timeout=1000",
+        ] {
+            let Err(err) = request_options(&[], query) else {
+                panic!("{} was accepted", query);
+            };
+
+            assert!(
+                err.contains("can't be set per request"),
+                "{}: {}",
+                query,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn names_are_decoded_before_the_check() {
+        assert!(request_options(&[], "working%2Ddir=%2Ftmp").is_err());
+        assert!(request_options(&[], "alpha%2Dfill=1").is_ok());
+    }
+}
@@ -0,0 +1,104 @@
+//! Explorer context menu entries, so artists can right-click a PNG or a
+//! folder and fix it without dragging it onto the exe.
+
+#[cfg(target_os = "windows")]
+use winreg::{enums::HKEY_CURRENT_USER, RegKey};
+
+/// Keys under HKCU that own the context menu entries.
+#[cfg(target_os = "windows")]
+static MENU_KEYS: &[&str] = &[
+    r"Software\Classes\SystemFileAssociations\.png\shell\Pixfix",
+    r"Software\Classes\Directory\shell\Pixfix",
+];
+
+const MENU_LABEL: &str = "Fix alpha bleed";
+
+/// Writes the context menu entries pointing at the current exe, returning a
+/// line per value describing what changed. Running it again after the exe
+/// has moved updates the entries in place.
+#[cfg(target_os = "windows")]
+pub fn install() -> Result<Vec<String>, String> {
+    let exe = std::env::current_exe()
+        .map_err(|err| format!("Unable to find the Pixfix executable: {}", err))?;
+    let exe = exe.display().to_string();
+    let command = format!("\"{}\" \"%1\"", exe);
+
+    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+    let mut changes = Vec::new();
+
+    for menu_key in MENU_KEYS {
+        let values = [
+            (menu_key.to_string(), "", MENU_LABEL),
+            (menu_key.to_string(), "Icon", exe.as_str()),
+            (format!(r"{}\command", menu_key), "", command.as_str()),
+        ];
+
+        for (path, name, value) in values {
+            let (key, _) = hkcu
+                .create_subkey(&path)
+                .map_err(|err| format!("Unable to create \"HKCU\\{}\": {}", path, err))?;
+
+            let display_name = if name.is_empty() { "(Default)" } else { name };
+            let existing: Option<String> = key.get_value(name).ok();
+
+            if existing.as_deref() == Some(value) {
+                changes.push(format!("Unchanged HKCU\\{} {}", path, display_name));
+                continue;
+            }
+
+            key.set_value(name, &value)
+                .map_err(|err| format!("Unable to write \"HKCU\\{}\": {}", path, err))?;
+
+            changes.push(format!(
+                "{} HKCU\\{} {} = {}",
+                if existing.is_some() {
+                    "Updated"
+                } else {
+                    "Added"
+                },
+                path,
+                display_name,
+                value
+            ));
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Removes the context menu entries, returning a line per key.
+#[cfg(target_os = "windows")]
+pub fn uninstall() -> Result<Vec<String>, String> {
+    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+    let mut changes = Vec::new();
+
+    for menu_key in MENU_KEYS {
+        if hkcu.open_subkey(menu_key).is_err() {
+            changes.push(format!("Not installed HKCU\\{}", menu_key));
+            continue;
+        }
+
+        hkcu.delete_subkey_all(menu_key)
+            .map_err(|err| format!("Unable to remove \"HKCU\\{}\": {}", menu_key, err))?;
+
+        changes.push(format!("Removed HKCU\\{}", menu_key));
+    }
+
+    Ok(changes)
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn install() -> Result<Vec<String>, String> {
+    Err(format!(
+        "The \"{}\" menu entry can only be installed on Windows!",
+        MENU_LABEL
+    ))
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn uninstall() -> Result<Vec<String>, String> {
+    Err(format!(
+        "The \"{}\" menu entry can only be removed on Windows!",
+        MENU_LABEL
+    ))
+}
@@ -0,0 +1,57 @@
+//! `pixfix strip-stats`, which removes the `pixfix` text chunk
+//! `--output-stats png` adds, e.g. before shipping the images.
+
+use std::{ffi::OsString, path::Path};
+
+use crate::{
+    chunks, convert, diagnostic,
+    error::PixfixError,
+    files, human,
+    options::{self, Options},
+};
+
+/// The keyword of the text chunk `--output-stats png` writes.
+pub const KEYWORD: &str = "pixfix";
+
+/// Removes the stats of `path`, returning whether it had any.
+fn strip_file(path: &Path, options: &Options) -> Result<bool, PixfixError> {
+    let bytes = convert::read_input(path, options)?;
+
+    match chunks::remove_text(&bytes, KEYWORD) {
+        Some((stripped, removed)) if removed > 0 => {
+            convert::save(path, &stripped, options)?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Strips the stats of every image in `args`, printing each one that had
+/// them. Returns whether all of them could be read and saved.
+pub fn strip_stats(args: Vec<OsString>) -> Result<bool, String> {
+    let (options, args) = options::parse_args(args)?;
+    let files = files::resolve_files(args, &options).files;
+    let (mut stripped, mut failed) = (0, 0);
+
+    for path in &files {
+        match strip_file(path, &options) {
+            Ok(true) => {
+                human!("Stripped the stats of \"{}\"", path.display());
+                stripped += 1;
+            }
+            Ok(false) => {}
+            Err(err) => {
+                diagnostic!("Unable to strip \"{}\": {}", path.display(), err);
+                failed += 1;
+            }
+        }
+    }
+
+    human!(
+        "\nStripped the stats of {} of {} images",
+        stripped,
+        files.len()
+    );
+
+    Ok(failed == 0)
+}
@@ -0,0 +1,169 @@
+//! `pixfix validate`, which checks that images can be fixed without fixing
+//! them, to catch corrupted assets before a full run, and the quicker checks
+//! of `--validate-first`.
+
+use std::{
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use image::codecs::png::PngDecoder;
+
+use crate::{
+    archive::is_zip_file,
+    error::PixfixError,
+    files::{self, has_png_signature},
+    human,
+    options::{self, Options},
+    paths::io_path,
+};
+
+/// Splits `--max-size <pixels>` from the options used to find the files.
+pub fn parse_validate_args(args: Vec<OsString>) -> Result<(Option<u32>, Vec<OsString>), String> {
+    let mut max_size = None;
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let flag = arg.to_string_lossy();
+
+        match flag.as_ref() {
+            "--max-size" => max_size = Some(options::parse_value(&flag, args.next())?),
+            _ => rest.push(arg),
+        }
+    }
+
+    Ok((max_size, rest))
+}
+
+/// Why `path` can't be fixed, or `None` when it can.
+fn problem(path: &Path, max_size: Option<u32>) -> Option<String> {
+    if !has_png_signature(path) {
+        return Some("Not a PNG file".into());
+    }
+
+    let img = match image::open(io_path(path)) {
+        Ok(value) => value,
+        Err(err) => return Some(format!("Unable to decode: {}", err)),
+    };
+
+    if !img.color().has_alpha() {
+        return Some("No alpha channel".into());
+    }
+
+    if img.width() == 0 || img.height() == 0 {
+        return Some("It is empty".into());
+    }
+
+    match max_size {
+        Some(max_size) if img.width() > max_size || img.height() > max_size => Some(format!(
+            "{}x{} is larger than {}x{}",
+            img.width(),
+            img.height(),
+            max_size,
+            max_size
+        )),
+        _ => None,
+    }
+}
+
+/// Validates every image in `args`, printing an OK/FAIL line for each.
+/// Returns whether all of them passed.
+pub fn validate(args: Vec<OsString>) -> Result<bool, String> {
+    let (max_size, args) = parse_validate_args(args)?;
+    let (mut options, args) = options::parse_args(args)?;
+
+    // Nothing is written, so read-only files are as good as any.
+    options.check = true;
+
+    let resolved = files::resolve_files(args, &options);
+    let mut failed = 0;
+
+    for path in &resolved.files {
+        match problem(path, max_size) {
+            None => human!("OK    {}", path.display()),
+            Some(problem) => {
+                human!("FAIL  {} - {}", path.display(), problem);
+                failed += 1;
+            }
+        }
+    }
+
+    human!(
+        "\n{} of {} images are valid",
+        resolved.files.len() - failed,
+        resolved.files.len()
+    );
+
+    Ok(failed == 0)
+}
+
+/// Checks `path` without decoding its pixels: the PNG signature and header,
+/// and that it can be written when it is fixed in place. Images without an
+/// alpha channel pass, the run leaves them alone anyway.
+fn check_file(path: &Path, options: &Options) -> Result<(), PixfixError> {
+    if is_zip_file(path) {
+        return Ok(());
+    }
+
+    let file =
+        File::open(io_path(path)).map_err(|error| PixfixError::Read { attempts: 1, error })?;
+    PngDecoder::new(BufReader::new(file)).map_err(PixfixError::Decode)?;
+
+    let in_place = options
+        .output_formats()
+        .iter()
+        .any(|format| options.output_path_as(path, format) == path);
+    let writes = !options.check && options.diff_only.is_none();
+
+    // --force-writable clears the read-only flag itself.
+    let read_only =
+        std::fs::metadata(io_path(path)).is_ok_and(|metadata| metadata.permissions().readonly());
+
+    if in_place && writes && !(read_only && options.force_writable) {
+        OpenOptions::new()
+            .write(true)
+            .open(io_path(path))
+            .map_err(|error| PixfixError::Save { attempts: 1, error })?;
+    }
+
+    Ok(())
+}
+
+/// `--validate-first`: checks every file on `--jobs` threads before anything
+/// is fixed, returning the index of every file that failed and why.
+pub fn check_files(files: &[PathBuf], options: &Options) -> Vec<(usize, PixfixError)> {
+    let next = AtomicUsize::new(0);
+    let problems = Mutex::new(Vec::new());
+
+    std::thread::scope(|scope| {
+        for _ in 0..options.jobs.clamp(1, files.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = files.get(index) else {
+                    break;
+                };
+
+                if let Err(err) = check_file(path, options) {
+                    problems
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .push((index, err));
+                }
+            });
+        }
+    });
+
+    let mut problems = problems
+        .into_inner()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    problems.sort_by_key(|(index, _)| *index);
+
+    problems
+}
@@ -0,0 +1,57 @@
+//! The `--jobs` threads of a batch, shared with the work inside a file. Each
+//! file being fixed holds one of them, and a file that splits its work, like
+//! the cells of a sprite sheet, borrows the ones no other file is using.
+
+use std::{cell::RefCell, sync::Arc, thread::available_parallelism};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+thread_local! {
+    static POOL: RefCell<Option<Arc<Semaphore>>> = const { RefCell::new(None) };
+}
+
+/// Threads borrowed for the work of one file, given back when dropped.
+pub struct Borrowed {
+    _permits: Vec<OwnedSemaphorePermit>,
+    count: usize,
+}
+
+impl Borrowed {
+    /// How many threads may help, besides the one of the file itself.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+/// Runs `work` on this thread with `pool` as the threads it may borrow.
+pub fn with_pool<T>(pool: Arc<Semaphore>, work: impl FnOnce() -> T) -> T {
+    let previous = POOL.with(|current| current.replace(Some(pool)));
+    let result = work();
+    POOL.with(|current| *current.borrow_mut() = previous);
+
+    result
+}
+
+/// Borrows up to `wanted` idle threads. Outside of a batch nothing else is
+/// running, so every core but the current one may help.
+pub fn borrow(wanted: usize) -> Borrowed {
+    POOL.with(|pool| match pool.borrow().as_ref() {
+        Some(pool) => {
+            let permits: Vec<_> = (0..wanted)
+                .map_while(|_| pool.clone().try_acquire_owned().ok())
+                .collect();
+
+            Borrowed {
+                count: permits.len(),
+                _permits: permits,
+            }
+        }
+        None => Borrowed {
+            _permits: Vec::new(),
+            count: available_parallelism()
+                .map_or(1, |cores| cores.get())
+                .saturating_sub(1)
+                .min(wanted),
+        },
+    })
+}
@@ -0,0 +1,91 @@
+//! `--backend`, the Jump Flooding fills.
+
+mod common;
+
+use common::{pixfix, run, sprite_png, stderr};
+use pixfix::fixtures::TempDir;
+
+fn setup() -> TempDir {
+    let dir = TempDir::new("backend").unwrap();
+
+    for name in ["a.png", "b.png"] {
+        std::fs::write(dir.path().join(name), sprite_png()).unwrap();
+    }
+
+    dir
+}
+
+fn fixed(dir: &TempDir, name: &str) -> bool {
+    std::fs::read(dir.path().join(name)).unwrap() != sprite_png()
+}
+
+#[test]
+fn jump_flooding_fixes_images() {
+    let dir = setup();
+    let output = run(pixfix(dir.path()).args([
+        "--no-pause",
+        "--in-place",
+        "--backend",
+        "jfa",
+        "a.png",
+        "b.png",
+    ]));
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(fixed(&dir, "a.png") && fixed(&dir, "b.png"));
+}
+
+#[test]
+fn jump_flooding_fills_in_one_pass() {
+    let dir = setup();
+    let output = run(pixfix(dir.path()).args([
+        "--no-pause",
+        "--in-place",
+        "--backend",
+        "jfa",
+        "--repeat",
+        "2",
+        "a.png",
+    ]));
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("\"--repeat\" only works with \"--backend cpu\""));
+    assert!(!fixed(&dir, "a.png"));
+}
+
+/// Fixed on the GPU, or on the CPU with a warning where there is none.
+#[cfg(feature = "gpu")]
+#[test]
+fn the_gpu_backend_fixes_every_image_of_a_batch() {
+    let dir = setup();
+    let output = run(pixfix(dir.path()).args([
+        "--no-pause",
+        "--in-place",
+        "--backend",
+        "gpu",
+        "a.png",
+        "b.png",
+    ]));
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(fixed(&dir, "a.png") && fixed(&dir, "b.png"));
+    // The warning is only given once.
+    assert!(
+        stderr(&output)
+            .matches("filling on the CPU instead")
+            .count()
+            <= 1
+    );
+}
+
+#[cfg(not(feature = "gpu"))]
+#[test]
+fn without_the_feature_the_gpu_backend_says_how_to_build_it() {
+    let dir = setup();
+    let output =
+        run(pixfix(dir.path()).args(["--no-pause", "--in-place", "--backend", "gpu", "a.png"]));
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("--features gpu"));
+    assert!(!fixed(&dir, "a.png"));
+}
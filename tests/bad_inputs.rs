@@ -0,0 +1,71 @@
+//! One broken or missing file fails on its own, the rest of the run goes on.
+
+mod common;
+
+use common::{pixfix, run, sprite_png, stderr, stdout};
+use pixfix::fixtures::TempDir;
+
+/// A folder with a good sprite and the same sprite cut off halfway.
+fn setup() -> TempDir {
+    let dir = TempDir::new("bad-inputs").unwrap();
+    let png = sprite_png();
+    std::fs::write(dir.path().join("good.png"), &png).unwrap();
+    std::fs::write(dir.path().join("truncated.png"), &png[..png.len() / 2]).unwrap();
+
+    dir
+}
+
+fn fixed(dir: &TempDir, name: &str) -> bool {
+    let png = sprite_png();
+    let now = std::fs::read(dir.path().join(name)).unwrap();
+
+    now != png && now != png[..png.len() / 2]
+}
+
+#[test]
+fn bad_files_fail_on_their_own() {
+    let dir = setup();
+    let output = run(pixfix(dir.path()).args([
+        "--no-pause",
+        "--in-place",
+        "good.png",
+        "truncated.png",
+        "missing.png",
+    ]));
+    let stderr = stderr(&output);
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr);
+    assert!(fixed(&dir, "good.png"));
+    assert!(!fixed(&dir, "truncated.png"));
+    assert!(stderr.contains("Failed to fix \"truncated.png\" - The image is truncated at row"));
+    assert!(stderr.contains("\"missing.png\" - It does not exist!"));
+    assert!(stdout(&output).contains("Skipped 2 files that couldn't be fixed!"));
+}
+
+#[test]
+fn strict_runs_fail_on_bad_files() {
+    for bad in ["truncated.png", "missing.png"] {
+        let dir = setup();
+        let output =
+            run(pixfix(dir.path()).args(["--no-pause", "--in-place", "--strict", "good.png", bad]));
+
+        assert_eq!(
+            output.status.code(),
+            Some(1),
+            "{}: {}",
+            bad,
+            stderr(&output)
+        );
+    }
+}
+
+#[test]
+fn tolerant_runs_fix_truncated_files() {
+    let dir = setup();
+    let output =
+        run(pixfix(dir.path()).args(["--no-pause", "--in-place", "--tolerant", "truncated.png"]));
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(stderr(&output).contains("\"truncated.png\" is truncated at row"));
+    assert!(fixed(&dir, "truncated.png"));
+}
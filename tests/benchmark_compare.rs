@@ -0,0 +1,38 @@
+//! `pixfix benchmark-compare` of a folder against a copy of itself finds no
+//! difference at all.
+
+mod common;
+
+use common::{pixfix, run, sprite_png, stderr, stdout};
+use pixfix::fixtures::{eight_bit, gradient, sprite, TempDir};
+
+#[test]
+fn identical_folders_compare_as_identical() {
+    let dir = TempDir::new("benchmark-compare").unwrap();
+
+    for folder in ["before", "after"] {
+        std::fs::create_dir(dir.path().join(folder)).unwrap();
+        std::fs::write(dir.path().join(folder).join("a.png"), sprite_png()).unwrap();
+        std::fs::write(
+            dir.path().join(folder).join("b.png"),
+            eight_bit(sprite(32, gradient)),
+        )
+        .unwrap();
+    }
+
+    let output = run(pixfix(dir.path()).args(["benchmark-compare", "before", "after"]));
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let stdout = stdout(&output);
+    let rows: Vec<Vec<&str>> = stdout
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().collect())
+        .filter(|row: &Vec<&str>| row.len() == 4)
+        .collect();
+
+    assert_eq!(rows.len(), 2, "{}", stdout);
+    for row in rows {
+        assert_eq!(row[..3], ["inf", "1.0000", "0.0"], "{}", stdout);
+    }
+}
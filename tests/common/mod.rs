@@ -0,0 +1,51 @@
+//! Helpers shared by the tests that run the `pixfix` binary.
+
+#![allow(dead_code)]
+
+use std::{
+    path::Path,
+    process::{Command, Output},
+};
+
+use pixfix::fixtures::{fixtures, Fixture};
+
+/// The `pixfix` binary, run from `dir` without any `PIXFIX_*` variables of
+/// the environment the tests run in.
+pub fn pixfix(dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_pixfix"));
+    command.current_dir(dir);
+
+    for (name, _) in std::env::vars_os() {
+        if name.to_string_lossy().starts_with("PIXFIX_") {
+            command.env_remove(name);
+        }
+    }
+
+    command
+}
+
+/// Runs `command`, failing the test when it couldn't be started.
+pub fn run(command: &mut Command) -> Output {
+    command.output().expect("pixfix can be started")
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// The self-test fixture called `name`.
+pub fn fixture(name: &str) -> Fixture {
+    fixtures()
+        .into_iter()
+        .find(|fixture| fixture.name == name)
+        .unwrap_or_else(|| panic!("there is no fixture called {}", name))
+}
+
+/// A small image with transparent pixels to fix.
+pub fn sprite_png() -> Vec<u8> {
+    pixfix::fixtures::fringed_sprite()
+}
@@ -0,0 +1,23 @@
+//! A file passed several times, spelled differently, is fixed once.
+
+mod common;
+
+use common::{pixfix, run, sprite_png, stderr, stdout};
+use pixfix::fixtures::TempDir;
+
+#[test]
+fn one_file_given_three_ways_is_fixed_once() {
+    let dir = TempDir::new("duplicate-paths").unwrap();
+    let path = dir.path().join("a.png");
+    std::fs::write(&path, sprite_png()).unwrap();
+
+    let output = run(pixfix(dir.path())
+        .args(["--no-pause", "--in-place", "--print-modified", "a.png"])
+        .arg(&path)
+        .arg("./a.png"));
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).lines().count(), 1, "{}", stdout(&output));
+    assert!(stderr(&output).contains("Successfully fixed 1 images"));
+    assert!(std::fs::read(&path).unwrap() != sprite_png());
+}
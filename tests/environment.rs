@@ -0,0 +1,69 @@
+//! The `PIXFIX_*` variables and `--help`, on the binary.
+
+mod common;
+
+use common::{pixfix, run, sprite_png, stderr, stdout};
+use pixfix::{fixtures::TempDir, options::ENV_VARS};
+
+#[test]
+fn the_help_lists_every_variable() {
+    let dir = TempDir::new("help").unwrap();
+    let output = run(pixfix(dir.path()).arg("--help"));
+
+    assert!(output.status.success());
+
+    for (name, _) in ENV_VARS {
+        assert!(stdout(&output).contains(name), "{} is missing", name);
+    }
+}
+
+#[test]
+fn an_invalid_variable_stops_the_run() {
+    let dir = TempDir::new("environment").unwrap();
+    std::fs::write(dir.path().join("a.png"), sprite_png()).unwrap();
+
+    let output = run(pixfix(dir.path()).env("PIXFIX_JOBS", "many").args([
+        "--no-pause",
+        "--in-place",
+        "a.png",
+    ]));
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Invalid PIXFIX_JOBS"));
+    assert!(std::fs::read(dir.path().join("a.png")).unwrap() == sprite_png());
+}
+
+#[test]
+fn variables_apply_to_the_run() {
+    let dir = TempDir::new("environment").unwrap();
+    std::fs::write(dir.path().join("a.png"), sprite_png()).unwrap();
+
+    let output = run(pixfix(dir.path())
+        .env("PIXFIX_NO_PAUSE", "1")
+        .env("PIXFIX_FLAGS", "--check")
+        .arg("a.png"));
+
+    // A check of an image that needs fixing fails without changing it.
+    assert!(!output.status.success());
+    assert!(std::fs::read(dir.path().join("a.png")).unwrap() == sprite_png());
+}
+
+#[test]
+fn an_output_without_mirror_leaves_the_images_alone() {
+    let dir = TempDir::new("environment").unwrap();
+    std::fs::write(dir.path().join("a.png"), sprite_png()).unwrap();
+
+    for output in [
+        run(pixfix(dir.path()).env("PIXFIX_OUTPUT", "out").args([
+            "--no-pause",
+            "--in-place",
+            "a.png",
+        ])),
+        run(pixfix(dir.path()).args(["--no-pause", "--in-place", "-o", "out", "a.png"])),
+    ] {
+        assert!(!output.status.success());
+        assert!(stderr(&output).contains("--output only applies to data: URIs and --mirror"));
+        assert!(std::fs::read(dir.path().join("a.png")).unwrap() == sprite_png());
+        assert!(!dir.path().join("out").exists());
+    }
+}
@@ -0,0 +1,43 @@
+//! `--events` writes nothing but JSON to stdout.
+
+mod common;
+
+use common::{pixfix, run, sprite_png, stderr, stdout};
+use pixfix::fixtures::TempDir;
+use serde_json::Value;
+
+#[test]
+fn every_stdout_line_is_an_event() {
+    let dir = TempDir::new("events").unwrap();
+    std::fs::write(dir.path().join("a.png"), sprite_png()).unwrap();
+    std::fs::write(dir.path().join("b.png"), sprite_png()).unwrap();
+    std::fs::write(dir.path().join("broken.png"), "not a png").unwrap();
+    std::fs::write(dir.path().join("notes.txt"), "not an image").unwrap();
+
+    let output = run(pixfix(dir.path()).args(["--events", "--in-place", "."]));
+    let stdout = stdout(&output);
+
+    let events: Vec<Value> = stdout
+        .lines()
+        .map(|line| {
+            serde_json::from_str(line)
+                .unwrap_or_else(|err| panic!("{:?} isn't JSON: {}", line, err))
+        })
+        .collect();
+    let kinds: Vec<&str> = events
+        .iter()
+        .map(|event| event["event"].as_str().expect("every line has an event"))
+        .collect();
+
+    assert_eq!(kinds.first(), Some(&"start"));
+    assert_eq!(kinds.last(), Some(&"done"));
+    assert_eq!(kinds.iter().filter(|kind| **kind == "file").count(), 4);
+
+    let done = events.last().unwrap();
+    assert_eq!(done["fixed"], 2);
+    assert_eq!(done["ignored"], 1);
+
+    // The regular output went to stderr instead.
+    assert!(stderr(&output).contains("Successfully fixed 2 images"));
+    assert!(!stdout.contains("Successfully fixed"));
+}
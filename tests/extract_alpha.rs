@@ -0,0 +1,75 @@
+//! `pixfix extract-alpha` saves the alpha channel, and with `--silhouette`
+//! the mask of colored pixels, as grayscale PNGs without touching the input.
+
+mod common;
+
+use std::path::Path;
+
+use common::{pixfix, run, sprite_png, stderr};
+use image::{GrayImage, RgbaImage};
+use pixfix::fixtures::TempDir;
+
+fn setup() -> TempDir {
+    let dir = TempDir::new("extract-alpha").unwrap();
+    std::fs::write(dir.path().join("a.png"), sprite_png()).unwrap();
+
+    dir
+}
+
+fn input() -> RgbaImage {
+    image::load_from_memory(&sprite_png()).unwrap().to_rgba8()
+}
+
+fn gray(path: &Path) -> GrayImage {
+    let img = image::open(path).unwrap();
+    assert_eq!(img.color(), image::ColorType::L8);
+
+    img.to_luma8()
+}
+
+#[test]
+fn the_alpha_is_saved_next_to_the_image() {
+    let dir = setup();
+    let output = run(pixfix(dir.path()).args(["extract-alpha", "a.png"]));
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let alpha = gray(&dir.path().join("a_alpha.png"));
+    for (alpha, color) in alpha.pixels().zip(input().pixels()) {
+        assert_eq!(alpha.0[0], color.0[3]);
+    }
+
+    assert!(std::fs::read(dir.path().join("a.png")).unwrap() == sprite_png());
+    assert!(!dir.path().join("a_silhouette.png").exists());
+}
+
+#[test]
+fn the_silhouette_is_white_where_pixels_are_colored() {
+    let dir = setup();
+    let output = run(pixfix(dir.path()).args(["extract-alpha", "--silhouette", "a.png"]));
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let silhouette = gray(&dir.path().join("a_silhouette.png"));
+    for (mask, color) in silhouette.pixels().zip(input().pixels()) {
+        let expected = if color.0[3] == 0 { 0 } else { u8::MAX };
+        assert_eq!(mask.0[0], expected);
+    }
+
+    assert!(dir.path().join("a_alpha.png").exists());
+}
+
+#[test]
+fn output_puts_the_masks_in_another_folder() {
+    let dir = setup();
+    let output = run(pixfix(dir.path()).args([
+        "extract-alpha",
+        "--silhouette",
+        "--output",
+        "masks",
+        "a.png",
+    ]));
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    assert!(dir.path().join("masks/a_alpha.png").exists());
+    assert!(dir.path().join("masks/a_silhouette.png").exists());
+    assert!(!dir.path().join("a_alpha.png").exists());
+}
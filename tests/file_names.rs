@@ -0,0 +1,44 @@
+//! File names that aren't valid UTF-8, which Unix allows, are fixed like any
+//! other.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+use common::{pixfix, run, sprite_png, stderr};
+use pixfix::fixtures::TempDir;
+
+const NAME: &[u8] = b"bad\xff.png";
+
+fn setup() -> TempDir {
+    let dir = TempDir::new("file-names").unwrap();
+    std::fs::write(dir.path().join(OsStr::from_bytes(NAME)), sprite_png()).unwrap();
+
+    dir
+}
+
+fn fixed(dir: &TempDir) -> bool {
+    std::fs::read(dir.path().join(OsStr::from_bytes(NAME))).unwrap() != sprite_png()
+}
+
+#[test]
+fn a_non_utf8_argument_is_fixed() {
+    let dir = setup();
+    let output = run(pixfix(dir.path())
+        .args(["--no-pause", "--in-place"])
+        .arg(OsStr::from_bytes(NAME)));
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(fixed(&dir));
+}
+
+#[test]
+fn a_non_utf8_file_in_a_folder_is_fixed() {
+    let dir = setup();
+    let output = run(pixfix(dir.path()).args(["--no-pause", "--in-place", "."]));
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(fixed(&dir));
+}
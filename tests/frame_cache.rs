@@ -0,0 +1,68 @@
+//! Reusing the fill of an earlier frame gives the same bytes as filling
+//! every frame anew.
+
+mod common;
+
+use common::{pixfix, run, stderr};
+use pixfix::fixtures::{eight_bit, gradient, sprite, TempDir};
+
+/// Frames of one silhouette with their own colors, and one more frame with
+/// a silhouette of its own.
+fn frames() -> Vec<(String, Vec<u8>)> {
+    let mut frames: Vec<(String, Vec<u8>)> = (0..4u32)
+        .map(|frame| {
+            let png = eight_bit(sprite(40, move |x, y| {
+                let [r, g, _] = gradient(x + frame, y);
+                [r, g, (frame * 16_000) as u16]
+            }));
+
+            (format!("frame{}.png", frame), png)
+        })
+        .collect();
+    frames.push(("other.png".into(), eight_bit(sprite(48, gradient))));
+
+    frames
+}
+
+fn fixed(flags: &[&str]) -> Vec<Vec<u8>> {
+    let dir = TempDir::new("frame-cache").unwrap();
+
+    for (name, png) in frames() {
+        std::fs::write(dir.path().join(name), png).unwrap();
+    }
+
+    let output = run(pixfix(dir.path())
+        .args(["--no-pause", "--in-place", "--jobs", "1"])
+        .args(flags)
+        .arg("."));
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    frames()
+        .into_iter()
+        .map(|(name, png)| {
+            let fixed = std::fs::read(dir.path().join(name)).unwrap();
+            assert!(fixed != png);
+
+            fixed
+        })
+        .collect()
+}
+
+#[test]
+fn cached_frames_match_the_uncached_ones() {
+    assert!(fixed(&[]) == fixed(&["--no-frame-cache"]));
+}
+
+#[test]
+fn cached_frames_match_with_other_fill_options() {
+    for flags in [
+        &["--repeat", "2"][..],
+        &["--alpha-fill", "255"],
+        &["--max-border-pixels", "16"],
+    ] {
+        let mut uncached = flags.to_vec();
+        uncached.push("--no-frame-cache");
+
+        assert!(fixed(flags) == fixed(&uncached), "{:?}", flags);
+    }
+}
@@ -0,0 +1,155 @@
+//! `pixfix hook --staged` checks, and with `--fix` fixes, what the index
+//! holds for every staged PNG, whatever the working tree has.
+
+mod common;
+
+use std::{
+    path::Path,
+    process::{Command, Output},
+};
+
+use common::{pixfix, run, sprite_png, stderr};
+use pixfix::{
+    convert::{fix_bytes, FixedBytes},
+    fixtures::TempDir,
+    options::Options,
+};
+
+fn git(dir: &Path, args: &[&str]) -> Output {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args([
+            "-c",
+            "user.name=Pixfix",
+            "-c",
+            "user.email=pixfix@example.com",
+        ])
+        .args(args)
+        .output()
+        .expect("git can be started");
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    output
+}
+
+/// The staged content of `path`.
+fn staged(dir: &Path, path: &str) -> Vec<u8> {
+    git(dir, &["cat-file", "blob", &format!(":{}", path)]).stdout
+}
+
+fn fixed_sprite() -> Vec<u8> {
+    match fix_bytes(&sprite_png(), Path::new("a.png"), &Options::default()) {
+        Ok(FixedBytes::Fixed { bytes, .. }) => bytes,
+        _ => panic!("the sprite has pixels to fix"),
+    }
+}
+
+/// A repository with the fringed sprite staged as `art/a.png`.
+fn repository() -> TempDir {
+    let dir = TempDir::new("hook").unwrap();
+    git(dir.path(), &["init", "--quiet"]);
+    std::fs::create_dir(dir.path().join("art")).unwrap();
+    std::fs::write(dir.path().join("art/a.png"), sprite_png()).unwrap();
+    git(dir.path(), &["add", "art/a.png"]);
+
+    dir
+}
+
+#[test]
+fn a_fringed_staged_image_blocks_the_commit() {
+    let dir = repository();
+    let output = run(pixfix(&dir.path().join("art")).args(["hook", "--staged"]));
+
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(staged(dir.path(), "art/a.png") == sprite_png());
+}
+
+#[test]
+fn only_the_staged_content_is_checked() {
+    let dir = repository();
+    // Fixed in the working tree, but not staged.
+    std::fs::write(dir.path().join("art/a.png"), fixed_sprite()).unwrap();
+
+    let output = run(pixfix(dir.path()).args(["hook", "--staged"]));
+
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+}
+
+#[test]
+fn fix_fixes_the_index_and_a_matching_working_copy() {
+    let dir = repository();
+    let output = run(pixfix(dir.path()).args(["hook", "--staged", "--fix"]));
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(staged(dir.path(), "art/a.png") == fixed_sprite());
+    assert!(std::fs::read(dir.path().join("art/a.png")).unwrap() == fixed_sprite());
+}
+
+#[test]
+fn fix_leaves_unstaged_changes_alone() {
+    let dir = repository();
+    std::fs::write(dir.path().join("art/a.png"), b"work in progress").unwrap();
+
+    let output = run(pixfix(dir.path()).args(["hook", "--staged", "--fix"]));
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(staged(dir.path(), "art/a.png") == fixed_sprite());
+    assert_eq!(
+        std::fs::read(dir.path().join("art/a.png")).unwrap(),
+        b"work in progress"
+    );
+}
+
+#[test]
+fn nothing_staged_lets_the_commit_through() {
+    let dir = TempDir::new("hook").unwrap();
+    git(dir.path(), &["init", "--quiet"]);
+    std::fs::write(dir.path().join("notes.txt"), "not an image").unwrap();
+    git(dir.path(), &["add", "notes.txt"]);
+
+    let output = run(pixfix(dir.path()).args(["hook", "--staged"]));
+
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[test]
+fn install_writes_the_pre_commit_hook() {
+    let dir = TempDir::new("hook").unwrap();
+    git(dir.path(), &["init", "--quiet"]);
+
+    let output = run(pixfix(dir.path()).args(["hook", "install"]));
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let hook = std::fs::read_to_string(dir.path().join(".git/hooks/pre-commit")).unwrap();
+    assert!(hook.contains("hook --staged"));
+}
+
+#[test]
+fn install_fails_over_a_hook_of_someone_else() {
+    let dir = TempDir::new("hook").unwrap();
+    git(dir.path(), &["init", "--quiet"]);
+    std::fs::write(
+        dir.path().join(".git/hooks/pre-commit"),
+        "#!/bin/sh\nlint\n",
+    )
+    .unwrap();
+
+    let output = run(pixfix(dir.path()).args(["hook", "install"]));
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("wasn't written by Pixfix"));
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join(".git/hooks/pre-commit")).unwrap(),
+        "#!/bin/sh\nlint\n"
+    );
+}
+
+#[test]
+fn install_fails_outside_a_repository() {
+    let dir = TempDir::new("hook").unwrap();
+    let output = run(pixfix(dir.path())
+        .env("GIT_CEILING_DIRECTORIES", dir.path().parent().unwrap())
+        .args(["hook", "install"]));
+
+    assert_eq!(output.status.code(), Some(1));
+}
@@ -0,0 +1,105 @@
+//! Scripts, with no terminal on stdin, have to confirm overwriting images.
+//! Runs from a terminal can't be started from a test, `refuses_in_place`
+//! has unit tests for those.
+
+mod common;
+
+use std::process::{Command, Stdio};
+
+use common::{pixfix, run, sprite_png, stderr};
+use pixfix::fixtures::TempDir;
+
+fn setup() -> TempDir {
+    let dir = TempDir::new("in-place").unwrap();
+    std::fs::write(dir.path().join("a.png"), sprite_png()).unwrap();
+
+    dir
+}
+
+/// `pixfix` run from `dir` like a script piping into it.
+fn piped(dir: &TempDir) -> Command {
+    let mut command = pixfix(dir.path());
+    command.stdin(Stdio::piped());
+
+    command
+}
+
+fn fixed(dir: &TempDir) -> bool {
+    std::fs::read(dir.path().join("a.png")).unwrap() != sprite_png()
+}
+
+#[test]
+fn a_script_without_in_place_is_refused() {
+    let dir = setup();
+    let output = run(pixfix(dir.path()).args(["--no-pause", "a.png"]));
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Pass --in-place to confirm"));
+    assert!(!fixed(&dir));
+}
+
+#[test]
+fn a_script_with_in_place_goes_ahead() {
+    let dir = setup();
+    let output = run(pixfix(dir.path()).args(["--no-pause", "--in-place", "a.png"]));
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(fixed(&dir));
+}
+
+#[test]
+fn a_script_that_keeps_a_backup_goes_ahead() {
+    let dir = setup();
+    let output = run(pixfix(dir.path()).args(["--no-pause", "--backup-store", "a.png"]));
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(fixed(&dir));
+}
+
+#[test]
+fn piped_stdin_without_in_place_is_refused() {
+    let dir = setup();
+    let output = run(piped(&dir).args(["--no-pause", "a.png"]));
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Refusing to overwrite images in place from a script"));
+    assert!(!fixed(&dir));
+}
+
+#[test]
+fn piped_stdin_with_in_place_goes_ahead() {
+    let dir = setup();
+    let output = run(piped(&dir).args(["--no-pause", "--in-place", "a.png"]));
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(fixed(&dir));
+}
+
+#[test]
+fn piped_stdin_mirroring_elsewhere_goes_ahead() {
+    let dir = setup();
+    std::fs::create_dir(dir.path().join("in")).unwrap();
+    std::fs::rename(dir.path().join("a.png"), dir.path().join("in/a.png")).unwrap();
+
+    let output = run(piped(&dir).args([
+        "--no-pause",
+        "--recursive",
+        "--mirror",
+        "--output",
+        "out",
+        "in",
+    ]));
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(std::fs::read(dir.path().join("in/a.png")).unwrap() == sprite_png());
+    assert!(std::fs::read(dir.path().join("out/a.png")).unwrap() != sprite_png());
+}
+
+#[test]
+fn piped_stdin_with_an_output_but_no_mirror_is_refused() {
+    let dir = setup();
+    let output = run(piped(&dir).args(["--no-pause", "--output", "out", "a.png"]));
+
+    assert!(!output.status.success());
+    assert!(!fixed(&dir));
+}
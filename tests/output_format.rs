@@ -0,0 +1,69 @@
+//! `--output-format github|junit` keeps stdout for the results, everything
+//! else goes to stderr.
+
+mod common;
+
+use common::{pixfix, run, sprite_png, stderr, stdout};
+use pixfix::fixtures::TempDir;
+
+/// A good sprite, one cut off halfway and a file that isn't a PNG image.
+fn setup() -> TempDir {
+    let dir = TempDir::new("output-format").unwrap();
+    let png = sprite_png();
+    std::fs::write(dir.path().join("good.png"), &png).unwrap();
+    std::fs::write(dir.path().join("truncated.png"), &png[..png.len() / 2]).unwrap();
+    std::fs::write(dir.path().join("fake.png"), "not a png").unwrap();
+
+    dir
+}
+
+#[test]
+fn github_annotations_are_all_of_stdout() {
+    let dir = setup();
+    let output = run(pixfix(dir.path()).args([
+        "--no-pause",
+        "--in-place",
+        "--output-format",
+        "github",
+        ".",
+    ]));
+    let stdout = stdout(&output);
+    let lines: Vec<&str> = stdout.lines().collect();
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(lines.len(), 2, "{}", stdout);
+    assert!(lines
+        .iter()
+        .all(|line| line.starts_with("::error file=") || line.starts_with("::warning file=")));
+    assert!(lines.iter().any(|line| line.contains("truncated.png")));
+    assert!(lines.iter().any(|line| line.contains("fake.png")));
+
+    assert!(stderr(&output).contains("Successfully fixed 1 images"));
+}
+
+#[test]
+fn junit_reports_go_to_their_file() {
+    let dir = setup();
+    let output = run(pixfix(dir.path()).args([
+        "--no-pause",
+        "--in-place",
+        "--output-format",
+        "junit",
+        "--report-file",
+        "report.xml",
+        ".",
+    ]));
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output).contains("Successfully fixed 1 images"));
+    assert!(stderr(&output).contains("Wrote JUnit report to \"report.xml\""));
+
+    let report = std::fs::read_to_string(dir.path().join("report.xml")).unwrap();
+    assert!(
+        report.contains(r#"<testsuite name="pixfix" tests="3""#),
+        "{}",
+        report
+    );
+    assert!(report.contains("truncated.png"));
+}
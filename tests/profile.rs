@@ -0,0 +1,43 @@
+//! `pixfix profile`, which only does anything when built with the `profile`
+//! feature.
+
+mod common;
+
+use common::{pixfix, run, sprite_png, stderr};
+use pixfix::fixtures::TempDir;
+
+fn setup() -> TempDir {
+    let dir = TempDir::new("profile").unwrap();
+    std::fs::write(dir.path().join("sprite.png"), sprite_png()).unwrap();
+
+    dir
+}
+
+#[cfg(all(feature = "profile", unix))]
+#[test]
+fn a_profile_writes_a_flamegraph_and_leaves_the_image_alone() {
+    let dir = setup();
+    let output = run(pixfix(dir.path()).args(["profile", "sprite.png"]));
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(
+        std::fs::read_to_string(dir.path().join("pixfix_profile.svg"))
+            .unwrap()
+            .starts_with("<?xml")
+    );
+    assert_eq!(
+        std::fs::read(dir.path().join("sprite.png")).unwrap(),
+        sprite_png()
+    );
+}
+
+#[cfg(not(all(feature = "profile", unix)))]
+#[test]
+fn without_the_feature_a_profile_says_how_to_build_one() {
+    let dir = setup();
+    let output = run(pixfix(dir.path()).args(["profile", "sprite.png"]));
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("--features profile"));
+    assert!(!dir.path().join("pixfix_profile.svg").exists());
+}
@@ -0,0 +1,71 @@
+//! Identical input and options have to give identical bytes, whatever the
+//! number of threads, for build systems that cache on content hashes.
+
+mod common;
+
+use std::path::Path;
+
+use common::{pixfix, run, stderr};
+use image::DynamicImage;
+use pixfix::fixtures::{
+    eight_bit, encode, fringed_sprite, palette_png, scattered, sprite, TempDir,
+};
+
+fn inputs() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("sprite.png", fringed_sprite()),
+        (
+            "16-bit.png",
+            encode(DynamicImage::ImageRgba16(sprite(32, |x, y| {
+                [(x * 1999) as u16, (y * 1777) as u16, 12345]
+            }))),
+        ),
+        ("palette.png", palette_png()),
+        ("scattered.png", eight_bit(scattered(128, 128, 300))),
+    ]
+}
+
+/// Fixes a copy of `inputs` in a folder of its own, returning the bytes of
+/// every output in the same order.
+fn fix(args: &[&str]) -> Vec<Vec<u8>> {
+    let dir = TempDir::new("reproducible").unwrap();
+
+    for (name, png) in inputs() {
+        std::fs::write(dir.path().join(name), png).unwrap();
+    }
+
+    let output = run(pixfix(dir.path())
+        .args(["--no-pause", "--in-place"])
+        .args(args)
+        .arg("."));
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let (names, pngs): (Vec<_>, Vec<_>) = inputs().into_iter().unzip();
+    let fixed: Vec<Vec<u8>> = names
+        .iter()
+        .map(|name| std::fs::read(Path::new(dir.path()).join(name)).unwrap())
+        .collect();
+    assert!(fixed != pngs, "nothing was fixed");
+
+    fixed
+}
+
+#[test]
+fn fixing_twice_gives_the_same_bytes() {
+    assert!(fix(&["--jobs", "1"]) == fix(&["--jobs", "1"]));
+}
+
+#[test]
+fn the_thread_count_doesnt_change_the_bytes() {
+    assert!(fix(&["--jobs", "1"]) == fix(&["--jobs", "8"]));
+}
+
+#[test]
+fn sprite_sheet_cells_on_several_threads_give_the_same_bytes() {
+    let grid = ["--sprite-sheet-grid", "4x4"];
+
+    assert!(
+        fix(&[&grid[..], &["--jobs", "1"]].concat())
+            == fix(&[&grid[..], &["--jobs", "8"]].concat())
+    );
+}
@@ -0,0 +1,49 @@
+//! `pixfix rojo` fails with a message saying what is wrong with the project.
+
+mod common;
+
+use common::{pixfix, run, stderr};
+use pixfix::fixtures::TempDir;
+
+fn rojo(dir: &TempDir, args: &[&str]) -> (Option<i32>, String) {
+    let output = run(pixfix(dir.path()).arg("rojo").args(args).arg("--no-pause"));
+
+    (output.status.code(), stderr(&output))
+}
+
+#[test]
+fn a_missing_project_argument_is_a_usage_error() {
+    let dir = TempDir::new("rojo").unwrap();
+    let output = run(pixfix(dir.path()).arg("rojo"));
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("Usage: pixfix rojo"));
+}
+
+#[test]
+fn a_missing_project_file_fails() {
+    let dir = TempDir::new("rojo").unwrap();
+    let (code, stderr) = rojo(&dir, &["missing.project.json"]);
+
+    assert_eq!(code, Some(1));
+    assert!(stderr.contains("Unable to read Rojo project"), "{}", stderr);
+}
+
+#[test]
+fn an_invalid_project_file_fails() {
+    let dir = TempDir::new("rojo").unwrap();
+    std::fs::write(dir.path().join("broken.project.json"), "{ not json").unwrap();
+    std::fs::write(dir.path().join("empty.project.json"), "{}").unwrap();
+
+    let (code, stderr) = rojo(&dir, &["broken.project.json"]);
+    assert_eq!(code, Some(1));
+    assert!(
+        stderr.contains("Unable to parse Rojo project"),
+        "{}",
+        stderr
+    );
+
+    let (code, stderr) = rojo(&dir, &["empty.project.json"]);
+    assert_eq!(code, Some(1));
+    assert!(stderr.contains("is not a Rojo project"), "{}", stderr);
+}
@@ -0,0 +1,24 @@
+//! The cases of `pixfix self-test`, run on every build.
+
+use pixfix::{
+    fixtures::{fixtures, TempDir},
+    options::Options,
+    selftest::run_fixture,
+};
+
+#[test]
+fn every_self_test_case_passes() {
+    let dir = TempDir::new("selftest").unwrap();
+    let options = Options::default();
+
+    let failures: Vec<String> = fixtures()
+        .iter()
+        .filter_map(|fixture| {
+            run_fixture(fixture, dir.path(), &options)
+                .err()
+                .map(|err| format!("{}: {}", fixture.name, err))
+        })
+        .collect();
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,21 @@
+//! The Explorer menu entry only exists on Windows, elsewhere installing or
+//! removing it is refused with a failing exit code scripts can see.
+
+#![cfg(not(target_os = "windows"))]
+
+mod common;
+
+use common::{pixfix, run, stderr};
+use pixfix::fixtures::TempDir;
+
+#[test]
+fn shell_integration_is_refused_off_windows() {
+    let dir = TempDir::new("shell-integration").unwrap();
+
+    for command in ["install-shell-integration", "uninstall-shell-integration"] {
+        let output = run(pixfix(dir.path()).arg(command));
+
+        assert_eq!(output.status.code(), Some(1), "{}", command);
+        assert!(stderr(&output).contains("only"), "{}", stderr(&output));
+    }
+}
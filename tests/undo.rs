@@ -0,0 +1,40 @@
+//! `pixfix undo` puts back what a `--journal` run overwrote, unless the
+//! files changed since.
+
+mod common;
+
+use common::{pixfix, run, sprite_png, stderr};
+use pixfix::fixtures::TempDir;
+
+/// A folder with `a.png` fixed in place, its original kept in `journal`.
+fn fixed() -> TempDir {
+    let dir = TempDir::new("undo").unwrap();
+    std::fs::write(dir.path().join("a.png"), sprite_png()).unwrap();
+
+    let output = run(pixfix(dir.path()).args(["--no-pause", "--journal", "journal", "a.png"]));
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(std::fs::read(dir.path().join("a.png")).unwrap() != sprite_png());
+
+    dir
+}
+
+#[test]
+fn undo_restores_the_original_bytes() {
+    let dir = fixed();
+    let output = run(pixfix(dir.path()).args(["undo", "--journal", "journal"]));
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(std::fs::read(dir.path().join("a.png")).unwrap() == sprite_png());
+}
+
+#[test]
+fn undo_refuses_a_file_changed_since_it_was_fixed() {
+    let dir = fixed();
+    std::fs::write(dir.path().join("a.png"), b"edited").unwrap();
+
+    let output = run(pixfix(dir.path()).args(["undo", "--journal", "journal"]));
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Changed since it was fixed"));
+    assert_eq!(std::fs::read(dir.path().join("a.png")).unwrap(), b"edited");
+}
@@ -0,0 +1,34 @@
+//! Mistakes in the command line fail the run, so scripts and CI notice them.
+
+mod common;
+
+use common::{pixfix, run, sprite_png, stderr};
+use pixfix::fixtures::TempDir;
+
+fn setup() -> TempDir {
+    let dir = TempDir::new("usage").unwrap();
+    std::fs::write(dir.path().join("a.png"), sprite_png()).unwrap();
+
+    dir
+}
+
+#[test]
+fn an_invalid_option_value_exits_with_2() {
+    let dir = setup();
+
+    for args in [
+        &["--retries", "abc"][..],
+        &["--jobs", "many"],
+        &["--alpha-fill", "300"],
+    ] {
+        let output = run(pixfix(dir.path())
+            .args(["--no-pause", "--in-place"])
+            .args(args)
+            .arg("a.png"));
+
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+        assert!(!stderr(&output).is_empty());
+    }
+
+    assert!(std::fs::read(dir.path().join("a.png")).unwrap() == sprite_png());
+}
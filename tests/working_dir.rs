@@ -0,0 +1,43 @@
+mod common;
+
+use common::{pixfix, run, sprite_png, stderr};
+use pixfix::fixtures::TempDir;
+
+fn setup() -> TempDir {
+    let dir = TempDir::new("working-dir").unwrap();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    std::fs::write(dir.path().join("sub/a.png"), sprite_png()).unwrap();
+
+    dir
+}
+
+#[test]
+fn the_environment_changes_the_directory_once() {
+    let dir = setup();
+    let output = run(pixfix(dir.path())
+        .env("PIXFIX_FLAGS", "--working-dir sub")
+        .args(["--no-pause", "--in-place", "a.png"]));
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(std::fs::read(dir.path().join("sub/a.png")).unwrap() != sprite_png());
+}
+
+#[test]
+fn the_command_line_changes_the_directory_once() {
+    let dir = setup();
+    let output =
+        run(pixfix(dir.path()).args(["--working-dir", "sub", "--no-pause", "--in-place", "a.png"]));
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(std::fs::read(dir.path().join("sub/a.png")).unwrap() != sprite_png());
+}
+
+#[test]
+fn the_command_line_takes_precedence_over_the_environment() {
+    let dir = setup();
+    let output = run(pixfix(dir.path())
+        .env("PIXFIX_FLAGS", "--working-dir missing")
+        .args(["--working-dir", "sub", "--no-pause", "--in-place", "a.png"]));
+
+    assert!(output.status.success(), "{}", stderr(&output));
+}